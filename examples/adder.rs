@@ -13,15 +13,18 @@ fn main() -> io::Result<()> {
         ]),
     };
 
-    module.types.push(types::FunctionType {
-        parameter_types: vec![types::ValType::F32, types::ValType::F32],
-        return_types: vec![types::ValType::F32],
-    });
+    module.types.push(
+        types::FunctionType {
+            parameter_types: vec![types::ValType::F32, types::ValType::F32],
+            return_types: vec![types::ValType::F32],
+        }
+        .into(),
+    );
     module.functions.push(0);
     module.code.push(add);
     module.exports.push(sections::Export {
         name: String::from("add"),
-        desc: sections::Desc::Function(0),
+        desc: sections::ExportDesc::Function(0),
     });
 
     let mut file = fs::OpenOptions::new()
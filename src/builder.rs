@@ -0,0 +1,943 @@
+//! Higher level builders that keep track of indices
+//!
+//! Items added to a [`ModuleBuilder`](struct.ModuleBuilder.html) are referred to by typed handles,
+//! their final indices are only assigned by [`finish`](struct.ModuleBuilder.html#method.finish)
+
+use crate::{
+    error::Result,
+    instr::{BlockType, Catch, Expr, Instruction, TryHandler},
+    module::Module,
+    sections::*,
+    types::{self, ValType},
+    validate::{error, Location},
+};
+use std::{borrow::Cow, mem};
+
+/// A function of a [`ModuleBuilder`](struct.ModuleBuilder.html), imported or defined
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct FuncId(u32);
+
+/// A global of a [`ModuleBuilder`](struct.ModuleBuilder.html), imported or defined
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct GlobalId(u32);
+
+/// A memory of a [`ModuleBuilder`](struct.ModuleBuilder.html), imported or defined
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct MemoryId(u32);
+
+/// A table of a [`ModuleBuilder`](struct.ModuleBuilder.html), imported or defined
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct TableId(u32);
+
+/// A tag of a [`ModuleBuilder`](struct.ModuleBuilder.html), imported or defined
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct TagId(u32);
+
+/// A type of a [`ModuleBuilder`](struct.ModuleBuilder.html)
+///
+/// Type indices don't change at [`finish`](struct.ModuleBuilder.html#method.finish),
+/// so this is already the final index
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct TypeId(u32);
+
+macro_rules! handle_into_index {
+    ($($handle:ident => $idx:ident),*) => {
+        $(
+            impl From<$handle> for $idx {
+                fn from(handle: $handle) -> Self {
+                    handle.0
+                }
+            }
+        )*
+    };
+}
+
+handle_into_index!(
+    FuncId => FuncIdx,
+    GlobalId => GlobalIdx,
+    MemoryId => MemoryIdx,
+    TableId => TableIdx,
+    TagId => TagIdx,
+    TypeId => TypeIdx
+);
+
+/// Where an item of an index space comes from
+#[derive(Debug, Copy, Clone)]
+enum Origin {
+    /// The nth import of its kind
+    Imported(u32),
+    /// The nth item of its kind defined in the module
+    Defined(u32),
+}
+
+/// The items of an index space in the order they were added
+#[derive(Debug, Default)]
+struct Space {
+    items: Vec<Origin>,
+    imported: u32,
+    defined: u32,
+}
+
+impl Space {
+    fn import(&mut self) -> u32 {
+        self.items.push(Origin::Imported(self.imported));
+        self.imported += 1;
+        self.items.len() as u32 - 1
+    }
+
+    fn define(&mut self) -> u32 {
+        self.items.push(Origin::Defined(self.defined));
+        self.defined += 1;
+        self.items.len() as u32 - 1
+    }
+
+    /// The final index of every handle, imports come first
+    fn indices(&self) -> Vec<u32> {
+        self.items
+            .iter()
+            .map(|origin| match *origin {
+                Origin::Imported(n) => n,
+                Origin::Defined(n) => self.imported + n,
+            })
+            .collect()
+    }
+}
+
+/// Builds a [`Module`](../module/struct.Module.html) handing out typed handles
+/// instead of raw indices
+///
+/// Imports can be added at any point, at [`finish`](#method.finish) they're moved in front
+/// of the defined items and every index is fixed up accordingly.
+///
+/// Instructions, offsets and init expressions passed to the builder refer to functions,
+/// globals, tables, memories and tags by converting their handles with `into()`,
+/// raw indices that don't come from a handle of this builder are rejected by `finish`.
+///
+/// # Example
+///
+/// ```
+/// # use wasm_builder::{builder::ModuleBuilder, instr::*, sections::Function, types::*};
+/// let mut builder = ModuleBuilder::new();
+/// let ty = builder.add_type(FunctionType {
+///     parameter_types: vec![],
+///     return_types: vec![],
+/// });
+///
+/// let main = builder.add_function(
+///     ty,
+///     Function {
+///         locals: vec![],
+///         body: Expr(vec![]),
+///     },
+/// );
+/// // Added after `main` but it still gets index 0
+/// let log = builder.import_function("env", "log", ty);
+/// builder.define_function(
+///     main,
+///     Function {
+///         locals: vec![],
+///         body: Expr(vec![Instruction::Call(log.into())]),
+///     },
+/// );
+/// builder.export_function("main", main);
+///
+/// let module = builder.finish().unwrap();
+/// assert_eq!(module.code[0].body, Expr(vec![Instruction::Call(0)]));
+/// assert!(module.validate().is_ok());
+/// ```
+#[derive(Debug, Default)]
+pub struct ModuleBuilder<'a> {
+    types: Vec<types::RecGroup>,
+    imports: Vec<Import>,
+    funcs: Space,
+    tables: Space,
+    memories: Space,
+    globals: Space,
+    tags: Space,
+    functions: Vec<(TypeIdx, Option<Function>)>,
+    table_defs: Vec<Table>,
+    memory_types: Vec<types::MemoryType>,
+    global_defs: Vec<Global>,
+    tag_types: Vec<TypeIdx>,
+    exports: Vec<Export>,
+    start: Option<FuncIdx>,
+    elements: Vec<Element>,
+    data: Vec<Data<'a>>,
+    custom_sections: Vec<CustomSection<'a>>,
+}
+
+impl<'a> ModuleBuilder<'a> {
+    /// Creates an empty builder
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn sub_types(&self) -> impl Iterator<Item = &types::SubType> {
+        self.types.iter().flat_map(|group| group.0.iter())
+    }
+
+    /// Adds a function type, reusing an equal type if it was already added
+    pub fn add_type(&mut self, ty: types::FunctionType) -> TypeId {
+        let group = types::RecGroup::from(ty);
+        let mut idx = 0;
+        for other in self.types.iter() {
+            if *other == group {
+                return TypeId(idx);
+            }
+            idx += other.0.len() as u32;
+        }
+        self.add_rec_group(|_| group)[0]
+    }
+
+    /// Adds a recursion group, its types can refer to each other and to earlier types
+    ///
+    /// `group` is given the handle of the group's first type, the others follow it
+    /// with consecutive indices. Returns the handles of every type of the group
+    pub fn add_rec_group(&mut self, group: impl FnOnce(TypeId) -> types::RecGroup) -> Vec<TypeId> {
+        let first = self.sub_types().count() as u32;
+        let group = group(TypeId(first));
+        let ids = (first..first + group.0.len() as u32).map(TypeId).collect();
+        self.types.push(group);
+        ids
+    }
+
+    /// Returns the type behind a handle
+    pub fn get_type(&self, ty: TypeId) -> &types::SubType {
+        self.sub_types()
+            .nth(ty.0 as usize)
+            .expect("type of another builder")
+    }
+
+    fn import(&mut self, module: impl Into<String>, name: impl Into<String>, desc: Desc) {
+        self.imports.push(Import {
+            module: module.into(),
+            name: name.into(),
+            desc,
+        });
+    }
+
+    /// Imports a function of type `ty`
+    pub fn import_function(
+        &mut self,
+        module: impl Into<String>,
+        name: impl Into<String>,
+        ty: TypeId,
+    ) -> FuncId {
+        self.import(module, name, Desc::Function(ty.0));
+        FuncId(self.funcs.import())
+    }
+
+    /// Imports a table
+    pub fn import_table(
+        &mut self,
+        module: impl Into<String>,
+        name: impl Into<String>,
+        ty: types::TableType,
+    ) -> TableId {
+        self.import(module, name, Desc::Table(ty));
+        TableId(self.tables.import())
+    }
+
+    /// Imports a memory
+    pub fn import_memory(
+        &mut self,
+        module: impl Into<String>,
+        name: impl Into<String>,
+        ty: types::MemoryType,
+    ) -> MemoryId {
+        self.import(module, name, Desc::Memory(ty));
+        MemoryId(self.memories.import())
+    }
+
+    /// Imports a global
+    pub fn import_global(
+        &mut self,
+        module: impl Into<String>,
+        name: impl Into<String>,
+        ty: types::GlobalType,
+    ) -> GlobalId {
+        self.import(module, name, Desc::Global(ty));
+        GlobalId(self.globals.import())
+    }
+
+    /// Imports a tag whose exceptions carry the parameters of `ty`
+    pub fn import_tag(
+        &mut self,
+        module: impl Into<String>,
+        name: impl Into<String>,
+        ty: TypeId,
+    ) -> TagId {
+        self.import(module, name, Desc::Tag(ty.0));
+        TagId(self.tags.import())
+    }
+
+    /// Adds a function of type `ty` whose body is given later with
+    /// [`define_function`](#method.define_function)
+    ///
+    /// Useful for recursive functions and functions that call each other
+    pub fn declare_function(&mut self, ty: TypeId) -> FuncId {
+        self.functions.push((ty.0, None));
+        FuncId(self.funcs.define())
+    }
+
+    /// Sets the body of a function added by this builder, replacing any previous one
+    ///
+    /// # Panics
+    ///
+    /// If `func` is an imported function
+    pub fn define_function(&mut self, func: FuncId, body: Function) {
+        match self.funcs.items[func.0 as usize] {
+            Origin::Defined(n) => self.functions[n as usize].1 = Some(body),
+            Origin::Imported(_) => panic!("imported functions can't be defined"),
+        }
+    }
+
+    /// Adds a function of type `ty`
+    pub fn add_function(&mut self, ty: TypeId, body: Function) -> FuncId {
+        let func = self.declare_function(ty);
+        self.define_function(func, body);
+        func
+    }
+
+    /// Adds a table
+    pub fn add_table(&mut self, ty: types::TableType) -> TableId {
+        self.table_defs.push(ty.into());
+        TableId(self.tables.define())
+    }
+
+    /// Adds a table whose elements start as the value of the constant expression `init`
+    ///
+    /// Unlike [`add_table`](#method.add_table) the element type doesn't need to be nullable
+    pub fn add_table_with_init(&mut self, ty: types::TableType, init: Expr) -> TableId {
+        self.table_defs.push(Table {
+            ty,
+            init: Some(init),
+        });
+        TableId(self.tables.define())
+    }
+
+    /// Adds a memory
+    pub fn add_memory(&mut self, ty: types::MemoryType) -> MemoryId {
+        self.memory_types.push(ty);
+        MemoryId(self.memories.define())
+    }
+
+    /// Adds a global initialized by the constant expression `init`
+    pub fn add_global(&mut self, ty: types::GlobalType, init: Expr) -> GlobalId {
+        self.global_defs.push(Global { ty, init });
+        GlobalId(self.globals.define())
+    }
+
+    /// Adds a tag whose exceptions carry the parameters of `ty`
+    pub fn add_tag(&mut self, ty: TypeId) -> TagId {
+        self.tag_types.push(ty.0);
+        TagId(self.tags.define())
+    }
+
+    fn export(&mut self, name: impl Into<String>, desc: ExportDesc) {
+        self.exports.push(Export {
+            name: name.into(),
+            desc,
+        });
+    }
+
+    /// Exports a function
+    pub fn export_function(&mut self, name: impl Into<String>, func: FuncId) {
+        self.export(name, ExportDesc::Function(func.0))
+    }
+
+    /// Exports a table
+    pub fn export_table(&mut self, name: impl Into<String>, table: TableId) {
+        self.export(name, ExportDesc::Table(table.0))
+    }
+
+    /// Exports a memory
+    pub fn export_memory(&mut self, name: impl Into<String>, mem: MemoryId) {
+        self.export(name, ExportDesc::Memory(mem.0))
+    }
+
+    /// Exports a global
+    pub fn export_global(&mut self, name: impl Into<String>, global: GlobalId) {
+        self.export(name, ExportDesc::Global(global.0))
+    }
+
+    /// Exports a tag
+    pub fn export_tag(&mut self, name: impl Into<String>, tag: TagId) {
+        self.export(name, ExportDesc::Tag(tag.0))
+    }
+
+    /// Sets the function that is called when the module is instantiated
+    pub fn set_start(&mut self, func: FuncId) {
+        self.start = Some(func.0);
+    }
+
+    /// Adds an element segment writing `init` into `table` starting at `offset`,
+    /// returning its index
+    pub fn add_element(&mut self, table: TableId, offset: Expr, init: Vec<FuncId>) -> ElemIdx {
+        let mode = ElementMode::Active {
+            table: table.0,
+            offset,
+        };
+        self.push_element(mode, init)
+    }
+
+    /// Adds an element segment that is only copied into a table by `table.init`,
+    /// returning its index
+    pub fn add_passive_element(&mut self, init: Vec<FuncId>) -> ElemIdx {
+        self.push_element(ElementMode::Passive, init)
+    }
+
+    /// Adds an element segment declaring functions that are only referenced by `ref.func`
+    /// in function bodies, returning its index
+    pub fn add_declarative_element(&mut self, funcs: Vec<FuncId>) -> ElemIdx {
+        self.push_element(ElementMode::Declarative, funcs)
+    }
+
+    fn push_element(&mut self, mode: ElementMode, init: Vec<FuncId>) -> ElemIdx {
+        self.elements.push(Element {
+            mode,
+            items: ElementItems::Functions(init.into_iter().map(|func| func.0).collect()),
+        });
+        self.elements.len() as u32 - 1
+    }
+
+    /// Adds a data segment writing `init` into `mem` starting at `offset`, returning its index
+    pub fn add_data(
+        &mut self,
+        mem: MemoryId,
+        offset: Expr,
+        init: impl Into<Cow<'a, [u8]>>,
+    ) -> DataIdx {
+        self.push_data(DataMode::Active { mem: mem.0, offset }, init.into())
+    }
+
+    /// Adds a data segment that is only copied into memory by `memory.init`, returning its index
+    pub fn add_passive_data(&mut self, init: impl Into<Cow<'a, [u8]>>) -> DataIdx {
+        self.push_data(DataMode::Passive, init.into())
+    }
+
+    fn push_data(&mut self, mode: DataMode, init: Cow<'a, [u8]>) -> DataIdx {
+        self.data.push(Data { mode, init });
+        self.data.len() as u32 - 1
+    }
+
+    /// Adds a custom section named `name` holding `data`
+    pub fn add_custom_section(
+        &mut self,
+        name: impl Into<String>,
+        data: impl Into<Cow<'a, [u8]>>,
+        placement: Placement,
+    ) {
+        self.custom_sections.push(CustomSection {
+            name: name.into(),
+            data: data.into(),
+            placement,
+        });
+    }
+
+    /// Assigns the final indices and builds the module
+    ///
+    /// Fails if a declared function was never defined or if an instruction uses
+    /// an index that doesn't come from a handle of this builder
+    pub fn finish(self) -> Result<Module<'a>> {
+        let remap = Remap {
+            funcs: self.funcs.indices(),
+            tables: self.tables.indices(),
+            memories: self.memories.indices(),
+            globals: self.globals.indices(),
+            tags: self.tags.indices(),
+        };
+
+        let mut module = Module::new();
+        module.types = self.types;
+        module.imports = self.imports;
+        module.memory = self.memory_types;
+        module.tags = self.tag_types;
+        module.custom_sections = self.custom_sections;
+
+        let imported_tables = self.tables.imported;
+        for (i, mut table) in self.table_defs.into_iter().enumerate() {
+            if let Some(ref mut init) = table.init {
+                remap.expr(init, format!("table {}", imported_tables as usize + i))?;
+            }
+            module.tables.push(table);
+        }
+
+        let imported_globals = self.globals.imported;
+        for (i, mut global) in self.global_defs.into_iter().enumerate() {
+            let location = format!("global {}", imported_globals as usize + i);
+            remap.expr(&mut global.init, location)?;
+            module.globals.push(global);
+        }
+
+        let imported_funcs = self.funcs.imported;
+        for (i, (ty, body)) in self.functions.into_iter().enumerate() {
+            let location = format!("func {}", imported_funcs as usize + i);
+            let mut body = match body {
+                Some(body) => body,
+                None => return Err(error(location, "declared but never defined")),
+            };
+            remap.expr(&mut body.body, location)?;
+            module.functions.push(ty);
+            module.code.push(body);
+        }
+
+        for (i, mut export) in self.exports.into_iter().enumerate() {
+            let context = format!("export {}", i);
+            export.desc = match export.desc {
+                ExportDesc::Function(idx) => ExportDesc::Function(remap.func(&context, idx)?),
+                ExportDesc::Table(idx) => ExportDesc::Table(remap.table(&context, idx)?),
+                ExportDesc::Memory(idx) => ExportDesc::Memory(remap.memory(&context, idx)?),
+                ExportDesc::Global(idx) => ExportDesc::Global(remap.global(&context, idx)?),
+                ExportDesc::Tag(idx) => ExportDesc::Tag(remap.tag(&context, idx)?),
+            };
+            module.exports.push(export);
+        }
+
+        if let Some(start) = self.start {
+            module.start = Some(remap.func("start", start)?);
+        }
+
+        for (i, mut elem) in self.elements.into_iter().enumerate() {
+            let context = format!("elem {}", i);
+            if let ElementMode::Active {
+                ref mut table,
+                ref mut offset,
+            } = elem.mode
+            {
+                *table = remap.table(&context, *table)?;
+                remap.expr(offset, context.clone())?;
+            }
+            if let ElementItems::Functions(ref mut funcs) = elem.items {
+                for func in funcs.iter_mut() {
+                    *func = remap.func(&context, *func)?;
+                }
+            }
+            module.elements.push(elem);
+        }
+
+        for (i, mut data) in self.data.into_iter().enumerate() {
+            let context = format!("data {}", i);
+            if let DataMode::Active {
+                ref mut mem,
+                ref mut offset,
+            } = data.mode
+            {
+                *mem = remap.memory(&context, *mem)?;
+                remap.expr(offset, context)?;
+            }
+            module.data.push(data);
+        }
+
+        Ok(module)
+    }
+}
+
+/// Builds the locals and body of a function of a given type
+///
+/// # Example
+///
+/// ```
+/// # use wasm_builder::{builder::*, instr::*, types::*};
+/// let mut func = FunctionBuilder::new(FunctionType {
+///     parameter_types: vec![ValType::I32],
+///     return_types: vec![ValType::I32],
+/// });
+/// // The parameter is local 0
+/// let a = func.add_local(ValType::I32);
+/// let b = func.add_local(ValType::I32);
+/// assert_eq!((a, b), (1, 2));
+///
+/// func.push(Instruction::LocalGet(0));
+/// func.push(Instruction::LocalTee(a));
+/// func.push(Instruction::LocalTee(b));
+///
+/// let mut builder = ModuleBuilder::new();
+/// let (ty, func) = func.finish(&mut builder);
+/// // Both locals are declared by a single entry
+/// assert_eq!(func.locals.len(), 1);
+/// builder.add_function(ty, func);
+/// ```
+#[derive(Debug, Clone)]
+pub struct FunctionBuilder {
+    ty: types::FunctionType,
+    locals: Vec<Local>,
+    len: u32,
+    body: Vec<Instruction>,
+    /// The labels of the enclosing blocks, innermost last
+    labels: Vec<u32>,
+    next_label: u32,
+    location: Location,
+}
+
+/// The target of a branch, handed out by the control-flow methods of
+/// [`FunctionBuilder`](struct.FunctionBuilder.html)
+///
+/// A label can only be used inside the closure of the block it was given to
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Label(u32);
+
+impl FunctionBuilder {
+    /// Creates a function of type `ty` with no locals and an empty body
+    pub fn new(ty: types::FunctionType) -> Self {
+        FunctionBuilder {
+            len: ty.parameter_types.len() as u32,
+            ty,
+            locals: Vec::new(),
+            body: Vec::new(),
+            labels: Vec::new(),
+            next_label: 0,
+            location: Location::new("function"),
+        }
+    }
+
+    /// The type of the function
+    pub fn ty(&self) -> &types::FunctionType {
+        &self.ty
+    }
+
+    /// Adds a local, returning its index
+    ///
+    /// Parameters take the first indices so the first local comes right after them
+    pub fn add_local(&mut self, ty: ValType) -> LocalIdx {
+        match self.locals.last_mut() {
+            Some(local) if local.ty == ty => local.n += 1,
+            _ => self.locals.push(Local { n: 1, ty }),
+        }
+        self.len += 1;
+        self.len - 1
+    }
+
+    /// Returns the type of a parameter or local
+    pub fn local_type(&self, idx: LocalIdx) -> Option<ValType> {
+        if let Some(ty) = self.ty.parameter_types.get(idx as usize) {
+            return Some(*ty);
+        }
+
+        let mut first = self.ty.parameter_types.len() as u32;
+        for local in self.locals.iter() {
+            if idx < first + local.n {
+                return Some(local.ty);
+            }
+            first += local.n;
+        }
+        None
+    }
+
+    /// Appends an instruction to the body
+    pub fn push(&mut self, instr: Instruction) {
+        self.body.push(instr);
+    }
+
+    fn new_label(&mut self) -> Label {
+        self.next_label += 1;
+        Label(self.next_label - 1)
+    }
+
+    /// Builds the instructions of a nested block, returning them
+    fn nested<F>(&mut self, kind: &'static str, label: Label, build: F) -> Result<Vec<Instruction>>
+    where
+        F: FnOnce(&mut Self, Label) -> Result<()>,
+    {
+        self.location.push(kind, self.body.len());
+        self.labels.push(label.0);
+        let outer = mem::take(&mut self.body);
+        let res = build(self, label);
+        let instrs = mem::replace(&mut self.body, outer);
+        self.labels.pop();
+        self.location.pop();
+
+        res.map(|_| instrs)
+    }
+
+    /// Appends a `block`, branching to its label jumps to the end of the block
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use wasm_builder::{builder::*, instr::*, types::*};
+    /// let mut func = FunctionBuilder::new(FunctionType {
+    ///     parameter_types: vec![ValType::I32],
+    ///     return_types: vec![],
+    /// });
+    ///
+    /// let mut escaped = None;
+    /// func.block(BlockType::Empty, |func, outer| {
+    ///     func.loop_(BlockType::Empty, |func, _| {
+    ///         func.push(Instruction::LocalGet(0));
+    ///         // One level up from the loop
+    ///         func.br_if(outer)
+    ///     })?;
+    ///     escaped = Some(outer);
+    ///     Ok(())
+    /// })
+    /// .unwrap();
+    /// // The block has ended so its label can't be used anymore
+    /// assert!(func.br(escaped.unwrap()).is_err());
+    ///
+    /// let (_, func) = func.finish(&mut ModuleBuilder::new());
+    /// assert_eq!(
+    ///     func.body,
+    ///     Expr(vec![Instruction::Block {
+    ///         ty: BlockType::Empty,
+    ///         instrs: vec![Instruction::Loop {
+    ///             ty: BlockType::Empty,
+    ///             instrs: vec![Instruction::LocalGet(0), Instruction::BranchIf(1)],
+    ///         }],
+    ///     }])
+    /// );
+    /// ```
+    pub fn block<F>(&mut self, ty: BlockType, build: F) -> Result<()>
+    where
+        F: FnOnce(&mut Self, Label) -> Result<()>,
+    {
+        let label = self.new_label();
+        let instrs = self.nested("block", label, build)?;
+        self.push(Instruction::Block { ty, instrs });
+        Ok(())
+    }
+
+    /// Appends a `loop`, branching to its label jumps back to the start of the loop
+    pub fn loop_<F>(&mut self, ty: BlockType, build: F) -> Result<()>
+    where
+        F: FnOnce(&mut Self, Label) -> Result<()>,
+    {
+        let label = self.new_label();
+        let instrs = self.nested("loop", label, build)?;
+        self.push(Instruction::Loop { ty, instrs });
+        Ok(())
+    }
+
+    /// Appends an `if` without an `else` branch
+    pub fn if_<F>(&mut self, ty: BlockType, accept: F) -> Result<()>
+    where
+        F: FnOnce(&mut Self, Label) -> Result<()>,
+    {
+        let label = self.new_label();
+        let accept_instrs = self.nested("if", label, accept)?;
+        self.push(Instruction::If {
+            ty,
+            accept_instrs,
+            reject_instrs: None,
+        });
+        Ok(())
+    }
+
+    /// Appends an `if` with an `else` branch, both branches get the same label
+    pub fn if_else<F, G>(&mut self, ty: BlockType, accept: F, reject: G) -> Result<()>
+    where
+        F: FnOnce(&mut Self, Label) -> Result<()>,
+        G: FnOnce(&mut Self, Label) -> Result<()>,
+    {
+        let label = self.new_label();
+        let accept_instrs = self.nested("if", label, accept)?;
+        let reject_instrs = self.nested("else", label, reject)?;
+        self.push(Instruction::If {
+            ty,
+            accept_instrs,
+            reject_instrs: Some(reject_instrs),
+        });
+        Ok(())
+    }
+
+    /// The relative depth of `label` from the current block
+    fn depth(&self, label: Label) -> Result<LabelIdx> {
+        match self.labels.iter().rev().position(|other| *other == label.0) {
+            Some(depth) => Ok(depth as LabelIdx),
+            None => Err(error(
+                self.location.instr(self.body.len()),
+                "label used outside of its block",
+            )),
+        }
+    }
+
+    /// Appends a `br` to `label`
+    pub fn br(&mut self, label: Label) -> Result<()> {
+        let depth = self.depth(label)?;
+        self.push(Instruction::Branch(depth));
+        Ok(())
+    }
+
+    /// Appends a `br_if` to `label`
+    pub fn br_if(&mut self, label: Label) -> Result<()> {
+        let depth = self.depth(label)?;
+        self.push(Instruction::BranchIf(depth));
+        Ok(())
+    }
+
+    /// Appends a `br_on_null` to `label`, taken when the reference on the stack is null
+    pub fn br_on_null(&mut self, label: Label) -> Result<()> {
+        let depth = self.depth(label)?;
+        self.push(Instruction::BranchOnNull(depth));
+        Ok(())
+    }
+
+    /// Appends a `br_on_non_null` to `label`, which receives the reference when it isn't null
+    pub fn br_on_non_null(&mut self, label: Label) -> Result<()> {
+        let depth = self.depth(label)?;
+        self.push(Instruction::BranchOnNonNull(depth));
+        Ok(())
+    }
+
+    /// Appends a `br_table` branching to `labels[n]` or to `default` if `n` is out of bounds
+    pub fn br_table(&mut self, labels: &[Label], default: Label) -> Result<()> {
+        let labels = labels
+            .iter()
+            .map(|label| self.depth(*label))
+            .collect::<Result<_>>()?;
+        let operand = self.depth(default)?;
+        self.push(Instruction::BranchTable { labels, operand });
+        Ok(())
+    }
+
+    /// Adds the function type to `module`, returning its index and the function
+    pub fn finish(self, module: &mut ModuleBuilder) -> (TypeId, Function) {
+        let ty = module.add_type(self.ty);
+        let func = Function {
+            locals: self.locals,
+            body: Expr(self.body),
+        };
+        (ty, func)
+    }
+}
+
+/// Maps the handles of every index space to their final indices
+struct Remap {
+    funcs: Vec<FuncIdx>,
+    tables: Vec<TableIdx>,
+    memories: Vec<MemoryIdx>,
+    globals: Vec<GlobalIdx>,
+    tags: Vec<TagIdx>,
+}
+
+impl Remap {
+    fn get(indices: &[u32], context: &str, kind: &str, handle: u32) -> Result<u32> {
+        match indices.get(handle as usize) {
+            Some(idx) => Ok(*idx),
+            None => Err(error(context, format!("unknown {} {}", kind, handle))),
+        }
+    }
+
+    fn func(&self, context: &str, handle: FuncIdx) -> Result<FuncIdx> {
+        Remap::get(&self.funcs, context, "function", handle)
+    }
+
+    fn table(&self, context: &str, handle: TableIdx) -> Result<TableIdx> {
+        Remap::get(&self.tables, context, "table", handle)
+    }
+
+    fn memory(&self, context: &str, handle: MemoryIdx) -> Result<MemoryIdx> {
+        Remap::get(&self.memories, context, "memory", handle)
+    }
+
+    fn global(&self, context: &str, handle: GlobalIdx) -> Result<GlobalIdx> {
+        Remap::get(&self.globals, context, "global", handle)
+    }
+
+    fn tag(&self, context: &str, handle: TagIdx) -> Result<TagIdx> {
+        Remap::get(&self.tags, context, "tag", handle)
+    }
+
+    fn expr(&self, expr: &mut Expr, location: String) -> Result<()> {
+        self.instrs(&mut expr.0, &mut Location::new(location))
+    }
+
+    fn instrs(&self, instrs: &mut [Instruction], location: &mut Location) -> Result<()> {
+        for (i, instr) in instrs.iter_mut().enumerate() {
+            let context = location.instr(i).to_string();
+
+            if let Some(mem) = instr.memarg_mut() {
+                mem.memory = self.memory(&context, mem.memory)?;
+            }
+
+            match instr {
+                Instruction::Block { instrs, .. } => {
+                    location.push("block", i);
+                    self.instrs(instrs, location)?;
+                    location.pop();
+                }
+                Instruction::Loop { instrs, .. } => {
+                    location.push("loop", i);
+                    self.instrs(instrs, location)?;
+                    location.pop();
+                }
+                Instruction::If {
+                    accept_instrs,
+                    reject_instrs,
+                    ..
+                } => {
+                    location.push("if", i);
+                    self.instrs(accept_instrs, location)?;
+                    location.pop();
+                    if let Some(reject_instrs) = reject_instrs {
+                        location.push("else", i);
+                        self.instrs(reject_instrs, location)?;
+                        location.pop();
+                    }
+                }
+                Instruction::TryTable {
+                    catches, instrs, ..
+                } => {
+                    for catch in catches.iter_mut() {
+                        if let Catch::Catch { tag, .. } | Catch::CatchRef { tag, .. } = catch {
+                            *tag = self.tag(&context, *tag)?;
+                        }
+                    }
+                    location.push("try_table", i);
+                    self.instrs(instrs, location)?;
+                    location.pop();
+                }
+                Instruction::Try {
+                    instrs, handler, ..
+                } => {
+                    location.push("try", i);
+                    self.instrs(instrs, location)?;
+                    location.pop();
+                    if let TryHandler::Catches { catches, catch_all } = handler {
+                        for (tag, instrs) in catches.iter_mut() {
+                            *tag = self.tag(&context, *tag)?;
+                            location.push("catch", i);
+                            self.instrs(instrs, location)?;
+                            location.pop();
+                        }
+                        if let Some(instrs) = catch_all {
+                            location.push("catch_all", i);
+                            self.instrs(instrs, location)?;
+                            location.pop();
+                        }
+                    }
+                }
+                Instruction::Throw(tag) => *tag = self.tag(&context, *tag)?,
+                Instruction::Call(func)
+                | Instruction::ReturnCall(func)
+                | Instruction::RefFunc(func) => *func = self.func(&context, *func)?,
+                Instruction::CallIndirect { table, .. }
+                | Instruction::ReturnCallIndirect { table, .. }
+                | Instruction::TableGet(table)
+                | Instruction::TableSet(table)
+                | Instruction::TableGrow(table)
+                | Instruction::TableSize(table)
+                | Instruction::TableFill(table)
+                | Instruction::TableInit { table, .. } => *table = self.table(&context, *table)?,
+                Instruction::TableCopy { dst, src } => {
+                    *dst = self.table(&context, *dst)?;
+                    *src = self.table(&context, *src)?;
+                }
+                Instruction::GlobalGet(global) | Instruction::GlobalSet(global) => {
+                    *global = self.global(&context, *global)?
+                }
+                Instruction::MemorySize(mem)
+                | Instruction::MemoryGrow(mem)
+                | Instruction::MemoryFill(mem)
+                | Instruction::MemoryInit { mem, .. } => *mem = self.memory(&context, *mem)?,
+                Instruction::MemoryCopy { dst, src } => {
+                    *dst = self.memory(&context, *dst)?;
+                    *src = self.memory(&context, *src)?;
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,96 @@
+use std::{fmt, io};
+
+/// The errors that can happen while encoding or decoding a module
+#[derive(Debug)]
+pub enum Error {
+    /// The underlying reader or writer failed
+    Io(io::Error),
+    /// The instruction's operands don't correspond to any wasm instruction
+    /// (i.e. a `Load` of a `F32` with a storage size)
+    InvalidInstruction {
+        /// The offending instruction
+        instr: String,
+        /// Why it can't be encoded
+        reason: &'static str,
+    },
+    /// A count (vector length, section size, ...) doesn't fit in the `u32` used by the binary format
+    CountOverflow(usize),
+    /// The binary being decoded isn't a well formed module
+    Malformed(String),
+    /// The module is well formed but breaks one of the validation rules
+    Validation {
+        /// Where the problem was found (i.e. `export 2`)
+        context: String,
+        /// What the problem is
+        message: String,
+    },
+    /// The text being parsed isn't a well formed WebAssembly text module
+    Parse {
+        /// The line of the offending token, starting at 1
+        line: usize,
+        /// The column of the offending token, starting at 1
+        column: usize,
+        /// What the problem is
+        message: String,
+    },
+    /// The module uses a proposal that isn't enabled in the [`Features`](../features/struct.Features.html)
+    FeatureDisabled {
+        /// Where the proposal is used (i.e. `func 3, instr 2`)
+        context: String,
+        /// The name of the proposal
+        feature: &'static str,
+    },
+}
+
+/// Shorthand for results whose error is [`Error`](enum.Error.html)
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "I/O error: {}", err),
+            Error::InvalidInstruction { instr, reason } => {
+                write!(f, "invalid instruction {}: {}", instr, reason)
+            }
+            Error::CountOverflow(count) => write!(f, "count {} doesn't fit in a u32", count),
+            Error::Malformed(msg) => write!(f, "malformed module: {}", msg),
+            Error::Validation { context, message } => write!(f, "{}: {}", context, message),
+            Error::Parse {
+                line,
+                column,
+                message,
+            } => write!(f, "{}:{}: {}", line, column, message),
+            Error::FeatureDisabled { context, feature } => {
+                write!(f, "{}: the {} proposal is not enabled", context, feature)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+/// Allows using `?` on the crate's results inside functions returning `io::Result`
+impl From<Error> for io::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Io(err) => err,
+            Error::Malformed(_) | Error::Parse { .. } => {
+                io::Error::new(io::ErrorKind::InvalidData, err)
+            }
+            _ => io::Error::new(io::ErrorKind::InvalidInput, err),
+        }
+    }
+}
@@ -0,0 +1,566 @@
+use crate::{
+    error::{Error, Result},
+    instr::{BlockType, Instruction, TryHandler},
+    module::Module,
+    sections::*,
+    types,
+    validate::Location,
+};
+use std::fmt;
+
+/// The WebAssembly proposals a module is allowed to use
+///
+/// [`Default`](#impl-Default) enables the proposals that are part of the WebAssembly 2.0 spec,
+/// use [`Features::mvp`](#method.mvp) to target engines that only support the MVP
+///
+/// ```
+/// # use wasm_builder::{features::Features, module::Module, types::*};
+/// let mut module = Module::new();
+/// module.types.push(
+///     FunctionType {
+///         parameter_types: vec![],
+///         return_types: vec![ValType::I32, ValType::I32],
+///     }
+///     .into(),
+/// );
+///
+/// let mut wasm = Vec::new();
+/// assert!(module.encode_with_features(&mut wasm, &Features::mvp()).is_err());
+/// assert!(module.encode_with_features(&mut wasm, &Features::default()).is_ok());
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Features {
+    /// Import/Export of mutable globals
+    pub mutable_globals: bool,
+    /// Sign-extension operators (i.e. `i32.extend8_s`)
+    pub sign_extension: bool,
+    /// Non-trapping float-to-int conversions (i.e. `i32.trunc_sat_f32_s`)
+    pub saturating_float_to_int: bool,
+    /// Multiple results for functions and blocks, and block parameters
+    pub multi_value: bool,
+    /// Bulk memory operations and passive segments
+    pub bulk_memory: bool,
+    /// Reference types and multiple tables
+    pub reference_types: bool,
+    /// Fixed-width SIMD
+    pub simd: bool,
+    /// Shared memories and atomic instructions
+    pub threads: bool,
+    /// Multiple memories
+    pub multi_memory: bool,
+    /// 64-bit memories and tables
+    pub memory64: bool,
+    /// Tail calls
+    pub tail_call: bool,
+    /// Exception handling
+    pub exceptions: bool,
+    /// Typed function references
+    pub function_references: bool,
+    /// Garbage collection
+    pub gc: bool,
+    /// Integer arithmetic in constant expressions
+    pub extended_const: bool,
+}
+
+impl Features {
+    /// Only the features of the original WebAssembly release
+    pub fn mvp() -> Self {
+        Features {
+            mutable_globals: false,
+            sign_extension: false,
+            saturating_float_to_int: false,
+            multi_value: false,
+            bulk_memory: false,
+            reference_types: false,
+            simd: false,
+            threads: false,
+            multi_memory: false,
+            memory64: false,
+            tail_call: false,
+            exceptions: false,
+            function_references: false,
+            gc: false,
+            extended_const: false,
+        }
+    }
+
+    /// Every proposal supported by the crate
+    pub fn all() -> Self {
+        Features {
+            mutable_globals: true,
+            sign_extension: true,
+            saturating_float_to_int: true,
+            multi_value: true,
+            bulk_memory: true,
+            reference_types: true,
+            simd: true,
+            threads: true,
+            multi_memory: true,
+            memory64: true,
+            tail_call: true,
+            exceptions: true,
+            function_references: true,
+            gc: true,
+            extended_const: true,
+        }
+    }
+
+    fn require(
+        &self,
+        enabled: bool,
+        feature: &'static str,
+        context: impl fmt::Display,
+    ) -> Result<()> {
+        if enabled {
+            Ok(())
+        } else {
+            Err(Error::FeatureDisabled {
+                context: context.to_string(),
+                feature,
+            })
+        }
+    }
+
+    fn check_val_type(&self, ty: types::ValType, context: impl fmt::Display) -> Result<()> {
+        match ty {
+            types::ValType::Ref(ty) => self.check_ref_type(ty, context),
+            types::ValType::V128 => self.require(self.simd, "simd", context),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_ref_type(&self, ty: types::RefType, context: impl fmt::Display) -> Result<()> {
+        self.require(self.reference_types, "reference-types", &context)?;
+        if !ty.nullable || matches!(ty.heap_type, types::HeapType::Concrete(_)) {
+            self.require(self.function_references, "function-references", &context)?;
+        }
+        match ty.heap_type {
+            types::HeapType::Any
+            | types::HeapType::Eq
+            | types::HeapType::I31
+            | types::HeapType::Struct
+            | types::HeapType::Array
+            | types::HeapType::None
+            | types::HeapType::NoFunc
+            | types::HeapType::NoExtern => self.require(self.gc, "gc", context),
+            types::HeapType::Exn => self.require(self.exceptions, "exceptions", context),
+            types::HeapType::NoExn => {
+                self.require(self.exceptions, "exceptions", &context)?;
+                self.require(self.gc, "gc", context)
+            }
+            types::HeapType::Func | types::HeapType::Extern | types::HeapType::Concrete(_) => {
+                Ok(())
+            }
+        }
+    }
+
+    fn check_sub_type(&self, ty: &types::SubType, context: impl fmt::Display) -> Result<()> {
+        if !ty.is_final || ty.supertype.is_some() {
+            self.require(self.gc, "gc", &context)?;
+        }
+        let fields = match &ty.composite {
+            types::CompositeType::Func(ty) => return self.check_func_type(ty, context),
+            types::CompositeType::Struct(fields) => &fields[..],
+            types::CompositeType::Array(field) => std::slice::from_ref(field),
+        };
+        self.require(self.gc, "gc", &context)?;
+        for field in fields {
+            if let types::StorageType::Val(ty) = field.storage {
+                self.check_val_type(ty, &context)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn check_func_type(&self, ty: &types::FunctionType, context: impl fmt::Display) -> Result<()> {
+        if ty.return_types.len() > 1 {
+            self.require(self.multi_value, "multi-value", &context)?;
+        }
+        for ty in ty.parameter_types.iter().chain(ty.return_types.iter()) {
+            self.check_val_type(*ty, &context)?;
+        }
+        Ok(())
+    }
+
+    fn check_global_type(&self, ty: &types::GlobalType, context: impl fmt::Display) -> Result<()> {
+        if ty.mutable {
+            self.require(self.mutable_globals, "mutable-globals", &context)?;
+        }
+        self.check_val_type(ty.ty, context)
+    }
+
+    fn check_memory_type(&self, ty: &types::MemoryType, context: impl fmt::Display) -> Result<()> {
+        if ty.shared {
+            self.require(self.threads, "threads", &context)?;
+        }
+        if ty.memory64 {
+            self.require(self.memory64, "memory64", &context)?;
+        }
+        Ok(())
+    }
+
+    fn check_table_type(&self, ty: &types::TableType, context: impl fmt::Display) -> Result<()> {
+        if ty.table64 {
+            self.require(self.memory64, "memory64", &context)?;
+        }
+        // Tables of funcref are part of the MVP
+        match ty.elem_type {
+            types::RefType::FUNCREF => Ok(()),
+            elem_type => self.check_ref_type(elem_type, context),
+        }
+    }
+
+    fn check_instrs(&self, instrs: &[Instruction], location: &mut Location) -> Result<()> {
+        for (i, instr) in instrs.iter().enumerate() {
+            let context = location.instr(i);
+
+            if instr.memarg().is_some_and(|mem| mem.memory != 0) {
+                self.require(self.multi_memory, "multi-memory", &context)?;
+            }
+
+            match instr {
+                Instruction::Block { ty, instrs } | Instruction::Loop { ty, instrs } => {
+                    self.check_block_type(ty, &context)?;
+                    let kind = match instr {
+                        Instruction::Block { .. } => "block",
+                        _ => "loop",
+                    };
+                    location.push(kind, i);
+                    self.check_instrs(instrs, location)?;
+                    location.pop();
+                }
+                Instruction::If {
+                    ty,
+                    accept_instrs,
+                    reject_instrs,
+                } => {
+                    self.check_block_type(ty, &context)?;
+                    location.push("if", i);
+                    self.check_instrs(accept_instrs, location)?;
+                    location.pop();
+                    if let Some(reject_instrs) = reject_instrs {
+                        location.push("else", i);
+                        self.check_instrs(reject_instrs, location)?;
+                        location.pop();
+                    }
+                }
+                Instruction::TryTable { ty, instrs, .. } => {
+                    self.require(self.exceptions, "exceptions", &context)?;
+                    self.check_block_type(ty, &context)?;
+                    location.push("try_table", i);
+                    self.check_instrs(instrs, location)?;
+                    location.pop();
+                }
+                Instruction::Try {
+                    ty,
+                    instrs,
+                    handler,
+                } => {
+                    self.require(self.exceptions, "exceptions", &context)?;
+                    self.check_block_type(ty, &context)?;
+                    location.push("try", i);
+                    self.check_instrs(instrs, location)?;
+                    location.pop();
+                    if let TryHandler::Catches { catches, catch_all } = handler {
+                        for (_, instrs) in catches {
+                            location.push("catch", i);
+                            self.check_instrs(instrs, location)?;
+                            location.pop();
+                        }
+                        if let Some(instrs) = catch_all {
+                            location.push("catch_all", i);
+                            self.check_instrs(instrs, location)?;
+                            location.pop();
+                        }
+                    }
+                }
+                Instruction::Throw(_) | Instruction::ThrowRef | Instruction::Rethrow(_) => {
+                    self.require(self.exceptions, "exceptions", &context)?
+                }
+                Instruction::RefNull(heap_type) => {
+                    let ty = types::RefType {
+                        nullable: true,
+                        heap_type: *heap_type,
+                    };
+                    self.check_ref_type(ty, &context)?
+                }
+                Instruction::Extend { .. } => {
+                    self.require(self.sign_extension, "sign-extension", &context)?
+                }
+                Instruction::SaturateTruncate { .. } => self.require(
+                    self.saturating_float_to_int,
+                    "saturating float-to-int",
+                    &context,
+                )?,
+                Instruction::CallIndirect { table, .. } if *table != 0 => {
+                    self.require(self.reference_types, "reference-types", &context)?
+                }
+                Instruction::ReturnCall(_) => {
+                    self.require(self.tail_call, "tail-call", &context)?
+                }
+                Instruction::ReturnCallIndirect { table, .. } => {
+                    self.require(self.tail_call, "tail-call", &context)?;
+                    if *table != 0 {
+                        self.require(self.reference_types, "reference-types", &context)?
+                    }
+                }
+                Instruction::CallRef(_)
+                | Instruction::RefAsNonNull
+                | Instruction::BranchOnNull(_)
+                | Instruction::BranchOnNonNull(_) => {
+                    self.require(self.function_references, "function-references", &context)?
+                }
+                Instruction::ReturnCallRef(_) => {
+                    self.require(self.tail_call, "tail-call", &context)?;
+                    self.require(self.function_references, "function-references", &context)?
+                }
+                Instruction::TypedSelect(_)
+                | Instruction::TableGet(_)
+                | Instruction::TableSet(_)
+                | Instruction::RefIsNull
+                | Instruction::RefFunc(_)
+                | Instruction::TableGrow(_)
+                | Instruction::TableSize(_)
+                | Instruction::TableFill(_) => {
+                    self.require(self.reference_types, "reference-types", &context)?
+                }
+                Instruction::MemorySize(mem) | Instruction::MemoryGrow(mem) if *mem != 0 => {
+                    self.require(self.multi_memory, "multi-memory", &context)?
+                }
+                Instruction::MemoryInit { mem: 0, .. }
+                | Instruction::DataDrop(_)
+                | Instruction::MemoryCopy { dst: 0, src: 0 }
+                | Instruction::MemoryFill(0)
+                | Instruction::TableInit { table: 0, .. }
+                | Instruction::ElemDrop(_)
+                | Instruction::TableCopy { dst: 0, src: 0 } => {
+                    self.require(self.bulk_memory, "bulk-memory", &context)?
+                }
+                Instruction::TableInit { .. } | Instruction::TableCopy { .. } => {
+                    self.require(self.bulk_memory, "bulk-memory", &context)?;
+                    self.require(self.reference_types, "reference-types", &context)?;
+                }
+                Instruction::MemoryInit { .. }
+                | Instruction::MemoryCopy { .. }
+                | Instruction::MemoryFill(_) => {
+                    self.require(self.bulk_memory, "bulk-memory", &context)?;
+                    self.require(self.multi_memory, "multi-memory", &context)?;
+                }
+                Instruction::AtomicNotify(_)
+                | Instruction::AtomicWait { .. }
+                | Instruction::AtomicFence
+                | Instruction::AtomicLoad { .. }
+                | Instruction::AtomicStore { .. }
+                | Instruction::AtomicRmw { .. }
+                | Instruction::AtomicCompareExchange { .. } => {
+                    self.require(self.threads, "threads", &context)?
+                }
+                Instruction::RefTest(ty) | Instruction::RefCast(ty) => {
+                    self.require(self.gc, "gc", &context)?;
+                    self.check_ref_type(*ty, &context)?
+                }
+                Instruction::BranchOnCast { from, to, .. }
+                | Instruction::BranchOnCastFail { from, to, .. } => {
+                    self.require(self.gc, "gc", &context)?;
+                    self.check_ref_type(*from, &context)?;
+                    self.check_ref_type(*to, &context)?
+                }
+                Instruction::RefEq
+                | Instruction::StructNew(_)
+                | Instruction::StructNewDefault(_)
+                | Instruction::StructGet { .. }
+                | Instruction::StructSet { .. }
+                | Instruction::ArrayNew(_)
+                | Instruction::ArrayNewDefault(_)
+                | Instruction::ArrayNewFixed { .. }
+                | Instruction::ArrayNewData { .. }
+                | Instruction::ArrayNewElem { .. }
+                | Instruction::ArrayGet { .. }
+                | Instruction::ArraySet(_)
+                | Instruction::ArrayLen
+                | Instruction::ArrayFill(_)
+                | Instruction::ArrayCopy { .. }
+                | Instruction::ArrayInitData { .. }
+                | Instruction::ArrayInitElem { .. }
+                | Instruction::AnyConvertExtern
+                | Instruction::ExternConvertAny
+                | Instruction::RefI31
+                | Instruction::I31Get(_) => self.require(self.gc, "gc", &context)?,
+                _ if instr.is_simd() => self.require(self.simd, "simd", &context)?,
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_block_type(&self, ty: &BlockType, context: impl fmt::Display) -> Result<()> {
+        match ty {
+            BlockType::TypeIdx(_) => self.require(self.multi_value, "multi-value", context),
+            BlockType::Type(ty) => self.check_val_type(*ty, context),
+            BlockType::Empty => Ok(()),
+        }
+    }
+
+    fn check_expr(&self, instrs: &[Instruction], location: impl Into<String>) -> Result<()> {
+        self.check_instrs(instrs, &mut Location::new(location))
+    }
+
+    /// Checks a constant expression, integer arithmetic needs the "extended-const" proposal
+    fn check_const_expr(&self, instrs: &[Instruction], location: impl Into<String>) -> Result<()> {
+        let mut location = Location::new(location);
+        for (i, instr) in instrs.iter().enumerate() {
+            if let Instruction::Add(_) | Instruction::Subtract(_) | Instruction::Multiply(_) = instr
+            {
+                self.require(self.extended_const, "extended-const", location.instr(i))?;
+            }
+        }
+        self.check_instrs(instrs, &mut location)
+    }
+
+    /// Checks that the module doesn't use any disabled proposal
+    pub(crate) fn check_module(&self, module: &Module) -> Result<()> {
+        let mut idx = 0;
+        for group in module.types.iter() {
+            if group.0.len() != 1 {
+                self.require(self.gc, "gc", format_args!("type {}", idx))?;
+            }
+            for ty in group.0.iter() {
+                self.check_sub_type(ty, format_args!("type {}", idx))?;
+                idx += 1;
+            }
+        }
+
+        let mut tables = module.tables.len();
+        let mut memories = module.memory.len();
+        let mut globals = Vec::new();
+        for (i, import) in module.imports.iter().enumerate() {
+            match import.desc {
+                Desc::Table(ty) => {
+                    self.check_table_type(&ty, format_args!("import {}", i))?;
+                    tables += 1;
+                }
+                Desc::Memory(ty) => {
+                    self.check_memory_type(&ty, format_args!("import {}", i))?;
+                    memories += 1;
+                }
+                Desc::Global(ty) => {
+                    self.check_global_type(&ty, format_args!("import {}", i))?;
+                    globals.push(ty);
+                }
+                Desc::Tag(_) => {
+                    self.require(self.exceptions, "exceptions", format_args!("import {}", i))?
+                }
+                Desc::Function(_) => {}
+            }
+        }
+        globals.extend(module.globals.iter().map(|global| global.ty));
+
+        for (i, table) in module.tables.iter().enumerate() {
+            let context = format!("table {}", tables - module.tables.len() + i);
+            self.check_table_type(&table.ty, &context)?;
+            if let Some(ref init) = table.init {
+                self.require(self.function_references, "function-references", &context)?;
+                self.check_const_expr(&init.0, context)?;
+            }
+        }
+
+        for (i, memory) in module.memory.iter().enumerate() {
+            let context = format!("memory {}", memories - module.memory.len() + i);
+            self.check_memory_type(memory, context)?;
+        }
+
+        if tables > 1 {
+            self.require(self.reference_types, "reference-types", "module")?;
+        }
+        if memories > 1 {
+            self.require(self.multi_memory, "multi-memory", "module")?;
+        }
+
+        for (i, global) in module.globals.iter().enumerate() {
+            let location = format!("global {}", globals.len() - module.globals.len() + i);
+            self.check_val_type(global.ty.ty, &location)?;
+            self.check_const_expr(&global.init.0, location)?;
+        }
+
+        if !module.tags.is_empty() {
+            self.require(self.exceptions, "exceptions", "module")?;
+        }
+
+        for (i, export) in module.exports.iter().enumerate() {
+            match export.desc {
+                ExportDesc::Global(idx) => {
+                    if let Some(ty) = globals.get(idx as usize) {
+                        self.check_global_type(ty, format_args!("export {}", i))?;
+                    }
+                }
+                ExportDesc::Tag(_) => {
+                    self.require(self.exceptions, "exceptions", format_args!("export {}", i))?
+                }
+                _ => {}
+            }
+        }
+
+        for (i, elem) in module.elements.iter().enumerate() {
+            let context = format!("elem {}", i);
+            match &elem.mode {
+                ElementMode::Active { table, offset } => {
+                    if *table != 0 {
+                        self.require(self.reference_types, "reference-types", &context)?;
+                    }
+                    self.check_const_expr(&offset.0, &context)?;
+                }
+                ElementMode::Passive => self.require(self.bulk_memory, "bulk-memory", &context)?,
+                ElementMode::Declarative => {
+                    self.require(self.reference_types, "reference-types", &context)?
+                }
+            }
+            if let ElementItems::Expressions(ty, exprs) = &elem.items {
+                self.require(self.bulk_memory, "bulk-memory", &context)?;
+                self.check_ref_type(*ty, &context)?;
+                for expr in exprs.iter() {
+                    self.check_const_expr(&expr.0, &context)?;
+                }
+            }
+        }
+
+        let imported_funcs = module
+            .imports
+            .iter()
+            .filter(|import| matches!(import.desc, Desc::Function(_)))
+            .count();
+        for (i, func) in module.code.iter().enumerate() {
+            let location = format!("func {}", imported_funcs + i);
+            for local in func.locals.iter() {
+                self.check_val_type(local.ty, &location)?;
+            }
+            self.check_expr(&func.body.0, location)?;
+        }
+
+        for (i, data) in module.data.iter().enumerate() {
+            let context = format!("data {}", i);
+            match &data.mode {
+                DataMode::Active { offset, .. } => self.check_const_expr(&offset.0, context)?,
+                DataMode::Passive => self.require(self.bulk_memory, "bulk-memory", context)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The features that are part of the WebAssembly 2.0 spec
+impl Default for Features {
+    fn default() -> Self {
+        Features {
+            mutable_globals: true,
+            sign_extension: true,
+            saturating_float_to_int: true,
+            multi_value: true,
+            bulk_memory: true,
+            reference_types: true,
+            simd: true,
+            ..Features::mvp()
+        }
+    }
+}
@@ -1,6 +1,10 @@
 use super::sections::*;
 use super::types;
-use std::io::{self, Write};
+use crate::error::{Error, Result};
+use std::{
+    fmt,
+    io::{Read, Write},
+};
 use types::ValType;
 
 /// Specifies the return type of a block
@@ -15,15 +19,110 @@ pub enum BlockType {
 }
 
 impl BlockType {
-    pub(crate) fn encode(&self, writer: &mut impl Write) -> io::Result<usize> {
+    pub(crate) fn encode(&self, writer: &mut impl Write) -> Result<usize> {
         match self {
-            BlockType::Empty => writer.write(&[0x40]),
+            BlockType::Empty => types::encode_raw(writer, &[0x40]),
             BlockType::Type(ty) => types::encode_val_type(writer, *ty),
             // The index must be treated as signed to avoid collisions and as to not lose
             // precision a s33 is used (We encode it using a i64)
             BlockType::TypeIdx(idx) => types::encode_i64(writer, *idx as i64),
         }
     }
+
+    pub(crate) fn decode(reader: &mut impl Read) -> Result<Self> {
+        // Peek at the first byte, the empty type and value types start with a byte
+        // that would be a negative s33 otherwise
+        let byte = types::decode_byte(reader)?;
+        if byte == 0x40 {
+            return Ok(BlockType::Empty);
+        }
+        if let Some(ty) = types::decode_val_type_after(byte, reader)? {
+            return Ok(BlockType::Type(ty));
+        }
+
+        let idx = types::decode_i64(&mut [byte].chain(reader))?;
+        if idx < 0 || idx > u32::MAX as i64 {
+            return Err(types::malformed("invalid block type"));
+        }
+        Ok(BlockType::TypeIdx(idx as u32))
+    }
+}
+
+/// A handler of a `try_table`, branches to `label` when an exception it matches is thrown
+/// in the body
+///
+/// The labels are relative to the block enclosing the `try_table`
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Catch {
+    /// Catches the exceptions of `tag` passing their arguments to the label
+    Catch { tag: TagIdx, label: LabelIdx },
+    /// Like `Catch` but also passes the exception as an `exnref`
+    CatchRef { tag: TagIdx, label: LabelIdx },
+    /// Catches every exception passing nothing to the label
+    CatchAll(LabelIdx),
+    /// Catches every exception passing it as an `exnref`
+    CatchAllRef(LabelIdx),
+}
+
+impl Catch {
+    pub(crate) fn encode(&self, writer: &mut impl Write) -> Result<usize> {
+        match self {
+            Catch::Catch { tag, label } | Catch::CatchRef { tag, label } => {
+                let kind = match self {
+                    Catch::Catch { .. } => 0x00,
+                    _ => 0x01,
+                };
+                let mut length = types::encode_raw(writer, &[kind])?;
+                length += types::encode_u32(writer, *tag)?;
+                length += types::encode_u32(writer, *label)?;
+                Ok(length)
+            }
+            Catch::CatchAll(label) | Catch::CatchAllRef(label) => {
+                let kind = match self {
+                    Catch::CatchAll(_) => 0x02,
+                    _ => 0x03,
+                };
+                let mut length = types::encode_raw(writer, &[kind])?;
+                length += types::encode_u32(writer, *label)?;
+                Ok(length)
+            }
+        }
+    }
+
+    pub(crate) fn decode(reader: &mut impl Read) -> Result<Self> {
+        Ok(match types::decode_byte(reader)? {
+            0x00 => Catch::Catch {
+                tag: types::decode_u32(reader)?,
+                label: types::decode_u32(reader)?,
+            },
+            0x01 => Catch::CatchRef {
+                tag: types::decode_u32(reader)?,
+                label: types::decode_u32(reader)?,
+            },
+            0x02 => Catch::CatchAll(types::decode_u32(reader)?),
+            0x03 => Catch::CatchAllRef(types::decode_u32(reader)?),
+            kind => {
+                return Err(types::malformed(format!(
+                    "invalid catch kind 0x{:02X}",
+                    kind
+                )))
+            }
+        })
+    }
+}
+
+/// What ends the body of a legacy `try`
+#[derive(Debug, Clone, PartialEq)]
+pub enum TryHandler {
+    /// `catch` blocks for specific tags followed by an optional `catch_all` block,
+    /// the blocks get the arguments of the exception and share the label of the `try`
+    Catches {
+        catches: Vec<(TagIdx, Vec<Instruction>)>,
+        catch_all: Option<Vec<Instruction>>,
+    },
+    /// Rethrows the exceptions to the handler of the enclosing block `label`,
+    /// 0 being the block enclosing the `try`
+    Delegate(LabelIdx),
 }
 
 /// Describes the operation of a memory op
@@ -31,16 +130,37 @@ impl BlockType {
 pub struct MemoryArgument {
     /// The alignment of the operation
     pub alignment: u32,
-    /// The offset into memory
-    pub offset: u32,
+    /// The offset into memory, offsets that don't fit a `u32` require a 64-bit memory
+    pub offset: u64,
+    /// The memory being accessed, anything but 0 requires the "multi-memory" proposal
+    pub memory: MemoryIdx,
 }
 
 impl MemoryArgument {
-    pub(crate) fn encode(&self, writer: &mut impl Write) -> io::Result<usize> {
-        let mut length = types::encode_u32(writer, self.alignment)?;
-        length += types::encode_u32(writer, self.offset)?;
+    pub(crate) fn encode(&self, writer: &mut impl Write) -> Result<usize> {
+        // Bit 6 of the alignment flags that a memory index follows
+        let mut length = if self.memory == 0 {
+            types::encode_u32(writer, self.alignment)?
+        } else {
+            types::encode_u32(writer, self.alignment | 0x40)?
+                + types::encode_u32(writer, self.memory)?
+        };
+        length += types::encode_u64(writer, self.offset)?;
         Ok(length)
     }
+
+    pub(crate) fn decode(reader: &mut impl Read) -> Result<Self> {
+        let alignment = types::decode_u32(reader)?;
+        let memory = match alignment & 0x40 {
+            0x40 => types::decode_u32(reader)?,
+            _ => 0,
+        };
+        Ok(MemoryArgument {
+            alignment: alignment & !0x40,
+            offset: types::decode_u64(reader)?,
+            memory,
+        })
+    }
 }
 
 /// Describes how much should be read and written to memory
@@ -57,6 +177,21 @@ pub enum Literal {
     I64(i64),
     F32(f32),
     F64(f64),
+    /// The bits of a `v128`, lane 0 in the least significant bits
+    V128(u128),
+}
+
+impl Literal {
+    /// The type of the value
+    pub fn ty(&self) -> ValType {
+        match self {
+            Literal::I32(_) => ValType::I32,
+            Literal::I64(_) => ValType::I64,
+            Literal::F32(_) => ValType::F32,
+            Literal::F64(_) => ValType::F64,
+            Literal::V128(_) => ValType::V128,
+        }
+    }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -65,12 +200,143 @@ pub enum IntegerType {
     I64,
 }
 
+impl fmt::Display for IntegerType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        ValType::from(*self).fmt(f)
+    }
+}
+
+impl From<IntegerType> for ValType {
+    fn from(ty: IntegerType) -> Self {
+        match ty {
+            IntegerType::I32 => ValType::I32,
+            IntegerType::I64 => ValType::I64,
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum FloatType {
     F32,
     F64,
 }
 
+impl fmt::Display for FloatType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        ValType::from(*self).fmt(f)
+    }
+}
+
+impl From<FloatType> for ValType {
+    fn from(ty: FloatType) -> Self {
+        match ty {
+            FloatType::F32 => ValType::F32,
+            FloatType::F64 => ValType::F64,
+        }
+    }
+}
+
+/// How a `v128` is split into lanes by the vector instructions
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LaneShape {
+    I8x16,
+    I16x8,
+    I32x4,
+    I64x2,
+    F32x4,
+    F64x2,
+}
+
+impl LaneShape {
+    /// The number of lanes
+    pub fn lanes(&self) -> u8 {
+        match self {
+            LaneShape::I8x16 => 16,
+            LaneShape::I16x8 => 8,
+            LaneShape::I32x4 | LaneShape::F32x4 => 4,
+            LaneShape::I64x2 | LaneShape::F64x2 => 2,
+        }
+    }
+
+    /// The type of a lane once extracted, `i8` and `i16` lanes are extended to `i32`
+    pub fn lane_type(&self) -> ValType {
+        match self {
+            LaneShape::I8x16 | LaneShape::I16x8 | LaneShape::I32x4 => ValType::I32,
+            LaneShape::I64x2 => ValType::I64,
+            LaneShape::F32x4 => ValType::F32,
+            LaneShape::F64x2 => ValType::F64,
+        }
+    }
+
+    /// The log2 of the size in bytes of a lane
+    pub(crate) fn lane_alignment(&self) -> u32 {
+        match self {
+            LaneShape::I8x16 => 0,
+            LaneShape::I16x8 => 1,
+            LaneShape::I32x4 | LaneShape::F32x4 => 2,
+            LaneShape::I64x2 | LaneShape::F64x2 => 3,
+        }
+    }
+
+    /// The integer shape with lanes of half the size, used by extensions and narrowings
+    pub(crate) fn half(&self) -> Option<LaneShape> {
+        match self {
+            LaneShape::I16x8 => Some(LaneShape::I8x16),
+            LaneShape::I32x4 => Some(LaneShape::I16x8),
+            LaneShape::I64x2 => Some(LaneShape::I32x4),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for LaneShape {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LaneShape::I8x16 => "i8x16",
+            LaneShape::I16x8 => "i16x8",
+            LaneShape::I32x4 => "i32x4",
+            LaneShape::I64x2 => "i64x2",
+            LaneShape::F32x4 => "f32x4",
+            LaneShape::F64x2 => "f64x2",
+        })
+    }
+}
+
+/// The operation of an atomic read-modify-write instruction
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AtomicOp {
+    Add,
+    Subtract,
+    And,
+    Or,
+    Xor,
+    Exchange,
+}
+
+impl fmt::Display for AtomicOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AtomicOp::Add => "add",
+            AtomicOp::Subtract => "sub",
+            AtomicOp::And => "and",
+            AtomicOp::Or => "or",
+            AtomicOp::Xor => "xor",
+            AtomicOp::Exchange => "xchg",
+        })
+    }
+}
+
+/// The operand types of the atomic accesses in the order of their opcodes
+const ATOMIC_ACCESSES: [(IntegerType, Option<StorageType>); 7] = [
+    (IntegerType::I32, None),
+    (IntegerType::I64, None),
+    (IntegerType::I32, Some(StorageType::I8)),
+    (IntegerType::I32, Some(StorageType::I16)),
+    (IntegerType::I64, Some(StorageType::I8)),
+    (IntegerType::I64, Some(StorageType::I16)),
+    (IntegerType::I64, Some(StorageType::I32)),
+];
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Instruction {
     Unreachable,
@@ -88,6 +354,21 @@ pub enum Instruction {
         accept_instrs: Vec<Instruction>,
         reject_instrs: Option<Vec<Instruction>>,
     },
+    // The exception instructions require the "exceptions" proposal
+    Throw(TagIdx),
+    ThrowRef,
+    TryTable {
+        ty: BlockType,
+        catches: Vec<Catch>,
+        instrs: Vec<Instruction>,
+    },
+    // The legacy exception instructions, still the only ones some engines support
+    Try {
+        ty: BlockType,
+        instrs: Vec<Instruction>,
+        handler: TryHandler,
+    },
+    Rethrow(LabelIdx),
     Branch(LabelIdx),
     BranchIf(LabelIdx),
     BranchTable {
@@ -96,14 +377,32 @@ pub enum Instruction {
     },
     Return,
     Call(FuncIdx),
-    CallIndirect(TypeIdx),
+    CallIndirect {
+        ty: TypeIdx,
+        table: TableIdx,
+    },
+    // The tail calls require the "tail-call" proposal
+    ReturnCall(FuncIdx),
+    ReturnCallIndirect {
+        ty: TypeIdx,
+        table: TableIdx,
+    },
+    // The typed function references require the "function-references" proposal
+    CallRef(TypeIdx),
+    ReturnCallRef(TypeIdx),
+    RefAsNonNull,
+    BranchOnNull(LabelIdx),
+    BranchOnNonNull(LabelIdx),
     Drop,
     Select,
+    TypedSelect(Vec<ValType>),
     LocalGet(LocalIdx),
     LocalSet(LocalIdx),
     LocalTee(LocalIdx),
     GlobalGet(GlobalIdx),
     GlobalSet(GlobalIdx),
+    TableGet(TableIdx),
+    TableSet(TableIdx),
     Load {
         mem: MemoryArgument,
         ty: ValType,
@@ -114,8 +413,8 @@ pub enum Instruction {
         ty: ValType,
         storage: Option<StorageType>,
     },
-    MemorySize,
-    MemoryGrow,
+    MemorySize(MemoryIdx),
+    MemoryGrow(MemoryIdx),
     Const(Literal),
     EqualZero(IntegerType),
     Equal(ValType),
@@ -198,34 +497,756 @@ pub enum Instruction {
         ty: IntegerType,
         base: StorageType,
     },
+    RefNull(types::HeapType),
+    RefIsNull,
+    RefFunc(FuncIdx),
     SaturateTruncate {
         ty: IntegerType,
         float: FloatType,
         signed: bool,
     },
+    TableGrow(TableIdx),
+    TableSize(TableIdx),
+    TableFill(TableIdx),
+    MemoryInit {
+        data: DataIdx,
+        mem: MemoryIdx,
+    },
+    DataDrop(DataIdx),
+    MemoryCopy {
+        dst: MemoryIdx,
+        src: MemoryIdx,
+    },
+    MemoryFill(MemoryIdx),
+    TableInit {
+        elem: ElemIdx,
+        table: TableIdx,
+    },
+    ElemDrop(ElemIdx),
+    TableCopy {
+        dst: TableIdx,
+        src: TableIdx,
+    },
+    V128LoadExtend {
+        mem: MemoryArgument,
+        shape: LaneShape,
+        signed: bool,
+    },
+    V128LoadSplat {
+        mem: MemoryArgument,
+        shape: LaneShape,
+    },
+    V128LoadZero {
+        mem: MemoryArgument,
+        shape: LaneShape,
+    },
+    V128LoadLane {
+        mem: MemoryArgument,
+        shape: LaneShape,
+        lane: u8,
+    },
+    V128StoreLane {
+        mem: MemoryArgument,
+        shape: LaneShape,
+        lane: u8,
+    },
+    I8x16Shuffle([u8; 16]),
+    I8x16Swizzle,
+    Splat(LaneShape),
+    // signed is only given for i8x16 and i16x8
+    ExtractLane {
+        shape: LaneShape,
+        lane: u8,
+        signed: Option<bool>,
+    },
+    ReplaceLane {
+        shape: LaneShape,
+        lane: u8,
+    },
+    VectorEqual(LaneShape),
+    VectorNotEqual(LaneShape),
+    // signed is only given for integer shapes
+    VectorLessThan {
+        shape: LaneShape,
+        signed: Option<bool>,
+    },
+    VectorGreaterThan {
+        shape: LaneShape,
+        signed: Option<bool>,
+    },
+    VectorLessOrEqual {
+        shape: LaneShape,
+        signed: Option<bool>,
+    },
+    VectorGreaterOrEqual {
+        shape: LaneShape,
+        signed: Option<bool>,
+    },
+    V128Not,
+    V128And,
+    V128AndNot,
+    V128Or,
+    V128Xor,
+    V128BitSelect,
+    V128AnyTrue,
+    VectorAbsolute(LaneShape),
+    VectorNegate(LaneShape),
+    I8x16CountOnes,
+    VectorAllTrue(LaneShape),
+    VectorBitmask(LaneShape),
+    // shape is the one of the result
+    VectorNarrow {
+        shape: LaneShape,
+        signed: bool,
+    },
+    VectorExtend {
+        shape: LaneShape,
+        high: bool,
+        signed: bool,
+    },
+    VectorShiftLeft(LaneShape),
+    VectorShiftRight {
+        shape: LaneShape,
+        signed: bool,
+    },
+    VectorAdd(LaneShape),
+    VectorAddSaturate {
+        shape: LaneShape,
+        signed: bool,
+    },
+    VectorSubtract(LaneShape),
+    VectorSubtractSaturate {
+        shape: LaneShape,
+        signed: bool,
+    },
+    VectorMultiply(LaneShape),
+    VectorMinimum {
+        shape: LaneShape,
+        signed: Option<bool>,
+    },
+    VectorMaximum {
+        shape: LaneShape,
+        signed: Option<bool>,
+    },
+    VectorAverage(LaneShape),
+    VectorExtAddPairwise {
+        shape: LaneShape,
+        signed: bool,
+    },
+    VectorExtMultiply {
+        shape: LaneShape,
+        high: bool,
+        signed: bool,
+    },
+    I16x8Q15MulrSat,
+    I32x4DotI16x8,
+    VectorCeil(LaneShape),
+    VectorFloor(LaneShape),
+    VectorTruncate(LaneShape),
+    VectorNearest(LaneShape),
+    VectorSquareRoot(LaneShape),
+    VectorDivision(LaneShape),
+    VectorPseudoMinimum(LaneShape),
+    VectorPseudoMaximum(LaneShape),
+    // i32x4.trunc_sat_f32x4 or i32x4.trunc_sat_f64x2_zero
+    VectorTruncateSaturate {
+        float: FloatType,
+        signed: bool,
+    },
+    // f32x4.convert_i32x4 or f64x2.convert_low_i32x4
+    VectorConvert {
+        float: FloatType,
+        signed: bool,
+    },
+    F32x4DemoteF64x2Zero,
+    F64x2PromoteLowF32x4,
+    AtomicNotify(MemoryArgument),
+    // memory.atomic.wait32 or memory.atomic.wait64
+    AtomicWait {
+        mem: MemoryArgument,
+        ty: IntegerType,
+    },
+    AtomicFence,
+    // the narrow loads zero-extend their value
+    AtomicLoad {
+        mem: MemoryArgument,
+        ty: IntegerType,
+        storage: Option<StorageType>,
+    },
+    AtomicStore {
+        mem: MemoryArgument,
+        ty: IntegerType,
+        storage: Option<StorageType>,
+    },
+    AtomicRmw {
+        mem: MemoryArgument,
+        op: AtomicOp,
+        ty: IntegerType,
+        storage: Option<StorageType>,
+    },
+    AtomicCompareExchange {
+        mem: MemoryArgument,
+        ty: IntegerType,
+        storage: Option<StorageType>,
+    },
+    // The GC instructions require the "GC" proposal
+    RefEq,
+    StructNew(TypeIdx),
+    StructNewDefault(TypeIdx),
+    // signed is only given for packed fields
+    StructGet {
+        ty: TypeIdx,
+        field: FieldIdx,
+        signed: Option<bool>,
+    },
+    StructSet {
+        ty: TypeIdx,
+        field: FieldIdx,
+    },
+    ArrayNew(TypeIdx),
+    ArrayNewDefault(TypeIdx),
+    ArrayNewFixed {
+        ty: TypeIdx,
+        len: u32,
+    },
+    ArrayNewData {
+        ty: TypeIdx,
+        data: DataIdx,
+    },
+    ArrayNewElem {
+        ty: TypeIdx,
+        elem: ElemIdx,
+    },
+    // signed is only given for packed elements
+    ArrayGet {
+        ty: TypeIdx,
+        signed: Option<bool>,
+    },
+    ArraySet(TypeIdx),
+    ArrayLen,
+    ArrayFill(TypeIdx),
+    ArrayCopy {
+        dst: TypeIdx,
+        src: TypeIdx,
+    },
+    ArrayInitData {
+        ty: TypeIdx,
+        data: DataIdx,
+    },
+    ArrayInitElem {
+        ty: TypeIdx,
+        elem: ElemIdx,
+    },
+    RefTest(types::RefType),
+    RefCast(types::RefType),
+    // from is the type of the operand, branches if the cast to `to` succeeds
+    BranchOnCast {
+        label: LabelIdx,
+        from: types::RefType,
+        to: types::RefType,
+    },
+    // branches if the cast to `to` fails
+    BranchOnCastFail {
+        label: LabelIdx,
+        from: types::RefType,
+        to: types::RefType,
+    },
+    AnyConvertExtern,
+    ExternConvertAny,
+    RefI31,
+    // signed
+    I31Get(bool),
 }
 
 impl Instruction {
-    pub(crate) fn encode(&self, writer: &mut impl Write) -> io::Result<usize> {
+    pub(crate) fn invalid(&self, reason: &'static str) -> Error {
+        Error::InvalidInstruction {
+            instr: format!("{:?}", self),
+            reason,
+        }
+    }
+
+    /// The log2 of the number of bytes accessed by a memory instruction
+    ///
+    /// `None` if it isn't a memory access or if it accesses a reference, which can't be stored
+    pub(crate) fn natural_alignment(&self) -> Option<u32> {
+        let storage = match self {
+            Instruction::Load { ty, storage, .. } => (*ty, storage.map(|(_, storage)| storage)),
+            Instruction::Store { ty, storage, .. } => (*ty, *storage),
+            Instruction::AtomicLoad { ty, storage, .. }
+            | Instruction::AtomicStore { ty, storage, .. }
+            | Instruction::AtomicRmw { ty, storage, .. }
+            | Instruction::AtomicCompareExchange { ty, storage, .. } => ((*ty).into(), *storage),
+            Instruction::AtomicNotify(_) => return Some(2),
+            Instruction::AtomicWait { ty, .. } => ((*ty).into(), None),
+            Instruction::V128LoadExtend { .. } => return Some(3),
+            Instruction::V128LoadSplat { shape, .. }
+            | Instruction::V128LoadZero { shape, .. }
+            | Instruction::V128LoadLane { shape, .. }
+            | Instruction::V128StoreLane { shape, .. } => return Some(shape.lane_alignment()),
+            _ => return None,
+        };
+        Some(match storage {
+            (_, Some(StorageType::I8)) => 0,
+            (_, Some(StorageType::I16)) => 1,
+            (_, Some(StorageType::I32)) | (ValType::I32, None) | (ValType::F32, None) => 2,
+            (ValType::I64, None) | (ValType::F64, None) => 3,
+            (ValType::V128, None) => 4,
+            (ValType::Ref(_), None) => return None,
+        })
+    }
+
+    /// The memory argument of an instruction accessing memory
+    pub(crate) fn memarg(&self) -> Option<&MemoryArgument> {
+        match self {
+            Instruction::Load { mem, .. }
+            | Instruction::Store { mem, .. }
+            | Instruction::V128LoadExtend { mem, .. }
+            | Instruction::V128LoadSplat { mem, .. }
+            | Instruction::V128LoadZero { mem, .. }
+            | Instruction::V128LoadLane { mem, .. }
+            | Instruction::V128StoreLane { mem, .. }
+            | Instruction::AtomicNotify(mem)
+            | Instruction::AtomicWait { mem, .. }
+            | Instruction::AtomicLoad { mem, .. }
+            | Instruction::AtomicStore { mem, .. }
+            | Instruction::AtomicRmw { mem, .. }
+            | Instruction::AtomicCompareExchange { mem, .. } => Some(mem),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn memarg_mut(&mut self) -> Option<&mut MemoryArgument> {
+        match self {
+            Instruction::Load { mem, .. }
+            | Instruction::Store { mem, .. }
+            | Instruction::V128LoadExtend { mem, .. }
+            | Instruction::V128LoadSplat { mem, .. }
+            | Instruction::V128LoadZero { mem, .. }
+            | Instruction::V128LoadLane { mem, .. }
+            | Instruction::V128StoreLane { mem, .. }
+            | Instruction::AtomicNotify(mem)
+            | Instruction::AtomicWait { mem, .. }
+            | Instruction::AtomicLoad { mem, .. }
+            | Instruction::AtomicStore { mem, .. }
+            | Instruction::AtomicRmw { mem, .. }
+            | Instruction::AtomicCompareExchange { mem, .. } => Some(mem),
+            _ => None,
+        }
+    }
+
+    /// Whether the instruction is part of the SIMD proposal
+    pub(crate) fn is_simd(&self) -> bool {
         match self {
-            Instruction::Unreachable => writer.write(&[0x00]),
-            Instruction::NOP => writer.write(&[0x01]),
+            Instruction::Load { ty, .. } | Instruction::Store { ty, .. } => *ty == ValType::V128,
+            Instruction::Const(literal) => literal.ty() == ValType::V128,
+            _ => self.simd_opcode().is_ok(),
+        }
+    }
+
+    /// The opcode following the 0xFE prefix of an atomic memory access
+    ///
+    /// Fails for i32 accesses with a 32 bit storage size and for instructions that
+    /// aren't atomic memory accesses
+    pub(crate) fn atomic_opcode(&self) -> Result<u32> {
+        let access = |ty: &IntegerType, storage: &Option<StorageType>| {
+            ATOMIC_ACCESSES
+                .iter()
+                .position(|access| *access == (*ty, *storage))
+                .map(|idx| idx as u32)
+                .ok_or_else(|| self.invalid("i32 atomics can't have a 32 bit storage size"))
+        };
+
+        match self {
+            Instruction::AtomicNotify(_) => Ok(0x00),
+            Instruction::AtomicWait { ty, .. } => match ty {
+                IntegerType::I32 => Ok(0x01),
+                IntegerType::I64 => Ok(0x02),
+            },
+            Instruction::AtomicLoad { ty, storage, .. } => Ok(0x10 + access(ty, storage)?),
+            Instruction::AtomicStore { ty, storage, .. } => Ok(0x17 + access(ty, storage)?),
+            Instruction::AtomicRmw {
+                op, ty, storage, ..
+            } => Ok(0x1E + *op as u32 * 7 + access(ty, storage)?),
+            Instruction::AtomicCompareExchange { ty, storage, .. } => {
+                Ok(0x48 + access(ty, storage)?)
+            }
+            _ => Err(self.invalid("not an atomic memory access")),
+        }
+    }
+
+    /// The opcode following the 0xFB prefix of a GC instruction
+    ///
+    /// Fails for instructions that aren't GC instructions
+    pub(crate) fn gc_opcode(&self) -> Result<u32> {
+        let signed = |signed: &Option<bool>, base: u32| match signed {
+            None => base,
+            Some(true) => base + 1,
+            Some(false) => base + 2,
+        };
+
+        Ok(match self {
+            Instruction::StructNew(_) => 0x00,
+            Instruction::StructNewDefault(_) => 0x01,
+            Instruction::StructGet { signed: s, .. } => signed(s, 0x02),
+            Instruction::StructSet { .. } => 0x05,
+            Instruction::ArrayNew(_) => 0x06,
+            Instruction::ArrayNewDefault(_) => 0x07,
+            Instruction::ArrayNewFixed { .. } => 0x08,
+            Instruction::ArrayNewData { .. } => 0x09,
+            Instruction::ArrayNewElem { .. } => 0x0A,
+            Instruction::ArrayGet { signed: s, .. } => signed(s, 0x0B),
+            Instruction::ArraySet(_) => 0x0E,
+            Instruction::ArrayLen => 0x0F,
+            Instruction::ArrayFill(_) => 0x10,
+            Instruction::ArrayCopy { .. } => 0x11,
+            Instruction::ArrayInitData { .. } => 0x12,
+            Instruction::ArrayInitElem { .. } => 0x13,
+            Instruction::RefTest(ty) => 0x14 + ty.nullable as u32,
+            Instruction::RefCast(ty) => 0x16 + ty.nullable as u32,
+            Instruction::BranchOnCast { .. } => 0x18,
+            Instruction::BranchOnCastFail { .. } => 0x19,
+            Instruction::AnyConvertExtern => 0x1A,
+            Instruction::ExternConvertAny => 0x1B,
+            Instruction::RefI31 => 0x1C,
+            Instruction::I31Get(true) => 0x1D,
+            Instruction::I31Get(false) => 0x1E,
+            _ => return Err(self.invalid("not a GC instruction")),
+        })
+    }
+
+    /// The opcode following the 0xFD prefix of a vector instruction
+    ///
+    /// Fails for shapes the instruction doesn't support (i.e. `i8x16.mul`) and
+    /// for instructions that aren't vector instructions
+    pub(crate) fn simd_opcode(&self) -> Result<u32> {
+        use LaneShape::*;
+
+        // The comparisons of i8x16, i16x8 and i32x4 come in signed/unsigned pairs
+        // after eq and ne, f32x4 and f64x2 only have one of each and i64x2 only the
+        // signed ones
+        let compare = |shape: &LaneShape, signed: &Option<bool>, n: u32| match (shape, signed) {
+            (I8x16, Some(signed)) => Some(35 + 2 + n * 2 + !signed as u32),
+            (I16x8, Some(signed)) => Some(45 + 2 + n * 2 + !signed as u32),
+            (I32x4, Some(signed)) => Some(55 + 2 + n * 2 + !signed as u32),
+            (I64x2, Some(true)) => Some(216 + n),
+            (F32x4, None) => Some(65 + 2 + n),
+            (F64x2, None) => Some(71 + 2 + n),
+            _ => None,
+        };
+
+        let opcode = match self {
+            Instruction::Load {
+                ty: ValType::V128,
+                storage: None,
+                ..
+            } => Some(0),
+            Instruction::Store {
+                ty: ValType::V128,
+                storage: None,
+                ..
+            } => Some(11),
+            Instruction::Const(Literal::V128(_)) => Some(12),
+            Instruction::V128LoadExtend { shape, signed, .. } => match shape {
+                I16x8 => Some(1 + !signed as u32),
+                I32x4 => Some(3 + !signed as u32),
+                I64x2 => Some(5 + !signed as u32),
+                _ => None,
+            },
+            Instruction::V128LoadSplat { shape, .. } => match shape {
+                I8x16 => Some(7),
+                I16x8 => Some(8),
+                I32x4 => Some(9),
+                I64x2 => Some(10),
+                _ => None,
+            },
+            Instruction::V128LoadZero { shape, .. } => match shape {
+                I32x4 => Some(92),
+                I64x2 => Some(93),
+                _ => None,
+            },
+            Instruction::V128LoadLane { shape, .. } => match shape {
+                I8x16 => Some(84),
+                I16x8 => Some(85),
+                I32x4 => Some(86),
+                I64x2 => Some(87),
+                _ => None,
+            },
+            Instruction::V128StoreLane { shape, .. } => match shape {
+                I8x16 => Some(88),
+                I16x8 => Some(89),
+                I32x4 => Some(90),
+                I64x2 => Some(91),
+                _ => None,
+            },
+            Instruction::I8x16Shuffle(_) => Some(13),
+            Instruction::I8x16Swizzle => Some(14),
+            Instruction::Splat(shape) => Some(match shape {
+                I8x16 => 15,
+                I16x8 => 16,
+                I32x4 => 17,
+                I64x2 => 18,
+                F32x4 => 19,
+                F64x2 => 20,
+            }),
+            Instruction::ExtractLane { shape, signed, .. } => match (shape, signed) {
+                (I8x16, Some(signed)) => Some(21 + !signed as u32),
+                (I16x8, Some(signed)) => Some(24 + !signed as u32),
+                (I32x4, None) => Some(27),
+                (I64x2, None) => Some(29),
+                (F32x4, None) => Some(31),
+                (F64x2, None) => Some(33),
+                _ => None,
+            },
+            Instruction::ReplaceLane { shape, .. } => Some(match shape {
+                I8x16 => 23,
+                I16x8 => 26,
+                I32x4 => 28,
+                I64x2 => 30,
+                F32x4 => 32,
+                F64x2 => 34,
+            }),
+            Instruction::VectorEqual(shape) => Some(match shape {
+                I8x16 => 35,
+                I16x8 => 45,
+                I32x4 => 55,
+                I64x2 => 214,
+                F32x4 => 65,
+                F64x2 => 71,
+            }),
+            Instruction::VectorNotEqual(shape) => Some(match shape {
+                I8x16 => 36,
+                I16x8 => 46,
+                I32x4 => 56,
+                I64x2 => 215,
+                F32x4 => 66,
+                F64x2 => 72,
+            }),
+            Instruction::VectorLessThan { shape, signed } => compare(shape, signed, 0),
+            Instruction::VectorGreaterThan { shape, signed } => compare(shape, signed, 1),
+            Instruction::VectorLessOrEqual { shape, signed } => compare(shape, signed, 2),
+            Instruction::VectorGreaterOrEqual { shape, signed } => compare(shape, signed, 3),
+            Instruction::V128Not => Some(77),
+            Instruction::V128And => Some(78),
+            Instruction::V128AndNot => Some(79),
+            Instruction::V128Or => Some(80),
+            Instruction::V128Xor => Some(81),
+            Instruction::V128BitSelect => Some(82),
+            Instruction::V128AnyTrue => Some(83),
+            Instruction::F32x4DemoteF64x2Zero => Some(94),
+            Instruction::F64x2PromoteLowF32x4 => Some(95),
+            Instruction::VectorAbsolute(shape) => Some(match shape {
+                I8x16 => 96,
+                I16x8 => 128,
+                I32x4 => 160,
+                I64x2 => 192,
+                F32x4 => 224,
+                F64x2 => 236,
+            }),
+            Instruction::VectorNegate(shape) => Some(match shape {
+                I8x16 => 97,
+                I16x8 => 129,
+                I32x4 => 161,
+                I64x2 => 193,
+                F32x4 => 225,
+                F64x2 => 237,
+            }),
+            Instruction::I8x16CountOnes => Some(98),
+            Instruction::VectorAllTrue(shape) => match shape {
+                I8x16 => Some(99),
+                I16x8 => Some(131),
+                I32x4 => Some(163),
+                I64x2 => Some(195),
+                _ => None,
+            },
+            Instruction::VectorBitmask(shape) => match shape {
+                I8x16 => Some(100),
+                I16x8 => Some(132),
+                I32x4 => Some(164),
+                I64x2 => Some(196),
+                _ => None,
+            },
+            Instruction::VectorNarrow { shape, signed } => match shape {
+                I8x16 => Some(101 + !signed as u32),
+                I16x8 => Some(133 + !signed as u32),
+                _ => None,
+            },
+            Instruction::VectorExtend {
+                shape,
+                high,
+                signed,
+            } => {
+                let n = *high as u32 + !signed as u32 * 2;
+                match shape {
+                    I16x8 => Some(135 + n),
+                    I32x4 => Some(167 + n),
+                    I64x2 => Some(199 + n),
+                    _ => None,
+                }
+            }
+            Instruction::VectorShiftLeft(shape) => match shape {
+                I8x16 => Some(107),
+                I16x8 => Some(139),
+                I32x4 => Some(171),
+                I64x2 => Some(203),
+                _ => None,
+            },
+            Instruction::VectorShiftRight { shape, signed } => match shape {
+                I8x16 => Some(108 + !signed as u32),
+                I16x8 => Some(140 + !signed as u32),
+                I32x4 => Some(172 + !signed as u32),
+                I64x2 => Some(204 + !signed as u32),
+                _ => None,
+            },
+            Instruction::VectorAdd(shape) => Some(match shape {
+                I8x16 => 110,
+                I16x8 => 142,
+                I32x4 => 174,
+                I64x2 => 206,
+                F32x4 => 228,
+                F64x2 => 240,
+            }),
+            Instruction::VectorAddSaturate { shape, signed } => match shape {
+                I8x16 => Some(111 + !signed as u32),
+                I16x8 => Some(143 + !signed as u32),
+                _ => None,
+            },
+            Instruction::VectorSubtract(shape) => Some(match shape {
+                I8x16 => 113,
+                I16x8 => 145,
+                I32x4 => 177,
+                I64x2 => 209,
+                F32x4 => 229,
+                F64x2 => 241,
+            }),
+            Instruction::VectorSubtractSaturate { shape, signed } => match shape {
+                I8x16 => Some(114 + !signed as u32),
+                I16x8 => Some(146 + !signed as u32),
+                _ => None,
+            },
+            Instruction::VectorMultiply(shape) => match shape {
+                I16x8 => Some(149),
+                I32x4 => Some(181),
+                I64x2 => Some(213),
+                F32x4 => Some(230),
+                F64x2 => Some(242),
+                _ => None,
+            },
+            Instruction::VectorMinimum { shape, signed } => match (shape, signed) {
+                (I8x16, Some(signed)) => Some(118 + !signed as u32),
+                (I16x8, Some(signed)) => Some(150 + !signed as u32),
+                (I32x4, Some(signed)) => Some(182 + !signed as u32),
+                (F32x4, None) => Some(232),
+                (F64x2, None) => Some(244),
+                _ => None,
+            },
+            Instruction::VectorMaximum { shape, signed } => match (shape, signed) {
+                (I8x16, Some(signed)) => Some(120 + !signed as u32),
+                (I16x8, Some(signed)) => Some(152 + !signed as u32),
+                (I32x4, Some(signed)) => Some(184 + !signed as u32),
+                (F32x4, None) => Some(233),
+                (F64x2, None) => Some(245),
+                _ => None,
+            },
+            Instruction::VectorAverage(shape) => match shape {
+                I8x16 => Some(123),
+                I16x8 => Some(155),
+                _ => None,
+            },
+            Instruction::VectorExtAddPairwise { shape, signed } => match shape {
+                I16x8 => Some(124 + !signed as u32),
+                I32x4 => Some(126 + !signed as u32),
+                _ => None,
+            },
+            Instruction::VectorExtMultiply {
+                shape,
+                high,
+                signed,
+            } => {
+                let n = *high as u32 + !signed as u32 * 2;
+                match shape {
+                    I16x8 => Some(156 + n),
+                    I32x4 => Some(188 + n),
+                    I64x2 => Some(220 + n),
+                    _ => None,
+                }
+            }
+            Instruction::I16x8Q15MulrSat => Some(130),
+            Instruction::I32x4DotI16x8 => Some(186),
+            Instruction::VectorCeil(shape) => match shape {
+                F32x4 => Some(103),
+                F64x2 => Some(116),
+                _ => None,
+            },
+            Instruction::VectorFloor(shape) => match shape {
+                F32x4 => Some(104),
+                F64x2 => Some(117),
+                _ => None,
+            },
+            Instruction::VectorTruncate(shape) => match shape {
+                F32x4 => Some(105),
+                F64x2 => Some(122),
+                _ => None,
+            },
+            Instruction::VectorNearest(shape) => match shape {
+                F32x4 => Some(106),
+                F64x2 => Some(148),
+                _ => None,
+            },
+            Instruction::VectorSquareRoot(shape) => match shape {
+                F32x4 => Some(227),
+                F64x2 => Some(239),
+                _ => None,
+            },
+            Instruction::VectorDivision(shape) => match shape {
+                F32x4 => Some(231),
+                F64x2 => Some(243),
+                _ => None,
+            },
+            Instruction::VectorPseudoMinimum(shape) => match shape {
+                F32x4 => Some(234),
+                F64x2 => Some(246),
+                _ => None,
+            },
+            Instruction::VectorPseudoMaximum(shape) => match shape {
+                F32x4 => Some(235),
+                F64x2 => Some(247),
+                _ => None,
+            },
+            Instruction::VectorTruncateSaturate { float, signed } => match float {
+                FloatType::F32 => Some(248 + !signed as u32),
+                FloatType::F64 => Some(252 + !signed as u32),
+            },
+            Instruction::VectorConvert { float, signed } => match float {
+                FloatType::F32 => Some(250 + !signed as u32),
+                FloatType::F64 => Some(254 + !signed as u32),
+            },
+            _ => return Err(self.invalid("not a vector instruction")),
+        };
+
+        opcode.ok_or_else(|| self.invalid("the lane shape isn't supported by the instruction"))
+    }
+
+    pub(crate) fn encode(&self, writer: &mut impl Write) -> Result<usize> {
+        match self {
+            Instruction::Unreachable => types::encode_raw(writer, &[0x00]),
+            Instruction::NOP => types::encode_raw(writer, &[0x01]),
             Instruction::Block { ty, instrs } => {
-                let mut length = writer.write(&[0x02])?;
+                let mut length = types::encode_raw(writer, &[0x02])?;
                 length += ty.encode(writer)?;
                 for instr in instrs {
                     length += instr.encode(writer)?;
                 }
-                length += writer.write(&[0x0B])?;
+                length += types::encode_raw(writer, &[0x0B])?;
                 Ok(length)
             }
             Instruction::Loop { ty, instrs } => {
-                let mut length = writer.write(&[0x03])?;
+                let mut length = types::encode_raw(writer, &[0x03])?;
                 length += ty.encode(writer)?;
                 for instr in instrs {
                     length += instr.encode(writer)?;
                 }
-                length += writer.write(&[0x0B])?;
+                length += types::encode_raw(writer, &[0x0B])?;
                 Ok(length)
             }
             Instruction::If {
@@ -233,76 +1254,189 @@ impl Instruction {
                 accept_instrs,
                 reject_instrs,
             } => {
-                let mut length = writer.write(&[0x04])?;
+                let mut length = types::encode_raw(writer, &[0x04])?;
                 length += ty.encode(writer)?;
                 for instr in accept_instrs {
                     length += instr.encode(writer)?;
                 }
                 if let Some(reject) = reject_instrs {
-                    length += writer.write(&[0x05])?;
+                    length += types::encode_raw(writer, &[0x05])?;
                     for instr in reject {
                         length += instr.encode(writer)?;
                     }
                 }
-                length += writer.write(&[0x0B])?;
+                length += types::encode_raw(writer, &[0x0B])?;
+                Ok(length)
+            }
+            Instruction::Throw(tag) => {
+                let mut length = types::encode_raw(writer, &[0x08])?;
+                length += types::encode_u32(writer, *tag)?;
+                Ok(length)
+            }
+            Instruction::ThrowRef => types::encode_raw(writer, &[0x0A]),
+            Instruction::TryTable {
+                ty,
+                catches,
+                instrs,
+            } => {
+                let mut length = types::encode_raw(writer, &[0x1F])?;
+                length += ty.encode(writer)?;
+                length += types::encode_u32(writer, types::count_to_u32(catches.len())?)?;
+                for catch in catches {
+                    length += catch.encode(writer)?;
+                }
+                for instr in instrs {
+                    length += instr.encode(writer)?;
+                }
+                length += types::encode_raw(writer, &[0x0B])?;
+                Ok(length)
+            }
+            Instruction::Try {
+                ty,
+                instrs,
+                handler,
+            } => {
+                let mut length = types::encode_raw(writer, &[0x06])?;
+                length += ty.encode(writer)?;
+                for instr in instrs {
+                    length += instr.encode(writer)?;
+                }
+                match handler {
+                    TryHandler::Catches { catches, catch_all } => {
+                        for (tag, instrs) in catches {
+                            length += types::encode_raw(writer, &[0x07])?;
+                            length += types::encode_u32(writer, *tag)?;
+                            for instr in instrs {
+                                length += instr.encode(writer)?;
+                            }
+                        }
+                        if let Some(instrs) = catch_all {
+                            length += types::encode_raw(writer, &[0x19])?;
+                            for instr in instrs {
+                                length += instr.encode(writer)?;
+                            }
+                        }
+                        length += types::encode_raw(writer, &[0x0B])?;
+                    }
+                    TryHandler::Delegate(label) => {
+                        length += types::encode_raw(writer, &[0x18])?;
+                        length += types::encode_u32(writer, *label)?;
+                    }
+                }
+                Ok(length)
+            }
+            Instruction::Rethrow(label) => {
+                let mut length = types::encode_raw(writer, &[0x09])?;
+                length += types::encode_u32(writer, *label)?;
                 Ok(length)
             }
             Instruction::Branch(label) => {
-                let mut length = writer.write(&[0x0C])?;
+                let mut length = types::encode_raw(writer, &[0x0C])?;
                 length += types::encode_u32(writer, *label)?;
                 Ok(length)
             }
             Instruction::BranchIf(label) => {
-                let mut length = writer.write(&[0x0D])?;
+                let mut length = types::encode_raw(writer, &[0x0D])?;
                 length += types::encode_u32(writer, *label)?;
                 Ok(length)
             }
             Instruction::BranchTable { labels, operand } => {
-                let mut length = writer.write(&[0x0E])?;
+                let mut length = types::encode_raw(writer, &[0x0E])?;
                 let mut buf = Vec::new();
                 for label in labels {
                     types::encode_u32(&mut buf, *label)?;
                 }
-                length += types::encode_vec(writer, &buf, labels.len() as u32)?;
+                length += types::encode_vec(writer, &buf, labels.len())?;
                 length += types::encode_u32(writer, *operand)?;
                 Ok(length)
             }
-            Instruction::Return => writer.write(&[0x0F]),
+            Instruction::Return => types::encode_raw(writer, &[0x0F]),
             Instruction::Call(idx) => {
-                let mut length = writer.write(&[0x10])?;
+                let mut length = types::encode_raw(writer, &[0x10])?;
                 length += types::encode_u32(writer, *idx)?;
                 Ok(length)
             }
-            Instruction::CallIndirect(idx) => {
-                let mut length = writer.write(&[0x11])?;
+            Instruction::CallIndirect { ty, table } => {
+                let mut length = types::encode_raw(writer, &[0x11])?;
+                length += types::encode_u32(writer, *ty)?;
+                length += types::encode_u32(writer, *table)?;
+                Ok(length)
+            }
+            Instruction::ReturnCall(idx) => {
+                let mut length = types::encode_raw(writer, &[0x12])?;
                 length += types::encode_u32(writer, *idx)?;
-                length += writer.write(&[0x00])?;
                 Ok(length)
             }
-            Instruction::Drop => writer.write(&[0x1A]),
-            Instruction::Select => writer.write(&[0x1B]),
+            Instruction::ReturnCallIndirect { ty, table } => {
+                let mut length = types::encode_raw(writer, &[0x13])?;
+                length += types::encode_u32(writer, *ty)?;
+                length += types::encode_u32(writer, *table)?;
+                Ok(length)
+            }
+            Instruction::CallRef(ty) => {
+                let mut length = types::encode_raw(writer, &[0x14])?;
+                length += types::encode_u32(writer, *ty)?;
+                Ok(length)
+            }
+            Instruction::ReturnCallRef(ty) => {
+                let mut length = types::encode_raw(writer, &[0x15])?;
+                length += types::encode_u32(writer, *ty)?;
+                Ok(length)
+            }
+            Instruction::RefAsNonNull => types::encode_raw(writer, &[0xD4]),
+            Instruction::BranchOnNull(label) => {
+                let mut length = types::encode_raw(writer, &[0xD5])?;
+                length += types::encode_u32(writer, *label)?;
+                Ok(length)
+            }
+            Instruction::BranchOnNonNull(label) => {
+                let mut length = types::encode_raw(writer, &[0xD6])?;
+                length += types::encode_u32(writer, *label)?;
+                Ok(length)
+            }
+            Instruction::Drop => types::encode_raw(writer, &[0x1A]),
+            Instruction::Select => types::encode_raw(writer, &[0x1B]),
+            Instruction::TypedSelect(types) => {
+                let mut length = types::encode_raw(writer, &[0x1C])?;
+                let mut buf = Vec::with_capacity(types.len());
+                for ty in types {
+                    types::encode_val_type(&mut buf, *ty)?;
+                }
+                length += types::encode_vec(writer, &buf, types.len())?;
+                Ok(length)
+            }
             Instruction::LocalGet(idx) => {
-                let mut length = writer.write(&[0x20])?;
+                let mut length = types::encode_raw(writer, &[0x20])?;
                 length += types::encode_u32(writer, *idx)?;
                 Ok(length)
             }
             Instruction::LocalSet(idx) => {
-                let mut length = writer.write(&[0x21])?;
+                let mut length = types::encode_raw(writer, &[0x21])?;
                 length += types::encode_u32(writer, *idx)?;
                 Ok(length)
             }
             Instruction::LocalTee(idx) => {
-                let mut length = writer.write(&[0x22])?;
+                let mut length = types::encode_raw(writer, &[0x22])?;
                 length += types::encode_u32(writer, *idx)?;
                 Ok(length)
             }
             Instruction::GlobalGet(idx) => {
-                let mut length = writer.write(&[0x23])?;
+                let mut length = types::encode_raw(writer, &[0x23])?;
                 length += types::encode_u32(writer, *idx)?;
                 Ok(length)
             }
             Instruction::GlobalSet(idx) => {
-                let mut length = writer.write(&[0x24])?;
+                let mut length = types::encode_raw(writer, &[0x24])?;
+                length += types::encode_u32(writer, *idx)?;
+                Ok(length)
+            }
+            Instruction::TableGet(idx) => {
+                let mut length = types::encode_raw(writer, &[0x25])?;
+                length += types::encode_u32(writer, *idx)?;
+                Ok(length)
+            }
+            Instruction::TableSet(idx) => {
+                let mut length = types::encode_raw(writer, &[0x26])?;
                 length += types::encode_u32(writer, *idx)?;
                 Ok(length)
             }
@@ -314,22 +1448,26 @@ impl Instruction {
                             match storage.1 {
                                 StorageType::I8 => {
                                     if storage.0 {
-                                        length += writer.write(&[0x2C])?;
+                                        length += types::encode_raw(writer, &[0x2C])?;
                                     } else {
-                                        length += writer.write(&[0x2D])?;
+                                        length += types::encode_raw(writer, &[0x2D])?;
                                     }
                                 }
                                 StorageType::I16 => {
                                     if storage.0 {
-                                        length += writer.write(&[0x2E])?;
+                                        length += types::encode_raw(writer, &[0x2E])?;
                                     } else {
-                                        length += writer.write(&[0x2F])?;
+                                        length += types::encode_raw(writer, &[0x2F])?;
                                     }
                                 }
-                                StorageType::I32 => panic!(),
+                                StorageType::I32 => {
+                                    return Err(
+                                        self.invalid("i32 loads can't have a 32 bit storage size")
+                                    )
+                                }
                             }
                         } else {
-                            length += writer.write(&[0x28])?;
+                            length += types::encode_raw(writer, &[0x28])?;
                         }
                     }
                     ValType::I64 => {
@@ -337,44 +1475,55 @@ impl Instruction {
                             match storage.1 {
                                 StorageType::I8 => {
                                     if storage.0 {
-                                        length += writer.write(&[0x30])?;
+                                        length += types::encode_raw(writer, &[0x30])?;
                                     } else {
-                                        length += writer.write(&[0x31])?;
+                                        length += types::encode_raw(writer, &[0x31])?;
                                     }
                                 }
                                 StorageType::I16 => {
                                     if storage.0 {
-                                        length += writer.write(&[0x32])?;
+                                        length += types::encode_raw(writer, &[0x32])?;
                                     } else {
-                                        length += writer.write(&[0x33])?;
+                                        length += types::encode_raw(writer, &[0x33])?;
                                     }
                                 }
                                 StorageType::I32 => {
                                     if storage.0 {
-                                        length += writer.write(&[0x34])?;
+                                        length += types::encode_raw(writer, &[0x34])?;
                                     } else {
-                                        length += writer.write(&[0x35])?;
+                                        length += types::encode_raw(writer, &[0x35])?;
                                     }
                                 }
                             }
                         } else {
-                            length += writer.write(&[0x29])?;
+                            length += types::encode_raw(writer, &[0x29])?;
                         }
                     }
                     ValType::F32 => {
-                        if let Some(_) = storage {
-                            panic!()
+                        if storage.is_some() {
+                            return Err(self.invalid("float loads can't have a storage size"));
                         } else {
-                            length += writer.write(&[0x2A])?;
+                            length += types::encode_raw(writer, &[0x2A])?;
                         }
                     }
                     ValType::F64 => {
-                        if let Some(_) = storage {
-                            panic!()
+                        if storage.is_some() {
+                            return Err(self.invalid("float loads can't have a storage size"));
                         } else {
-                            length += writer.write(&[0x2B])?;
+                            length += types::encode_raw(writer, &[0x2B])?;
                         }
                     }
+                    ValType::V128 => {
+                        if storage.is_some() {
+                            return Err(self.invalid("v128 loads can't have a storage size"));
+                        } else {
+                            length += types::encode_raw(writer, &[0xFD])?;
+                            length += types::encode_u32(writer, 0)?;
+                        }
+                    }
+                    ValType::Ref(_) => {
+                        return Err(self.invalid("references can't be loaded from memory"))
+                    }
                 }
                 length += mem.encode(writer)?;
                 Ok(length)
@@ -386,318 +1535,2050 @@ impl Instruction {
                         if let Some(storage) = storage {
                             match storage {
                                 StorageType::I8 => {
-                                    length += writer.write(&[0x3A])?;
+                                    length += types::encode_raw(writer, &[0x3A])?;
                                 }
                                 StorageType::I16 => {
-                                    length += writer.write(&[0x3B])?;
+                                    length += types::encode_raw(writer, &[0x3B])?;
+                                }
+                                StorageType::I32 => {
+                                    return Err(
+                                        self.invalid("i32 stores can't have a 32 bit storage size")
+                                    )
                                 }
-                                StorageType::I32 => panic!(),
                             }
                         } else {
-                            length += writer.write(&[0x36])?;
+                            length += types::encode_raw(writer, &[0x36])?;
                         }
                     }
                     ValType::I64 => {
                         if let Some(storage) = storage {
                             match storage {
                                 StorageType::I8 => {
-                                    length += writer.write(&[0x3C])?;
+                                    length += types::encode_raw(writer, &[0x3C])?;
                                 }
                                 StorageType::I16 => {
-                                    length += writer.write(&[0x3D])?;
+                                    length += types::encode_raw(writer, &[0x3D])?;
                                 }
                                 StorageType::I32 => {
-                                    length += writer.write(&[0x3E])?;
+                                    length += types::encode_raw(writer, &[0x3E])?;
                                 }
                             }
                         } else {
-                            length += writer.write(&[0x37])?;
+                            length += types::encode_raw(writer, &[0x37])?;
                         }
                     }
                     ValType::F32 => {
-                        if let Some(_) = storage {
-                            panic!();
+                        if storage.is_some() {
+                            return Err(self.invalid("float stores can't have a storage size"));
                         } else {
-                            length += writer.write(&[0x38])?;
+                            length += types::encode_raw(writer, &[0x38])?;
                         }
                     }
                     ValType::F64 => {
-                        if let Some(_) = storage {
-                            panic!();
+                        if storage.is_some() {
+                            return Err(self.invalid("float stores can't have a storage size"));
+                        } else {
+                            length += types::encode_raw(writer, &[0x39])?;
+                        }
+                    }
+                    ValType::V128 => {
+                        if storage.is_some() {
+                            return Err(self.invalid("v128 stores can't have a storage size"));
                         } else {
-                            length += writer.write(&[0x39])?;
+                            length += types::encode_raw(writer, &[0xFD])?;
+                            length += types::encode_u32(writer, 11)?;
                         }
                     }
+                    ValType::Ref(_) => {
+                        return Err(self.invalid("references can't be stored in memory"))
+                    }
                 }
                 length += mem.encode(writer)?;
                 Ok(length)
             }
-            Instruction::MemorySize => writer.write(&[0x3f, 0x00]),
-            Instruction::MemoryGrow => writer.write(&[0x40, 0x00]),
+            Instruction::MemorySize(mem) => {
+                let mut length = types::encode_raw(writer, &[0x3f])?;
+                length += types::encode_u32(writer, *mem)?;
+                Ok(length)
+            }
+            Instruction::MemoryGrow(mem) => {
+                let mut length = types::encode_raw(writer, &[0x40])?;
+                length += types::encode_u32(writer, *mem)?;
+                Ok(length)
+            }
             Instruction::Const(literal) => match literal {
                 Literal::I32(int) => {
-                    let mut length = writer.write(&[0x41])?;
+                    let mut length = types::encode_raw(writer, &[0x41])?;
                     length += types::encode_i32(writer, *int)?;
                     Ok(length)
                 }
                 Literal::I64(long) => {
-                    let mut length = writer.write(&[0x42])?;
+                    let mut length = types::encode_raw(writer, &[0x42])?;
                     length += types::encode_i64(writer, *long)?;
                     Ok(length)
                 }
                 Literal::F32(float) => {
-                    let mut length = writer.write(&[0x43])?;
+                    let mut length = types::encode_raw(writer, &[0x43])?;
                     length += types::encode_f32(writer, *float)?;
                     Ok(length)
                 }
                 Literal::F64(double) => {
-                    let mut length = writer.write(&[0x44])?;
+                    let mut length = types::encode_raw(writer, &[0x44])?;
                     length += types::encode_f64(writer, *double)?;
                     Ok(length)
                 }
+                Literal::V128(bits) => {
+                    let mut length = types::encode_raw(writer, &[0xFD])?;
+                    length += types::encode_u32(writer, 12)?;
+                    length += types::encode_u128(writer, *bits)?;
+                    Ok(length)
+                }
             },
             Instruction::EqualZero(ty) => match ty {
-                IntegerType::I32 => writer.write(&[0x45]),
-                IntegerType::I64 => writer.write(&[0x50]),
+                IntegerType::I32 => types::encode_raw(writer, &[0x45]),
+                IntegerType::I64 => types::encode_raw(writer, &[0x50]),
             },
             Instruction::Equal(ty) => match ty {
-                ValType::I32 => writer.write(&[0x46]),
-                ValType::I64 => writer.write(&[0x51]),
-                ValType::F32 => writer.write(&[0x5B]),
-                ValType::F64 => writer.write(&[0x61]),
+                ValType::I32 => types::encode_raw(writer, &[0x46]),
+                ValType::I64 => types::encode_raw(writer, &[0x51]),
+                ValType::F32 => types::encode_raw(writer, &[0x5B]),
+                ValType::F64 => types::encode_raw(writer, &[0x61]),
+                ValType::V128 => Err(self.invalid("vectors need a lane shape")),
+                ValType::Ref(_) => {
+                    Err(self.invalid("references don't support arithmetic or comparisons"))
+                }
             },
             Instruction::NotEqual(ty) => match ty {
-                ValType::I32 => writer.write(&[0x47]),
-                ValType::I64 => writer.write(&[0x52]),
-                ValType::F32 => writer.write(&[0x5C]),
-                ValType::F64 => writer.write(&[0x62]),
+                ValType::I32 => types::encode_raw(writer, &[0x47]),
+                ValType::I64 => types::encode_raw(writer, &[0x52]),
+                ValType::F32 => types::encode_raw(writer, &[0x5C]),
+                ValType::F64 => types::encode_raw(writer, &[0x62]),
+                ValType::V128 => Err(self.invalid("vectors need a lane shape")),
+                ValType::Ref(_) => {
+                    Err(self.invalid("references don't support arithmetic or comparisons"))
+                }
             },
             Instruction::LessThanI32 { ty, signed } => match (ty, signed) {
-                (IntegerType::I32, true) => writer.write(&[0x48]),
-                (IntegerType::I32, false) => writer.write(&[0x49]),
-                (IntegerType::I64, true) => writer.write(&[0x53]),
-                (IntegerType::I64, false) => writer.write(&[0x54]),
+                (IntegerType::I32, true) => types::encode_raw(writer, &[0x48]),
+                (IntegerType::I32, false) => types::encode_raw(writer, &[0x49]),
+                (IntegerType::I64, true) => types::encode_raw(writer, &[0x53]),
+                (IntegerType::I64, false) => types::encode_raw(writer, &[0x54]),
             },
             Instruction::GreaterThanI32 { ty, signed } => match (ty, signed) {
-                (IntegerType::I32, true) => writer.write(&[0x4A]),
-                (IntegerType::I32, false) => writer.write(&[0x4B]),
-                (IntegerType::I64, true) => writer.write(&[0x55]),
-                (IntegerType::I64, false) => writer.write(&[0x56]),
+                (IntegerType::I32, true) => types::encode_raw(writer, &[0x4A]),
+                (IntegerType::I32, false) => types::encode_raw(writer, &[0x4B]),
+                (IntegerType::I64, true) => types::encode_raw(writer, &[0x55]),
+                (IntegerType::I64, false) => types::encode_raw(writer, &[0x56]),
             },
             Instruction::LessOrEqualI32 { ty, signed } => match (ty, signed) {
-                (IntegerType::I32, true) => writer.write(&[0x4C]),
-                (IntegerType::I32, false) => writer.write(&[0x4D]),
-                (IntegerType::I64, true) => writer.write(&[0x57]),
-                (IntegerType::I64, false) => writer.write(&[0x58]),
+                (IntegerType::I32, true) => types::encode_raw(writer, &[0x4C]),
+                (IntegerType::I32, false) => types::encode_raw(writer, &[0x4D]),
+                (IntegerType::I64, true) => types::encode_raw(writer, &[0x57]),
+                (IntegerType::I64, false) => types::encode_raw(writer, &[0x58]),
             },
             Instruction::GreaterOrEqualI32 { ty, signed } => match (ty, signed) {
-                (IntegerType::I32, true) => writer.write(&[0x4E]),
-                (IntegerType::I32, false) => writer.write(&[0x4F]),
-                (IntegerType::I64, true) => writer.write(&[0x59]),
-                (IntegerType::I64, false) => writer.write(&[0x5A]),
+                (IntegerType::I32, true) => types::encode_raw(writer, &[0x4E]),
+                (IntegerType::I32, false) => types::encode_raw(writer, &[0x4F]),
+                (IntegerType::I64, true) => types::encode_raw(writer, &[0x59]),
+                (IntegerType::I64, false) => types::encode_raw(writer, &[0x5A]),
             },
             Instruction::LessThanFloat(ty) => match ty {
-                FloatType::F32 => writer.write(&[0x5D]),
-                FloatType::F64 => writer.write(&[0x63]),
+                FloatType::F32 => types::encode_raw(writer, &[0x5D]),
+                FloatType::F64 => types::encode_raw(writer, &[0x63]),
             },
             Instruction::GreaterThanFloat(ty) => match ty {
-                FloatType::F32 => writer.write(&[0x5E]),
-                FloatType::F64 => writer.write(&[0x64]),
+                FloatType::F32 => types::encode_raw(writer, &[0x5E]),
+                FloatType::F64 => types::encode_raw(writer, &[0x64]),
             },
             Instruction::LessOrEqualFloat(ty) => match ty {
-                FloatType::F32 => writer.write(&[0x5F]),
-                FloatType::F64 => writer.write(&[0x65]),
+                FloatType::F32 => types::encode_raw(writer, &[0x5F]),
+                FloatType::F64 => types::encode_raw(writer, &[0x65]),
             },
             Instruction::GreaterOrEqualFloat(ty) => match ty {
-                FloatType::F32 => writer.write(&[0x60]),
-                FloatType::F64 => writer.write(&[0x66]),
+                FloatType::F32 => types::encode_raw(writer, &[0x60]),
+                FloatType::F64 => types::encode_raw(writer, &[0x66]),
             },
             Instruction::CountLeadingZero(ty) => match ty {
-                IntegerType::I32 => writer.write(&[0x67]),
-                IntegerType::I64 => writer.write(&[0x79]),
+                IntegerType::I32 => types::encode_raw(writer, &[0x67]),
+                IntegerType::I64 => types::encode_raw(writer, &[0x79]),
             },
             Instruction::CountTrailingZero(ty) => match ty {
-                IntegerType::I32 => writer.write(&[0x68]),
-                IntegerType::I64 => writer.write(&[0x7A]),
+                IntegerType::I32 => types::encode_raw(writer, &[0x68]),
+                IntegerType::I64 => types::encode_raw(writer, &[0x7A]),
             },
             Instruction::CountOnes(ty) => match ty {
-                IntegerType::I32 => writer.write(&[0x69]),
-                IntegerType::I64 => writer.write(&[0x7B]),
+                IntegerType::I32 => types::encode_raw(writer, &[0x69]),
+                IntegerType::I64 => types::encode_raw(writer, &[0x7B]),
             },
             Instruction::Add(ty) => match ty {
-                ValType::I32 => writer.write(&[0x6A]),
-                ValType::I64 => writer.write(&[0x7C]),
-                ValType::F32 => writer.write(&[0x92]),
-                ValType::F64 => writer.write(&[0xA0]),
+                ValType::I32 => types::encode_raw(writer, &[0x6A]),
+                ValType::I64 => types::encode_raw(writer, &[0x7C]),
+                ValType::F32 => types::encode_raw(writer, &[0x92]),
+                ValType::F64 => types::encode_raw(writer, &[0xA0]),
+                ValType::V128 => Err(self.invalid("vectors need a lane shape")),
+                ValType::Ref(_) => {
+                    Err(self.invalid("references don't support arithmetic or comparisons"))
+                }
             },
             Instruction::Subtract(ty) => match ty {
-                ValType::I32 => writer.write(&[0x6B]),
-                ValType::I64 => writer.write(&[0x7D]),
-                ValType::F32 => writer.write(&[0x93]),
-                ValType::F64 => writer.write(&[0xA1]),
+                ValType::I32 => types::encode_raw(writer, &[0x6B]),
+                ValType::I64 => types::encode_raw(writer, &[0x7D]),
+                ValType::F32 => types::encode_raw(writer, &[0x93]),
+                ValType::F64 => types::encode_raw(writer, &[0xA1]),
+                ValType::V128 => Err(self.invalid("vectors need a lane shape")),
+                ValType::Ref(_) => {
+                    Err(self.invalid("references don't support arithmetic or comparisons"))
+                }
             },
             Instruction::Multiply(ty) => match ty {
-                ValType::I32 => writer.write(&[0x6C]),
-                ValType::I64 => writer.write(&[0x7E]),
-                ValType::F32 => writer.write(&[0x94]),
-                ValType::F64 => writer.write(&[0xA2]),
+                ValType::I32 => types::encode_raw(writer, &[0x6C]),
+                ValType::I64 => types::encode_raw(writer, &[0x7E]),
+                ValType::F32 => types::encode_raw(writer, &[0x94]),
+                ValType::F64 => types::encode_raw(writer, &[0xA2]),
+                ValType::V128 => Err(self.invalid("vectors need a lane shape")),
+                ValType::Ref(_) => {
+                    Err(self.invalid("references don't support arithmetic or comparisons"))
+                }
             },
             Instruction::I32Division { ty, signed } => match (ty, signed) {
-                (IntegerType::I32, true) => writer.write(&[0x6D]),
-                (IntegerType::I32, false) => writer.write(&[0x6E]),
-                (IntegerType::I64, true) => writer.write(&[0x7F]),
-                (IntegerType::I64, false) => writer.write(&[0x80]),
+                (IntegerType::I32, true) => types::encode_raw(writer, &[0x6D]),
+                (IntegerType::I32, false) => types::encode_raw(writer, &[0x6E]),
+                (IntegerType::I64, true) => types::encode_raw(writer, &[0x7F]),
+                (IntegerType::I64, false) => types::encode_raw(writer, &[0x80]),
             },
             Instruction::FloatDivision(ty) => match ty {
-                FloatType::F32 => writer.write(&[0x95]),
-                FloatType::F64 => writer.write(&[0xA3]),
+                FloatType::F32 => types::encode_raw(writer, &[0x95]),
+                FloatType::F64 => types::encode_raw(writer, &[0xA3]),
             },
             Instruction::Remainder { ty, signed } => match (ty, signed) {
-                (IntegerType::I32, true) => writer.write(&[0x6F]),
-                (IntegerType::I32, false) => writer.write(&[0x70]),
-                (IntegerType::I64, true) => writer.write(&[0x81]),
-                (IntegerType::I64, false) => writer.write(&[0x82]),
+                (IntegerType::I32, true) => types::encode_raw(writer, &[0x6F]),
+                (IntegerType::I32, false) => types::encode_raw(writer, &[0x70]),
+                (IntegerType::I64, true) => types::encode_raw(writer, &[0x81]),
+                (IntegerType::I64, false) => types::encode_raw(writer, &[0x82]),
             },
             Instruction::And(ty) => match ty {
-                IntegerType::I32 => writer.write(&[0x71]),
-                IntegerType::I64 => writer.write(&[0x83]),
+                IntegerType::I32 => types::encode_raw(writer, &[0x71]),
+                IntegerType::I64 => types::encode_raw(writer, &[0x83]),
             },
             Instruction::Or(ty) => match ty {
-                IntegerType::I32 => writer.write(&[0x72]),
-                IntegerType::I64 => writer.write(&[0x84]),
+                IntegerType::I32 => types::encode_raw(writer, &[0x72]),
+                IntegerType::I64 => types::encode_raw(writer, &[0x84]),
             },
             Instruction::Xor(ty) => match ty {
-                IntegerType::I32 => writer.write(&[0x73]),
-                IntegerType::I64 => writer.write(&[0x85]),
+                IntegerType::I32 => types::encode_raw(writer, &[0x73]),
+                IntegerType::I64 => types::encode_raw(writer, &[0x85]),
             },
             Instruction::ShiftLeft(ty) => match ty {
-                IntegerType::I32 => writer.write(&[0x74]),
-                IntegerType::I64 => writer.write(&[0x86]),
+                IntegerType::I32 => types::encode_raw(writer, &[0x74]),
+                IntegerType::I64 => types::encode_raw(writer, &[0x86]),
             },
             Instruction::ShiftRight { ty, signed } => match (ty, signed) {
-                (IntegerType::I32, true) => writer.write(&[0x75]),
-                (IntegerType::I32, false) => writer.write(&[0x76]),
-                (IntegerType::I64, true) => writer.write(&[0x87]),
-                (IntegerType::I64, false) => writer.write(&[0x88]),
+                (IntegerType::I32, true) => types::encode_raw(writer, &[0x75]),
+                (IntegerType::I32, false) => types::encode_raw(writer, &[0x76]),
+                (IntegerType::I64, true) => types::encode_raw(writer, &[0x87]),
+                (IntegerType::I64, false) => types::encode_raw(writer, &[0x88]),
             },
             Instruction::LeftRotation(ty) => match ty {
-                IntegerType::I32 => writer.write(&[0x77]),
-                IntegerType::I64 => writer.write(&[0x78]),
+                IntegerType::I32 => types::encode_raw(writer, &[0x77]),
+                IntegerType::I64 => types::encode_raw(writer, &[0x89]),
             },
             Instruction::RightRotation(ty) => match ty {
-                IntegerType::I32 => writer.write(&[0x89]),
-                IntegerType::I64 => writer.write(&[0x8A]),
+                IntegerType::I32 => types::encode_raw(writer, &[0x78]),
+                IntegerType::I64 => types::encode_raw(writer, &[0x8A]),
             },
             Instruction::Absolute(ty) => match ty {
-                FloatType::F32 => writer.write(&[0x8B]),
-                FloatType::F64 => writer.write(&[0x99]),
+                FloatType::F32 => types::encode_raw(writer, &[0x8B]),
+                FloatType::F64 => types::encode_raw(writer, &[0x99]),
             },
             Instruction::Negate(ty) => match ty {
-                FloatType::F32 => writer.write(&[0x8C]),
-                FloatType::F64 => writer.write(&[0x9A]),
+                FloatType::F32 => types::encode_raw(writer, &[0x8C]),
+                FloatType::F64 => types::encode_raw(writer, &[0x9A]),
             },
             Instruction::Ceil(ty) => match ty {
-                FloatType::F32 => writer.write(&[0x8D]),
-                FloatType::F64 => writer.write(&[0x9B]),
+                FloatType::F32 => types::encode_raw(writer, &[0x8D]),
+                FloatType::F64 => types::encode_raw(writer, &[0x9B]),
             },
             Instruction::Floor(ty) => match ty {
-                FloatType::F32 => writer.write(&[0x8E]),
-                FloatType::F64 => writer.write(&[0x9C]),
+                FloatType::F32 => types::encode_raw(writer, &[0x8E]),
+                FloatType::F64 => types::encode_raw(writer, &[0x9C]),
             },
             Instruction::Truncate(ty) => match ty {
-                FloatType::F32 => writer.write(&[0x8F]),
-                FloatType::F64 => writer.write(&[0x9D]),
+                FloatType::F32 => types::encode_raw(writer, &[0x8F]),
+                FloatType::F64 => types::encode_raw(writer, &[0x9D]),
             },
             Instruction::Nearest(ty) => match ty {
-                FloatType::F32 => writer.write(&[0x90]),
-                FloatType::F64 => writer.write(&[0x9E]),
+                FloatType::F32 => types::encode_raw(writer, &[0x90]),
+                FloatType::F64 => types::encode_raw(writer, &[0x9E]),
             },
             Instruction::SquareRoot(ty) => match ty {
-                FloatType::F32 => writer.write(&[0x91]),
-                FloatType::F64 => writer.write(&[0x9F]),
+                FloatType::F32 => types::encode_raw(writer, &[0x91]),
+                FloatType::F64 => types::encode_raw(writer, &[0x9F]),
             },
             Instruction::Minimum(ty) => match ty {
-                FloatType::F32 => writer.write(&[0x96]),
-                FloatType::F64 => writer.write(&[0xA4]),
+                FloatType::F32 => types::encode_raw(writer, &[0x96]),
+                FloatType::F64 => types::encode_raw(writer, &[0xA4]),
             },
             Instruction::Maximum(ty) => match ty {
-                FloatType::F32 => writer.write(&[0x97]),
-                FloatType::F64 => writer.write(&[0xA5]),
+                FloatType::F32 => types::encode_raw(writer, &[0x97]),
+                FloatType::F64 => types::encode_raw(writer, &[0xA5]),
             },
             Instruction::CopySign(ty) => match ty {
-                FloatType::F32 => writer.write(&[0x98]),
-                FloatType::F64 => writer.write(&[0xA6]),
+                FloatType::F32 => types::encode_raw(writer, &[0x98]),
+                FloatType::F64 => types::encode_raw(writer, &[0xA6]),
             },
-            Instruction::I32Wrap => writer.write(&[0xA7]),
+            Instruction::I32Wrap => types::encode_raw(writer, &[0xA7]),
             Instruction::I32Extend(signed) => match signed {
-                true => writer.write(&[0xAC]),
-                false => writer.write(&[0xAD]),
+                true => types::encode_raw(writer, &[0xAC]),
+                false => types::encode_raw(writer, &[0xAD]),
             },
             Instruction::I32Truncate { ty, float, signed } => match ty {
                 IntegerType::I32 => match (float, signed) {
-                    (FloatType::F32, true) => writer.write(&[0xA8]),
-                    (FloatType::F32, false) => writer.write(&[0xA9]),
-                    (FloatType::F64, true) => writer.write(&[0xAA]),
-                    (FloatType::F64, false) => writer.write(&[0xAB]),
+                    (FloatType::F32, true) => types::encode_raw(writer, &[0xA8]),
+                    (FloatType::F32, false) => types::encode_raw(writer, &[0xA9]),
+                    (FloatType::F64, true) => types::encode_raw(writer, &[0xAA]),
+                    (FloatType::F64, false) => types::encode_raw(writer, &[0xAB]),
                 },
                 IntegerType::I64 => match (float, signed) {
-                    (FloatType::F32, true) => writer.write(&[0xAE]),
-                    (FloatType::F32, false) => writer.write(&[0xAF]),
-                    (FloatType::F64, true) => writer.write(&[0xB0]),
-                    (FloatType::F64, false) => writer.write(&[0xB1]),
+                    (FloatType::F32, true) => types::encode_raw(writer, &[0xAE]),
+                    (FloatType::F32, false) => types::encode_raw(writer, &[0xAF]),
+                    (FloatType::F64, true) => types::encode_raw(writer, &[0xB0]),
+                    (FloatType::F64, false) => types::encode_raw(writer, &[0xB1]),
                 },
             },
             Instruction::Convert { ty, tgt_ty, signed } => match ty {
                 FloatType::F32 => match (tgt_ty, signed) {
-                    (IntegerType::I32, true) => writer.write(&[0xB2]),
-                    (IntegerType::I32, false) => writer.write(&[0xB3]),
-                    (IntegerType::I64, true) => writer.write(&[0xB4]),
-                    (IntegerType::I64, false) => writer.write(&[0xB5]),
+                    (IntegerType::I32, true) => types::encode_raw(writer, &[0xB2]),
+                    (IntegerType::I32, false) => types::encode_raw(writer, &[0xB3]),
+                    (IntegerType::I64, true) => types::encode_raw(writer, &[0xB4]),
+                    (IntegerType::I64, false) => types::encode_raw(writer, &[0xB5]),
                 },
                 FloatType::F64 => match (tgt_ty, signed) {
-                    (IntegerType::I32, true) => writer.write(&[0xB7]),
-                    (IntegerType::I32, false) => writer.write(&[0xB8]),
-                    (IntegerType::I64, true) => writer.write(&[0xB9]),
-                    (IntegerType::I64, false) => writer.write(&[0xBA]),
+                    (IntegerType::I32, true) => types::encode_raw(writer, &[0xB7]),
+                    (IntegerType::I32, false) => types::encode_raw(writer, &[0xB8]),
+                    (IntegerType::I64, true) => types::encode_raw(writer, &[0xB9]),
+                    (IntegerType::I64, false) => types::encode_raw(writer, &[0xBA]),
                 },
             },
-            Instruction::FloatDemote => writer.write(&[0xB6]),
-            Instruction::FloatPromote => writer.write(&[0xBB]),
-            Instruction::I32ReI32erpret => writer.write(&[0xBC]),
-            Instruction::LongReI32erpret => writer.write(&[0xBD]),
-            Instruction::FloatReI32erpret => writer.write(&[0xBE]),
-            Instruction::DoubleReI32erpret => writer.write(&[0xBF]),
+            Instruction::FloatDemote => types::encode_raw(writer, &[0xB6]),
+            Instruction::FloatPromote => types::encode_raw(writer, &[0xBB]),
+            Instruction::I32ReI32erpret => types::encode_raw(writer, &[0xBC]),
+            Instruction::LongReI32erpret => types::encode_raw(writer, &[0xBD]),
+            Instruction::FloatReI32erpret => types::encode_raw(writer, &[0xBE]),
+            Instruction::DoubleReI32erpret => types::encode_raw(writer, &[0xBF]),
             Instruction::Extend { ty, base } => match ty {
                 IntegerType::I32 => match base {
-                    StorageType::I8 => writer.write(&[0xC0]),
-                    StorageType::I16 => writer.write(&[0xC1]),
-                    StorageType::I32 => panic!(),
+                    StorageType::I8 => types::encode_raw(writer, &[0xC0]),
+                    StorageType::I16 => types::encode_raw(writer, &[0xC1]),
+                    StorageType::I32 => Err(self.invalid("i32 can't be extended from 32 bits")),
                 },
                 IntegerType::I64 => match base {
-                    StorageType::I8 => writer.write(&[0xC2]),
-                    StorageType::I16 => writer.write(&[0xC3]),
-                    StorageType::I32 => writer.write(&[0xC4]),
+                    StorageType::I8 => types::encode_raw(writer, &[0xC2]),
+                    StorageType::I16 => types::encode_raw(writer, &[0xC3]),
+                    StorageType::I32 => types::encode_raw(writer, &[0xC4]),
                 },
             },
+            Instruction::RefNull(ty) => {
+                let mut length = types::encode_raw(writer, &[0xD0])?;
+                length += ty.encode(writer)?;
+                Ok(length)
+            }
+            Instruction::RefIsNull => types::encode_raw(writer, &[0xD1]),
+            Instruction::RefFunc(idx) => {
+                let mut length = types::encode_raw(writer, &[0xD2])?;
+                length += types::encode_u32(writer, *idx)?;
+                Ok(length)
+            }
             Instruction::SaturateTruncate { ty, float, signed } => {
-                writer.write(&[0xFC])?;
-                match ty {
+                types::encode_raw(writer, &[0xFC])?;
+                let length = match ty {
                     IntegerType::I32 => match (float, signed) {
-                        (FloatType::F32, true) => writer.write(&[0x00]),
-                        (FloatType::F32, false) => writer.write(&[0x01]),
-                        (FloatType::F64, true) => writer.write(&[0x02]),
-                        (FloatType::F64, false) => writer.write(&[0x03]),
+                        (FloatType::F32, true) => types::encode_raw(writer, &[0x00]),
+                        (FloatType::F32, false) => types::encode_raw(writer, &[0x01]),
+                        (FloatType::F64, true) => types::encode_raw(writer, &[0x02]),
+                        (FloatType::F64, false) => types::encode_raw(writer, &[0x03]),
                     },
                     IntegerType::I64 => match (float, signed) {
-                        (FloatType::F32, true) => writer.write(&[0x04]),
-                        (FloatType::F32, false) => writer.write(&[0x05]),
-                        (FloatType::F64, true) => writer.write(&[0x06]),
-                        (FloatType::F64, false) => writer.write(&[0x07]),
+                        (FloatType::F32, true) => types::encode_raw(writer, &[0x04]),
+                        (FloatType::F32, false) => types::encode_raw(writer, &[0x05]),
+                        (FloatType::F64, true) => types::encode_raw(writer, &[0x06]),
+                        (FloatType::F64, false) => types::encode_raw(writer, &[0x07]),
                     },
-                }
+                }?;
+                Ok(length + 1)
+            }
+            Instruction::TableGrow(idx)
+            | Instruction::TableSize(idx)
+            | Instruction::TableFill(idx) => {
+                let op = match self {
+                    Instruction::TableGrow(_) => 0x0F,
+                    Instruction::TableSize(_) => 0x10,
+                    _ => 0x11,
+                };
+                let mut length = types::encode_raw(writer, &[0xFC])?;
+                length += types::encode_u32(writer, op)?;
+                length += types::encode_u32(writer, *idx)?;
+                Ok(length)
+            }
+            Instruction::MemoryInit { data, mem } => {
+                let mut length = types::encode_raw(writer, &[0xFC, 0x08])?;
+                length += types::encode_u32(writer, *data)?;
+                length += types::encode_u32(writer, *mem)?;
+                Ok(length)
+            }
+            Instruction::DataDrop(data) => {
+                let mut length = types::encode_raw(writer, &[0xFC, 0x09])?;
+                length += types::encode_u32(writer, *data)?;
+                Ok(length)
+            }
+            Instruction::MemoryCopy { dst, src } => {
+                let mut length = types::encode_raw(writer, &[0xFC, 0x0A])?;
+                length += types::encode_u32(writer, *dst)?;
+                length += types::encode_u32(writer, *src)?;
+                Ok(length)
+            }
+            Instruction::MemoryFill(mem) => {
+                let mut length = types::encode_raw(writer, &[0xFC, 0x0B])?;
+                length += types::encode_u32(writer, *mem)?;
+                Ok(length)
+            }
+            Instruction::TableInit { elem, table } => {
+                let mut length = types::encode_raw(writer, &[0xFC, 0x0C])?;
+                length += types::encode_u32(writer, *elem)?;
+                length += types::encode_u32(writer, *table)?;
+                Ok(length)
+            }
+            Instruction::ElemDrop(elem) => {
+                let mut length = types::encode_raw(writer, &[0xFC, 0x0D])?;
+                length += types::encode_u32(writer, *elem)?;
+                Ok(length)
+            }
+            Instruction::TableCopy { dst, src } => {
+                let mut length = types::encode_raw(writer, &[0xFC, 0x0E])?;
+                length += types::encode_u32(writer, *dst)?;
+                length += types::encode_u32(writer, *src)?;
+                Ok(length)
+            }
+            Instruction::V128LoadExtend { mem, .. }
+            | Instruction::V128LoadSplat { mem, .. }
+            | Instruction::V128LoadZero { mem, .. } => {
+                let mut length = types::encode_raw(writer, &[0xFD])?;
+                length += types::encode_u32(writer, self.simd_opcode()?)?;
+                length += mem.encode(writer)?;
+                Ok(length)
+            }
+            Instruction::V128LoadLane { mem, lane, .. }
+            | Instruction::V128StoreLane { mem, lane, .. } => {
+                let mut length = types::encode_raw(writer, &[0xFD])?;
+                length += types::encode_u32(writer, self.simd_opcode()?)?;
+                length += mem.encode(writer)?;
+                length += types::encode_raw(writer, &[*lane])?;
+                Ok(length)
+            }
+            Instruction::I8x16Shuffle(lanes) => {
+                let mut length = types::encode_raw(writer, &[0xFD])?;
+                length += types::encode_u32(writer, self.simd_opcode()?)?;
+                length += types::encode_raw(writer, lanes)?;
+                Ok(length)
+            }
+            Instruction::ExtractLane { lane, .. } | Instruction::ReplaceLane { lane, .. } => {
+                let mut length = types::encode_raw(writer, &[0xFD])?;
+                length += types::encode_u32(writer, self.simd_opcode()?)?;
+                length += types::encode_raw(writer, &[*lane])?;
+                Ok(length)
+            }
+            Instruction::I8x16Swizzle
+            | Instruction::Splat(_)
+            | Instruction::VectorEqual(_)
+            | Instruction::VectorNotEqual(_)
+            | Instruction::VectorLessThan { .. }
+            | Instruction::VectorGreaterThan { .. }
+            | Instruction::VectorLessOrEqual { .. }
+            | Instruction::VectorGreaterOrEqual { .. }
+            | Instruction::V128Not
+            | Instruction::V128And
+            | Instruction::V128AndNot
+            | Instruction::V128Or
+            | Instruction::V128Xor
+            | Instruction::V128BitSelect
+            | Instruction::V128AnyTrue
+            | Instruction::VectorAbsolute(_)
+            | Instruction::VectorNegate(_)
+            | Instruction::I8x16CountOnes
+            | Instruction::VectorAllTrue(_)
+            | Instruction::VectorBitmask(_)
+            | Instruction::VectorNarrow { .. }
+            | Instruction::VectorExtend { .. }
+            | Instruction::VectorShiftLeft(_)
+            | Instruction::VectorShiftRight { .. }
+            | Instruction::VectorAdd(_)
+            | Instruction::VectorAddSaturate { .. }
+            | Instruction::VectorSubtract(_)
+            | Instruction::VectorSubtractSaturate { .. }
+            | Instruction::VectorMultiply(_)
+            | Instruction::VectorMinimum { .. }
+            | Instruction::VectorMaximum { .. }
+            | Instruction::VectorAverage(_)
+            | Instruction::VectorExtAddPairwise { .. }
+            | Instruction::VectorExtMultiply { .. }
+            | Instruction::I16x8Q15MulrSat
+            | Instruction::I32x4DotI16x8
+            | Instruction::VectorCeil(_)
+            | Instruction::VectorFloor(_)
+            | Instruction::VectorTruncate(_)
+            | Instruction::VectorNearest(_)
+            | Instruction::VectorSquareRoot(_)
+            | Instruction::VectorDivision(_)
+            | Instruction::VectorPseudoMinimum(_)
+            | Instruction::VectorPseudoMaximum(_)
+            | Instruction::VectorTruncateSaturate { .. }
+            | Instruction::VectorConvert { .. }
+            | Instruction::F32x4DemoteF64x2Zero
+            | Instruction::F64x2PromoteLowF32x4 => {
+                let mut length = types::encode_raw(writer, &[0xFD])?;
+                length += types::encode_u32(writer, self.simd_opcode()?)?;
+                Ok(length)
+            }
+            Instruction::AtomicNotify(mem)
+            | Instruction::AtomicWait { mem, .. }
+            | Instruction::AtomicLoad { mem, .. }
+            | Instruction::AtomicStore { mem, .. }
+            | Instruction::AtomicRmw { mem, .. }
+            | Instruction::AtomicCompareExchange { mem, .. } => {
+                let mut length = types::encode_raw(writer, &[0xFE])?;
+                length += types::encode_u32(writer, self.atomic_opcode()?)?;
+                length += mem.encode(writer)?;
+                Ok(length)
+            }
+            Instruction::AtomicFence => types::encode_raw(writer, &[0xFE, 0x03, 0x00]),
+            Instruction::RefEq => types::encode_raw(writer, &[0xD3]),
+            Instruction::StructNew(_)
+            | Instruction::StructNewDefault(_)
+            | Instruction::StructGet { .. }
+            | Instruction::StructSet { .. }
+            | Instruction::ArrayNew(_)
+            | Instruction::ArrayNewDefault(_)
+            | Instruction::ArrayNewFixed { .. }
+            | Instruction::ArrayNewData { .. }
+            | Instruction::ArrayNewElem { .. }
+            | Instruction::ArrayGet { .. }
+            | Instruction::ArraySet(_)
+            | Instruction::ArrayLen
+            | Instruction::ArrayFill(_)
+            | Instruction::ArrayCopy { .. }
+            | Instruction::ArrayInitData { .. }
+            | Instruction::ArrayInitElem { .. }
+            | Instruction::RefTest(_)
+            | Instruction::RefCast(_)
+            | Instruction::BranchOnCast { .. }
+            | Instruction::BranchOnCastFail { .. }
+            | Instruction::AnyConvertExtern
+            | Instruction::ExternConvertAny
+            | Instruction::RefI31
+            | Instruction::I31Get(_) => {
+                let mut length = types::encode_raw(writer, &[0xFB])?;
+                length += types::encode_u32(writer, self.gc_opcode()?)?;
+                let mut immediates = |idx: &[u32]| -> Result<usize> {
+                    let mut length = 0;
+                    for idx in idx {
+                        length += types::encode_u32(writer, *idx)?;
+                    }
+                    Ok(length)
+                };
+                length += match self {
+                    Instruction::StructNew(ty)
+                    | Instruction::StructNewDefault(ty)
+                    | Instruction::ArrayNew(ty)
+                    | Instruction::ArrayNewDefault(ty)
+                    | Instruction::ArrayGet { ty, .. }
+                    | Instruction::ArraySet(ty)
+                    | Instruction::ArrayFill(ty) => immediates(&[*ty])?,
+                    Instruction::StructGet { ty, field, .. }
+                    | Instruction::StructSet { ty, field } => immediates(&[*ty, *field])?,
+                    Instruction::ArrayNewFixed { ty, len } => immediates(&[*ty, *len])?,
+                    Instruction::ArrayNewData { ty, data }
+                    | Instruction::ArrayInitData { ty, data } => immediates(&[*ty, *data])?,
+                    Instruction::ArrayNewElem { ty, elem }
+                    | Instruction::ArrayInitElem { ty, elem } => immediates(&[*ty, *elem])?,
+                    Instruction::ArrayCopy { dst, src } => immediates(&[*dst, *src])?,
+                    Instruction::RefTest(ty) | Instruction::RefCast(ty) => {
+                        ty.heap_type.encode(writer)?
+                    }
+                    Instruction::BranchOnCast { label, from, to }
+                    | Instruction::BranchOnCastFail { label, from, to } => {
+                        // The nullability of both types is packed in a flags byte
+                        let flags = from.nullable as u8 | (to.nullable as u8) << 1;
+                        let mut length = types::encode_raw(writer, &[flags])?;
+                        length += types::encode_u32(writer, *label)?;
+                        length += from.heap_type.encode(writer)?;
+                        length += to.heap_type.encode(writer)?;
+                        length
+                    }
+                    _ => 0,
+                };
+                Ok(length)
+            }
+        }
+    }
+}
+
+/// The opcode that terminated a decoded instruction sequence
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Terminator {
+    End,
+    Else,
+    // The ends of the bodies of a legacy try
+    Catch(TagIdx),
+    CatchAll,
+    Delegate(LabelIdx),
+}
+
+impl Terminator {
+    /// The error for a terminator found where it isn't allowed
+    fn unexpected(&self) -> Error {
+        match self {
+            Terminator::End => types::malformed("unexpected end"),
+            Terminator::Else => types::malformed("else found outside of an if block"),
+            Terminator::Catch(_) | Terminator::CatchAll | Terminator::Delegate(_) => {
+                types::malformed("catch or delegate found outside of a try block")
+            }
+        }
+    }
+}
+
+/// The deepest nesting of blocks that will be decoded or parsed, deeper bodies are rejected
+/// rather than overflowing the stack
+pub const MAX_NESTING: usize = 256;
+
+/// Decodes instructions until an end (0x0B), else (0x05), catch (0x07), catch_all (0x19)
+/// or delegate (0x18) opcode is found, `depth` is the number of enclosing blocks
+fn decode_instrs(reader: &mut impl Read, depth: usize) -> Result<(Vec<Instruction>, Terminator)> {
+    if depth > MAX_NESTING {
+        return Err(types::malformed("nesting too deep"));
+    }
+
+    let mut instrs = Vec::new();
+
+    loop {
+        match types::decode_byte(reader)? {
+            0x0B => return Ok((instrs, Terminator::End)),
+            0x05 => return Ok((instrs, Terminator::Else)),
+            0x07 => return Ok((instrs, Terminator::Catch(types::decode_u32(reader)?))),
+            0x19 => return Ok((instrs, Terminator::CatchAll)),
+            0x18 => return Ok((instrs, Terminator::Delegate(types::decode_u32(reader)?))),
+            opcode => instrs.push(Instruction::decode(opcode, reader, depth)?),
+        }
+    }
+}
+
+/// Decodes the body of a block that can't have an else branch
+fn decode_block_body(reader: &mut impl Read, depth: usize) -> Result<Vec<Instruction>> {
+    match decode_instrs(reader, depth)? {
+        (instrs, Terminator::End) => Ok(instrs),
+        (_, terminator) => Err(terminator.unexpected()),
+    }
+}
+
+/// Decodes the rest of a legacy try after its block type
+fn decode_try(reader: &mut impl Read, depth: usize) -> Result<(Vec<Instruction>, TryHandler)> {
+    let (instrs, mut terminator) = decode_instrs(reader, depth)?;
+    if let Terminator::Delegate(label) = terminator {
+        return Ok((instrs, TryHandler::Delegate(label)));
+    }
+
+    let mut catches = Vec::new();
+    let mut catch_all = None;
+    loop {
+        terminator = match terminator {
+            Terminator::End => break,
+            Terminator::Catch(tag) if catch_all.is_none() => {
+                let (body, next) = decode_instrs(reader, depth)?;
+                catches.push((tag, body));
+                next
             }
+            Terminator::CatchAll if catch_all.is_none() => {
+                let (body, next) = decode_instrs(reader, depth)?;
+                catch_all = Some(body);
+                next
+            }
+            terminator => return Err(terminator.unexpected()),
+        };
+    }
+    Ok((instrs, TryHandler::Catches { catches, catch_all }))
+}
+
+/// Decodes the atomic instruction following a 0xFE prefix
+fn decode_atomic(reader: &mut impl Read) -> Result<Instruction> {
+    const OPS: [AtomicOp; 6] = [
+        AtomicOp::Add,
+        AtomicOp::Subtract,
+        AtomicOp::And,
+        AtomicOp::Or,
+        AtomicOp::Xor,
+        AtomicOp::Exchange,
+    ];
+
+    let opcode = types::decode_u32(reader)?;
+    if opcode == 0x03 {
+        if types::decode_byte(reader)? != 0x00 {
+            return Err(types::malformed("atomic.fence flags must be zero"));
         }
+        return Ok(Instruction::AtomicFence);
     }
+
+    let mem = MemoryArgument::decode(reader)?;
+    Ok(match opcode {
+        0x00 => Instruction::AtomicNotify(mem),
+        0x01 => Instruction::AtomicWait {
+            mem,
+            ty: IntegerType::I32,
+        },
+        0x02 => Instruction::AtomicWait {
+            mem,
+            ty: IntegerType::I64,
+        },
+        0x10..=0x16 => {
+            let (ty, storage) = ATOMIC_ACCESSES[opcode as usize - 0x10];
+            Instruction::AtomicLoad { mem, ty, storage }
+        }
+        0x17..=0x1D => {
+            let (ty, storage) = ATOMIC_ACCESSES[opcode as usize - 0x17];
+            Instruction::AtomicStore { mem, ty, storage }
+        }
+        0x1E..=0x47 => {
+            let (ty, storage) = ATOMIC_ACCESSES[(opcode as usize - 0x1E) % 7];
+            Instruction::AtomicRmw {
+                mem,
+                op: OPS[(opcode as usize - 0x1E) / 7],
+                ty,
+                storage,
+            }
+        }
+        0x48..=0x4E => {
+            let (ty, storage) = ATOMIC_ACCESSES[opcode as usize - 0x48];
+            Instruction::AtomicCompareExchange { mem, ty, storage }
+        }
+        _ => {
+            return Err(types::malformed(format!(
+                "invalid opcode 0xFE 0x{:02X}",
+                opcode
+            )))
+        }
+    })
+}
+
+/// Decodes the GC instruction following a 0xFB prefix
+fn decode_gc(reader: &mut impl Read) -> Result<Instruction> {
+    use Instruction::*;
+
+    let ref_type = |reader: &mut _, nullable| -> Result<types::RefType> {
+        Ok(types::RefType {
+            nullable,
+            heap_type: types::HeapType::decode(reader)?,
+        })
+    };
+    let signed = |opcode, base| match opcode - base {
+        0 => None,
+        1 => Some(true),
+        _ => Some(false),
+    };
+
+    let opcode = types::decode_u32(reader)?;
+    Ok(match opcode {
+        0x00 => StructNew(types::decode_u32(reader)?),
+        0x01 => StructNewDefault(types::decode_u32(reader)?),
+        0x02..=0x04 => StructGet {
+            ty: types::decode_u32(reader)?,
+            field: types::decode_u32(reader)?,
+            signed: signed(opcode, 0x02),
+        },
+        0x05 => StructSet {
+            ty: types::decode_u32(reader)?,
+            field: types::decode_u32(reader)?,
+        },
+        0x06 => ArrayNew(types::decode_u32(reader)?),
+        0x07 => ArrayNewDefault(types::decode_u32(reader)?),
+        0x08 => ArrayNewFixed {
+            ty: types::decode_u32(reader)?,
+            len: types::decode_u32(reader)?,
+        },
+        0x09 => ArrayNewData {
+            ty: types::decode_u32(reader)?,
+            data: types::decode_u32(reader)?,
+        },
+        0x0A => ArrayNewElem {
+            ty: types::decode_u32(reader)?,
+            elem: types::decode_u32(reader)?,
+        },
+        0x0B..=0x0D => ArrayGet {
+            ty: types::decode_u32(reader)?,
+            signed: signed(opcode, 0x0B),
+        },
+        0x0E => ArraySet(types::decode_u32(reader)?),
+        0x0F => ArrayLen,
+        0x10 => ArrayFill(types::decode_u32(reader)?),
+        0x11 => ArrayCopy {
+            dst: types::decode_u32(reader)?,
+            src: types::decode_u32(reader)?,
+        },
+        0x12 => ArrayInitData {
+            ty: types::decode_u32(reader)?,
+            data: types::decode_u32(reader)?,
+        },
+        0x13 => ArrayInitElem {
+            ty: types::decode_u32(reader)?,
+            elem: types::decode_u32(reader)?,
+        },
+        0x14 | 0x15 => RefTest(ref_type(reader, opcode == 0x15)?),
+        0x16 | 0x17 => RefCast(ref_type(reader, opcode == 0x17)?),
+        0x18 | 0x19 => {
+            let flags = types::decode_byte(reader)?;
+            if flags & !0x03 != 0 {
+                return Err(types::malformed(format!(
+                    "invalid br_on_cast flags 0x{:02X}",
+                    flags
+                )));
+            }
+            let label = types::decode_u32(reader)?;
+            let from = ref_type(reader, flags & 0x01 != 0)?;
+            let to = ref_type(reader, flags & 0x02 != 0)?;
+            match opcode {
+                0x18 => BranchOnCast { label, from, to },
+                _ => BranchOnCastFail { label, from, to },
+            }
+        }
+        0x1A => AnyConvertExtern,
+        0x1B => ExternConvertAny,
+        0x1C => RefI31,
+        0x1D => I31Get(true),
+        0x1E => I31Get(false),
+        _ => {
+            return Err(types::malformed(format!(
+                "invalid opcode 0xFB 0x{:02X}",
+                opcode
+            )))
+        }
+    })
+}
+
+/// Decodes the vector instruction following a 0xFD prefix
+fn decode_simd(reader: &mut impl Read) -> Result<Instruction> {
+    use Instruction::*;
+    use LaneShape::*;
+
+    Ok(match types::decode_u32(reader)? {
+        0 => Load {
+            mem: MemoryArgument::decode(reader)?,
+            ty: ValType::V128,
+            storage: None,
+        },
+        1 => V128LoadExtend {
+            mem: MemoryArgument::decode(reader)?,
+            shape: I16x8,
+            signed: true,
+        },
+        2 => V128LoadExtend {
+            mem: MemoryArgument::decode(reader)?,
+            shape: I16x8,
+            signed: false,
+        },
+        3 => V128LoadExtend {
+            mem: MemoryArgument::decode(reader)?,
+            shape: I32x4,
+            signed: true,
+        },
+        4 => V128LoadExtend {
+            mem: MemoryArgument::decode(reader)?,
+            shape: I32x4,
+            signed: false,
+        },
+        5 => V128LoadExtend {
+            mem: MemoryArgument::decode(reader)?,
+            shape: I64x2,
+            signed: true,
+        },
+        6 => V128LoadExtend {
+            mem: MemoryArgument::decode(reader)?,
+            shape: I64x2,
+            signed: false,
+        },
+        7 => V128LoadSplat {
+            mem: MemoryArgument::decode(reader)?,
+            shape: I8x16,
+        },
+        8 => V128LoadSplat {
+            mem: MemoryArgument::decode(reader)?,
+            shape: I16x8,
+        },
+        9 => V128LoadSplat {
+            mem: MemoryArgument::decode(reader)?,
+            shape: I32x4,
+        },
+        10 => V128LoadSplat {
+            mem: MemoryArgument::decode(reader)?,
+            shape: I64x2,
+        },
+        11 => Store {
+            mem: MemoryArgument::decode(reader)?,
+            ty: ValType::V128,
+            storage: None,
+        },
+        12 => Const(Literal::V128(types::decode_u128(reader)?)),
+        13 => {
+            let mut lanes = [0; 16];
+            reader.read_exact(&mut lanes)?;
+            I8x16Shuffle(lanes)
+        }
+        14 => I8x16Swizzle,
+        15 => Splat(I8x16),
+        16 => Splat(I16x8),
+        17 => Splat(I32x4),
+        18 => Splat(I64x2),
+        19 => Splat(F32x4),
+        20 => Splat(F64x2),
+        21 => ExtractLane {
+            shape: I8x16,
+            lane: types::decode_byte(reader)?,
+            signed: Some(true),
+        },
+        22 => ExtractLane {
+            shape: I8x16,
+            lane: types::decode_byte(reader)?,
+            signed: Some(false),
+        },
+        23 => ReplaceLane {
+            shape: I8x16,
+            lane: types::decode_byte(reader)?,
+        },
+        24 => ExtractLane {
+            shape: I16x8,
+            lane: types::decode_byte(reader)?,
+            signed: Some(true),
+        },
+        25 => ExtractLane {
+            shape: I16x8,
+            lane: types::decode_byte(reader)?,
+            signed: Some(false),
+        },
+        26 => ReplaceLane {
+            shape: I16x8,
+            lane: types::decode_byte(reader)?,
+        },
+        27 => ExtractLane {
+            shape: I32x4,
+            lane: types::decode_byte(reader)?,
+            signed: None,
+        },
+        28 => ReplaceLane {
+            shape: I32x4,
+            lane: types::decode_byte(reader)?,
+        },
+        29 => ExtractLane {
+            shape: I64x2,
+            lane: types::decode_byte(reader)?,
+            signed: None,
+        },
+        30 => ReplaceLane {
+            shape: I64x2,
+            lane: types::decode_byte(reader)?,
+        },
+        31 => ExtractLane {
+            shape: F32x4,
+            lane: types::decode_byte(reader)?,
+            signed: None,
+        },
+        32 => ReplaceLane {
+            shape: F32x4,
+            lane: types::decode_byte(reader)?,
+        },
+        33 => ExtractLane {
+            shape: F64x2,
+            lane: types::decode_byte(reader)?,
+            signed: None,
+        },
+        34 => ReplaceLane {
+            shape: F64x2,
+            lane: types::decode_byte(reader)?,
+        },
+        35 => VectorEqual(I8x16),
+        36 => VectorNotEqual(I8x16),
+        37 => VectorLessThan {
+            shape: I8x16,
+            signed: Some(true),
+        },
+        38 => VectorLessThan {
+            shape: I8x16,
+            signed: Some(false),
+        },
+        39 => VectorGreaterThan {
+            shape: I8x16,
+            signed: Some(true),
+        },
+        40 => VectorGreaterThan {
+            shape: I8x16,
+            signed: Some(false),
+        },
+        41 => VectorLessOrEqual {
+            shape: I8x16,
+            signed: Some(true),
+        },
+        42 => VectorLessOrEqual {
+            shape: I8x16,
+            signed: Some(false),
+        },
+        43 => VectorGreaterOrEqual {
+            shape: I8x16,
+            signed: Some(true),
+        },
+        44 => VectorGreaterOrEqual {
+            shape: I8x16,
+            signed: Some(false),
+        },
+        45 => VectorEqual(I16x8),
+        46 => VectorNotEqual(I16x8),
+        47 => VectorLessThan {
+            shape: I16x8,
+            signed: Some(true),
+        },
+        48 => VectorLessThan {
+            shape: I16x8,
+            signed: Some(false),
+        },
+        49 => VectorGreaterThan {
+            shape: I16x8,
+            signed: Some(true),
+        },
+        50 => VectorGreaterThan {
+            shape: I16x8,
+            signed: Some(false),
+        },
+        51 => VectorLessOrEqual {
+            shape: I16x8,
+            signed: Some(true),
+        },
+        52 => VectorLessOrEqual {
+            shape: I16x8,
+            signed: Some(false),
+        },
+        53 => VectorGreaterOrEqual {
+            shape: I16x8,
+            signed: Some(true),
+        },
+        54 => VectorGreaterOrEqual {
+            shape: I16x8,
+            signed: Some(false),
+        },
+        55 => VectorEqual(I32x4),
+        56 => VectorNotEqual(I32x4),
+        57 => VectorLessThan {
+            shape: I32x4,
+            signed: Some(true),
+        },
+        58 => VectorLessThan {
+            shape: I32x4,
+            signed: Some(false),
+        },
+        59 => VectorGreaterThan {
+            shape: I32x4,
+            signed: Some(true),
+        },
+        60 => VectorGreaterThan {
+            shape: I32x4,
+            signed: Some(false),
+        },
+        61 => VectorLessOrEqual {
+            shape: I32x4,
+            signed: Some(true),
+        },
+        62 => VectorLessOrEqual {
+            shape: I32x4,
+            signed: Some(false),
+        },
+        63 => VectorGreaterOrEqual {
+            shape: I32x4,
+            signed: Some(true),
+        },
+        64 => VectorGreaterOrEqual {
+            shape: I32x4,
+            signed: Some(false),
+        },
+        65 => VectorEqual(F32x4),
+        66 => VectorNotEqual(F32x4),
+        67 => VectorLessThan {
+            shape: F32x4,
+            signed: None,
+        },
+        68 => VectorGreaterThan {
+            shape: F32x4,
+            signed: None,
+        },
+        69 => VectorLessOrEqual {
+            shape: F32x4,
+            signed: None,
+        },
+        70 => VectorGreaterOrEqual {
+            shape: F32x4,
+            signed: None,
+        },
+        71 => VectorEqual(F64x2),
+        72 => VectorNotEqual(F64x2),
+        73 => VectorLessThan {
+            shape: F64x2,
+            signed: None,
+        },
+        74 => VectorGreaterThan {
+            shape: F64x2,
+            signed: None,
+        },
+        75 => VectorLessOrEqual {
+            shape: F64x2,
+            signed: None,
+        },
+        76 => VectorGreaterOrEqual {
+            shape: F64x2,
+            signed: None,
+        },
+        77 => V128Not,
+        78 => V128And,
+        79 => V128AndNot,
+        80 => V128Or,
+        81 => V128Xor,
+        82 => V128BitSelect,
+        83 => V128AnyTrue,
+        84 => V128LoadLane {
+            mem: MemoryArgument::decode(reader)?,
+            shape: I8x16,
+            lane: types::decode_byte(reader)?,
+        },
+        85 => V128LoadLane {
+            mem: MemoryArgument::decode(reader)?,
+            shape: I16x8,
+            lane: types::decode_byte(reader)?,
+        },
+        86 => V128LoadLane {
+            mem: MemoryArgument::decode(reader)?,
+            shape: I32x4,
+            lane: types::decode_byte(reader)?,
+        },
+        87 => V128LoadLane {
+            mem: MemoryArgument::decode(reader)?,
+            shape: I64x2,
+            lane: types::decode_byte(reader)?,
+        },
+        88 => V128StoreLane {
+            mem: MemoryArgument::decode(reader)?,
+            shape: I8x16,
+            lane: types::decode_byte(reader)?,
+        },
+        89 => V128StoreLane {
+            mem: MemoryArgument::decode(reader)?,
+            shape: I16x8,
+            lane: types::decode_byte(reader)?,
+        },
+        90 => V128StoreLane {
+            mem: MemoryArgument::decode(reader)?,
+            shape: I32x4,
+            lane: types::decode_byte(reader)?,
+        },
+        91 => V128StoreLane {
+            mem: MemoryArgument::decode(reader)?,
+            shape: I64x2,
+            lane: types::decode_byte(reader)?,
+        },
+        92 => V128LoadZero {
+            mem: MemoryArgument::decode(reader)?,
+            shape: I32x4,
+        },
+        93 => V128LoadZero {
+            mem: MemoryArgument::decode(reader)?,
+            shape: I64x2,
+        },
+        94 => F32x4DemoteF64x2Zero,
+        95 => F64x2PromoteLowF32x4,
+        96 => VectorAbsolute(I8x16),
+        97 => VectorNegate(I8x16),
+        98 => I8x16CountOnes,
+        99 => VectorAllTrue(I8x16),
+        100 => VectorBitmask(I8x16),
+        101 => VectorNarrow {
+            shape: I8x16,
+            signed: true,
+        },
+        102 => VectorNarrow {
+            shape: I8x16,
+            signed: false,
+        },
+        103 => VectorCeil(F32x4),
+        104 => VectorFloor(F32x4),
+        105 => VectorTruncate(F32x4),
+        106 => VectorNearest(F32x4),
+        107 => VectorShiftLeft(I8x16),
+        108 => VectorShiftRight {
+            shape: I8x16,
+            signed: true,
+        },
+        109 => VectorShiftRight {
+            shape: I8x16,
+            signed: false,
+        },
+        110 => VectorAdd(I8x16),
+        111 => VectorAddSaturate {
+            shape: I8x16,
+            signed: true,
+        },
+        112 => VectorAddSaturate {
+            shape: I8x16,
+            signed: false,
+        },
+        113 => VectorSubtract(I8x16),
+        114 => VectorSubtractSaturate {
+            shape: I8x16,
+            signed: true,
+        },
+        115 => VectorSubtractSaturate {
+            shape: I8x16,
+            signed: false,
+        },
+        116 => VectorCeil(F64x2),
+        117 => VectorFloor(F64x2),
+        118 => VectorMinimum {
+            shape: I8x16,
+            signed: Some(true),
+        },
+        119 => VectorMinimum {
+            shape: I8x16,
+            signed: Some(false),
+        },
+        120 => VectorMaximum {
+            shape: I8x16,
+            signed: Some(true),
+        },
+        121 => VectorMaximum {
+            shape: I8x16,
+            signed: Some(false),
+        },
+        122 => VectorTruncate(F64x2),
+        123 => VectorAverage(I8x16),
+        124 => VectorExtAddPairwise {
+            shape: I16x8,
+            signed: true,
+        },
+        125 => VectorExtAddPairwise {
+            shape: I16x8,
+            signed: false,
+        },
+        126 => VectorExtAddPairwise {
+            shape: I32x4,
+            signed: true,
+        },
+        127 => VectorExtAddPairwise {
+            shape: I32x4,
+            signed: false,
+        },
+        128 => VectorAbsolute(I16x8),
+        129 => VectorNegate(I16x8),
+        130 => I16x8Q15MulrSat,
+        131 => VectorAllTrue(I16x8),
+        132 => VectorBitmask(I16x8),
+        133 => VectorNarrow {
+            shape: I16x8,
+            signed: true,
+        },
+        134 => VectorNarrow {
+            shape: I16x8,
+            signed: false,
+        },
+        135 => VectorExtend {
+            shape: I16x8,
+            high: false,
+            signed: true,
+        },
+        136 => VectorExtend {
+            shape: I16x8,
+            high: true,
+            signed: true,
+        },
+        137 => VectorExtend {
+            shape: I16x8,
+            high: false,
+            signed: false,
+        },
+        138 => VectorExtend {
+            shape: I16x8,
+            high: true,
+            signed: false,
+        },
+        139 => VectorShiftLeft(I16x8),
+        140 => VectorShiftRight {
+            shape: I16x8,
+            signed: true,
+        },
+        141 => VectorShiftRight {
+            shape: I16x8,
+            signed: false,
+        },
+        142 => VectorAdd(I16x8),
+        143 => VectorAddSaturate {
+            shape: I16x8,
+            signed: true,
+        },
+        144 => VectorAddSaturate {
+            shape: I16x8,
+            signed: false,
+        },
+        145 => VectorSubtract(I16x8),
+        146 => VectorSubtractSaturate {
+            shape: I16x8,
+            signed: true,
+        },
+        147 => VectorSubtractSaturate {
+            shape: I16x8,
+            signed: false,
+        },
+        148 => VectorNearest(F64x2),
+        149 => VectorMultiply(I16x8),
+        150 => VectorMinimum {
+            shape: I16x8,
+            signed: Some(true),
+        },
+        151 => VectorMinimum {
+            shape: I16x8,
+            signed: Some(false),
+        },
+        152 => VectorMaximum {
+            shape: I16x8,
+            signed: Some(true),
+        },
+        153 => VectorMaximum {
+            shape: I16x8,
+            signed: Some(false),
+        },
+        155 => VectorAverage(I16x8),
+        156 => VectorExtMultiply {
+            shape: I16x8,
+            high: false,
+            signed: true,
+        },
+        157 => VectorExtMultiply {
+            shape: I16x8,
+            high: true,
+            signed: true,
+        },
+        158 => VectorExtMultiply {
+            shape: I16x8,
+            high: false,
+            signed: false,
+        },
+        159 => VectorExtMultiply {
+            shape: I16x8,
+            high: true,
+            signed: false,
+        },
+        160 => VectorAbsolute(I32x4),
+        161 => VectorNegate(I32x4),
+        163 => VectorAllTrue(I32x4),
+        164 => VectorBitmask(I32x4),
+        167 => VectorExtend {
+            shape: I32x4,
+            high: false,
+            signed: true,
+        },
+        168 => VectorExtend {
+            shape: I32x4,
+            high: true,
+            signed: true,
+        },
+        169 => VectorExtend {
+            shape: I32x4,
+            high: false,
+            signed: false,
+        },
+        170 => VectorExtend {
+            shape: I32x4,
+            high: true,
+            signed: false,
+        },
+        171 => VectorShiftLeft(I32x4),
+        172 => VectorShiftRight {
+            shape: I32x4,
+            signed: true,
+        },
+        173 => VectorShiftRight {
+            shape: I32x4,
+            signed: false,
+        },
+        174 => VectorAdd(I32x4),
+        177 => VectorSubtract(I32x4),
+        181 => VectorMultiply(I32x4),
+        182 => VectorMinimum {
+            shape: I32x4,
+            signed: Some(true),
+        },
+        183 => VectorMinimum {
+            shape: I32x4,
+            signed: Some(false),
+        },
+        184 => VectorMaximum {
+            shape: I32x4,
+            signed: Some(true),
+        },
+        185 => VectorMaximum {
+            shape: I32x4,
+            signed: Some(false),
+        },
+        186 => I32x4DotI16x8,
+        188 => VectorExtMultiply {
+            shape: I32x4,
+            high: false,
+            signed: true,
+        },
+        189 => VectorExtMultiply {
+            shape: I32x4,
+            high: true,
+            signed: true,
+        },
+        190 => VectorExtMultiply {
+            shape: I32x4,
+            high: false,
+            signed: false,
+        },
+        191 => VectorExtMultiply {
+            shape: I32x4,
+            high: true,
+            signed: false,
+        },
+        192 => VectorAbsolute(I64x2),
+        193 => VectorNegate(I64x2),
+        195 => VectorAllTrue(I64x2),
+        196 => VectorBitmask(I64x2),
+        199 => VectorExtend {
+            shape: I64x2,
+            high: false,
+            signed: true,
+        },
+        200 => VectorExtend {
+            shape: I64x2,
+            high: true,
+            signed: true,
+        },
+        201 => VectorExtend {
+            shape: I64x2,
+            high: false,
+            signed: false,
+        },
+        202 => VectorExtend {
+            shape: I64x2,
+            high: true,
+            signed: false,
+        },
+        203 => VectorShiftLeft(I64x2),
+        204 => VectorShiftRight {
+            shape: I64x2,
+            signed: true,
+        },
+        205 => VectorShiftRight {
+            shape: I64x2,
+            signed: false,
+        },
+        206 => VectorAdd(I64x2),
+        209 => VectorSubtract(I64x2),
+        213 => VectorMultiply(I64x2),
+        214 => VectorEqual(I64x2),
+        215 => VectorNotEqual(I64x2),
+        216 => VectorLessThan {
+            shape: I64x2,
+            signed: Some(true),
+        },
+        217 => VectorGreaterThan {
+            shape: I64x2,
+            signed: Some(true),
+        },
+        218 => VectorLessOrEqual {
+            shape: I64x2,
+            signed: Some(true),
+        },
+        219 => VectorGreaterOrEqual {
+            shape: I64x2,
+            signed: Some(true),
+        },
+        220 => VectorExtMultiply {
+            shape: I64x2,
+            high: false,
+            signed: true,
+        },
+        221 => VectorExtMultiply {
+            shape: I64x2,
+            high: true,
+            signed: true,
+        },
+        222 => VectorExtMultiply {
+            shape: I64x2,
+            high: false,
+            signed: false,
+        },
+        223 => VectorExtMultiply {
+            shape: I64x2,
+            high: true,
+            signed: false,
+        },
+        224 => VectorAbsolute(F32x4),
+        225 => VectorNegate(F32x4),
+        227 => VectorSquareRoot(F32x4),
+        228 => VectorAdd(F32x4),
+        229 => VectorSubtract(F32x4),
+        230 => VectorMultiply(F32x4),
+        231 => VectorDivision(F32x4),
+        232 => VectorMinimum {
+            shape: F32x4,
+            signed: None,
+        },
+        233 => VectorMaximum {
+            shape: F32x4,
+            signed: None,
+        },
+        234 => VectorPseudoMinimum(F32x4),
+        235 => VectorPseudoMaximum(F32x4),
+        236 => VectorAbsolute(F64x2),
+        237 => VectorNegate(F64x2),
+        239 => VectorSquareRoot(F64x2),
+        240 => VectorAdd(F64x2),
+        241 => VectorSubtract(F64x2),
+        242 => VectorMultiply(F64x2),
+        243 => VectorDivision(F64x2),
+        244 => VectorMinimum {
+            shape: F64x2,
+            signed: None,
+        },
+        245 => VectorMaximum {
+            shape: F64x2,
+            signed: None,
+        },
+        246 => VectorPseudoMinimum(F64x2),
+        247 => VectorPseudoMaximum(F64x2),
+        248 => VectorTruncateSaturate {
+            float: FloatType::F32,
+            signed: true,
+        },
+        249 => VectorTruncateSaturate {
+            float: FloatType::F32,
+            signed: false,
+        },
+        250 => VectorConvert {
+            float: FloatType::F32,
+            signed: true,
+        },
+        251 => VectorConvert {
+            float: FloatType::F32,
+            signed: false,
+        },
+        252 => VectorTruncateSaturate {
+            float: FloatType::F64,
+            signed: true,
+        },
+        253 => VectorTruncateSaturate {
+            float: FloatType::F64,
+            signed: false,
+        },
+        254 => VectorConvert {
+            float: FloatType::F64,
+            signed: true,
+        },
+        255 => VectorConvert {
+            float: FloatType::F64,
+            signed: false,
+        },
+        op => {
+            return Err(types::malformed(format!(
+                "invalid opcode 0xFD 0x{:02X}",
+                op
+            )))
+        }
+    })
+}
+
+impl Instruction {
+    /// Decodes the instruction starting with `opcode`, nested blocks are decoded recursively
+    /// one level deeper than `depth`
+    pub(crate) fn decode(opcode: u8, reader: &mut impl Read, depth: usize) -> Result<Self> {
+        match opcode {
+            0x02 | 0x03 | 0x04 | 0x06 | 0x1F => Self::decode_block(opcode, reader, depth + 1),
+            _ => Self::decode_flat(opcode, reader),
+        }
+    }
+
+    /// Decodes a block, loop, if, try or try_table whose body is at `depth`
+    fn decode_block(opcode: u8, reader: &mut impl Read, depth: usize) -> Result<Self> {
+        use Instruction::*;
+
+        Ok(match opcode {
+            0x02 => Block {
+                ty: BlockType::decode(reader)?,
+                instrs: decode_block_body(reader, depth)?,
+            },
+            0x03 => Loop {
+                ty: BlockType::decode(reader)?,
+                instrs: decode_block_body(reader, depth)?,
+            },
+            0x04 => {
+                let ty = BlockType::decode(reader)?;
+                let (accept_instrs, terminator) = decode_instrs(reader, depth)?;
+                let reject_instrs = match terminator {
+                    Terminator::End => None,
+                    Terminator::Else => Some(decode_block_body(reader, depth)?),
+                    terminator => return Err(terminator.unexpected()),
+                };
+                If {
+                    ty,
+                    accept_instrs,
+                    reject_instrs,
+                }
+            }
+            0x06 => {
+                let ty = BlockType::decode(reader)?;
+                let (instrs, handler) = decode_try(reader, depth)?;
+                Try {
+                    ty,
+                    instrs,
+                    handler,
+                }
+            }
+            0x1F => TryTable {
+                ty: BlockType::decode(reader)?,
+                catches: types::decode_vec(reader, Catch::decode)?,
+                instrs: decode_block_body(reader, depth)?,
+            },
+            op => unreachable!("0x{:02X} is not a block opcode", op),
+        })
+    }
+
+    /// Decodes an instruction without a nested body
+    ///
+    /// Kept out of line so that its large frame isn't part of every level of the recursion
+    /// through nested blocks
+    #[inline(never)]
+    fn decode_flat(opcode: u8, reader: &mut impl Read) -> Result<Self> {
+        use Instruction::*;
+
+        let instr = match opcode {
+            0x00 => Unreachable,
+            0x01 => NOP,
+            0x08 => Throw(types::decode_u32(reader)?),
+            0x09 => Rethrow(types::decode_u32(reader)?),
+            0x0A => ThrowRef,
+            0x0C => Branch(types::decode_u32(reader)?),
+            0x0D => BranchIf(types::decode_u32(reader)?),
+            0x0E => BranchTable {
+                labels: types::decode_vec(reader, types::decode_u32)?,
+                operand: types::decode_u32(reader)?,
+            },
+            0x0F => Return,
+            0x10 => Call(types::decode_u32(reader)?),
+            0x11 => CallIndirect {
+                ty: types::decode_u32(reader)?,
+                table: types::decode_u32(reader)?,
+            },
+            0x12 => ReturnCall(types::decode_u32(reader)?),
+            0x13 => ReturnCallIndirect {
+                ty: types::decode_u32(reader)?,
+                table: types::decode_u32(reader)?,
+            },
+            0x14 => CallRef(types::decode_u32(reader)?),
+            0x15 => ReturnCallRef(types::decode_u32(reader)?),
+            0x1A => Drop,
+            0x1B => Select,
+            0x1C => TypedSelect(types::decode_result_type(reader)?),
+            0x20 => LocalGet(types::decode_u32(reader)?),
+            0x21 => LocalSet(types::decode_u32(reader)?),
+            0x22 => LocalTee(types::decode_u32(reader)?),
+            0x23 => GlobalGet(types::decode_u32(reader)?),
+            0x24 => GlobalSet(types::decode_u32(reader)?),
+            0x25 => TableGet(types::decode_u32(reader)?),
+            0x26 => TableSet(types::decode_u32(reader)?),
+            0x28..=0x35 => {
+                let (ty, storage) = match opcode {
+                    0x28 => (ValType::I32, None),
+                    0x29 => (ValType::I64, None),
+                    0x2A => (ValType::F32, None),
+                    0x2B => (ValType::F64, None),
+                    0x2C => (ValType::I32, Some((true, StorageType::I8))),
+                    0x2D => (ValType::I32, Some((false, StorageType::I8))),
+                    0x2E => (ValType::I32, Some((true, StorageType::I16))),
+                    0x2F => (ValType::I32, Some((false, StorageType::I16))),
+                    0x30 => (ValType::I64, Some((true, StorageType::I8))),
+                    0x31 => (ValType::I64, Some((false, StorageType::I8))),
+                    0x32 => (ValType::I64, Some((true, StorageType::I16))),
+                    0x33 => (ValType::I64, Some((false, StorageType::I16))),
+                    0x34 => (ValType::I64, Some((true, StorageType::I32))),
+                    _ => (ValType::I64, Some((false, StorageType::I32))),
+                };
+                Load {
+                    mem: MemoryArgument::decode(reader)?,
+                    ty,
+                    storage,
+                }
+            }
+            0x36..=0x3E => {
+                let (ty, storage) = match opcode {
+                    0x36 => (ValType::I32, None),
+                    0x37 => (ValType::I64, None),
+                    0x38 => (ValType::F32, None),
+                    0x39 => (ValType::F64, None),
+                    0x3A => (ValType::I32, Some(StorageType::I8)),
+                    0x3B => (ValType::I32, Some(StorageType::I16)),
+                    0x3C => (ValType::I64, Some(StorageType::I8)),
+                    0x3D => (ValType::I64, Some(StorageType::I16)),
+                    _ => (ValType::I64, Some(StorageType::I32)),
+                };
+                Store {
+                    mem: MemoryArgument::decode(reader)?,
+                    ty,
+                    storage,
+                }
+            }
+            0x3F => MemorySize(types::decode_u32(reader)?),
+            0x40 => MemoryGrow(types::decode_u32(reader)?),
+            0x41 => Const(Literal::I32(types::decode_i32(reader)?)),
+            0x42 => Const(Literal::I64(types::decode_i64(reader)?)),
+            0x43 => Const(Literal::F32(types::decode_f32(reader)?)),
+            0x44 => Const(Literal::F64(types::decode_f64(reader)?)),
+            0x45 => EqualZero(IntegerType::I32),
+            0x46 => Equal(ValType::I32),
+            0x47 => NotEqual(ValType::I32),
+            0x48 => LessThanI32 {
+                ty: IntegerType::I32,
+                signed: true,
+            },
+            0x49 => LessThanI32 {
+                ty: IntegerType::I32,
+                signed: false,
+            },
+            0x4A => GreaterThanI32 {
+                ty: IntegerType::I32,
+                signed: true,
+            },
+            0x4B => GreaterThanI32 {
+                ty: IntegerType::I32,
+                signed: false,
+            },
+            0x4C => LessOrEqualI32 {
+                ty: IntegerType::I32,
+                signed: true,
+            },
+            0x4D => LessOrEqualI32 {
+                ty: IntegerType::I32,
+                signed: false,
+            },
+            0x4E => GreaterOrEqualI32 {
+                ty: IntegerType::I32,
+                signed: true,
+            },
+            0x4F => GreaterOrEqualI32 {
+                ty: IntegerType::I32,
+                signed: false,
+            },
+            0x50 => EqualZero(IntegerType::I64),
+            0x51 => Equal(ValType::I64),
+            0x52 => NotEqual(ValType::I64),
+            0x53 => LessThanI32 {
+                ty: IntegerType::I64,
+                signed: true,
+            },
+            0x54 => LessThanI32 {
+                ty: IntegerType::I64,
+                signed: false,
+            },
+            0x55 => GreaterThanI32 {
+                ty: IntegerType::I64,
+                signed: true,
+            },
+            0x56 => GreaterThanI32 {
+                ty: IntegerType::I64,
+                signed: false,
+            },
+            0x57 => LessOrEqualI32 {
+                ty: IntegerType::I64,
+                signed: true,
+            },
+            0x58 => LessOrEqualI32 {
+                ty: IntegerType::I64,
+                signed: false,
+            },
+            0x59 => GreaterOrEqualI32 {
+                ty: IntegerType::I64,
+                signed: true,
+            },
+            0x5A => GreaterOrEqualI32 {
+                ty: IntegerType::I64,
+                signed: false,
+            },
+            0x5B => Equal(ValType::F32),
+            0x5C => NotEqual(ValType::F32),
+            0x5D => LessThanFloat(FloatType::F32),
+            0x5E => GreaterThanFloat(FloatType::F32),
+            0x5F => LessOrEqualFloat(FloatType::F32),
+            0x60 => GreaterOrEqualFloat(FloatType::F32),
+            0x61 => Equal(ValType::F64),
+            0x62 => NotEqual(ValType::F64),
+            0x63 => LessThanFloat(FloatType::F64),
+            0x64 => GreaterThanFloat(FloatType::F64),
+            0x65 => LessOrEqualFloat(FloatType::F64),
+            0x66 => GreaterOrEqualFloat(FloatType::F64),
+            0x67 => CountLeadingZero(IntegerType::I32),
+            0x68 => CountTrailingZero(IntegerType::I32),
+            0x69 => CountOnes(IntegerType::I32),
+            0x6A => Add(ValType::I32),
+            0x6B => Subtract(ValType::I32),
+            0x6C => Multiply(ValType::I32),
+            0x6D => I32Division {
+                ty: IntegerType::I32,
+                signed: true,
+            },
+            0x6E => I32Division {
+                ty: IntegerType::I32,
+                signed: false,
+            },
+            0x6F => Remainder {
+                ty: IntegerType::I32,
+                signed: true,
+            },
+            0x70 => Remainder {
+                ty: IntegerType::I32,
+                signed: false,
+            },
+            0x71 => And(IntegerType::I32),
+            0x72 => Or(IntegerType::I32),
+            0x73 => Xor(IntegerType::I32),
+            0x74 => ShiftLeft(IntegerType::I32),
+            0x75 => ShiftRight {
+                ty: IntegerType::I32,
+                signed: true,
+            },
+            0x76 => ShiftRight {
+                ty: IntegerType::I32,
+                signed: false,
+            },
+            0x77 => LeftRotation(IntegerType::I32),
+            0x78 => RightRotation(IntegerType::I32),
+            0x79 => CountLeadingZero(IntegerType::I64),
+            0x7A => CountTrailingZero(IntegerType::I64),
+            0x7B => CountOnes(IntegerType::I64),
+            0x7C => Add(ValType::I64),
+            0x7D => Subtract(ValType::I64),
+            0x7E => Multiply(ValType::I64),
+            0x7F => I32Division {
+                ty: IntegerType::I64,
+                signed: true,
+            },
+            0x80 => I32Division {
+                ty: IntegerType::I64,
+                signed: false,
+            },
+            0x81 => Remainder {
+                ty: IntegerType::I64,
+                signed: true,
+            },
+            0x82 => Remainder {
+                ty: IntegerType::I64,
+                signed: false,
+            },
+            0x83 => And(IntegerType::I64),
+            0x84 => Or(IntegerType::I64),
+            0x85 => Xor(IntegerType::I64),
+            0x86 => ShiftLeft(IntegerType::I64),
+            0x87 => ShiftRight {
+                ty: IntegerType::I64,
+                signed: true,
+            },
+            0x88 => ShiftRight {
+                ty: IntegerType::I64,
+                signed: false,
+            },
+            0x89 => LeftRotation(IntegerType::I64),
+            0x8A => RightRotation(IntegerType::I64),
+            0x8B => Absolute(FloatType::F32),
+            0x8C => Negate(FloatType::F32),
+            0x8D => Ceil(FloatType::F32),
+            0x8E => Floor(FloatType::F32),
+            0x8F => Truncate(FloatType::F32),
+            0x90 => Nearest(FloatType::F32),
+            0x91 => SquareRoot(FloatType::F32),
+            0x92 => Add(ValType::F32),
+            0x93 => Subtract(ValType::F32),
+            0x94 => Multiply(ValType::F32),
+            0x95 => FloatDivision(FloatType::F32),
+            0x96 => Minimum(FloatType::F32),
+            0x97 => Maximum(FloatType::F32),
+            0x98 => CopySign(FloatType::F32),
+            0x99 => Absolute(FloatType::F64),
+            0x9A => Negate(FloatType::F64),
+            0x9B => Ceil(FloatType::F64),
+            0x9C => Floor(FloatType::F64),
+            0x9D => Truncate(FloatType::F64),
+            0x9E => Nearest(FloatType::F64),
+            0x9F => SquareRoot(FloatType::F64),
+            0xA0 => Add(ValType::F64),
+            0xA1 => Subtract(ValType::F64),
+            0xA2 => Multiply(ValType::F64),
+            0xA3 => FloatDivision(FloatType::F64),
+            0xA4 => Minimum(FloatType::F64),
+            0xA5 => Maximum(FloatType::F64),
+            0xA6 => CopySign(FloatType::F64),
+            0xA7 => I32Wrap,
+            0xA8..=0xAB | 0xAE..=0xB1 => {
+                let ty = if opcode < 0xAE {
+                    IntegerType::I32
+                } else {
+                    IntegerType::I64
+                };
+                let (float, signed) = match opcode {
+                    0xA8 | 0xAE => (FloatType::F32, true),
+                    0xA9 | 0xAF => (FloatType::F32, false),
+                    0xAA | 0xB0 => (FloatType::F64, true),
+                    _ => (FloatType::F64, false),
+                };
+                I32Truncate { ty, float, signed }
+            }
+            0xAC => I32Extend(true),
+            0xAD => I32Extend(false),
+            0xB2..=0xB5 | 0xB7..=0xBA => {
+                let ty = if opcode < 0xB6 {
+                    FloatType::F32
+                } else {
+                    FloatType::F64
+                };
+                let (tgt_ty, signed) = match opcode {
+                    0xB2 | 0xB7 => (IntegerType::I32, true),
+                    0xB3 | 0xB8 => (IntegerType::I32, false),
+                    0xB4 | 0xB9 => (IntegerType::I64, true),
+                    _ => (IntegerType::I64, false),
+                };
+                Convert { ty, tgt_ty, signed }
+            }
+            0xB6 => FloatDemote,
+            0xBB => FloatPromote,
+            0xBC => I32ReI32erpret,
+            0xBD => LongReI32erpret,
+            0xBE => FloatReI32erpret,
+            0xBF => DoubleReI32erpret,
+            0xC0 => Extend {
+                ty: IntegerType::I32,
+                base: StorageType::I8,
+            },
+            0xC1 => Extend {
+                ty: IntegerType::I32,
+                base: StorageType::I16,
+            },
+            0xC2 => Extend {
+                ty: IntegerType::I64,
+                base: StorageType::I8,
+            },
+            0xC3 => Extend {
+                ty: IntegerType::I64,
+                base: StorageType::I16,
+            },
+            0xC4 => Extend {
+                ty: IntegerType::I64,
+                base: StorageType::I32,
+            },
+            0xD0 => RefNull(types::HeapType::decode(reader)?),
+            0xD1 => RefIsNull,
+            0xD2 => RefFunc(types::decode_u32(reader)?),
+            0xD3 => RefEq,
+            0xD4 => RefAsNonNull,
+            0xD5 => BranchOnNull(types::decode_u32(reader)?),
+            0xD6 => BranchOnNonNull(types::decode_u32(reader)?),
+            0xFB => decode_gc(reader)?,
+            0xFD => decode_simd(reader)?,
+            0xFE => decode_atomic(reader)?,
+            0xFC => {
+                let (ty, float, signed) = match types::decode_u32(reader)? {
+                    0x08 => {
+                        return Ok(MemoryInit {
+                            data: types::decode_u32(reader)?,
+                            mem: types::decode_u32(reader)?,
+                        })
+                    }
+                    0x09 => return Ok(DataDrop(types::decode_u32(reader)?)),
+                    0x0A => {
+                        return Ok(MemoryCopy {
+                            dst: types::decode_u32(reader)?,
+                            src: types::decode_u32(reader)?,
+                        })
+                    }
+                    0x0B => return Ok(MemoryFill(types::decode_u32(reader)?)),
+                    0x0C => {
+                        return Ok(TableInit {
+                            elem: types::decode_u32(reader)?,
+                            table: types::decode_u32(reader)?,
+                        })
+                    }
+                    0x0D => return Ok(ElemDrop(types::decode_u32(reader)?)),
+                    0x0E => {
+                        return Ok(TableCopy {
+                            dst: types::decode_u32(reader)?,
+                            src: types::decode_u32(reader)?,
+                        })
+                    }
+                    0x0F => return Ok(TableGrow(types::decode_u32(reader)?)),
+                    0x10 => return Ok(TableSize(types::decode_u32(reader)?)),
+                    0x11 => return Ok(TableFill(types::decode_u32(reader)?)),
+                    0x00 => (IntegerType::I32, FloatType::F32, true),
+                    0x01 => (IntegerType::I32, FloatType::F32, false),
+                    0x02 => (IntegerType::I32, FloatType::F64, true),
+                    0x03 => (IntegerType::I32, FloatType::F64, false),
+                    0x04 => (IntegerType::I64, FloatType::F32, true),
+                    0x05 => (IntegerType::I64, FloatType::F32, false),
+                    0x06 => (IntegerType::I64, FloatType::F64, true),
+                    0x07 => (IntegerType::I64, FloatType::F64, false),
+                    op => {
+                        return Err(types::malformed(format!(
+                            "invalid opcode 0xFC 0x{:02X}",
+                            op
+                        )))
+                    }
+                };
+                SaturateTruncate { ty, float, signed }
+            }
+            _ => return Err(types::malformed(format!("invalid opcode 0x{:02X}", opcode))),
+        };
+
+        Ok(instr)
+    }
+}
+
+/// Whether any of the instructions, including nested ones, refers to a data segment
+///
+/// Such code can only be decoded if the module has a data count section
+pub(crate) fn uses_data_count(instrs: &[Instruction]) -> bool {
+    instrs.iter().any(|instr| match instr {
+        Instruction::MemoryInit { .. }
+        | Instruction::DataDrop(_)
+        | Instruction::ArrayNewData { .. }
+        | Instruction::ArrayInitData { .. } => true,
+        Instruction::Block { instrs, .. } | Instruction::Loop { instrs, .. } => {
+            uses_data_count(instrs)
+        }
+        Instruction::If {
+            accept_instrs,
+            reject_instrs,
+            ..
+        } => {
+            uses_data_count(accept_instrs) || reject_instrs.as_deref().is_some_and(uses_data_count)
+        }
+        Instruction::TryTable { instrs, .. } => uses_data_count(instrs),
+        Instruction::Try {
+            instrs, handler, ..
+        } => {
+            uses_data_count(instrs)
+                || match handler {
+                    TryHandler::Catches { catches, catch_all } => {
+                        catches.iter().any(|(_, instrs)| uses_data_count(instrs))
+                            || catch_all.as_deref().is_some_and(uses_data_count)
+                    }
+                    TryHandler::Delegate(_) => false,
+                }
+        }
+        _ => false,
+    })
 }
 
 /// Expressions are encoded instruction sequences terminated by an end opcode (0x0B)
@@ -705,15 +3586,91 @@ impl Instruction {
 pub struct Expr(pub Vec<Instruction>);
 
 impl Expr {
-    pub(crate) fn encode(&self, writer: &mut impl Write) -> io::Result<usize> {
+    pub(crate) fn encode(&self, writer: &mut impl Write) -> Result<usize> {
         let mut length = 0;
 
         for instr in self.0.iter() {
             length += instr.encode(writer)?;
         }
 
-        length += writer.write(&[0x0B])?;
+        length += types::encode_raw(writer, &[0x0B])?;
 
         Ok(length)
     }
+
+    /// Decodes an expression up to and including its terminating end opcode
+    ///
+    /// The nested `Block`, `Loop` and `If` instructions are rebuilt from the flat
+    /// opcode stream, anything after the final end opcode is left unread
+    pub fn decode(reader: &mut impl Read) -> Result<Self> {
+        match decode_instrs(reader, 0)? {
+            (instrs, Terminator::End) => Ok(Expr(instrs)),
+            (_, terminator) => Err(terminator.unexpected()),
+        }
+    }
+
+    /// Decodes an expression that must span the whole of `bytes`
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use wasm_builder::instr::*;
+    /// // block (result i32) i32.const 1 end end
+    /// let expr = Expr::from_bytes(&[0x02, 0x7F, 0x41, 0x01, 0x0B, 0x0B]).unwrap();
+    ///
+    /// assert_eq!(
+    ///     expr,
+    ///     Expr(vec![Instruction::Block {
+    ///         ty: BlockType::Type(wasm_builder::types::ValType::I32),
+    ///         instrs: vec![Instruction::Const(Literal::I32(1))],
+    ///     }])
+    /// );
+    /// ```
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut reader = bytes;
+        let expr = Expr::decode(&mut reader)?;
+        if !reader.is_empty() {
+            return Err(types::malformed(
+                "unexpected bytes after the end of the expression",
+            ));
+        }
+        Ok(expr)
+    }
+
+    /// The constant expression `global.get base` plus `offset`, i.e. for data segments
+    /// placed relative to an imported `__memory_base` global
+    ///
+    /// `ty` must be the type of the global, for `I32` the offset wraps around to 32 bits like
+    /// the addition does. The addition requires the "extended-const" proposal so a zero
+    /// `offset` is left out
+    ///
+    /// ```rust
+    /// # use wasm_builder::instr::*;
+    /// assert_eq!(
+    ///     Expr::global_offset(0, IntegerType::I32, 16),
+    ///     Expr(vec![
+    ///         Instruction::GlobalGet(0),
+    ///         Instruction::Const(Literal::I32(16)),
+    ///         Instruction::Add(wasm_builder::types::ValType::I32),
+    ///     ])
+    /// );
+    /// assert_eq!(
+    ///     Expr::global_offset(0, IntegerType::I64, 0),
+    ///     Expr(vec![Instruction::GlobalGet(0)])
+    /// );
+    /// ```
+    pub fn global_offset(base: GlobalIdx, ty: IntegerType, offset: i64) -> Self {
+        if offset == 0 {
+            return Expr(vec![Instruction::GlobalGet(base)]);
+        }
+        let offset = match ty {
+            IntegerType::I32 => Literal::I32(offset as i32),
+            IntegerType::I64 => Literal::I64(offset),
+        };
+        Expr(vec![
+            Instruction::GlobalGet(base),
+            Instruction::Const(offset),
+            Instruction::Add(ty.into()),
+        ])
+    }
 }
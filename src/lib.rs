@@ -2,7 +2,10 @@
 //!
 //! # Example
 //!
-//! ```rust
+//! ```no_run
+//! # use std::{fs, io};
+//! # use wasm_builder::*;
+//! # fn main() -> io::Result<()> {
 //! let mut module = module::Module::new();
 //!
 //! let add = sections::Function {
@@ -14,15 +17,18 @@
 //!     ]),
 //! };
 //!
-//! module.types.push(types::FunctionType {
-//!     parameter_types: vec![types::ValType::F32, types::ValType::F32],
-//!     return_types: vec![types::ValType::F32],
-//! });
+//! module.types.push(
+//!     types::FunctionType {
+//!         parameter_types: vec![types::ValType::F32, types::ValType::F32],
+//!         return_types: vec![types::ValType::F32],
+//!     }
+//!     .into(),
+//! );
 //! module.functions.push(0);
 //! module.code.push(add);
 //! module.exports.push(sections::Export {
 //!     name: String::from("add"),
-//!     desc: sections::Desc::Function(0),
+//!     desc: sections::ExportDesc::Function(0),
 //! });
 //!
 //! let mut file = fs::OpenOptions::new()
@@ -33,9 +39,17 @@
 //! module.encode(&mut file)?;
 //!
 //! Ok(())
+//! # }
 //!```
 
+pub mod builder;
+pub mod error;
+pub mod features;
 pub mod instr;
 pub mod module;
+pub mod parse;
+pub mod print;
 pub mod sections;
+mod typecheck;
 pub mod types;
+mod validate;
@@ -1,5 +1,5 @@
-use crate::{sections, types};
-use std::io::{self, Write};
+use crate::{error::Result, features::Features, instr, sections, types, validate};
+use std::io::{self, Read, Write};
 
 // The WASM magic byte sequence (\0asm) needed in every module
 const MAGIC: [u8; 4] = [0x00, 0x61, 0x73, 0x6D];
@@ -8,23 +8,25 @@ const VERSION: [u8; 4] = [0x01, 0x00, 0x00, 0x00]; // Version 1
 /// Represents a wasm binary module
 ///
 /// The binary encoding of a module is organized into sections.
-
+///
 /// Most sections correspond to one component of a module record,
 /// except that function definitions are split into two sections,
 /// separating their type declarations in the function section from
 /// their bodies in the code section.
 #[derive(Debug, Clone)]
 pub struct Module<'a> {
-    /// types section
-    pub types: Vec<types::FunctionType>,
+    /// types section, every recursion group declares one or more consecutive type indices
+    pub types: Vec<types::RecGroup>,
     /// imports section
     pub imports: Vec<sections::Import>,
     /// functions section
     pub functions: Vec<sections::TypeIdx>,
     /// tables section
-    pub tables: Vec<types::TableType>,
+    pub tables: Vec<sections::Table>,
     /// memory section
     pub memory: Vec<types::MemoryType>,
+    /// tag section, the type index of every tag's parameters
+    pub tags: Vec<sections::TypeIdx>,
     /// globals section
     pub globals: Vec<sections::Global>,
     /// exports section
@@ -37,6 +39,14 @@ pub struct Module<'a> {
     pub code: Vec<sections::Function>,
     /// data section
     pub data: Vec<sections::Data<'a>>,
+    /// custom sections, interleaved with the known sections according to their placement
+    pub custom_sections: Vec<sections::CustomSection<'a>>,
+}
+
+impl<'a> Default for Module<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<'a> Module<'a> {
@@ -48,53 +58,262 @@ impl<'a> Module<'a> {
             functions: vec![],
             tables: vec![],
             memory: vec![],
+            tags: vec![],
             globals: vec![],
             exports: vec![],
             start: None,
             elements: vec![],
             code: vec![],
             data: vec![],
+            custom_sections: vec![],
         }
     }
 
     /// Writes the binary wasm to a type implementing Write
-    pub fn encode(&self, writer: &mut impl Write) -> io::Result<()> {
-        writer.write(&MAGIC)?;
-        writer.write(&VERSION)?;
-        if self.types.len() != 0 {
-            sections::encode_type_section(writer, &self.types)?;
-        }
-        if self.imports.len() != 0 {
-            sections::encode_import_section(writer, &self.imports)?;
-        }
-        if self.functions.len() != 0 {
-            sections::encode_function_section(writer, &self.functions)?;
-        }
-        if self.tables.len() != 0 {
-            sections::encode_table_section(writer, &self.tables)?;
+    ///
+    /// Every proposal supported by the crate may be used, so any decoded module can be
+    /// encoded again. This is more permissive than [`validate`](#method.validate), which only
+    /// allows the default features. Use
+    /// [`encode_with_features`](#method.encode_with_features) to make sure the binary only
+    /// needs the proposals a given engine supports
+    pub fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        self.encode_with_features(writer, &Features::all())
+    }
+
+    /// Writes the binary wasm to a type implementing Write
+    ///
+    /// Fails without writing anything if the module uses a proposal that isn't enabled in `features`
+    pub fn encode_with_features(&self, writer: &mut impl Write, features: &Features) -> Result<()> {
+        features.check_module(self)?;
+
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&VERSION)?;
+        for id in sections::SectionId::ALL {
+            self.encode_custom_sections(writer, sections::Placement::Before(id))?;
+            self.encode_section(writer, id)?;
+            self.encode_custom_sections(writer, sections::Placement::After(id))?;
         }
-        if self.memory.len() != 0 {
-            sections::encode_memory_section(writer, &self.memory)?;
+        self.encode_custom_sections(writer, sections::Placement::End)?;
+
+        Ok(())
+    }
+
+    /// Writes the known section `id`, empty sections are left out
+    fn encode_section(&self, writer: &mut impl Write, id: sections::SectionId) -> Result<()> {
+        use sections::SectionId;
+
+        match id {
+            SectionId::Type if !self.types.is_empty() => {
+                sections::encode_type_section(writer, &self.types)
+            }
+            SectionId::Import if !self.imports.is_empty() => {
+                sections::encode_import_section(writer, &self.imports)
+            }
+            SectionId::Function if !self.functions.is_empty() => {
+                sections::encode_function_section(writer, &self.functions)
+            }
+            SectionId::Table if !self.tables.is_empty() => {
+                sections::encode_table_section(writer, &self.tables)
+            }
+            SectionId::Memory if !self.memory.is_empty() => {
+                sections::encode_memory_section(writer, &self.memory)
+            }
+            SectionId::Tag if !self.tags.is_empty() => {
+                sections::encode_tag_section(writer, &self.tags)
+            }
+            SectionId::Global if !self.globals.is_empty() => {
+                sections::encode_global_section(writer, &self.globals)
+            }
+            SectionId::Export if !self.exports.is_empty() => {
+                sections::encode_export_section(writer, &self.exports)
+            }
+            SectionId::Start => match self.start {
+                Some(start) => sections::encode_start_section(writer, start),
+                None => Ok(()),
+            },
+            SectionId::Element if !self.elements.is_empty() => {
+                sections::encode_element_section(writer, &self.elements)
+            }
+            SectionId::DataCount if self.uses_data_count() => {
+                sections::encode_data_count_section(writer, types::count_to_u32(self.data.len())?)
+            }
+            SectionId::Code if !self.code.is_empty() => {
+                sections::encode_code_section(writer, &self.code)
+            }
+            SectionId::Data if !self.data.is_empty() => {
+                sections::encode_data_section(writer, &self.data)
+            }
+            _ => Ok(()),
         }
-        if self.globals.len() != 0 {
-            sections::encode_global_section(writer, &self.globals)?;
+    }
+
+    /// Writes the custom sections placed at `placement`, in the order they were added
+    fn encode_custom_sections(
+        &self,
+        writer: &mut impl Write,
+        placement: sections::Placement,
+    ) -> Result<()> {
+        for section in self.custom_sections.iter() {
+            if section.placement == placement {
+                section.encode(writer)?;
+            }
         }
-        if self.exports.len() != 0 {
-            sections::encode_export_section(writer, &self.exports)?;
+        Ok(())
+    }
+
+    /// Whether the code refers to data segments, which requires a data count section
+    fn uses_data_count(&self) -> bool {
+        self.code
+            .iter()
+            .any(|func| instr::uses_data_count(&func.body.0))
+    }
+
+    /// Checks that every index used in the module refers to an existing item of the right kind
+    /// and that every function body is well typed
+    ///
+    /// Imported functions, tables, memories and globals come first in their index spaces,
+    /// followed by the ones defined in the module.
+    ///
+    /// Type errors point at the offending instruction, i.e.
+    /// `func 3, block 2, instr 5: expected i32 got f64`
+    ///
+    /// Only the [default features](../features/struct.Features.html#impl-Default) may be used,
+    /// unlike [`encode`](#method.encode) which allows every proposal, so a module that encodes
+    /// may still fail to validate
+    pub fn validate(&self) -> Result<()> {
+        self.validate_with_features(&Features::default())
+    }
+
+    /// Validates the module like [`validate`](#method.validate) allowing only the proposals
+    /// enabled in `features`
+    pub fn validate_with_features(&self, features: &Features) -> Result<()> {
+        validate::validate_module(self, features)
+    }
+
+    /// Parses a binary wasm module
+    ///
+    /// Custom sections are placed after the known section preceding them,
+    /// so encoding the module again keeps them where they were
+    pub fn decode(bytes: &[u8]) -> Result<Module<'static>> {
+        let mut reader = bytes;
+        Module::decode_from(&mut reader)
+    }
+
+    /// Parses a binary wasm module from a type implementing Read
+    ///
+    /// Custom sections keep their position like in [`decode`](#method.decode)
+    pub fn decode_from(reader: &mut impl Read) -> Result<Module<'static>> {
+        let mut header = [0; 8];
+        reader.read_exact(&mut header)?;
+        if header[..4] != MAGIC {
+            return Err(types::malformed("magic header not detected"));
         }
-        if let Some(start) = self.start {
-            sections::encode_start_section(writer, start)?;
+        if header[4..] != VERSION {
+            return Err(types::malformed("unknown binary version"));
         }
-        if self.elements.len() != 0 {
-            sections::encode_element_section(writer, &self.elements)?;
+
+        let mut module = Module::new();
+        let mut last_section: Option<sections::Section> = None;
+        let mut data_count = None;
+
+        while let Some((id, size)) = sections::decode_section_header(reader)? {
+            let section = sections::Section::from_id(id)
+                .ok_or_else(|| types::malformed(format!("unknown section id {}", id)))?;
+
+            let mut buf = Vec::new();
+            reader.take(size as u64).read_to_end(&mut buf)?;
+            if buf.len() != size as usize {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+
+            if section == sections::Section::Custom {
+                let placement = match last_section.and_then(sections::SectionId::from_section) {
+                    Some(id) => sections::Placement::After(id),
+                    None => sections::Placement::Before(sections::SectionId::Type),
+                };
+                let section = sections::CustomSection::decode(&mut &buf[..], placement)?;
+                module.custom_sections.push(section);
+                continue;
+            }
+
+            if let Some(last) = last_section {
+                if section.order() <= last.order() {
+                    return Err(types::malformed(format!(
+                        "unexpected {:?} section after {:?} section",
+                        section, last
+                    )));
+                }
+            }
+            last_section = Some(section);
+
+            let mut payload = &buf[..];
+            match section {
+                sections::Section::Custom => unreachable!(),
+                sections::Section::Type => {
+                    module.types = sections::decode_type_section(&mut payload)?
+                }
+                sections::Section::Import => {
+                    module.imports = sections::decode_import_section(&mut payload)?
+                }
+                sections::Section::Function => {
+                    module.functions = sections::decode_function_section(&mut payload)?
+                }
+                sections::Section::Table => {
+                    module.tables = sections::decode_table_section(&mut payload)?
+                }
+                sections::Section::Memory => {
+                    module.memory = sections::decode_memory_section(&mut payload)?
+                }
+                sections::Section::Tag => module.tags = sections::decode_tag_section(&mut payload)?,
+                sections::Section::Global => {
+                    module.globals = sections::decode_global_section(&mut payload)?
+                }
+                sections::Section::Export => {
+                    module.exports = sections::decode_export_section(&mut payload)?
+                }
+                sections::Section::Start => {
+                    module.start = Some(sections::decode_start_section(&mut payload)?)
+                }
+                sections::Section::Element => {
+                    module.elements = sections::decode_element_section(&mut payload)?
+                }
+                sections::Section::DataCount => {
+                    data_count = Some(sections::decode_data_count_section(&mut payload)?)
+                }
+                sections::Section::Code => {
+                    module.code = sections::decode_code_section(&mut payload)?
+                }
+                sections::Section::Data => {
+                    module.data = sections::decode_data_section(&mut payload)?
+                }
+            }
+
+            if !payload.is_empty() {
+                return Err(types::malformed(format!(
+                    "{:?} section size mismatch",
+                    section
+                )));
+            }
         }
-        if self.code.len() != 0 {
-            sections::encode_code_section(writer, &self.code)?;
+
+        if module.functions.len() != module.code.len() {
+            return Err(types::malformed(
+                "function and code section have inconsistent lengths",
+            ));
         }
-        if self.data.len() != 0 {
-            sections::encode_data_section(writer, &self.data)?;
+
+        match data_count {
+            Some(count) if count as usize != module.data.len() => {
+                return Err(types::malformed(
+                    "data count and data section have inconsistent lengths",
+                ))
+            }
+            None if module.uses_data_count() => {
+                return Err(types::malformed("data count section required"))
+            }
+            _ => {}
         }
 
-        Ok(())
+        Ok(module)
     }
 }
@@ -0,0 +1,2731 @@
+//! Parses modules written in the WebAssembly text format
+//!
+//! Symbolic `$names` are resolved to indices, the names themselves aren't kept
+
+use crate::{
+    error::{Error, Result},
+    instr::{
+        AtomicOp, BlockType, Catch, Expr, FloatType, Instruction, IntegerType, LaneShape, Literal,
+        MemoryArgument, StorageType, TryHandler, MAX_NESTING,
+    },
+    module::Module,
+    print,
+    sections::*,
+    types::{self, ValType},
+};
+use std::{borrow::Cow, collections::HashMap};
+
+/// Parses a module written in the text format
+///
+/// `(module ...)`, `(module binary "...")` and a bare sequence of module fields are accepted
+///
+/// # Example
+///
+/// ```rust
+/// # use wasm_builder::{instr::*, parse, types::ValType};
+/// let module = parse::parse_module(
+///     r#"(module
+///         (func $add (export "add") (param $a i32) (param $b i32) (result i32)
+///             (i32.add (local.get $a) (local.get $b))))"#,
+/// )
+/// .unwrap();
+///
+/// assert_eq!(
+///     module.code[0].body,
+///     Expr(vec![
+///         Instruction::LocalGet(0),
+///         Instruction::LocalGet(1),
+///         Instruction::Add(ValType::I32),
+///     ])
+/// );
+/// ```
+pub fn parse_module(text: &str) -> Result<Module<'static>> {
+    parse(text).map_err(|err| err.into_error(text))
+}
+
+fn parse(text: &str) -> PResult<Module<'static>> {
+    let sexprs = lex(text)?;
+
+    let mut cursor = Cursor::new(&sexprs, 0);
+    if let [Sexpr::List(items, offset)] = &sexprs[..] {
+        if let Some(Sexpr::Atom(Token::Keyword("module"), _)) = items.first() {
+            cursor = Cursor::new(items, *offset);
+            cursor.next();
+            cursor.id();
+
+            if cursor.peek_keyword() == Some("binary") {
+                let offset = cursor.offset();
+                cursor.next();
+                let mut bytes = Vec::new();
+                while !cursor.is_empty() {
+                    bytes.extend_from_slice(cursor.string()?);
+                }
+                return Module::decode(&bytes).map_err(|err| ParseError {
+                    offset,
+                    message: err.to_string(),
+                });
+            }
+        }
+    }
+
+    let mut parser = ModuleParser::default();
+    for field in cursor.rest() {
+        parser.declare(field)?;
+    }
+    for field in cursor.rest() {
+        parser.define_types(field)?;
+    }
+    for field in cursor.rest() {
+        parser.define(field)?;
+    }
+
+    Ok(parser.module)
+}
+
+/// A parse error pointing at a byte offset of the text
+struct ParseError {
+    offset: usize,
+    message: String,
+}
+
+impl ParseError {
+    fn into_error(self, text: &str) -> Error {
+        let before = &text[..self.offset];
+        let line_start = before.rfind('\n').map_or(0, |idx| idx + 1);
+        Error::Parse {
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+            message: self.message,
+        }
+    }
+}
+
+type PResult<T> = std::result::Result<T, ParseError>;
+
+fn error<T>(offset: usize, message: impl Into<String>) -> PResult<T> {
+    Err(ParseError {
+        offset,
+        message: message.into(),
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token<'a> {
+    /// Starts with a lowercase letter (i.e. `i32.add` or `offset=4`)
+    Keyword(&'a str),
+    /// A `$name` without the `$`
+    Id(&'a str),
+    /// Starts with a digit or a sign
+    Number(&'a str),
+    String(Vec<u8>),
+}
+
+/// A token or a parenthesized list, along with the offset where it starts
+#[derive(Debug)]
+enum Sexpr<'a> {
+    Atom(Token<'a>, usize),
+    List(Vec<Sexpr<'a>>, usize),
+}
+
+impl<'a> Sexpr<'a> {
+    fn offset(&self) -> usize {
+        match self {
+            Sexpr::Atom(_, offset) | Sexpr::List(_, offset) => *offset,
+        }
+    }
+}
+
+fn is_idchar(byte: u8) -> bool {
+    matches!(byte,
+        b'0'..=b'9' | b'A'..=b'Z' | b'a'..=b'z' | b'!' | b'#' | b'$' | b'%' | b'&' | b'\''
+        | b'*' | b'+' | b'-' | b'.' | b'/' | b':' | b'<' | b'=' | b'>' | b'?' | b'@' | b'\\'
+        | b'^' | b'_' | b'`' | b'|' | b'~')
+}
+
+/// Splits the text into tokens, grouping them by parentheses and skipping comments
+fn lex(text: &str) -> PResult<Vec<Sexpr<'_>>> {
+    let bytes = text.as_bytes();
+    let mut stack = vec![(Vec::new(), 0)];
+    let mut pos = 0;
+
+    while pos < bytes.len() {
+        let start = pos;
+        match bytes[pos] {
+            b' ' | b'\t' | b'\n' | b'\r' => pos += 1,
+            b';' if bytes.get(pos + 1) == Some(&b';') => {
+                while pos < bytes.len() && bytes[pos] != b'\n' {
+                    pos += 1;
+                }
+            }
+            b'(' if bytes.get(pos + 1) == Some(&b';') => {
+                let mut depth = 0;
+                loop {
+                    match bytes.get(pos..pos + 2) {
+                        Some(b"(;") => {
+                            depth += 1;
+                            pos += 2;
+                        }
+                        Some(b";)") => {
+                            depth -= 1;
+                            pos += 2;
+                            if depth == 0 {
+                                break;
+                            }
+                        }
+                        Some(_) => pos += 1,
+                        None => return error(start, "unterminated block comment"),
+                    }
+                }
+            }
+            b'(' => {
+                stack.push((Vec::new(), start));
+                pos += 1;
+            }
+            b')' => {
+                if stack.len() == 1 {
+                    return error(start, "unexpected closing parenthesis");
+                }
+                let (items, offset) = stack.pop().unwrap();
+                stack.last_mut().unwrap().0.push(Sexpr::List(items, offset));
+                pos += 1;
+            }
+            b'"' => {
+                let (string, end) = lex_string(bytes, pos)?;
+                stack
+                    .last_mut()
+                    .unwrap()
+                    .0
+                    .push(Sexpr::Atom(Token::String(string), start));
+                pos = end;
+            }
+            byte if is_idchar(byte) => {
+                while pos < bytes.len() && is_idchar(bytes[pos]) {
+                    pos += 1;
+                }
+                let word = &text[start..pos];
+                let token = match byte {
+                    b'$' if word.len() > 1 => Token::Id(&word[1..]),
+                    b'a'..=b'z' => Token::Keyword(word),
+                    b'0'..=b'9' | b'+' | b'-' => Token::Number(word),
+                    _ => return error(start, format!("unknown token {}", word)),
+                };
+                stack.last_mut().unwrap().0.push(Sexpr::Atom(token, start));
+            }
+            _ => {
+                let c = text[pos..].chars().next().unwrap();
+                return error(start, format!("unexpected character {:?}", c));
+            }
+        }
+    }
+
+    if stack.len() > 1 {
+        return error(stack.last().unwrap().1, "unclosed parenthesis");
+    }
+    Ok(stack.pop().unwrap().0)
+}
+
+/// Lexes the string starting at `start`, returning its bytes and the offset after it
+fn lex_string(bytes: &[u8], start: usize) -> PResult<(Vec<u8>, usize)> {
+    let hex = |byte: u8| (byte as char).to_digit(16);
+    let mut out = Vec::new();
+    let mut pos = start + 1;
+
+    loop {
+        let byte = match bytes.get(pos) {
+            Some(byte) => *byte,
+            None => return error(start, "unterminated string"),
+        };
+        pos += 1;
+
+        match byte {
+            b'"' => return Ok((out, pos)),
+            b'\\' => {
+                let escape = bytes.get(pos).copied().unwrap_or(0);
+                pos += 1;
+                match escape {
+                    b't' => out.push(b'\t'),
+                    b'n' => out.push(b'\n'),
+                    b'r' => out.push(b'\r'),
+                    b'"' | b'\'' | b'\\' => out.push(escape),
+                    b'u' => {
+                        let end = match bytes[pos..].iter().position(|b| *b == b'}') {
+                            Some(len) if bytes.get(pos) == Some(&b'{') => pos + len,
+                            _ => return error(pos - 2, "malformed unicode escape"),
+                        };
+                        let c = std::str::from_utf8(&bytes[pos + 1..end])
+                            .ok()
+                            .and_then(|digits| strip_underscores(digits, true))
+                            .and_then(|digits| u32::from_str_radix(&digits, 16).ok())
+                            .and_then(std::char::from_u32);
+                        match c {
+                            Some(c) => out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+                            None => return error(pos - 2, "malformed unicode escape"),
+                        }
+                        pos = end + 1;
+                    }
+                    _ => match (hex(escape), bytes.get(pos).and_then(|b| hex(*b))) {
+                        (Some(high), Some(low)) => {
+                            out.push((high * 16 + low) as u8);
+                            pos += 1;
+                        }
+                        _ => return error(pos - 2, "unknown escape sequence"),
+                    },
+                }
+            }
+            0x00..=0x1F | 0x7F => return error(pos - 1, "control character in string"),
+            _ => out.push(byte),
+        }
+    }
+}
+
+/// Removes the underscores separating digits, fails if one isn't between two digits
+fn strip_underscores(s: &str, hex: bool) -> Option<String> {
+    let is_digit = |c: Option<char>| match c {
+        Some(c) if hex => c.is_ascii_hexdigit(),
+        Some(c) => c.is_ascii_digit(),
+        None => false,
+    };
+
+    let mut out = String::with_capacity(s.len());
+    let mut prev = None;
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '_' {
+            if !is_digit(prev) || !is_digit(chars.peek().copied()) {
+                return None;
+            }
+        } else {
+            out.push(c);
+        }
+        prev = Some(c);
+    }
+    Some(out)
+}
+
+fn parse_uint(s: &str) -> Option<u64> {
+    let (digits, radix) = match s.strip_prefix("0x") {
+        Some(hex) => (strip_underscores(hex, true)?, 16),
+        None => (strip_underscores(s, false)?, 10),
+    };
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+    u64::from_str_radix(&digits, radix).ok()
+}
+
+/// Splits an integer literal into its sign and magnitude
+fn parse_int(s: &str) -> Option<(bool, u64)> {
+    match s.as_bytes().first()? {
+        b'-' => Some((true, parse_uint(&s[1..])?)),
+        b'+' => Some((false, parse_uint(&s[1..])?)),
+        _ => Some((false, parse_uint(s)?)),
+    }
+}
+
+/// Parses an integer literal into a 32 bit value, unsigned literals may use the full `u32` range
+fn parse_i32(s: &str) -> Option<i32> {
+    match parse_int(s)? {
+        (true, magnitude) if magnitude <= 1 << 31 => Some((magnitude as i64).wrapping_neg() as i32),
+        (false, magnitude) if magnitude <= u32::MAX as u64 => Some(magnitude as u32 as i32),
+        _ => None,
+    }
+}
+
+/// Parses an integer literal into a 64 bit value, unsigned literals may use the full `u64` range
+fn parse_i64(s: &str) -> Option<i64> {
+    match parse_int(s)? {
+        (true, magnitude) if magnitude <= 1 << 63 => Some((magnitude as i64).wrapping_neg()),
+        (false, magnitude) => Some(magnitude as i64),
+        _ => None,
+    }
+}
+
+/// Parses an integer literal into a value of `bits` bits, unsigned literals may use the full range
+fn parse_lane_int(s: &str, bits: u32) -> Option<u64> {
+    let mask = u64::MAX >> (64 - bits);
+    match parse_int(s)? {
+        (true, magnitude) if magnitude <= 1 << (bits - 1) => Some(magnitude.wrapping_neg() & mask),
+        (false, magnitude) if magnitude <= mask => Some(magnitude),
+        _ => None,
+    }
+}
+
+/// Parses a float literal into the bits of a float with the given mantissa and exponent widths
+///
+/// Literals that round to infinity are rejected
+fn parse_float(s: &str, mantissa: u32, exponent: u32) -> Option<u64> {
+    let (negative, s) = match s.as_bytes().first()? {
+        b'-' => (true, &s[1..]),
+        b'+' => (false, &s[1..]),
+        _ => (false, s),
+    };
+    let sign = (negative as u64) << (mantissa + exponent);
+    let infinity = ((1 << exponent) - 1) << mantissa;
+    let mantissa_mask = (1 << mantissa) - 1;
+
+    let bits = if s == "inf" {
+        infinity
+    } else if s == "nan" {
+        infinity | 1 << (mantissa - 1)
+    } else if let Some(payload) = s.strip_prefix("nan:0x") {
+        match u64::from_str_radix(&strip_underscores(payload, true)?, 16) {
+            Ok(payload) if payload != 0 && payload <= mantissa_mask => infinity | payload,
+            _ => return None,
+        }
+    } else if let Some(hex) = s.strip_prefix("0x") {
+        parse_hex_float(hex, mantissa, exponent)?
+    } else {
+        if !s.starts_with(|c: char| c.is_ascii_digit()) {
+            return None;
+        }
+        let s = strip_underscores(s, false)?;
+        if mantissa == 23 {
+            let value = s.parse::<f32>().ok().filter(|value| value.is_finite())?;
+            value.to_bits() as u64
+        } else {
+            let value = s.parse::<f64>().ok().filter(|value| value.is_finite())?;
+            value.to_bits()
+        }
+    };
+
+    Some(sign | bits)
+}
+
+/// Parses the part after `0x` of a hexadecimal float, rounding to nearest even
+fn parse_hex_float(s: &str, mantissa: u32, exponent: u32) -> Option<u64> {
+    let (significand, exp) = match s.find(['p', 'P']) {
+        Some(idx) => {
+            let (negative, magnitude) = parse_int(&s[idx + 1..])?;
+            if s[idx + 1..]
+                .trim_start_matches(['+', '-'])
+                .starts_with("0x")
+            {
+                return None;
+            }
+            // Anything past this is out of range for every float type anyway
+            let magnitude = magnitude.min(1 << 20) as i64;
+            (&s[..idx], if negative { -magnitude } else { magnitude })
+        }
+        None => (s, 0),
+    };
+    let (int, frac) = match significand.find('.') {
+        Some(idx) => (&significand[..idx], &significand[idx + 1..]),
+        None => (significand, ""),
+    };
+    let int = strip_underscores(int, true)?;
+    let frac = strip_underscores(frac, true)?;
+    if int.is_empty() {
+        return None;
+    }
+
+    // The value is m * 2^e, plus something smaller than the last bit of m if sticky
+    let mut m: u64 = 0;
+    let mut e = exp;
+    let mut sticky = false;
+    for (i, c) in int.chars().chain(frac.chars()).enumerate() {
+        let digit = c.to_digit(16)? as u64;
+        let fractional = i >= int.len();
+        if m >> 60 == 0 {
+            m = m << 4 | digit;
+            if fractional {
+                e -= 4;
+            }
+        } else {
+            sticky |= digit != 0;
+            if !fractional {
+                e += 4;
+            }
+        }
+    }
+    if m == 0 {
+        return Some(0);
+    }
+
+    let bias = (1i64 << (exponent - 1)) - 1;
+    let msb = 63 - m.leading_zeros() as i64;
+    // The exponent of the last bit of the result, subnormals share the one of the smallest normal
+    let mut unit = (msb + e).max(1 - bias) - mantissa as i64;
+    let shift = unit - e;
+
+    let mut q = if shift <= 0 {
+        m << -shift
+    } else if shift > 64 {
+        0
+    } else {
+        let wide = m as u128;
+        let q = (wide >> shift) as u64;
+        let rem = wide & ((1 << shift) - 1);
+        let half = 1 << (shift - 1);
+        if rem > half || (rem == half && (sticky || q & 1 == 1)) {
+            q + 1
+        } else {
+            q
+        }
+    };
+    if q >> (mantissa + 1) != 0 {
+        q >>= 1;
+        unit += 1;
+    }
+
+    if q >> mantissa == 0 {
+        // Subnormal
+        return Some(q);
+    }
+    let biased = unit + mantissa as i64 + bias;
+    if biased >= (1 << exponent) - 1 {
+        return None;
+    }
+    Some((biased as u64) << mantissa | (q & ((1 << mantissa) - 1)))
+}
+
+/// Walks over the items of a list
+struct Cursor<'s, 'a> {
+    items: &'s [Sexpr<'a>],
+    pos: usize,
+    /// The offset of the list, used for errors once every item has been consumed
+    offset: usize,
+}
+
+impl<'s, 'a> Cursor<'s, 'a> {
+    fn new(items: &'s [Sexpr<'a>], offset: usize) -> Self {
+        Cursor {
+            items,
+            pos: 0,
+            offset,
+        }
+    }
+
+    fn peek(&self) -> Option<&'s Sexpr<'a>> {
+        self.items.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<&'s Sexpr<'a>> {
+        let item = self.items.get(self.pos);
+        self.pos += item.is_some() as usize;
+        item
+    }
+
+    fn rest(&self) -> &'s [Sexpr<'a>] {
+        &self.items[self.pos..]
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.items.len()
+    }
+
+    /// The offset of the next item, or of the list if there are none left
+    fn offset(&self) -> usize {
+        self.peek().map_or(self.offset, Sexpr::offset)
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> PResult<T> {
+        error(self.offset(), message)
+    }
+
+    /// Fails if there are items left
+    fn end(&self) -> PResult<()> {
+        match self.peek() {
+            Some(_) => self.error("unexpected token"),
+            None => Ok(()),
+        }
+    }
+
+    fn peek_keyword(&self) -> Option<&'a str> {
+        match self.peek() {
+            Some(Sexpr::Atom(Token::Keyword(keyword), _)) => Some(keyword),
+            _ => None,
+        }
+    }
+
+    fn keyword(&mut self) -> PResult<&'a str> {
+        match self.peek_keyword() {
+            Some(keyword) => {
+                self.pos += 1;
+                Ok(keyword)
+            }
+            None => self.error("expected a keyword"),
+        }
+    }
+
+    /// Consumes the next item if it's an identifier
+    fn id(&mut self) -> Option<&'a str> {
+        match self.peek() {
+            Some(Sexpr::Atom(Token::Id(id), _)) => {
+                self.pos += 1;
+                Some(id)
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the next item is an index, either numeric or symbolic
+    fn peek_index(&self) -> bool {
+        matches!(
+            self.peek(),
+            Some(Sexpr::Atom(Token::Number(_), _)) | Some(Sexpr::Atom(Token::Id(_), _))
+        )
+    }
+
+    /// Whether the next two items are indices
+    fn peek_index_pair(&self) -> bool {
+        matches!(
+            self.rest(),
+            [
+                Sexpr::Atom(Token::Number(_), _) | Sexpr::Atom(Token::Id(_), _),
+                Sexpr::Atom(Token::Number(_), _) | Sexpr::Atom(Token::Id(_), _),
+                ..
+            ]
+        )
+    }
+
+    /// The keyword starting the next item if it's a list
+    fn peek_list(&self) -> Option<&'a str> {
+        match self.peek() {
+            Some(Sexpr::List(items, _)) => match items.first() {
+                Some(Sexpr::Atom(Token::Keyword(keyword), _)) => Some(keyword),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Consumes the next item if it's a list starting with `keyword`,
+    /// returning a cursor over the rest of the list
+    fn list(&mut self, keyword: &str) -> Option<Cursor<'s, 'a>> {
+        if self.peek_list() != Some(keyword) {
+            return None;
+        }
+        match self.next() {
+            Some(Sexpr::List(items, offset)) => {
+                let mut cursor = Cursor::new(items, *offset);
+                cursor.pos = 1;
+                Some(cursor)
+            }
+            _ => None,
+        }
+    }
+
+    fn string(&mut self) -> PResult<&'s [u8]> {
+        match self.peek() {
+            Some(Sexpr::Atom(Token::String(string), _)) => {
+                self.pos += 1;
+                Ok(string)
+            }
+            _ => self.error("expected a string"),
+        }
+    }
+
+    fn name(&mut self) -> PResult<String> {
+        let offset = self.offset();
+        match String::from_utf8(self.string()?.to_vec()) {
+            Ok(name) => Ok(name),
+            Err(_) => error(offset, "malformed UTF-8 encoding"),
+        }
+    }
+
+    /// Consumes a number or keyword (for `inf` and `nan`) literal
+    fn literal(&mut self) -> PResult<(&'a str, usize)> {
+        match self.peek() {
+            Some(Sexpr::Atom(Token::Number(literal), offset))
+            | Some(Sexpr::Atom(Token::Keyword(literal), offset)) => {
+                self.pos += 1;
+                Ok((literal, *offset))
+            }
+            _ => self.error("expected a number"),
+        }
+    }
+
+    fn u32(&mut self) -> PResult<u32> {
+        let (literal, offset) = self.literal()?;
+        match parse_uint(literal) {
+            Some(value) if value <= u32::MAX as u64 => Ok(value as u32),
+            _ => error(offset, format!("invalid u32 {}", literal)),
+        }
+    }
+
+    /// Parses a lane index or a shuffle lane
+    fn lane(&mut self) -> PResult<u8> {
+        let (literal, offset) = self.literal()?;
+        match parse_uint(literal) {
+            Some(value) if value <= u8::MAX as u64 => Ok(value as u8),
+            _ => error(offset, format!("invalid lane index {}", literal)),
+        }
+    }
+
+    fn val_type(&mut self, types: &Names) -> PResult<ValType> {
+        let ty = match self.peek_keyword() {
+            Some("i32") => ValType::I32,
+            Some("i64") => ValType::I64,
+            Some("f32") => ValType::F32,
+            Some("f64") => ValType::F64,
+            Some("v128") => ValType::V128,
+            _ if self.peek_ref_type() => return Ok(ValType::Ref(self.ref_type(types)?)),
+            _ => return self.error("expected a value type"),
+        };
+        self.pos += 1;
+        Ok(ty)
+    }
+
+    /// Whether the next item is a reference type, either `(ref ...)` or a shorthand
+    fn peek_ref_type(&self) -> bool {
+        self.peek_list() == Some("ref")
+            || self.peek_keyword().and_then(ref_type_shorthand).is_some()
+    }
+
+    fn ref_type(&mut self, types: &Names) -> PResult<types::RefType> {
+        if let Some(mut list) = self.list("ref") {
+            let nullable = list.peek_keyword() == Some("null");
+            if nullable {
+                list.next();
+            }
+            let heap_type = list.heap_type(types)?;
+            list.end()?;
+            return Ok(types::RefType {
+                nullable,
+                heap_type,
+            });
+        }
+
+        match self.peek_keyword().and_then(ref_type_shorthand) {
+            Some(heap_type) => {
+                self.pos += 1;
+                Ok(types::RefType {
+                    nullable: true,
+                    heap_type,
+                })
+            }
+            None => self.error("expected a reference type"),
+        }
+    }
+
+    /// An abstract heap type or a type index
+    fn heap_type(&mut self, types: &Names) -> PResult<types::HeapType> {
+        if self.peek_index() {
+            return Ok(types::HeapType::Concrete(types.index(self, "type")?));
+        }
+        let ty = match self.peek_keyword() {
+            Some("func") => types::HeapType::Func,
+            Some("extern") => types::HeapType::Extern,
+            Some("any") => types::HeapType::Any,
+            Some("eq") => types::HeapType::Eq,
+            Some("i31") => types::HeapType::I31,
+            Some("struct") => types::HeapType::Struct,
+            Some("array") => types::HeapType::Array,
+            Some("exn") => types::HeapType::Exn,
+            Some("none") => types::HeapType::None,
+            Some("nofunc") => types::HeapType::NoFunc,
+            Some("noextern") => types::HeapType::NoExtern,
+            Some("noexn") => types::HeapType::NoExn,
+            _ => return self.error("expected a heap type"),
+        };
+        self.pos += 1;
+        Ok(ty)
+    }
+
+    /// Parses `i8`, `i16` or a value type
+    fn storage_type(&mut self, types: &Names) -> PResult<types::StorageType> {
+        let ty = match self.peek_keyword() {
+            Some("i8") => types::StorageType::I8,
+            Some("i16") => types::StorageType::I16,
+            _ => return Ok(types::StorageType::Val(self.val_type(types)?)),
+        };
+        self.pos += 1;
+        Ok(ty)
+    }
+
+    /// Parses a storage type optionally wrapped in `(mut ...)`
+    fn field_type(&mut self, types: &Names) -> PResult<types::FieldType> {
+        match self.list("mut") {
+            Some(mut list) => {
+                let storage = list.storage_type(types)?;
+                list.end()?;
+                Ok(types::FieldType {
+                    storage,
+                    mutable: true,
+                })
+            }
+            None => Ok(types::FieldType {
+                storage: self.storage_type(types)?,
+                mutable: false,
+            }),
+        }
+    }
+
+    fn u64(&mut self) -> PResult<u64> {
+        let (literal, offset) = self.literal()?;
+        match parse_uint(literal) {
+            Some(value) => Ok(value),
+            None => error(offset, format!("invalid u64 {}", literal)),
+        }
+    }
+
+    /// Consumes the optional index type of a memory or table, returns whether it's `i64`
+    fn index_type(&mut self) -> bool {
+        match self.peek_keyword() {
+            Some("i64") => {
+                self.next();
+                true
+            }
+            Some("i32") => {
+                self.next();
+                false
+            }
+            _ => false,
+        }
+    }
+
+    /// Parses a bound of the limits of a memory or table with the given index type
+    fn bound(&mut self, is_64: bool) -> PResult<u64> {
+        match is_64 {
+            true => self.u64(),
+            false => self.u32().map(u64::from),
+        }
+    }
+
+    fn limits(&mut self, is_64: bool) -> PResult<types::Limits> {
+        let min = self.bound(is_64)?;
+        let max = match self.peek() {
+            Some(Sexpr::Atom(Token::Number(_), _)) => Some(self.bound(is_64)?),
+            _ => None,
+        };
+        Ok(types::Limits { min, max })
+    }
+
+    fn memory_type(&mut self) -> PResult<types::MemoryType> {
+        let memory64 = self.index_type();
+        self.memory_limits(memory64)
+    }
+
+    /// The rest of a memory type after its index type
+    fn memory_limits(&mut self, memory64: bool) -> PResult<types::MemoryType> {
+        let lim = self.limits(memory64)?;
+        let shared = self.peek_keyword() == Some("shared");
+        if shared {
+            self.next();
+        }
+        Ok(types::MemoryType {
+            lim,
+            shared,
+            memory64,
+        })
+    }
+
+    fn table_type(&mut self, types: &Names) -> PResult<types::TableType> {
+        let table64 = self.index_type();
+        self.table_limits(table64, types)
+    }
+
+    /// The rest of a table type after its index type
+    fn table_limits(&mut self, table64: bool, types: &Names) -> PResult<types::TableType> {
+        let lim = self.limits(table64)?;
+        let elem_type = self.ref_type(types)?;
+        Ok(types::TableType {
+            elem_type,
+            lim,
+            table64,
+        })
+    }
+
+    fn global_type(&mut self, types: &Names) -> PResult<types::GlobalType> {
+        match self.list("mut") {
+            Some(mut list) => {
+                let ty = list.val_type(types)?;
+                list.end()?;
+                Ok(types::GlobalType { ty, mutable: true })
+            }
+            None => Ok(types::GlobalType {
+                ty: self.val_type(types)?,
+                mutable: false,
+            }),
+        }
+    }
+
+    /// Parses `(param ...)* (result ...)*`, returning the names of the parameters
+    fn func_type(&mut self, types: &Names) -> PResult<(types::FunctionType, Vec<Option<String>>)> {
+        let mut ty = types::FunctionType {
+            parameter_types: Vec::new(),
+            return_types: Vec::new(),
+        };
+        let mut names = Vec::new();
+
+        while let Some(mut list) = self.list("param") {
+            if let Some(id) = list.id() {
+                ty.parameter_types.push(list.val_type(types)?);
+                names.push(Some(id.to_string()));
+                list.end()?;
+            }
+            while !list.is_empty() {
+                ty.parameter_types.push(list.val_type(types)?);
+                names.push(None);
+            }
+        }
+        while let Some(mut list) = self.list("result") {
+            while !list.is_empty() {
+                ty.return_types.push(list.val_type(types)?);
+            }
+        }
+
+        Ok((ty, names))
+    }
+}
+
+/// The symbolic names of an index space
+#[derive(Default)]
+struct Names {
+    names: HashMap<String, u32>,
+    len: u32,
+}
+
+impl Names {
+    /// Adds an item to the index space, returning its index
+    fn declare(&mut self, id: Option<&str>, offset: usize) -> PResult<u32> {
+        if let Some(id) = id {
+            if self.names.insert(id.to_string(), self.len).is_some() {
+                return error(offset, format!("duplicate identifier ${}", id));
+            }
+        }
+        self.len += 1;
+        Ok(self.len - 1)
+    }
+
+    /// Consumes an index, resolving it if symbolic
+    fn index(&self, cursor: &mut Cursor, kind: &str) -> PResult<u32> {
+        match cursor.peek() {
+            Some(Sexpr::Atom(Token::Id(id), offset)) => match self.names.get(*id) {
+                Some(idx) => {
+                    cursor.next();
+                    Ok(*idx)
+                }
+                None => error(*offset, format!("unknown {} ${}", kind, id)),
+            },
+            Some(Sexpr::Atom(Token::Number(_), _)) => cursor.u32(),
+            _ => cursor.error(format!("expected a {} index", kind)),
+        }
+    }
+}
+
+/// The names in scope inside a function
+#[derive(Default)]
+struct FuncBody {
+    locals: Names,
+    /// The labels of the enclosing blocks, innermost last
+    labels: Vec<Option<String>>,
+    /// The number of enclosing instruction sequences and folded operands
+    depth: usize,
+}
+
+impl FuncBody {
+    /// Goes one level deeper, failing at `offset` past the nesting limit
+    fn nest(&mut self, offset: usize) -> PResult<()> {
+        if self.depth > MAX_NESTING {
+            return error(offset, "nesting too deep");
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn label(&self, cursor: &mut Cursor) -> PResult<LabelIdx> {
+        match cursor.peek() {
+            Some(Sexpr::Atom(Token::Id(id), offset)) => {
+                match self
+                    .labels
+                    .iter()
+                    .rev()
+                    .position(|label| label.as_deref() == Some(*id))
+                {
+                    Some(depth) => {
+                        cursor.next();
+                        Ok(depth as LabelIdx)
+                    }
+                    None => error(*offset, format!("unknown label ${}", id)),
+                }
+            }
+            _ => cursor.u32(),
+        }
+    }
+
+    /// Parses the optional label after an `end` or `else`, which must match the block's
+    fn end_label(&self, cursor: &mut Cursor, label: Option<&str>) -> PResult<()> {
+        let offset = cursor.offset();
+        match cursor.id() {
+            Some(id) if Some(id) != label => error(offset, format!("mismatched label ${}", id)),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Default)]
+struct ModuleParser {
+    module: Module<'static>,
+    types: Names,
+    /// The field names of the struct types
+    fields: HashMap<TypeIdx, Names>,
+    funcs: Names,
+    tables: Names,
+    memories: Names,
+    globals: Names,
+    tags: Names,
+    elems: Names,
+    datas: Names,
+    /// Whether a function, table, memory, global or tag has been defined, imports must come first
+    defined: bool,
+}
+
+impl ModuleParser {
+    fn space(&mut self, kind: &str) -> &mut Names {
+        match kind {
+            "func" => &mut self.funcs,
+            "table" => &mut self.tables,
+            "memory" => &mut self.memories,
+            "tag" => &mut self.tags,
+            _ => &mut self.globals,
+        }
+    }
+
+    /// Assigns an index to every item, so that items can be referred to before they're defined
+    fn declare(&mut self, field: &Sexpr) -> PResult<()> {
+        let (kind, mut cursor) = field_cursor(field)?;
+        match kind {
+            "type" => {
+                self.types.declare(cursor.id(), field.offset())?;
+            }
+            "rec" => {
+                while !cursor.is_empty() {
+                    let offset = cursor.offset();
+                    match cursor.list("type") {
+                        Some(mut ty) => self.types.declare(ty.id(), offset)?,
+                        None => return error(offset, "expected a type definition"),
+                    };
+                }
+            }
+            "import" => {
+                cursor.string()?;
+                cursor.string()?;
+                let offset = cursor.offset();
+                let (kind, mut desc) = match cursor.next() {
+                    Some(desc @ Sexpr::List(..)) => field_cursor(desc)?,
+                    _ => return error(offset, "expected an import description"),
+                };
+                if !matches!(kind, "func" | "table" | "memory" | "global" | "tag") {
+                    return error(offset, format!("unknown import kind {}", kind));
+                }
+                if self.defined {
+                    return error(field.offset(), "imports must come before any definition");
+                }
+                let id = desc.id();
+                self.space(kind).declare(id, offset)?;
+            }
+            "func" | "table" | "memory" | "global" | "tag" => {
+                let id = cursor.id();
+                self.space(kind).declare(id, field.offset())?;
+                while cursor.list("export").is_some() {}
+                if cursor.peek_list() == Some("import") {
+                    if self.defined {
+                        return error(field.offset(), "imports must come before any definition");
+                    }
+                } else {
+                    self.defined = true;
+                }
+
+                // Inline segments
+                if kind == "table" {
+                    cursor.index_type();
+                }
+                if kind == "table" && cursor.peek_ref_type() {
+                    cursor.next();
+                    if cursor.peek_list() == Some("elem") {
+                        self.elems.declare(None, field.offset())?;
+                    }
+                }
+                if kind == "memory" && cursor.peek_list() == Some("data") {
+                    self.datas.declare(None, field.offset())?;
+                }
+            }
+            "elem" => {
+                self.elems.declare(cursor.id(), field.offset())?;
+            }
+            "data" => {
+                self.datas.declare(cursor.id(), field.offset())?;
+            }
+            "export" | "start" => {}
+            _ => return error(field.offset(), format!("unknown module field {}", kind)),
+        }
+        Ok(())
+    }
+
+    /// Collects the explicit types, before any implicit type of a type use is added
+    fn define_types(&mut self, field: &Sexpr) -> PResult<()> {
+        let (kind, mut cursor) = field_cursor(field)?;
+        let first = self.sub_types().count() as TypeIdx;
+        let group = match kind {
+            "type" => vec![self.sub_type(first, &mut cursor)?],
+            "rec" => {
+                let mut group = Vec::new();
+                while let Some(mut ty) = cursor.list("type") {
+                    let idx = first + group.len() as TypeIdx;
+                    group.push(self.sub_type(idx, &mut ty)?);
+                }
+                group
+            }
+            _ => return Ok(()),
+        };
+        cursor.end()?;
+        self.module.types.push(types::RecGroup(group));
+        Ok(())
+    }
+
+    /// Parses the rest of a `(type ...)`, either `(sub final? typeidx? comptype)` or `comptype`
+    fn sub_type(&mut self, idx: TypeIdx, cursor: &mut Cursor) -> PResult<types::SubType> {
+        cursor.id();
+        let ty = match cursor.list("sub") {
+            Some(mut sub) => {
+                let is_final = sub.peek_keyword() == Some("final");
+                if is_final {
+                    sub.next();
+                }
+                let supertype = match sub.peek_index() {
+                    true => Some(self.types.index(&mut sub, "type")?),
+                    false => None,
+                };
+                let composite = self.composite_type(idx, &mut sub)?;
+                sub.end()?;
+                types::SubType {
+                    is_final,
+                    supertype,
+                    composite,
+                }
+            }
+            None => types::SubType {
+                is_final: true,
+                supertype: None,
+                composite: self.composite_type(idx, cursor)?,
+            },
+        };
+        cursor.end()?;
+        Ok(ty)
+    }
+
+    /// Parses `(func ...)`, `(struct (field ...)*)` or `(array fieldtype)`
+    fn composite_type(
+        &mut self,
+        idx: TypeIdx,
+        cursor: &mut Cursor,
+    ) -> PResult<types::CompositeType> {
+        if let Some(mut func) = cursor.list("func") {
+            let (ty, _) = func.func_type(&self.types)?;
+            func.end()?;
+            return Ok(types::CompositeType::Func(ty));
+        }
+        if let Some(mut array) = cursor.list("array") {
+            let field = array.field_type(&self.types)?;
+            array.end()?;
+            return Ok(types::CompositeType::Array(field));
+        }
+        let mut list = match cursor.list("struct") {
+            Some(list) => list,
+            None => return cursor.error("expected a composite type"),
+        };
+
+        let mut names = Names::default();
+        let mut fields = Vec::new();
+        while let Some(mut field) = list.list("field") {
+            let offset = field.offset();
+            if let Some(id) = field.id() {
+                names.declare(Some(id), offset)?;
+                fields.push(field.field_type(&self.types)?);
+                field.end()?;
+            }
+            while !field.is_empty() {
+                names.declare(None, offset)?;
+                fields.push(field.field_type(&self.types)?);
+            }
+        }
+        list.end()?;
+        self.fields.insert(idx, names);
+        Ok(types::CompositeType::Struct(fields))
+    }
+
+    fn define(&mut self, field: &Sexpr) -> PResult<()> {
+        let (kind, mut cursor) = field_cursor(field)?;
+        match kind {
+            // Already added by `define_types`
+            "type" | "rec" => return Ok(()),
+            "import" => {
+                let module = cursor.name()?;
+                let name = cursor.name()?;
+                let (kind, mut desc) = field_cursor(cursor.next().unwrap())?;
+                desc.id();
+                let desc = self.import_desc(kind, &mut desc)?;
+                self.module.imports.push(Import { module, name, desc });
+            }
+            "func" | "table" | "memory" | "global" | "tag" => {
+                let idx = self.next_index(kind);
+                cursor.id();
+                while let Some(mut export) = cursor.list("export") {
+                    let name = export.name()?;
+                    export.end()?;
+                    let desc = match kind {
+                        "func" => ExportDesc::Function(idx),
+                        "table" => ExportDesc::Table(idx),
+                        "memory" => ExportDesc::Memory(idx),
+                        "tag" => ExportDesc::Tag(idx),
+                        _ => ExportDesc::Global(idx),
+                    };
+                    self.module.exports.push(Export { name, desc });
+                }
+
+                if let Some(mut import) = cursor.list("import") {
+                    let module = import.name()?;
+                    let name = import.name()?;
+                    import.end()?;
+                    let desc = self.import_desc(kind, &mut cursor)?;
+                    self.module.imports.push(Import { module, name, desc });
+                    return Ok(());
+                }
+
+                match kind {
+                    "func" => self.function(&mut cursor)?,
+                    "table" => self.table(idx, &mut cursor)?,
+                    "memory" => self.memory(idx, &mut cursor)?,
+                    "tag" => {
+                        let ty = self.anonymous_type_use(&mut cursor)?;
+                        self.module.tags.push(ty);
+                    }
+                    _ => {
+                        let ty = cursor.global_type(&self.types)?;
+                        let init = self.expr(&mut cursor)?;
+                        self.module.globals.push(Global { ty, init });
+                    }
+                }
+            }
+            "export" => {
+                let name = cursor.name()?;
+                let (kind, mut list) = match cursor.next() {
+                    Some(desc @ Sexpr::List(..)) => field_cursor(desc)?,
+                    _ => return error(field.offset(), "expected an export description"),
+                };
+                let desc = match kind {
+                    "func" => ExportDesc::Function(self.funcs.index(&mut list, "function")?),
+                    "table" => ExportDesc::Table(self.tables.index(&mut list, "table")?),
+                    "memory" => ExportDesc::Memory(self.memories.index(&mut list, "memory")?),
+                    "global" => ExportDesc::Global(self.globals.index(&mut list, "global")?),
+                    "tag" => ExportDesc::Tag(self.tags.index(&mut list, "tag")?),
+                    _ => return error(field.offset(), format!("unknown export kind {}", kind)),
+                };
+                list.end()?;
+                self.module.exports.push(Export { name, desc });
+            }
+            "start" => {
+                if self.module.start.is_some() {
+                    return error(field.offset(), "multiple start functions");
+                }
+                self.module.start = Some(self.funcs.index(&mut cursor, "function")?);
+            }
+            "elem" => {
+                cursor.id();
+                let mode = if cursor.peek_keyword() == Some("declare") {
+                    cursor.next();
+                    ElementMode::Declarative
+                } else {
+                    let table = match cursor.list("table") {
+                        Some(mut list) => {
+                            let idx = self.tables.index(&mut list, "table")?;
+                            list.end()?;
+                            Some(idx)
+                        }
+                        None if cursor.peek_index() => {
+                            Some(self.tables.index(&mut cursor, "table")?)
+                        }
+                        None => None,
+                    };
+                    // Segments without a table or an offset are passive
+                    match (table, cursor.peek()) {
+                        (None, None) | (None, Some(Sexpr::Atom(..))) => ElementMode::Passive,
+                        (None, _) if cursor.peek_ref_type() => ElementMode::Passive,
+                        (table, _) => ElementMode::Active {
+                            table: table.unwrap_or(0),
+                            offset: self.offset(&mut cursor)?,
+                        },
+                    }
+                };
+                let items = self.elem_items(&mut cursor)?;
+                self.module.elements.push(Element { mode, items });
+            }
+            "data" => {
+                cursor.id();
+                let mem = match cursor.list("memory") {
+                    Some(mut list) => {
+                        let idx = self.memories.index(&mut list, "memory")?;
+                        list.end()?;
+                        Some(idx)
+                    }
+                    None if cursor.peek_index() => {
+                        Some(self.memories.index(&mut cursor, "memory")?)
+                    }
+                    None => None,
+                };
+                // Segments without a memory or an offset are passive
+                let mode = match (mem, cursor.peek()) {
+                    (None, None) | (None, Some(Sexpr::Atom(..))) => DataMode::Passive,
+                    (mem, _) => DataMode::Active {
+                        mem: mem.unwrap_or(0),
+                        offset: self.offset(&mut cursor)?,
+                    },
+                };
+                let mut init = Vec::new();
+                while !cursor.is_empty() {
+                    init.extend_from_slice(cursor.string()?);
+                }
+                self.module.data.push(Data {
+                    mode,
+                    init: Cow::Owned(init),
+                });
+            }
+            _ => unreachable!("unknown fields are rejected while declaring"),
+        }
+
+        cursor.end()
+    }
+
+    /// The index of the next item of the given kind, imports included
+    fn next_index(&self, kind: &str) -> u32 {
+        let imported = self
+            .module
+            .imports
+            .iter()
+            .filter(|import| match import.desc {
+                Desc::Function(_) => kind == "func",
+                Desc::Table(_) => kind == "table",
+                Desc::Memory(_) => kind == "memory",
+                Desc::Global(_) => kind == "global",
+                Desc::Tag(_) => kind == "tag",
+            })
+            .count();
+        let defined = match kind {
+            "func" => self.module.functions.len(),
+            "table" => self.module.tables.len(),
+            "memory" => self.module.memory.len(),
+            "tag" => self.module.tags.len(),
+            _ => self.module.globals.len(),
+        };
+        (imported + defined) as u32
+    }
+
+    fn import_desc(&mut self, kind: &str, cursor: &mut Cursor) -> PResult<Desc> {
+        let desc = match kind {
+            "func" => Desc::Function(self.type_use(cursor)?.0),
+            "table" => Desc::Table(cursor.table_type(&self.types)?),
+            "memory" => Desc::Memory(cursor.memory_type()?),
+            "tag" => Desc::Tag(self.anonymous_type_use(cursor)?),
+            _ => Desc::Global(cursor.global_type(&self.types)?),
+        };
+        cursor.end()?;
+        Ok(desc)
+    }
+
+    /// The types of every recursion group, in index order
+    fn sub_types(&self) -> impl Iterator<Item = &types::SubType> {
+        self.module.types.iter().flat_map(|group| group.0.iter())
+    }
+
+    /// Finds the index of a function type, adding it at the end of the types if there are none
+    ///
+    /// Only function types defined on their own, without `rec` or `sub`, can be reused
+    fn type_idx(&mut self, ty: types::FunctionType) -> TypeIdx {
+        let group = types::RecGroup::from(ty);
+        let mut idx = 0;
+        for other in self.module.types.iter() {
+            if *other == group {
+                return idx;
+            }
+            idx += other.0.len() as TypeIdx;
+        }
+        self.module.types.push(group);
+        idx
+    }
+
+    /// Parses `(type x)? (param ...)* (result ...)*`, returning the type index
+    /// and the names of the parameters
+    fn type_use(&mut self, cursor: &mut Cursor) -> PResult<(TypeIdx, Vec<Option<String>>)> {
+        let offset = cursor.offset();
+        let explicit = match cursor.list("type") {
+            Some(mut list) => {
+                let idx = self.types.index(&mut list, "type")?;
+                list.end()?;
+                Some(idx)
+            }
+            None => None,
+        };
+        let (ty, names) = cursor.func_type(&self.types)?;
+
+        match explicit {
+            Some(idx) => {
+                // Unknown indices are left for validation to report, unless
+                // there is an inline type to check them against
+                let expected = self
+                    .sub_types()
+                    .nth(idx as usize)
+                    .and_then(|ty| ty.func_type());
+                let expected = match expected {
+                    Some(expected) => expected,
+                    None if names.is_empty() && ty.return_types.is_empty() => {
+                        return Ok((idx, names))
+                    }
+                    None => return error(offset, format!("unknown type {}", idx)),
+                };
+                if ty.parameter_types.is_empty() && ty.return_types.is_empty() {
+                    Ok((idx, vec![None; expected.parameter_types.len()]))
+                } else if *expected != ty {
+                    error(
+                        offset,
+                        format!("inline function type doesn't match type {}", idx),
+                    )
+                } else {
+                    Ok((idx, names))
+                }
+            }
+            None => Ok((self.type_idx(ty), names)),
+        }
+    }
+
+    /// Parses a type use whose parameters can't be named
+    fn anonymous_type_use(&mut self, cursor: &mut Cursor) -> PResult<TypeIdx> {
+        let offset = cursor.offset();
+        let (idx, names) = self.type_use(cursor)?;
+        if names.iter().any(Option::is_some) {
+            return error(offset, "parameters can't be named here");
+        }
+        Ok(idx)
+    }
+
+    /// Parses an optional table index, defaulting to table 0
+    fn table_index(&self, cursor: &mut Cursor) -> PResult<TableIdx> {
+        if cursor.peek_index() {
+            self.tables.index(cursor, "table")
+        } else {
+            Ok(0)
+        }
+    }
+
+    /// Parses an optional memory index, defaulting to memory 0
+    fn memory_index(&self, cursor: &mut Cursor) -> PResult<MemoryIdx> {
+        if cursor.peek_index() {
+            self.memories.index(cursor, "memory")
+        } else {
+            Ok(0)
+        }
+    }
+
+    /// Parses a field index of the struct type `ty`
+    fn field_index(&self, ty: TypeIdx, cursor: &mut Cursor) -> PResult<FieldIdx> {
+        match self.fields.get(&ty) {
+            Some(names) => names.index(cursor, "field"),
+            None => Names::default().index(cursor, "field"),
+        }
+    }
+
+    /// Parses `memory? offset=N? align=N?`
+    fn memarg(&self, cursor: &mut Cursor, natural_alignment: u32) -> PResult<MemoryArgument> {
+        let memory = self.memory_index(cursor)?;
+        memarg(cursor, memory, natural_alignment)
+    }
+
+    /// Parses the catch clauses of a `try_table`, their labels are relative to its parent
+    fn catches(&self, body: &FuncBody, cursor: &mut Cursor) -> PResult<Vec<Catch>> {
+        let mut catches = Vec::new();
+        loop {
+            let catch = match cursor.peek_list() {
+                Some("catch") => {
+                    let mut list = cursor.list("catch").unwrap();
+                    let tag = self.tags.index(&mut list, "tag")?;
+                    let label = body.label(&mut list)?;
+                    list.end()?;
+                    Catch::Catch { tag, label }
+                }
+                Some("catch_ref") => {
+                    let mut list = cursor.list("catch_ref").unwrap();
+                    let tag = self.tags.index(&mut list, "tag")?;
+                    let label = body.label(&mut list)?;
+                    list.end()?;
+                    Catch::CatchRef { tag, label }
+                }
+                Some("catch_all") => {
+                    let mut list = cursor.list("catch_all").unwrap();
+                    let label = body.label(&mut list)?;
+                    list.end()?;
+                    Catch::CatchAll(label)
+                }
+                Some("catch_all_ref") => {
+                    let mut list = cursor.list("catch_all_ref").unwrap();
+                    let label = body.label(&mut list)?;
+                    list.end()?;
+                    Catch::CatchAllRef(label)
+                }
+                _ => return Ok(catches),
+            };
+            catches.push(catch);
+        }
+    }
+
+    fn block_type(&mut self, cursor: &mut Cursor) -> PResult<BlockType> {
+        if cursor.peek_list() == Some("type") {
+            return Ok(BlockType::TypeIdx(self.anonymous_type_use(cursor)?));
+        }
+        let offset = cursor.offset();
+        let (ty, names) = cursor.func_type(&self.types)?;
+        if names.iter().any(Option::is_some) {
+            return error(offset, "parameters can't be named here");
+        }
+        Ok(match (&ty.parameter_types[..], &ty.return_types[..]) {
+            ([], []) => BlockType::Empty,
+            ([], [result]) => BlockType::Type(*result),
+            _ => BlockType::TypeIdx(self.type_idx(ty)),
+        })
+    }
+
+    fn function(&mut self, cursor: &mut Cursor) -> PResult<()> {
+        let mut body = FuncBody::default();
+
+        let offset = cursor.offset();
+        let (ty, params) = self.type_use(cursor)?;
+        for name in params.iter() {
+            body.locals.declare(name.as_deref(), offset)?;
+        }
+
+        let mut locals: Vec<Local> = Vec::new();
+        while let Some(mut list) = cursor.list("local") {
+            let offset = list.offset();
+            let id = list.id();
+            let mut types = Vec::new();
+            if id.is_some() {
+                types.push(list.val_type(&self.types)?);
+                list.end()?;
+            }
+            while !list.is_empty() {
+                types.push(list.val_type(&self.types)?);
+            }
+            for ty in types {
+                body.locals.declare(id, offset)?;
+                match locals.last_mut() {
+                    Some(local) if local.ty == ty => local.n += 1,
+                    _ => locals.push(Local { n: 1, ty }),
+                }
+            }
+        }
+
+        let instrs = self.instrs(&mut body, cursor)?;
+        self.module.functions.push(ty);
+        self.module.code.push(Function {
+            locals,
+            body: Expr(instrs),
+        });
+        Ok(())
+    }
+
+    fn table(&mut self, idx: TableIdx, cursor: &mut Cursor) -> PResult<()> {
+        let table64 = cursor.index_type();
+        if !cursor.peek_ref_type() {
+            let ty = cursor.table_limits(table64, &self.types)?;
+            // The elements start as the value of the optional init expression
+            let init = match cursor.is_empty() {
+                true => None,
+                false => Some(self.expr(cursor)?),
+            };
+            self.module.tables.push(Table { ty, init });
+            return Ok(());
+        }
+
+        // Inline element segment
+        let elem_type = cursor.ref_type(&self.types)?;
+        let mut list = match cursor.list("elem") {
+            Some(list) => list,
+            None => return cursor.error("expected an inline element segment"),
+        };
+        let items = match list.peek() {
+            Some(Sexpr::List(..)) => {
+                let mut exprs = Vec::new();
+                while !list.is_empty() {
+                    exprs.push(self.elem_expr(&mut list)?);
+                }
+                ElementItems::Expressions(elem_type, exprs)
+            }
+            _ => {
+                let mut funcs = Vec::new();
+                while !list.is_empty() {
+                    funcs.push(self.funcs.index(&mut list, "function")?);
+                }
+                ElementItems::Functions(funcs)
+            }
+        };
+        let len = match &items {
+            ElementItems::Functions(funcs) => funcs.len(),
+            ElementItems::Expressions(_, exprs) => exprs.len(),
+        } as u64;
+        self.module.tables.push(
+            types::TableType {
+                elem_type,
+                lim: types::Limits {
+                    min: len,
+                    max: Some(len),
+                },
+                table64,
+            }
+            .into(),
+        );
+        self.module.elements.push(Element {
+            mode: ElementMode::Active {
+                table: idx,
+                offset: Expr(vec![zero_index(table64)]),
+            },
+            items,
+        });
+        Ok(())
+    }
+
+    /// Parses `func funcidx*`, `reftype expr*` or the bare function indices of the
+    /// abbreviated active segments
+    fn elem_items(&mut self, cursor: &mut Cursor) -> PResult<ElementItems> {
+        if cursor.peek_ref_type() {
+            let ty = cursor.ref_type(&self.types)?;
+            let mut exprs = Vec::new();
+            while !cursor.is_empty() {
+                exprs.push(self.elem_expr(cursor)?);
+            }
+            return Ok(ElementItems::Expressions(ty, exprs));
+        }
+
+        if cursor.peek_keyword() == Some("func") {
+            cursor.next();
+        }
+        let mut funcs = Vec::new();
+        while !cursor.is_empty() {
+            funcs.push(self.funcs.index(cursor, "function")?);
+        }
+        Ok(ElementItems::Functions(funcs))
+    }
+
+    fn memory(&mut self, idx: MemoryIdx, cursor: &mut Cursor) -> PResult<()> {
+        let memory64 = cursor.index_type();
+        let mut list = match cursor.list("data") {
+            Some(list) => list,
+            None => {
+                let ty = cursor.memory_limits(memory64)?;
+                self.module.memory.push(ty);
+                return Ok(());
+            }
+        };
+
+        // Inline data segment
+        let mut init = Vec::new();
+        while !list.is_empty() {
+            init.extend_from_slice(list.string()?);
+        }
+        let pages = (init.len() as u64).div_ceil(0x10000);
+        self.module.memory.push(types::MemoryType {
+            lim: types::Limits {
+                min: pages,
+                max: Some(pages),
+            },
+            shared: false,
+            memory64,
+        });
+        self.module.data.push(Data {
+            mode: DataMode::Active {
+                mem: idx,
+                offset: Expr(vec![zero_index(memory64)]),
+            },
+            init: Cow::Owned(init),
+        });
+        Ok(())
+    }
+
+    /// Parses the instructions left in the cursor as a constant expression
+    fn expr(&mut self, cursor: &mut Cursor) -> PResult<Expr> {
+        let instrs = self.instrs(&mut FuncBody::default(), cursor)?;
+        cursor.end()?;
+        Ok(Expr(instrs))
+    }
+
+    /// Parses the offset of a segment, either `(offset instr*)` or a single folded instruction
+    fn offset(&mut self, cursor: &mut Cursor) -> PResult<Expr> {
+        self.wrapped_expr(cursor, "offset", "expected an offset expression")
+    }
+
+    /// Parses an element expression, `(item ...)` or a single folded instruction
+    fn elem_expr(&mut self, cursor: &mut Cursor) -> PResult<Expr> {
+        self.wrapped_expr(cursor, "item", "expected an element expression")
+    }
+
+    /// Parses `(keyword instr*)` or a single folded instruction as a constant expression
+    fn wrapped_expr(&mut self, cursor: &mut Cursor, keyword: &str, message: &str) -> PResult<Expr> {
+        if let Some(mut list) = cursor.list(keyword) {
+            return self.expr(&mut list);
+        }
+        let mut instrs = Vec::new();
+        match cursor.next() {
+            Some(item @ Sexpr::List(..)) => {
+                self.folded(&mut FuncBody::default(), item, &mut instrs)?
+            }
+            _ => return error(cursor.offset(), message),
+        }
+        Ok(Expr(instrs))
+    }
+
+    /// Parses instructions, either flat or folded, up to the end of the list or a keyword
+    /// closing a block (`end`, `else`, `catch`, `catch_all` or `delegate`)
+    fn instrs(&mut self, body: &mut FuncBody, cursor: &mut Cursor) -> PResult<Vec<Instruction>> {
+        body.nest(cursor.offset())?;
+        let mut instrs = Vec::new();
+        loop {
+            match cursor.peek() {
+                None | Some(Sexpr::Atom(Token::Keyword("end"), _)) => break,
+                Some(Sexpr::Atom(
+                    Token::Keyword("else" | "catch" | "catch_all" | "delegate"),
+                    _,
+                )) => break,
+                Some(item @ Sexpr::List(..)) => {
+                    cursor.next();
+                    self.folded(body, item, &mut instrs)?;
+                }
+                Some(_) => {
+                    let instr = self.flat(body, cursor)?;
+                    instrs.push(instr);
+                }
+            }
+        }
+        body.depth -= 1;
+        Ok(instrs)
+    }
+
+    fn flat(&mut self, body: &mut FuncBody, cursor: &mut Cursor) -> PResult<Instruction> {
+        let offset = cursor.offset();
+        let name = match cursor.keyword() {
+            Ok(name) => name,
+            Err(_) => return cursor.error("expected an instruction"),
+        };
+
+        match name {
+            "block" | "loop" | "if" => {
+                let label = cursor.id();
+                let ty = self.block_type(cursor)?;
+                body.labels.push(label.map(str::to_string));
+                let instrs = self.instrs(body, cursor)?;
+                let mut reject_instrs = None;
+                if name == "if" && cursor.peek_keyword() == Some("else") {
+                    cursor.next();
+                    body.end_label(cursor, label)?;
+                    reject_instrs = Some(self.instrs(body, cursor)?);
+                }
+                body.labels.pop();
+
+                if cursor.peek_keyword() != Some("end") {
+                    return cursor.error(format!("expected end of {}", name));
+                }
+                cursor.next();
+                body.end_label(cursor, label)?;
+
+                Ok(match name {
+                    "block" => Instruction::Block { ty, instrs },
+                    "loop" => Instruction::Loop { ty, instrs },
+                    _ => Instruction::If {
+                        ty,
+                        accept_instrs: instrs,
+                        reject_instrs,
+                    },
+                })
+            }
+            "try_table" => {
+                let label = cursor.id();
+                let ty = self.block_type(cursor)?;
+                let catches = self.catches(body, cursor)?;
+                body.labels.push(label.map(str::to_string));
+                let instrs = self.instrs(body, cursor)?;
+                body.labels.pop();
+
+                if cursor.peek_keyword() != Some("end") {
+                    return cursor.error("expected end of try_table");
+                }
+                cursor.next();
+                body.end_label(cursor, label)?;
+                Ok(Instruction::TryTable {
+                    ty,
+                    catches,
+                    instrs,
+                })
+            }
+            "try" => {
+                let label = cursor.id();
+                let ty = self.block_type(cursor)?;
+                body.labels.push(label.map(str::to_string));
+                let instrs = self.instrs(body, cursor)?;
+
+                let mut catches = Vec::new();
+                while cursor.peek_keyword() == Some("catch") {
+                    cursor.next();
+                    if cursor.peek_index_pair() {
+                        body.end_label(cursor, label)?;
+                    }
+                    let tag = self.tags.index(cursor, "tag")?;
+                    catches.push((tag, self.instrs(body, cursor)?));
+                }
+                let mut catch_all = None;
+                if cursor.peek_keyword() == Some("catch_all") {
+                    cursor.next();
+                    body.end_label(cursor, label)?;
+                    catch_all = Some(self.instrs(body, cursor)?);
+                }
+                body.labels.pop();
+
+                // The label of delegate is resolved outside of the try block
+                if catches.is_empty()
+                    && catch_all.is_none()
+                    && cursor.peek_keyword() == Some("delegate")
+                {
+                    cursor.next();
+                    let handler = TryHandler::Delegate(body.label(cursor)?);
+                    return Ok(Instruction::Try {
+                        ty,
+                        instrs,
+                        handler,
+                    });
+                }
+
+                if cursor.peek_keyword() != Some("end") {
+                    return cursor.error("expected end of try");
+                }
+                cursor.next();
+                body.end_label(cursor, label)?;
+                Ok(Instruction::Try {
+                    ty,
+                    instrs,
+                    handler: TryHandler::Catches { catches, catch_all },
+                })
+            }
+            _ => self.plain(name, offset, body, cursor),
+        }
+    }
+
+    /// Parses a folded instruction, appending it to `instrs` after its operands
+    fn folded(
+        &mut self,
+        body: &mut FuncBody,
+        item: &Sexpr,
+        instrs: &mut Vec<Instruction>,
+    ) -> PResult<()> {
+        let mut cursor = match item {
+            Sexpr::List(items, offset) => Cursor::new(items, *offset),
+            Sexpr::Atom(_, offset) => return error(*offset, "expected a folded instruction"),
+        };
+        let offset = cursor.offset();
+        let name = match cursor.keyword() {
+            Ok(name) => name,
+            Err(_) => return cursor.error("expected an instruction"),
+        };
+
+        match name {
+            "block" | "loop" => {
+                let label = cursor.id();
+                let ty = self.block_type(&mut cursor)?;
+                body.labels.push(label.map(str::to_string));
+                let block = self.instrs(body, &mut cursor)?;
+                body.labels.pop();
+                cursor.end()?;
+                instrs.push(match name {
+                    "block" => Instruction::Block { ty, instrs: block },
+                    _ => Instruction::Loop { ty, instrs: block },
+                });
+            }
+            "if" => {
+                let label = cursor.id();
+                let ty = self.block_type(&mut cursor)?;
+                while let Some(item @ Sexpr::List(..)) = cursor.peek() {
+                    if cursor.peek_list() == Some("then") {
+                        break;
+                    }
+                    cursor.next();
+                    self.folded(body, item, instrs)?;
+                }
+
+                body.labels.push(label.map(str::to_string));
+                let mut then = match cursor.list("then") {
+                    Some(then) => then,
+                    None => return cursor.error("expected (then ...)"),
+                };
+                let accept_instrs = self.instrs(body, &mut then)?;
+                then.end()?;
+                let reject_instrs = match cursor.list("else") {
+                    Some(mut list) => {
+                        let reject_instrs = self.instrs(body, &mut list)?;
+                        list.end()?;
+                        Some(reject_instrs)
+                    }
+                    None => None,
+                };
+                body.labels.pop();
+                cursor.end()?;
+
+                instrs.push(Instruction::If {
+                    ty,
+                    accept_instrs,
+                    reject_instrs,
+                });
+            }
+            "try_table" => {
+                let label = cursor.id();
+                let ty = self.block_type(&mut cursor)?;
+                let catches = self.catches(body, &mut cursor)?;
+                body.labels.push(label.map(str::to_string));
+                let block = self.instrs(body, &mut cursor)?;
+                body.labels.pop();
+                cursor.end()?;
+                instrs.push(Instruction::TryTable {
+                    ty,
+                    catches,
+                    instrs: block,
+                });
+            }
+            "try" => {
+                let label = cursor.id();
+                let ty = self.block_type(&mut cursor)?;
+                body.labels.push(label.map(str::to_string));
+                let mut list = match cursor.list("do") {
+                    Some(list) => list,
+                    None => return cursor.error("expected (do ...)"),
+                };
+                let block = self.instrs(body, &mut list)?;
+                list.end()?;
+
+                let mut catches = Vec::new();
+                while let Some(mut list) = cursor.list("catch") {
+                    let tag = self.tags.index(&mut list, "tag")?;
+                    catches.push((tag, self.instrs(body, &mut list)?));
+                    list.end()?;
+                }
+                let mut catch_all = None;
+                if let Some(mut list) = cursor.list("catch_all") {
+                    catch_all = Some(self.instrs(body, &mut list)?);
+                    list.end()?;
+                }
+                body.labels.pop();
+
+                let handler = if catches.is_empty()
+                    && catch_all.is_none()
+                    && cursor.peek_list() == Some("delegate")
+                {
+                    let mut list = cursor.list("delegate").unwrap();
+                    let label = body.label(&mut list)?;
+                    list.end()?;
+                    TryHandler::Delegate(label)
+                } else {
+                    TryHandler::Catches { catches, catch_all }
+                };
+                cursor.end()?;
+                instrs.push(Instruction::Try {
+                    ty,
+                    instrs: block,
+                    handler,
+                });
+            }
+            _ => {
+                let instr = self.plain(name, offset, body, &mut cursor)?;
+                body.nest(offset)?;
+                while let Some(item) = cursor.next() {
+                    self.folded(body, item, instrs)?;
+                }
+                body.depth -= 1;
+                instrs.push(instr);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Parses the immediates of an instruction that doesn't contain other instructions
+    fn plain(
+        &mut self,
+        name: &str,
+        offset: usize,
+        body: &FuncBody,
+        cursor: &mut Cursor,
+    ) -> PResult<Instruction> {
+        Ok(match name {
+            "unreachable" => Instruction::Unreachable,
+            "nop" => Instruction::NOP,
+            "br" => Instruction::Branch(body.label(cursor)?),
+            "br_if" => Instruction::BranchIf(body.label(cursor)?),
+            "br_table" => {
+                let mut labels = Vec::new();
+                while cursor.peek_index() {
+                    labels.push(body.label(cursor)?);
+                }
+                match labels.pop() {
+                    Some(operand) => Instruction::BranchTable { labels, operand },
+                    None => return cursor.error("expected a label"),
+                }
+            }
+            "return" => Instruction::Return,
+            "throw" => Instruction::Throw(self.tags.index(cursor, "tag")?),
+            "throw_ref" => Instruction::ThrowRef,
+            "rethrow" => Instruction::Rethrow(body.label(cursor)?),
+            "call" => Instruction::Call(self.funcs.index(cursor, "function")?),
+            "call_indirect" => {
+                let table = self.table_index(cursor)?;
+                let ty = self.anonymous_type_use(cursor)?;
+                Instruction::CallIndirect { ty, table }
+            }
+            "return_call" => Instruction::ReturnCall(self.funcs.index(cursor, "function")?),
+            "return_call_indirect" => {
+                let table = self.table_index(cursor)?;
+                let ty = self.anonymous_type_use(cursor)?;
+                Instruction::ReturnCallIndirect { ty, table }
+            }
+            "call_ref" => Instruction::CallRef(self.types.index(cursor, "type")?),
+            "return_call_ref" => Instruction::ReturnCallRef(self.types.index(cursor, "type")?),
+            "ref.as_non_null" => Instruction::RefAsNonNull,
+            "br_on_null" => Instruction::BranchOnNull(body.label(cursor)?),
+            "br_on_non_null" => Instruction::BranchOnNonNull(body.label(cursor)?),
+            "drop" => Instruction::Drop,
+            "select" => {
+                let mut types = Vec::new();
+                let mut typed = false;
+                while let Some(mut list) = cursor.list("result") {
+                    typed = true;
+                    while !list.is_empty() {
+                        types.push(list.val_type(&self.types)?);
+                    }
+                }
+                if typed {
+                    Instruction::TypedSelect(types)
+                } else {
+                    Instruction::Select
+                }
+            }
+            "local.get" => Instruction::LocalGet(body.locals.index(cursor, "local")?),
+            "local.set" => Instruction::LocalSet(body.locals.index(cursor, "local")?),
+            "local.tee" => Instruction::LocalTee(body.locals.index(cursor, "local")?),
+            "global.get" => Instruction::GlobalGet(self.globals.index(cursor, "global")?),
+            "global.set" => Instruction::GlobalSet(self.globals.index(cursor, "global")?),
+            "table.get" => Instruction::TableGet(self.table_index(cursor)?),
+            "table.set" => Instruction::TableSet(self.table_index(cursor)?),
+            "table.size" => Instruction::TableSize(self.table_index(cursor)?),
+            "table.grow" => Instruction::TableGrow(self.table_index(cursor)?),
+            "table.fill" => Instruction::TableFill(self.table_index(cursor)?),
+            "ref.null" => Instruction::RefNull(cursor.heap_type(&self.types)?),
+            "ref.is_null" => Instruction::RefIsNull,
+            "ref.func" => Instruction::RefFunc(self.funcs.index(cursor, "function")?),
+            "ref.eq" => Instruction::RefEq,
+            "ref.test" => Instruction::RefTest(cursor.ref_type(&self.types)?),
+            "ref.cast" => Instruction::RefCast(cursor.ref_type(&self.types)?),
+            "br_on_cast" | "br_on_cast_fail" => {
+                let label = body.label(cursor)?;
+                let from = cursor.ref_type(&self.types)?;
+                let to = cursor.ref_type(&self.types)?;
+                match name {
+                    "br_on_cast" => Instruction::BranchOnCast { label, from, to },
+                    _ => Instruction::BranchOnCastFail { label, from, to },
+                }
+            }
+            "struct.new" => Instruction::StructNew(self.types.index(cursor, "type")?),
+            "struct.new_default" => {
+                Instruction::StructNewDefault(self.types.index(cursor, "type")?)
+            }
+            "struct.get" | "struct.get_s" | "struct.get_u" => {
+                let ty = self.types.index(cursor, "type")?;
+                Instruction::StructGet {
+                    ty,
+                    field: self.field_index(ty, cursor)?,
+                    signed: packed_signedness(name),
+                }
+            }
+            "struct.set" => {
+                let ty = self.types.index(cursor, "type")?;
+                Instruction::StructSet {
+                    ty,
+                    field: self.field_index(ty, cursor)?,
+                }
+            }
+            "array.new" => Instruction::ArrayNew(self.types.index(cursor, "type")?),
+            "array.new_default" => Instruction::ArrayNewDefault(self.types.index(cursor, "type")?),
+            "array.new_fixed" => Instruction::ArrayNewFixed {
+                ty: self.types.index(cursor, "type")?,
+                len: cursor.u32()?,
+            },
+            "array.new_data" => Instruction::ArrayNewData {
+                ty: self.types.index(cursor, "type")?,
+                data: self.datas.index(cursor, "data segment")?,
+            },
+            "array.new_elem" => Instruction::ArrayNewElem {
+                ty: self.types.index(cursor, "type")?,
+                elem: self.elems.index(cursor, "elem segment")?,
+            },
+            "array.get" | "array.get_s" | "array.get_u" => Instruction::ArrayGet {
+                ty: self.types.index(cursor, "type")?,
+                signed: packed_signedness(name),
+            },
+            "array.set" => Instruction::ArraySet(self.types.index(cursor, "type")?),
+            "array.len" => Instruction::ArrayLen,
+            "array.fill" => Instruction::ArrayFill(self.types.index(cursor, "type")?),
+            "array.copy" => Instruction::ArrayCopy {
+                dst: self.types.index(cursor, "type")?,
+                src: self.types.index(cursor, "type")?,
+            },
+            "array.init_data" => Instruction::ArrayInitData {
+                ty: self.types.index(cursor, "type")?,
+                data: self.datas.index(cursor, "data segment")?,
+            },
+            "array.init_elem" => Instruction::ArrayInitElem {
+                ty: self.types.index(cursor, "type")?,
+                elem: self.elems.index(cursor, "elem segment")?,
+            },
+            "any.convert_extern" => Instruction::AnyConvertExtern,
+            "extern.convert_any" => Instruction::ExternConvertAny,
+            "ref.i31" => Instruction::RefI31,
+            "i31.get_s" => Instruction::I31Get(true),
+            "i31.get_u" => Instruction::I31Get(false),
+            "memory.size" => Instruction::MemorySize(self.memory_index(cursor)?),
+            "memory.grow" => Instruction::MemoryGrow(self.memory_index(cursor)?),
+            "memory.init" => {
+                // The memory is only given when both indices are present
+                let mem = if cursor.peek_index_pair() {
+                    self.memories.index(cursor, "memory")?
+                } else {
+                    0
+                };
+                let data = self.datas.index(cursor, "data segment")?;
+                Instruction::MemoryInit { data, mem }
+            }
+            "data.drop" => Instruction::DataDrop(self.datas.index(cursor, "data segment")?),
+            "memory.copy" => {
+                if cursor.peek_index() {
+                    let dst = self.memories.index(cursor, "memory")?;
+                    let src = self.memories.index(cursor, "memory")?;
+                    Instruction::MemoryCopy { dst, src }
+                } else {
+                    Instruction::MemoryCopy { dst: 0, src: 0 }
+                }
+            }
+            "memory.fill" => Instruction::MemoryFill(self.memory_index(cursor)?),
+            "memory.atomic.notify" => Instruction::AtomicNotify(self.memarg(cursor, 2)?),
+            "memory.atomic.wait32" => Instruction::AtomicWait {
+                mem: self.memarg(cursor, 2)?,
+                ty: IntegerType::I32,
+            },
+            "memory.atomic.wait64" => Instruction::AtomicWait {
+                mem: self.memarg(cursor, 3)?,
+                ty: IntegerType::I64,
+            },
+            "atomic.fence" => Instruction::AtomicFence,
+            "table.init" => {
+                // The table is only given when both indices are present
+                let table = if cursor.peek_index_pair() {
+                    self.tables.index(cursor, "table")?
+                } else {
+                    0
+                };
+                let elem = self.elems.index(cursor, "elem segment")?;
+                Instruction::TableInit { elem, table }
+            }
+            "elem.drop" => Instruction::ElemDrop(self.elems.index(cursor, "elem segment")?),
+            "table.copy" => {
+                if cursor.peek_index() {
+                    let dst = self.tables.index(cursor, "table")?;
+                    let src = self.tables.index(cursor, "table")?;
+                    Instruction::TableCopy { dst, src }
+                } else {
+                    Instruction::TableCopy { dst: 0, src: 0 }
+                }
+            }
+            "i32.const" | "i64.const" | "f32.const" | "f64.const" => {
+                let (literal, literal_offset) = cursor.literal()?;
+                let value = match name {
+                    "i32.const" => parse_i32(literal).map(Literal::I32),
+                    "i64.const" => parse_i64(literal).map(Literal::I64),
+                    "f32.const" => parse_float(literal, 23, 8)
+                        .map(|bits| Literal::F32(f32::from_bits(bits as u32))),
+                    _ => {
+                        parse_float(literal, 52, 11).map(|bits| Literal::F64(f64::from_bits(bits)))
+                    }
+                };
+                match value {
+                    Some(value) => Instruction::Const(value),
+                    None => return error(literal_offset, format!("invalid constant {}", literal)),
+                }
+            }
+            "v128.const" => {
+                let shape_offset = cursor.offset();
+                let shape = match cursor.keyword()? {
+                    "i8x16" => LaneShape::I8x16,
+                    "i16x8" => LaneShape::I16x8,
+                    "i32x4" => LaneShape::I32x4,
+                    "i64x2" => LaneShape::I64x2,
+                    "f32x4" => LaneShape::F32x4,
+                    "f64x2" => LaneShape::F64x2,
+                    shape => return error(shape_offset, format!("unknown lane shape {}", shape)),
+                };
+                let bits = 128 / shape.lanes() as u32;
+                let mut value = 0;
+                for lane in 0..shape.lanes() as u32 {
+                    let (literal, literal_offset) = cursor.literal()?;
+                    let lane_value = match shape {
+                        LaneShape::F32x4 => parse_float(literal, 23, 8),
+                        LaneShape::F64x2 => parse_float(literal, 52, 11),
+                        _ => parse_lane_int(literal, bits),
+                    };
+                    match lane_value {
+                        Some(lane_value) => value |= (lane_value as u128) << (lane * bits),
+                        None => {
+                            return error(literal_offset, format!("invalid constant {}", literal))
+                        }
+                    }
+                }
+                Instruction::Const(Literal::V128(value))
+            }
+            _ => match numeric(name).or_else(|| vector(name)) {
+                Some(mut instr) => {
+                    let natural = instr.natural_alignment();
+                    match &mut instr {
+                        Instruction::Load { mem, .. }
+                        | Instruction::Store { mem, .. }
+                        | Instruction::V128LoadExtend { mem, .. }
+                        | Instruction::V128LoadSplat { mem, .. }
+                        | Instruction::V128LoadZero { mem, .. }
+                        | Instruction::AtomicLoad { mem, .. }
+                        | Instruction::AtomicStore { mem, .. }
+                        | Instruction::AtomicRmw { mem, .. }
+                        | Instruction::AtomicCompareExchange { mem, .. } => {
+                            *mem = self.memarg(cursor, natural.unwrap())?
+                        }
+                        Instruction::V128LoadLane { mem, lane, .. }
+                        | Instruction::V128StoreLane { mem, lane, .. } => {
+                            // The lane is a bare index too, so a lone index is the lane
+                            let memory = match cursor.rest() {
+                                [Sexpr::Atom(Token::Id(_), _), ..]
+                                | [Sexpr::Atom(Token::Number(_), _), Sexpr::Atom(Token::Number(_), _), ..] => {
+                                    self.memories.index(cursor, "memory")?
+                                }
+                                [Sexpr::Atom(Token::Number(_), _), Sexpr::Atom(Token::Keyword(keyword), _), ..]
+                                    if keyword.starts_with("offset=")
+                                        || keyword.starts_with("align=") =>
+                                {
+                                    self.memories.index(cursor, "memory")?
+                                }
+                                _ => 0,
+                            };
+                            *mem = memarg(cursor, memory, natural.unwrap())?;
+                            *lane = cursor.lane()?;
+                        }
+                        Instruction::ExtractLane { lane, .. }
+                        | Instruction::ReplaceLane { lane, .. } => *lane = cursor.lane()?,
+                        Instruction::I8x16Shuffle(lanes) => {
+                            for lane in lanes.iter_mut() {
+                                *lane = cursor.lane()?;
+                            }
+                        }
+                        _ => {}
+                    }
+                    instr
+                }
+                None => return error(offset, format!("unknown instruction {}", name)),
+            },
+        })
+    }
+}
+
+/// The signedness of the `_s`/`_u` variants of `struct.get` and `array.get`
+fn packed_signedness(name: &str) -> Option<bool> {
+    match &name[name.len() - 2..] {
+        "_s" => Some(true),
+        "_u" => Some(false),
+        _ => None,
+    }
+}
+
+/// The heap type of a nullable reference type shorthand (i.e. `funcref`)
+fn ref_type_shorthand(keyword: &str) -> Option<types::HeapType> {
+    Some(match keyword {
+        "funcref" => types::HeapType::Func,
+        "externref" => types::HeapType::Extern,
+        "anyref" => types::HeapType::Any,
+        "eqref" => types::HeapType::Eq,
+        "i31ref" => types::HeapType::I31,
+        "structref" => types::HeapType::Struct,
+        "arrayref" => types::HeapType::Array,
+        "exnref" => types::HeapType::Exn,
+        "nullref" => types::HeapType::None,
+        "nullfuncref" => types::HeapType::NoFunc,
+        "nullexternref" => types::HeapType::NoExtern,
+        "nullexnref" => types::HeapType::NoExn,
+        _ => return None,
+    })
+}
+
+/// Splits a list into the keyword at its start and a cursor over the rest of it
+fn field_cursor<'s, 'a>(item: &'s Sexpr<'a>) -> PResult<(&'a str, Cursor<'s, 'a>)> {
+    match item {
+        Sexpr::List(items, offset) => {
+            let mut cursor = Cursor::new(items, *offset);
+            let keyword = cursor.keyword()?;
+            Ok((keyword, cursor))
+        }
+        Sexpr::Atom(_, offset) => error(*offset, "expected a list"),
+    }
+}
+
+/// The offset of an inline segment of a memory or table with the given index type
+fn zero_index(is_64: bool) -> Instruction {
+    match is_64 {
+        true => Instruction::Const(Literal::I64(0)),
+        false => Instruction::Const(Literal::I32(0)),
+    }
+}
+
+/// Parses `offset=N? align=N?` of an access to `memory`
+fn memarg(
+    cursor: &mut Cursor,
+    memory: MemoryIdx,
+    natural_alignment: u32,
+) -> PResult<MemoryArgument> {
+    let mut mem = MemoryArgument {
+        alignment: natural_alignment,
+        offset: 0,
+        memory,
+    };
+
+    if let Some(offset) = cursor
+        .peek_keyword()
+        .and_then(|kw| kw.strip_prefix("offset="))
+    {
+        mem.offset = match parse_uint(offset) {
+            Some(offset) => offset,
+            None => return cursor.error("invalid memory offset"),
+        };
+        cursor.next();
+    }
+    if let Some(align) = cursor
+        .peek_keyword()
+        .and_then(|kw| kw.strip_prefix("align="))
+    {
+        mem.alignment = match parse_uint(align) {
+            Some(align) if align.is_power_of_two() => align.trailing_zeros(),
+            _ => return cursor.error("alignment must be a power of two"),
+        };
+        cursor.next();
+    }
+
+    Ok(mem)
+}
+
+/// Splits a conversion suffix like `f32_s` into its operand type and signedness
+fn float_operand(suffix: &str) -> Option<(FloatType, bool)> {
+    match suffix {
+        "f32_s" => Some((FloatType::F32, true)),
+        "f32_u" => Some((FloatType::F32, false)),
+        "f64_s" => Some((FloatType::F64, true)),
+        "f64_u" => Some((FloatType::F64, false)),
+        _ => None,
+    }
+}
+
+/// Splits a conversion suffix like `i32_s` into its operand type and signedness
+fn integer_operand(suffix: &str) -> Option<(IntegerType, bool)> {
+    match suffix {
+        "i32_s" => Some((IntegerType::I32, true)),
+        "i32_u" => Some((IntegerType::I32, false)),
+        "i64_s" => Some((IntegerType::I64, true)),
+        "i64_u" => Some((IntegerType::I64, false)),
+        _ => None,
+    }
+}
+
+/// Instructions named `<type>.<op>` without immediates, loads and stores get a default memarg
+fn numeric(name: &str) -> Option<Instruction> {
+    let (prefix, op) = name.split_once('.')?;
+    let ty = match prefix {
+        "i32" => ValType::I32,
+        "i64" => ValType::I64,
+        "f32" => ValType::F32,
+        "f64" => ValType::F64,
+        "v128" => ValType::V128,
+        _ => return None,
+    };
+    // v128 only shares loads and stores with the scalar types
+    if ty == ValType::V128 && op != "load" && op != "store" {
+        return None;
+    }
+    let mem = MemoryArgument {
+        alignment: 0,
+        offset: 0,
+        memory: 0,
+    };
+
+    Some(match op {
+        "eq" => Instruction::Equal(ty),
+        "ne" => Instruction::NotEqual(ty),
+        "add" => Instruction::Add(ty),
+        "sub" => Instruction::Subtract(ty),
+        "mul" => Instruction::Multiply(ty),
+        "load" => Instruction::Load {
+            mem,
+            ty,
+            storage: None,
+        },
+        "store" => Instruction::Store {
+            mem,
+            ty,
+            storage: None,
+        },
+        _ => match ty {
+            ValType::I32 if op.starts_with("atomic.") => atomic(IntegerType::I32, &op[7..])?,
+            ValType::I64 if op.starts_with("atomic.") => atomic(IntegerType::I64, &op[7..])?,
+            ValType::I32 => integer_op(IntegerType::I32, op)?,
+            ValType::I64 => integer_op(IntegerType::I64, op)?,
+            ValType::F32 => float_op(FloatType::F32, op)?,
+            ValType::F64 => float_op(FloatType::F64, op)?,
+            ValType::V128 | ValType::Ref(_) => return None,
+        },
+    })
+}
+
+/// Atomic accesses named `<type>.atomic.<op>`, `op` being what follows `atomic.`
+fn atomic(ty: IntegerType, op: &str) -> Option<Instruction> {
+    let mem = MemoryArgument {
+        alignment: 0,
+        offset: 0,
+        memory: 0,
+    };
+    // The storage size follows the kind of access, i.e. `load8_u` or `rmw16.add_u`
+    let (kind, rest) = op.split_at(
+        op.find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(op.len()),
+    );
+    let (bits, rest) = rest.split_at(
+        rest.find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len()),
+    );
+    let storage = match bits {
+        "" => None,
+        "8" => Some(StorageType::I8),
+        "16" => Some(StorageType::I16),
+        "32" => Some(StorageType::I32),
+        _ => return None,
+    };
+    // Narrow loads and read-modify-writes always zero-extend
+    let suffix = if storage.is_some() { "_u" } else { "" };
+
+    let instr = match (kind, rest) {
+        ("load", rest) if rest == suffix => Instruction::AtomicLoad { mem, ty, storage },
+        ("store", "") => Instruction::AtomicStore { mem, ty, storage },
+        ("rmw", rest) => {
+            let op = match rest.strip_prefix('.')?.strip_suffix(suffix)? {
+                "add" => AtomicOp::Add,
+                "sub" => AtomicOp::Subtract,
+                "and" => AtomicOp::And,
+                "or" => AtomicOp::Or,
+                "xor" => AtomicOp::Xor,
+                "xchg" => AtomicOp::Exchange,
+                "cmpxchg" => {
+                    return checked_atomic(Instruction::AtomicCompareExchange { mem, ty, storage })
+                }
+                _ => return None,
+            };
+            Instruction::AtomicRmw {
+                mem,
+                op,
+                ty,
+                storage,
+            }
+        }
+        _ => return None,
+    };
+    checked_atomic(instr)
+}
+
+/// Rejects i32 accesses with a 32 bit storage size
+fn checked_atomic(instr: Instruction) -> Option<Instruction> {
+    instr.atomic_opcode().ok()?;
+    Some(instr)
+}
+
+/// Vector instructions named `<shape>.<op>` or `v128.<op>`, immediates get a default value
+fn vector(name: &str) -> Option<Instruction> {
+    let (prefix, op) = name.split_once('.')?;
+    let mem = MemoryArgument {
+        alignment: 0,
+        offset: 0,
+        memory: 0,
+    };
+    // The shape of the lanes read by a memory instruction like `v128.load16_splat`
+    let read_shape = |bits: &str| match bits {
+        "8" => Some(LaneShape::I8x16),
+        "16" => Some(LaneShape::I16x8),
+        "32" => Some(LaneShape::I32x4),
+        "64" => Some(LaneShape::I64x2),
+        _ => None,
+    };
+    let (base, signed) = match op.strip_suffix("_zero").unwrap_or(op) {
+        op if op.ends_with("_s") => (&op[..op.len() - 2], Some(true)),
+        op if op.ends_with("_u") => (&op[..op.len() - 2], Some(false)),
+        op => (op, None),
+    };
+
+    let instr = if prefix == "v128" {
+        match op {
+            "not" => Instruction::V128Not,
+            "and" => Instruction::V128And,
+            "andnot" => Instruction::V128AndNot,
+            "or" => Instruction::V128Or,
+            "xor" => Instruction::V128Xor,
+            "bitselect" => Instruction::V128BitSelect,
+            "any_true" => Instruction::V128AnyTrue,
+            _ => {
+                if let Some(bits) = op
+                    .strip_suffix("_splat")
+                    .and_then(|op| op.strip_prefix("load"))
+                {
+                    let shape = read_shape(bits)?;
+                    Instruction::V128LoadSplat { mem, shape }
+                } else if let Some(bits) = op
+                    .strip_suffix("_zero")
+                    .and_then(|op| op.strip_prefix("load"))
+                {
+                    let shape = read_shape(bits)?;
+                    Instruction::V128LoadZero { mem, shape }
+                } else if let Some(bits) = op
+                    .strip_suffix("_lane")
+                    .and_then(|op| op.strip_prefix("load"))
+                {
+                    let shape = read_shape(bits)?;
+                    Instruction::V128LoadLane {
+                        mem,
+                        shape,
+                        lane: 0,
+                    }
+                } else if let Some(bits) = op
+                    .strip_suffix("_lane")
+                    .and_then(|op| op.strip_prefix("store"))
+                {
+                    let shape = read_shape(bits)?;
+                    Instruction::V128StoreLane {
+                        mem,
+                        shape,
+                        lane: 0,
+                    }
+                } else {
+                    // `load8x8_s` reads 8 lanes of 8 bits into a vector of 16 bit lanes
+                    let (bits, _) = base.strip_prefix("load")?.split_once('x')?;
+                    let shape = match read_shape(bits)? {
+                        LaneShape::I8x16 => LaneShape::I16x8,
+                        LaneShape::I16x8 => LaneShape::I32x4,
+                        _ => LaneShape::I64x2,
+                    };
+                    Instruction::V128LoadExtend {
+                        mem,
+                        shape,
+                        signed: signed?,
+                    }
+                }
+            }
+        }
+    } else {
+        let shape = match prefix {
+            "i8x16" => LaneShape::I8x16,
+            "i16x8" => LaneShape::I16x8,
+            "i32x4" => LaneShape::I32x4,
+            "i64x2" => LaneShape::I64x2,
+            "f32x4" => LaneShape::F32x4,
+            "f64x2" => LaneShape::F64x2,
+            _ => return None,
+        };
+        let float = match shape {
+            LaneShape::F64x2 => FloatType::F64,
+            _ => FloatType::F32,
+        };
+        match (base, signed) {
+            ("shuffle", None) => Instruction::I8x16Shuffle([0; 16]),
+            ("swizzle", None) => Instruction::I8x16Swizzle,
+            ("splat", None) => Instruction::Splat(shape),
+            ("extract_lane", signed) => Instruction::ExtractLane {
+                shape,
+                lane: 0,
+                signed,
+            },
+            ("replace_lane", None) => Instruction::ReplaceLane { shape, lane: 0 },
+            ("eq", None) => Instruction::VectorEqual(shape),
+            ("ne", None) => Instruction::VectorNotEqual(shape),
+            ("lt", signed) => Instruction::VectorLessThan { shape, signed },
+            ("gt", signed) => Instruction::VectorGreaterThan { shape, signed },
+            ("le", signed) => Instruction::VectorLessOrEqual { shape, signed },
+            ("ge", signed) => Instruction::VectorGreaterOrEqual { shape, signed },
+            ("abs", None) => Instruction::VectorAbsolute(shape),
+            ("neg", None) => Instruction::VectorNegate(shape),
+            ("popcnt", None) => Instruction::I8x16CountOnes,
+            ("all_true", None) => Instruction::VectorAllTrue(shape),
+            ("bitmask", None) => Instruction::VectorBitmask(shape),
+            ("shl", None) => Instruction::VectorShiftLeft(shape),
+            ("shr", Some(signed)) => Instruction::VectorShiftRight { shape, signed },
+            ("add", None) => Instruction::VectorAdd(shape),
+            ("add_sat", Some(signed)) => Instruction::VectorAddSaturate { shape, signed },
+            ("sub", None) => Instruction::VectorSubtract(shape),
+            ("sub_sat", Some(signed)) => Instruction::VectorSubtractSaturate { shape, signed },
+            ("mul", None) => Instruction::VectorMultiply(shape),
+            ("min", signed) => Instruction::VectorMinimum { shape, signed },
+            ("max", signed) => Instruction::VectorMaximum { shape, signed },
+            ("avgr", Some(false)) => Instruction::VectorAverage(shape),
+            ("q15mulr_sat", Some(true)) => Instruction::I16x8Q15MulrSat,
+            ("dot_i16x8", Some(true)) => Instruction::I32x4DotI16x8,
+            ("ceil", None) => Instruction::VectorCeil(shape),
+            ("floor", None) => Instruction::VectorFloor(shape),
+            ("trunc", None) => Instruction::VectorTruncate(shape),
+            ("nearest", None) => Instruction::VectorNearest(shape),
+            ("sqrt", None) => Instruction::VectorSquareRoot(shape),
+            ("div", None) => Instruction::VectorDivision(shape),
+            ("pmin", None) => Instruction::VectorPseudoMinimum(shape),
+            ("pmax", None) => Instruction::VectorPseudoMaximum(shape),
+            ("trunc_sat_f32x4", Some(signed)) => Instruction::VectorTruncateSaturate {
+                float: FloatType::F32,
+                signed,
+            },
+            ("trunc_sat_f64x2", Some(signed)) => Instruction::VectorTruncateSaturate {
+                float: FloatType::F64,
+                signed,
+            },
+            ("convert_i32x4", Some(signed)) | ("convert_low_i32x4", Some(signed)) => {
+                Instruction::VectorConvert { float, signed }
+            }
+            ("demote_f64x2", None) => Instruction::F32x4DemoteF64x2Zero,
+            ("promote_low_f32x4", None) => Instruction::F64x2PromoteLowF32x4,
+            (base, Some(signed)) if base.starts_with("narrow_") => {
+                Instruction::VectorNarrow { shape, signed }
+            }
+            (base, Some(signed)) if base.starts_with("extadd_pairwise_") => {
+                Instruction::VectorExtAddPairwise { shape, signed }
+            }
+            (base, Some(signed)) if base.starts_with("extend_") => Instruction::VectorExtend {
+                shape,
+                high: base.starts_with("extend_high_"),
+                signed,
+            },
+            (base, Some(signed)) if base.starts_with("extmul_") => Instruction::VectorExtMultiply {
+                shape,
+                high: base.starts_with("extmul_high_"),
+                signed,
+            },
+            _ => return None,
+        }
+    };
+
+    // Only the shape and the suffix were looked at, so the instruction must print
+    // back to the same name (i.e. `i16x8.narrow_i64x2_s` or `i64x2.lt_u` don't)
+    let printed = print::vector(&instr).ok()?;
+    match printed.split(' ').next() == Some(name) {
+        true => Some(instr),
+        false => None,
+    }
+}
+
+fn integer_op(ty: IntegerType, op: &str) -> Option<Instruction> {
+    let mem = MemoryArgument {
+        alignment: 0,
+        offset: 0,
+        memory: 0,
+    };
+    let load = |signed, storage| Instruction::Load {
+        mem,
+        ty: ty.into(),
+        storage: Some((signed, storage)),
+    };
+    let store = |storage| Instruction::Store {
+        mem,
+        ty: ty.into(),
+        storage: Some(storage),
+    };
+
+    Some(match op {
+        "eqz" => Instruction::EqualZero(ty),
+        "lt_s" => Instruction::LessThanI32 { ty, signed: true },
+        "lt_u" => Instruction::LessThanI32 { ty, signed: false },
+        "gt_s" => Instruction::GreaterThanI32 { ty, signed: true },
+        "gt_u" => Instruction::GreaterThanI32 { ty, signed: false },
+        "le_s" => Instruction::LessOrEqualI32 { ty, signed: true },
+        "le_u" => Instruction::LessOrEqualI32 { ty, signed: false },
+        "ge_s" => Instruction::GreaterOrEqualI32 { ty, signed: true },
+        "ge_u" => Instruction::GreaterOrEqualI32 { ty, signed: false },
+        "clz" => Instruction::CountLeadingZero(ty),
+        "ctz" => Instruction::CountTrailingZero(ty),
+        "popcnt" => Instruction::CountOnes(ty),
+        "div_s" => Instruction::I32Division { ty, signed: true },
+        "div_u" => Instruction::I32Division { ty, signed: false },
+        "rem_s" => Instruction::Remainder { ty, signed: true },
+        "rem_u" => Instruction::Remainder { ty, signed: false },
+        "and" => Instruction::And(ty),
+        "or" => Instruction::Or(ty),
+        "xor" => Instruction::Xor(ty),
+        "shl" => Instruction::ShiftLeft(ty),
+        "shr_s" => Instruction::ShiftRight { ty, signed: true },
+        "shr_u" => Instruction::ShiftRight { ty, signed: false },
+        "rotl" => Instruction::LeftRotation(ty),
+        "rotr" => Instruction::RightRotation(ty),
+        "load8_s" => load(true, StorageType::I8),
+        "load8_u" => load(false, StorageType::I8),
+        "load16_s" => load(true, StorageType::I16),
+        "load16_u" => load(false, StorageType::I16),
+        "load32_s" if ty == IntegerType::I64 => load(true, StorageType::I32),
+        "load32_u" if ty == IntegerType::I64 => load(false, StorageType::I32),
+        "store8" => store(StorageType::I8),
+        "store16" => store(StorageType::I16),
+        "store32" if ty == IntegerType::I64 => store(StorageType::I32),
+        "extend8_s" => Instruction::Extend {
+            ty,
+            base: StorageType::I8,
+        },
+        "extend16_s" => Instruction::Extend {
+            ty,
+            base: StorageType::I16,
+        },
+        "extend32_s" if ty == IntegerType::I64 => Instruction::Extend {
+            ty,
+            base: StorageType::I32,
+        },
+        "wrap_i64" if ty == IntegerType::I32 => Instruction::I32Wrap,
+        "extend_i32_s" if ty == IntegerType::I64 => Instruction::I32Extend(true),
+        "extend_i32_u" if ty == IntegerType::I64 => Instruction::I32Extend(false),
+        "reinterpret_f32" if ty == IntegerType::I32 => Instruction::I32ReI32erpret,
+        "reinterpret_f64" if ty == IntegerType::I64 => Instruction::LongReI32erpret,
+        _ => {
+            if let Some((float, signed)) = op.strip_prefix("trunc_sat_").and_then(float_operand) {
+                Instruction::SaturateTruncate { ty, float, signed }
+            } else {
+                let (float, signed) = op.strip_prefix("trunc_").and_then(float_operand)?;
+                Instruction::I32Truncate { ty, float, signed }
+            }
+        }
+    })
+}
+
+fn float_op(ty: FloatType, op: &str) -> Option<Instruction> {
+    Some(match op {
+        "lt" => Instruction::LessThanFloat(ty),
+        "gt" => Instruction::GreaterThanFloat(ty),
+        "le" => Instruction::LessOrEqualFloat(ty),
+        "ge" => Instruction::GreaterOrEqualFloat(ty),
+        "abs" => Instruction::Absolute(ty),
+        "neg" => Instruction::Negate(ty),
+        "ceil" => Instruction::Ceil(ty),
+        "floor" => Instruction::Floor(ty),
+        "trunc" => Instruction::Truncate(ty),
+        "nearest" => Instruction::Nearest(ty),
+        "sqrt" => Instruction::SquareRoot(ty),
+        "div" => Instruction::FloatDivision(ty),
+        "min" => Instruction::Minimum(ty),
+        "max" => Instruction::Maximum(ty),
+        "copysign" => Instruction::CopySign(ty),
+        "demote_f64" if ty == FloatType::F32 => Instruction::FloatDemote,
+        "promote_f32" if ty == FloatType::F64 => Instruction::FloatPromote,
+        "reinterpret_i32" if ty == FloatType::F32 => Instruction::FloatReI32erpret,
+        "reinterpret_i64" if ty == FloatType::F64 => Instruction::DoubleReI32erpret,
+        _ => {
+            let (tgt_ty, signed) = op.strip_prefix("convert_").and_then(integer_operand)?;
+            Instruction::Convert { ty, tgt_ty, signed }
+        }
+    })
+}
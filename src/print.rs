@@ -0,0 +1,1559 @@
+//! Prints modules and expressions in the WebAssembly text format
+//!
+//! Items are referred to by index, the index of every definition is printed next to it
+//! as a comment (i.e. `(func (;3;) ...)`)
+
+use crate::{
+    error::Result,
+    instr::{
+        BlockType, Catch, Expr, FloatType, Instruction, IntegerType, LaneShape, Literal,
+        MemoryArgument, StorageType, TryHandler,
+    },
+    module::Module,
+    sections::*,
+    types::{self, ValType},
+};
+use std::fmt::Write;
+
+/// How instructions are laid out
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Style {
+    /// One instruction per line, blocks are closed with `end`
+    ///
+    /// ```wat
+    /// local.get 0
+    /// local.get 1
+    /// i32.add
+    /// ```
+    Flat,
+    /// Operands are nested inside the instruction that consumes them
+    ///
+    /// ```wat
+    /// (i32.add
+    ///   (local.get 0)
+    ///   (local.get 1))
+    /// ```
+    Folded,
+}
+
+/// Prints a module in the text format
+///
+/// # Example
+///
+/// ```rust
+/// # use wasm_builder::{instr::*, module::Module, print, sections, types::*};
+/// let mut module = Module::new();
+/// module.types.push(
+///     FunctionType {
+///         parameter_types: vec![ValType::I32],
+///         return_types: vec![ValType::I32],
+///     }
+///     .into(),
+/// );
+/// module.functions.push(0);
+/// module.code.push(sections::Function {
+///     locals: vec![],
+///     body: Expr(vec![
+///         Instruction::LocalGet(0),
+///         Instruction::Const(Literal::I32(1)),
+///         Instruction::Add(ValType::I32),
+///     ]),
+/// });
+///
+/// assert_eq!(
+///     print::print_module(&module, print::Style::Folded).unwrap(),
+///     "(module
+///   (type (;0;) (func (param i32) (result i32)))
+///   (func (;0;) (type 0) (param i32) (result i32)
+///     (i32.add (local.get 0) (i32.const 1))
+///   )
+/// )
+/// "
+/// );
+/// ```
+pub fn print_module(module: &Module, style: Style) -> Result<String> {
+    let mut printer = Printer::new(module, style);
+    printer.module(module)?;
+    Ok(printer.out)
+}
+
+/// Prints the instructions of an expression, one per line (or per tree when folded)
+///
+/// Without the surrounding module the types of calls aren't known, so they
+/// are never folded with their operands
+pub fn print_expr(expr: &Expr, style: Style) -> Result<String> {
+    let mut printer = Printer {
+        types: Vec::new(),
+        funcs: Vec::new(),
+        tags: Vec::new(),
+        style,
+        out: String::new(),
+    };
+    printer.instrs(&expr.0, None, 0)?;
+    Ok(printer.out)
+}
+
+const INDENT: &str = "  ";
+
+/// A folded instruction
+struct Node {
+    /// The instruction with its immediates (i.e. `i32.load offset=4`)
+    text: String,
+    /// The instructions producing its operands
+    operands: Vec<Node>,
+    /// The instruction sequences of a block (`then` and `else` for an if)
+    bodies: Vec<(Option<String>, Vec<Node>)>,
+}
+
+impl Node {
+    fn is_inline(&self) -> bool {
+        self.bodies.is_empty() && self.operands.iter().all(Node::is_inline)
+    }
+
+    fn write_inline(&self, out: &mut String) {
+        out.push('(');
+        out.push_str(&self.text);
+        for operand in self.operands.iter() {
+            out.push(' ');
+            operand.write_inline(out);
+        }
+        out.push(')');
+    }
+}
+
+struct Printer<'m> {
+    /// The types of every recursion group, in index order
+    types: Vec<&'m types::SubType>,
+    /// The type index of every function
+    funcs: Vec<TypeIdx>,
+    /// The type index of every tag
+    tags: Vec<TypeIdx>,
+    style: Style,
+    out: String,
+}
+
+impl<'m> Printer<'m> {
+    fn new(module: &'m Module, style: Style) -> Self {
+        let funcs = module
+            .imports
+            .iter()
+            .filter_map(|import| match import.desc {
+                Desc::Function(ty) => Some(ty),
+                _ => None,
+            })
+            .chain(module.functions.iter().copied())
+            .collect();
+        let tags = module
+            .imports
+            .iter()
+            .filter_map(|import| match import.desc {
+                Desc::Tag(ty) => Some(ty),
+                _ => None,
+            })
+            .chain(module.tags.iter().copied())
+            .collect();
+
+        Printer {
+            types: module
+                .types
+                .iter()
+                .flat_map(|group| group.0.iter())
+                .collect(),
+            funcs,
+            tags,
+            style,
+            out: String::new(),
+        }
+    }
+
+    fn indent(&mut self, depth: usize) {
+        for _ in 0..depth {
+            self.out.push_str(INDENT);
+        }
+    }
+
+    fn line(&mut self, depth: usize, text: &str) {
+        self.indent(depth);
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn module(&mut self, module: &Module) -> Result<()> {
+        self.out.push_str("(module\n");
+
+        let mut idx = 0;
+        for group in module.types.iter() {
+            match &group.0[..] {
+                [ty] => {
+                    self.line(1, &format!("(type (;{};) {})", idx, sub_type(ty)));
+                    idx += 1;
+                }
+                [] => self.line(1, "(rec)"),
+                group => {
+                    self.line(1, "(rec");
+                    for ty in group {
+                        self.line(2, &format!("(type (;{};) {})", idx, sub_type(ty)));
+                        idx += 1;
+                    }
+                    self.line(1, ")");
+                }
+            }
+        }
+
+        let (mut funcs, mut tables, mut memories, mut globals, mut tags) = (0, 0, 0, 0, 0);
+        for import in module.imports.iter() {
+            let desc = match import.desc {
+                Desc::Function(ty) => {
+                    funcs += 1;
+                    format!("(func (;{};) (type {}))", funcs - 1, ty)
+                }
+                Desc::Table(ty) => {
+                    tables += 1;
+                    format!("(table (;{};) {})", tables - 1, table_type(&ty))
+                }
+                Desc::Memory(ty) => {
+                    memories += 1;
+                    format!("(memory (;{};) {})", memories - 1, memory_type(&ty))
+                }
+                Desc::Global(ty) => {
+                    globals += 1;
+                    format!("(global (;{};) {})", globals - 1, global_type(&ty))
+                }
+                Desc::Tag(ty) => {
+                    tags += 1;
+                    format!("(tag (;{};) (type {}))", tags - 1, ty)
+                }
+            };
+            let line = format!(
+                "(import {} {} {})",
+                string(import.module.as_bytes()),
+                string(import.name.as_bytes()),
+                desc
+            );
+            self.line(1, &line);
+        }
+
+        for (i, (ty, func)) in module.functions.iter().zip(module.code.iter()).enumerate() {
+            self.function(funcs + i, *ty, func)?;
+        }
+
+        for (i, table) in module.tables.iter().enumerate() {
+            let line = match table.init {
+                Some(ref init) => format!(
+                    "(table (;{};) {} {})",
+                    tables + i,
+                    table_type(&table.ty),
+                    self.inline_expr(&init.0)?
+                ),
+                None => format!("(table (;{};) {})", tables + i, table_type(&table.ty)),
+            };
+            self.line(1, &line);
+        }
+
+        for (i, memory) in module.memory.iter().enumerate() {
+            let line = format!("(memory (;{};) {})", memories + i, memory_type(memory));
+            self.line(1, &line);
+        }
+
+        for (i, ty) in module.tags.iter().enumerate() {
+            self.line(1, &format!("(tag (;{};) (type {}))", tags + i, ty));
+        }
+
+        for (i, global) in module.globals.iter().enumerate() {
+            let line = format!(
+                "(global (;{};) {} {})",
+                globals + i,
+                global_type(&global.ty),
+                self.inline_expr(&global.init.0)?
+            );
+            self.line(1, &line);
+        }
+
+        for export in module.exports.iter() {
+            let desc = match export.desc {
+                ExportDesc::Function(idx) => format!("(func {})", idx),
+                ExportDesc::Table(idx) => format!("(table {})", idx),
+                ExportDesc::Memory(idx) => format!("(memory {})", idx),
+                ExportDesc::Global(idx) => format!("(global {})", idx),
+                ExportDesc::Tag(idx) => format!("(tag {})", idx),
+            };
+            let line = format!("(export {} {})", string(export.name.as_bytes()), desc);
+            self.line(1, &line);
+        }
+
+        if let Some(start) = module.start {
+            self.line(1, &format!("(start {})", start));
+        }
+
+        for (i, elem) in module.elements.iter().enumerate() {
+            let mut line = format!("(elem (;{};)", i);
+            match &elem.mode {
+                ElementMode::Active { table, offset } => {
+                    if *table != 0 {
+                        write!(line, " (table {})", table).unwrap();
+                    }
+                    write!(line, " {}", self.offset(&offset.0)?).unwrap();
+                }
+                ElementMode::Passive => {}
+                ElementMode::Declarative => line.push_str(" declare"),
+            }
+            match &elem.items {
+                ElementItems::Functions(funcs) => {
+                    line.push_str(" func");
+                    for idx in funcs.iter() {
+                        write!(line, " {}", idx).unwrap();
+                    }
+                }
+                ElementItems::Expressions(ty, exprs) => {
+                    write!(line, " {}", ty).unwrap();
+                    for expr in exprs.iter() {
+                        write!(line, " {}", self.wrapped_expr("item", &expr.0)?).unwrap();
+                    }
+                }
+            }
+            line.push(')');
+            self.line(1, &line);
+        }
+
+        for (i, data) in module.data.iter().enumerate() {
+            let mut line = format!("(data (;{};)", i);
+            if let DataMode::Active { mem, offset } = &data.mode {
+                if *mem != 0 {
+                    write!(line, " (memory {})", mem).unwrap();
+                }
+                write!(line, " {}", self.offset(&offset.0)?).unwrap();
+            }
+            write!(line, " {})", string(&data.init)).unwrap();
+            self.line(1, &line);
+        }
+
+        self.out.push_str(")\n");
+        Ok(())
+    }
+
+    fn function(&mut self, idx: usize, ty: TypeIdx, func: &Function) -> Result<()> {
+        let mut header = format!("(func (;{};) (type {})", idx, ty);
+        let func_ty = self.func_type(ty);
+        if let Some(func_ty) = func_ty {
+            header.push_str(&func_type(func_ty));
+        }
+        self.line(1, &header);
+
+        for local in func.locals.iter().filter(|local| local.n != 0) {
+            let mut line = String::from("(local");
+            for _ in 0..local.n {
+                write!(line, " {}", local.ty).unwrap();
+            }
+            line.push(')');
+            self.line(2, &line);
+        }
+
+        let results = func_ty.map(|ty| ty.return_types.len());
+        self.instrs(&func.body.0, results, 2)?;
+        self.line(1, ")");
+        Ok(())
+    }
+
+    /// Prints a sequence of instructions at the given depth
+    ///
+    /// `results` is the number of results of the enclosing function, if known
+    fn instrs(
+        &mut self,
+        instrs: &[Instruction],
+        results: Option<usize>,
+        depth: usize,
+    ) -> Result<()> {
+        match self.style {
+            Style::Flat => self.flat(instrs, depth),
+            Style::Folded => {
+                let mut labels = vec![results.unwrap_or(0)];
+                let nodes = self.fold(instrs, &mut labels, results)?;
+                for node in nodes.iter() {
+                    self.node(node, depth);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn flat(&mut self, instrs: &[Instruction], depth: usize) -> Result<()> {
+        for instr in instrs {
+            match instr {
+                Instruction::Block { ty, instrs } | Instruction::Loop { ty, instrs } => {
+                    self.line(depth, &block_header(instr, ty));
+                    self.flat(instrs, depth + 1)?;
+                    self.line(depth, "end");
+                }
+                Instruction::If {
+                    ty,
+                    accept_instrs,
+                    reject_instrs,
+                } => {
+                    self.line(depth, &block_header(instr, ty));
+                    self.flat(accept_instrs, depth + 1)?;
+                    if let Some(reject_instrs) = reject_instrs {
+                        self.line(depth, "else");
+                        self.flat(reject_instrs, depth + 1)?;
+                    }
+                    self.line(depth, "end");
+                }
+                Instruction::TryTable { ty, instrs, .. } => {
+                    self.line(depth, &block_header(instr, ty));
+                    self.flat(instrs, depth + 1)?;
+                    self.line(depth, "end");
+                }
+                Instruction::Try {
+                    ty,
+                    instrs,
+                    handler,
+                } => {
+                    self.line(depth, &block_header(instr, ty));
+                    self.flat(instrs, depth + 1)?;
+                    match handler {
+                        TryHandler::Catches { catches, catch_all } => {
+                            for (tag, instrs) in catches {
+                                self.line(depth, &format!("catch {}", tag));
+                                self.flat(instrs, depth + 1)?;
+                            }
+                            if let Some(instrs) = catch_all {
+                                self.line(depth, "catch_all");
+                                self.flat(instrs, depth + 1)?;
+                            }
+                            self.line(depth, "end");
+                        }
+                        TryHandler::Delegate(label) => {
+                            self.line(depth, &format!("delegate {}", label))
+                        }
+                    }
+                }
+                _ => self.line(depth, &plain(instr)?),
+            }
+        }
+        Ok(())
+    }
+
+    fn node(&mut self, node: &Node, depth: usize) {
+        self.indent(depth);
+        if node.is_inline() {
+            node.write_inline(&mut self.out);
+            self.out.push('\n');
+            return;
+        }
+
+        self.out.push('(');
+        self.out.push_str(&node.text);
+        self.out.push('\n');
+        for operand in node.operands.iter() {
+            self.node(operand, depth + 1);
+        }
+        for (keyword, body) in node.bodies.iter() {
+            match keyword {
+                Some(keyword) if body.is_empty() => self.line(depth + 1, &format!("({})", keyword)),
+                Some(keyword) => {
+                    self.line(depth + 1, &format!("({}", keyword));
+                    for node in body.iter() {
+                        self.node(node, depth + 2);
+                    }
+                    self.line(depth + 1, ")");
+                }
+                None => {
+                    for node in body.iter() {
+                        self.node(node, depth + 1);
+                    }
+                }
+            }
+        }
+        self.line(depth, ")");
+    }
+
+    /// Prints a constant expression on a single line
+    fn inline_expr(&self, instrs: &[Instruction]) -> Result<String> {
+        let mut out = String::new();
+        match self.style {
+            Style::Flat => {
+                for instr in instrs {
+                    if !out.is_empty() {
+                        out.push(' ');
+                    }
+                    out.push_str(&plain(instr)?);
+                }
+            }
+            Style::Folded => {
+                for node in self.fold(instrs, &mut Vec::new(), None)?.iter() {
+                    if !out.is_empty() {
+                        out.push(' ');
+                    }
+                    node.write_inline(&mut out);
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    /// Prints the offset expression of a segment
+    fn offset(&self, instrs: &[Instruction]) -> Result<String> {
+        self.wrapped_expr("offset", instrs)
+    }
+
+    /// Prints a constant expression, wrapped in `(keyword ...)` unless it's a single
+    /// folded instruction
+    fn wrapped_expr(&self, keyword: &str, instrs: &[Instruction]) -> Result<String> {
+        let expr = self.inline_expr(instrs)?;
+        Ok(match self.style {
+            Style::Flat if instrs.len() == 1 => format!("({})", expr),
+            Style::Folded if self.fold(instrs, &mut Vec::new(), None)?.len() == 1 => expr,
+            _ => format!("({} {})", keyword, expr),
+        })
+    }
+
+    fn func_type(&self, idx: TypeIdx) -> Option<&'m types::FunctionType> {
+        self.types.get(idx as usize).and_then(|ty| ty.func_type())
+    }
+
+    /// The number of fields of a struct type
+    fn fields(&self, idx: TypeIdx) -> Option<usize> {
+        match self.types.get(idx as usize).map(|ty| &ty.composite) {
+            Some(types::CompositeType::Struct(fields)) => Some(fields.len()),
+            _ => None,
+        }
+    }
+
+    /// The number of values taken and produced by a block type
+    fn block_arity(&self, ty: &BlockType) -> (usize, usize) {
+        match ty {
+            BlockType::Empty => (0, 0),
+            BlockType::Type(_) => (0, 1),
+            BlockType::TypeIdx(idx) => match self.func_type(*idx) {
+                Some(ty) => (ty.parameter_types.len(), ty.return_types.len()),
+                None => (0, 0),
+            },
+        }
+    }
+
+    fn func_arity(&self, ty: Option<TypeIdx>) -> (usize, usize) {
+        match ty.and_then(|ty| self.func_type(ty)) {
+            Some(ty) => (ty.parameter_types.len(), ty.return_types.len()),
+            None => (0, 0),
+        }
+    }
+
+    /// The number of operands consumed and results produced by an instruction
+    ///
+    /// `labels` holds the arity of the labels in scope, innermost last. Unknown
+    /// arities are reported as `(0, 0)` which just prevents folding
+    fn arity(
+        &self,
+        instr: &Instruction,
+        labels: &[usize],
+        results: Option<usize>,
+    ) -> (usize, usize) {
+        let label = |idx: LabelIdx| {
+            labels
+                .len()
+                .checked_sub(idx as usize + 1)
+                .map(|i| labels[i])
+        };
+
+        match instr {
+            Instruction::Unreachable | Instruction::NOP => (0, 0),
+            // The parameters of a block can't be folded into it
+            Instruction::Block { ty, .. } | Instruction::Loop { ty, .. } => {
+                (0, self.block_arity(ty).1)
+            }
+            Instruction::If { ty, .. } => {
+                let (params, results) = self.block_arity(ty);
+                (params + 1, results)
+            }
+            Instruction::TryTable { ty, .. } | Instruction::Try { ty, .. } => {
+                (0, self.block_arity(ty).1)
+            }
+            Instruction::Throw(tag) => {
+                let (params, _) = self.func_arity(self.tags.get(*tag as usize).copied());
+                (params, 0)
+            }
+            Instruction::ThrowRef => (1, 0),
+            Instruction::Rethrow(_) => (0, 0),
+            Instruction::Branch(idx) => (label(*idx).unwrap_or(0), 0),
+            Instruction::BranchIf(idx) => match label(*idx) {
+                Some(arity) => (arity + 1, arity),
+                None => (0, 0),
+            },
+            Instruction::BranchTable { operand, .. } => match label(*operand) {
+                Some(arity) => (arity + 1, 0),
+                None => (0, 0),
+            },
+            Instruction::Return => (results.unwrap_or(0), 0),
+            Instruction::Call(idx) => self.func_arity(self.funcs.get(*idx as usize).copied()),
+            Instruction::CallIndirect { ty, .. } => match self.func_type(*ty) {
+                Some(ty) => (ty.parameter_types.len() + 1, ty.return_types.len()),
+                None => (0, 0),
+            },
+            Instruction::ReturnCall(idx) => {
+                let (params, _) = self.func_arity(self.funcs.get(*idx as usize).copied());
+                (params, 0)
+            }
+            Instruction::ReturnCallIndirect { ty, .. } => match self.func_type(*ty) {
+                Some(ty) => (ty.parameter_types.len() + 1, 0),
+                None => (0, 0),
+            },
+            Instruction::CallRef(ty) => match self.func_type(*ty) {
+                Some(ty) => (ty.parameter_types.len() + 1, ty.return_types.len()),
+                None => (0, 0),
+            },
+            Instruction::ReturnCallRef(ty) => match self.func_type(*ty) {
+                Some(ty) => (ty.parameter_types.len() + 1, 0),
+                None => (0, 0),
+            },
+            Instruction::RefAsNonNull => (1, 1),
+            // The reference is kept on the stack when br_on_null doesn't branch
+            Instruction::BranchOnNull(idx) => match label(*idx) {
+                Some(arity) => (arity + 1, arity + 1),
+                None => (0, 0),
+            },
+            // The label receives the reference, which is dropped when it's null
+            Instruction::BranchOnNonNull(idx) => match label(*idx) {
+                Some(arity) => (arity, arity.saturating_sub(1)),
+                None => (0, 0),
+            },
+            Instruction::Drop => (1, 0),
+            Instruction::Select | Instruction::TypedSelect(_) => (3, 1),
+            Instruction::LocalGet(_) | Instruction::GlobalGet(_) => (0, 1),
+            Instruction::LocalSet(_) | Instruction::GlobalSet(_) => (1, 0),
+            Instruction::TableGet(_) => (1, 1),
+            Instruction::TableSet(_) => (2, 0),
+            Instruction::RefNull(_) | Instruction::RefFunc(_) | Instruction::TableSize(_) => (0, 1),
+            Instruction::RefIsNull => (1, 1),
+            Instruction::RefEq => (2, 1),
+            Instruction::StructNew(ty) => match self.fields(*ty) {
+                Some(fields) => (fields, 1),
+                None => (0, 0),
+            },
+            Instruction::StructNewDefault(_) => (0, 1),
+            Instruction::StructGet { .. } => (1, 1),
+            Instruction::StructSet { .. } => (2, 0),
+            Instruction::ArrayNew(_) => (2, 1),
+            Instruction::ArrayNewDefault(_) => (1, 1),
+            Instruction::ArrayNewFixed { len, .. } => (*len as usize, 1),
+            Instruction::ArrayNewData { .. } | Instruction::ArrayNewElem { .. } => (2, 1),
+            Instruction::ArrayGet { .. } => (2, 1),
+            Instruction::ArraySet(_) => (3, 0),
+            Instruction::ArrayFill(_)
+            | Instruction::ArrayInitData { .. }
+            | Instruction::ArrayInitElem { .. } => (4, 0),
+            Instruction::ArrayCopy { .. } => (5, 0),
+            Instruction::BranchOnCast { label: idx, .. }
+            | Instruction::BranchOnCastFail { label: idx, .. } => match label(*idx) {
+                Some(arity) => (arity, arity),
+                None => (0, 0),
+            },
+            Instruction::ArrayLen
+            | Instruction::RefTest(_)
+            | Instruction::RefCast(_)
+            | Instruction::AnyConvertExtern
+            | Instruction::ExternConvertAny
+            | Instruction::RefI31
+            | Instruction::I31Get(_) => (1, 1),
+            Instruction::TableGrow(_) => (2, 1),
+            Instruction::TableFill(_)
+            | Instruction::MemoryInit { .. }
+            | Instruction::MemoryCopy { .. }
+            | Instruction::MemoryFill(_)
+            | Instruction::TableInit { .. }
+            | Instruction::TableCopy { .. } => (3, 0),
+            Instruction::DataDrop(_) | Instruction::ElemDrop(_) => (0, 0),
+            Instruction::LocalTee(_) => (1, 1),
+            Instruction::Load { .. }
+            | Instruction::V128LoadExtend { .. }
+            | Instruction::V128LoadSplat { .. }
+            | Instruction::V128LoadZero { .. } => (1, 1),
+            Instruction::V128LoadLane { .. } => (2, 1),
+            Instruction::V128StoreLane { .. } => (2, 0),
+            Instruction::Splat(_)
+            | Instruction::ExtractLane { .. }
+            | Instruction::V128Not
+            | Instruction::V128AnyTrue
+            | Instruction::VectorAbsolute(_)
+            | Instruction::VectorNegate(_)
+            | Instruction::I8x16CountOnes
+            | Instruction::VectorAllTrue(_)
+            | Instruction::VectorBitmask(_)
+            | Instruction::VectorExtend { .. }
+            | Instruction::VectorExtAddPairwise { .. }
+            | Instruction::VectorCeil(_)
+            | Instruction::VectorFloor(_)
+            | Instruction::VectorTruncate(_)
+            | Instruction::VectorNearest(_)
+            | Instruction::VectorSquareRoot(_)
+            | Instruction::VectorTruncateSaturate { .. }
+            | Instruction::VectorConvert { .. }
+            | Instruction::F32x4DemoteF64x2Zero
+            | Instruction::F64x2PromoteLowF32x4 => (1, 1),
+            Instruction::I8x16Shuffle(_)
+            | Instruction::I8x16Swizzle
+            | Instruction::ReplaceLane { .. }
+            | Instruction::VectorEqual(_)
+            | Instruction::VectorNotEqual(_)
+            | Instruction::VectorLessThan { .. }
+            | Instruction::VectorGreaterThan { .. }
+            | Instruction::VectorLessOrEqual { .. }
+            | Instruction::VectorGreaterOrEqual { .. }
+            | Instruction::V128And
+            | Instruction::V128AndNot
+            | Instruction::V128Or
+            | Instruction::V128Xor
+            | Instruction::VectorNarrow { .. }
+            | Instruction::VectorShiftLeft(_)
+            | Instruction::VectorShiftRight { .. }
+            | Instruction::VectorAdd(_)
+            | Instruction::VectorAddSaturate { .. }
+            | Instruction::VectorSubtract(_)
+            | Instruction::VectorSubtractSaturate { .. }
+            | Instruction::VectorMultiply(_)
+            | Instruction::VectorMinimum { .. }
+            | Instruction::VectorMaximum { .. }
+            | Instruction::VectorAverage(_)
+            | Instruction::VectorExtMultiply { .. }
+            | Instruction::I16x8Q15MulrSat
+            | Instruction::I32x4DotI16x8
+            | Instruction::VectorDivision(_)
+            | Instruction::VectorPseudoMinimum(_)
+            | Instruction::VectorPseudoMaximum(_) => (2, 1),
+            Instruction::V128BitSelect => (3, 1),
+            Instruction::AtomicFence => (0, 0),
+            Instruction::AtomicLoad { .. } => (1, 1),
+            Instruction::AtomicStore { .. } => (2, 0),
+            Instruction::AtomicNotify(_) | Instruction::AtomicRmw { .. } => (2, 1),
+            Instruction::AtomicWait { .. } | Instruction::AtomicCompareExchange { .. } => (3, 1),
+            Instruction::Store { .. } => (2, 0),
+            Instruction::MemorySize(_) | Instruction::Const(_) => (0, 1),
+            Instruction::MemoryGrow(_) => (1, 1),
+            Instruction::EqualZero(_)
+            | Instruction::CountLeadingZero(_)
+            | Instruction::CountTrailingZero(_)
+            | Instruction::CountOnes(_)
+            | Instruction::Absolute(_)
+            | Instruction::Negate(_)
+            | Instruction::Ceil(_)
+            | Instruction::Floor(_)
+            | Instruction::Truncate(_)
+            | Instruction::Nearest(_)
+            | Instruction::SquareRoot(_)
+            | Instruction::I32Wrap
+            | Instruction::I32Extend(_)
+            | Instruction::I32Truncate { .. }
+            | Instruction::Convert { .. }
+            | Instruction::FloatDemote
+            | Instruction::FloatPromote
+            | Instruction::I32ReI32erpret
+            | Instruction::LongReI32erpret
+            | Instruction::FloatReI32erpret
+            | Instruction::DoubleReI32erpret
+            | Instruction::Extend { .. }
+            | Instruction::SaturateTruncate { .. } => (1, 1),
+            Instruction::Equal(_)
+            | Instruction::NotEqual(_)
+            | Instruction::LessThanI32 { .. }
+            | Instruction::GreaterThanI32 { .. }
+            | Instruction::LessOrEqualI32 { .. }
+            | Instruction::GreaterOrEqualI32 { .. }
+            | Instruction::LessThanFloat(_)
+            | Instruction::GreaterThanFloat(_)
+            | Instruction::LessOrEqualFloat(_)
+            | Instruction::GreaterOrEqualFloat(_)
+            | Instruction::Add(_)
+            | Instruction::Subtract(_)
+            | Instruction::Multiply(_)
+            | Instruction::I32Division { .. }
+            | Instruction::FloatDivision(_)
+            | Instruction::Remainder { .. }
+            | Instruction::And(_)
+            | Instruction::Or(_)
+            | Instruction::Xor(_)
+            | Instruction::ShiftLeft(_)
+            | Instruction::ShiftRight { .. }
+            | Instruction::LeftRotation(_)
+            | Instruction::RightRotation(_)
+            | Instruction::Minimum(_)
+            | Instruction::Maximum(_)
+            | Instruction::CopySign(_) => (2, 1),
+        }
+    }
+
+    /// Groups a sequence of instructions into folded trees
+    ///
+    /// An instruction takes the trees before it as operands only when they produce exactly
+    /// the values it consumes, so unfolding the trees always gives back the same sequence
+    fn fold(
+        &self,
+        instrs: &[Instruction],
+        labels: &mut Vec<usize>,
+        results: Option<usize>,
+    ) -> Result<Vec<Node>> {
+        let mut nodes = Vec::new();
+        // Trees producing a single value that haven't been consumed yet
+        let mut pending: Vec<Node> = Vec::new();
+
+        for instr in instrs {
+            let (pops, pushes) = self.arity(instr, labels, results);
+
+            let mut node = match instr {
+                Instruction::Block { ty, instrs } | Instruction::Loop { ty, instrs } => {
+                    let (params, results_len) = self.block_arity(ty);
+                    labels.push(match instr {
+                        Instruction::Loop { .. } => params,
+                        _ => results_len,
+                    });
+                    let body = self.fold(instrs, labels, results)?;
+                    labels.pop();
+                    Node {
+                        text: block_header(instr, ty),
+                        operands: Vec::new(),
+                        bodies: vec![(None, body)],
+                    }
+                }
+                Instruction::If {
+                    ty,
+                    accept_instrs,
+                    reject_instrs,
+                } => {
+                    labels.push(self.block_arity(ty).1);
+                    let mut bodies = vec![(
+                        Some("then".to_string()),
+                        self.fold(accept_instrs, labels, results)?,
+                    )];
+                    if let Some(reject_instrs) = reject_instrs {
+                        bodies.push((
+                            Some("else".to_string()),
+                            self.fold(reject_instrs, labels, results)?,
+                        ));
+                    }
+                    labels.pop();
+                    Node {
+                        text: block_header(instr, ty),
+                        operands: Vec::new(),
+                        bodies,
+                    }
+                }
+                Instruction::TryTable { ty, instrs, .. } => {
+                    labels.push(self.block_arity(ty).1);
+                    let body = self.fold(instrs, labels, results)?;
+                    labels.pop();
+                    Node {
+                        text: block_header(instr, ty),
+                        operands: Vec::new(),
+                        bodies: vec![(None, body)],
+                    }
+                }
+                Instruction::Try {
+                    ty,
+                    instrs,
+                    handler,
+                } => {
+                    labels.push(self.block_arity(ty).1);
+                    let mut bodies =
+                        vec![(Some("do".to_string()), self.fold(instrs, labels, results)?)];
+                    match handler {
+                        TryHandler::Catches { catches, catch_all } => {
+                            for (tag, instrs) in catches {
+                                let body = self.fold(instrs, labels, results)?;
+                                bodies.push((Some(format!("catch {}", tag)), body));
+                            }
+                            if let Some(instrs) = catch_all {
+                                let body = self.fold(instrs, labels, results)?;
+                                bodies.push((Some("catch_all".to_string()), body));
+                            }
+                        }
+                        TryHandler::Delegate(label) => {
+                            bodies.push((Some(format!("delegate {}", label)), Vec::new()))
+                        }
+                    }
+                    labels.pop();
+                    Node {
+                        text: block_header(instr, ty),
+                        operands: Vec::new(),
+                        bodies,
+                    }
+                }
+                _ => Node {
+                    text: plain(instr)?,
+                    operands: Vec::new(),
+                    bodies: Vec::new(),
+                },
+            };
+
+            if pops <= pending.len() {
+                node.operands = pending.split_off(pending.len() - pops);
+            } else {
+                nodes.append(&mut pending);
+            }
+
+            if pushes == 1 {
+                pending.push(node);
+            } else {
+                nodes.append(&mut pending);
+                nodes.push(node);
+            }
+        }
+
+        nodes.append(&mut pending);
+        Ok(nodes)
+    }
+}
+
+fn block_header(instr: &Instruction, ty: &BlockType) -> String {
+    let mut header = String::from(match instr {
+        Instruction::Block { .. } => "block",
+        Instruction::Loop { .. } => "loop",
+        Instruction::TryTable { .. } => "try_table",
+        Instruction::Try { .. } => "try",
+        _ => "if",
+    });
+    match ty {
+        BlockType::Empty => {}
+        BlockType::Type(ty) => write!(header, " (result {})", ty).unwrap(),
+        BlockType::TypeIdx(idx) => write!(header, " (type {})", idx).unwrap(),
+    }
+    if let Instruction::TryTable { catches, .. } = instr {
+        for catch in catches {
+            match catch {
+                Catch::Catch { tag, label } => write!(header, " (catch {} {})", tag, label),
+                Catch::CatchRef { tag, label } => {
+                    write!(header, " (catch_ref {} {})", tag, label)
+                }
+                Catch::CatchAll(label) => write!(header, " (catch_all {})", label),
+                Catch::CatchAllRef(label) => write!(header, " (catch_all_ref {})", label),
+            }
+            .unwrap();
+        }
+    }
+    header
+}
+
+fn func_type(ty: &types::FunctionType) -> String {
+    let mut out = String::new();
+    if !ty.parameter_types.is_empty() {
+        out.push_str(" (param");
+        for ty in ty.parameter_types.iter() {
+            write!(out, " {}", ty).unwrap();
+        }
+        out.push(')');
+    }
+    if !ty.return_types.is_empty() {
+        out.push_str(" (result");
+        for ty in ty.return_types.iter() {
+            write!(out, " {}", ty).unwrap();
+        }
+        out.push(')');
+    }
+    out
+}
+
+/// Prints a type definition without its `(type ...)` wrapper
+fn sub_type(ty: &types::SubType) -> String {
+    let composite = match &ty.composite {
+        types::CompositeType::Func(ty) => format!("(func{})", func_type(ty)),
+        types::CompositeType::Struct(fields) => {
+            let mut out = String::from("(struct");
+            for field in fields {
+                write!(out, " (field {})", field_type(field)).unwrap();
+            }
+            out.push(')');
+            out
+        }
+        types::CompositeType::Array(field) => format!("(array {})", field_type(field)),
+    };
+    if ty.is_final && ty.supertype.is_none() {
+        return composite;
+    }
+
+    let mut out = String::from("(sub");
+    if ty.is_final {
+        out.push_str(" final");
+    }
+    if let Some(supertype) = ty.supertype {
+        write!(out, " {}", supertype).unwrap();
+    }
+    write!(out, " {})", composite).unwrap();
+    out
+}
+
+fn field_type(ty: &types::FieldType) -> String {
+    match ty.mutable {
+        true => format!("(mut {})", ty.storage),
+        false => ty.storage.to_string(),
+    }
+}
+
+fn limits(lim: &types::Limits) -> String {
+    match lim.max {
+        Some(max) => format!("{} {}", lim.min, max),
+        None => lim.min.to_string(),
+    }
+}
+
+fn memory_type(ty: &types::MemoryType) -> String {
+    let mut out = index_type(ty.memory64);
+    out.push_str(&limits(&ty.lim));
+    if ty.shared {
+        out.push_str(" shared");
+    }
+    out
+}
+
+fn table_type(ty: &types::TableType) -> String {
+    format!(
+        "{}{} {}",
+        index_type(ty.table64),
+        limits(&ty.lim),
+        ty.elem_type
+    )
+}
+
+/// The index type of a memory or table followed by a space, empty for the default `i32`
+fn index_type(is_64: bool) -> String {
+    match is_64 {
+        true => "i64 ".to_string(),
+        false => String::new(),
+    }
+}
+
+fn global_type(ty: &types::GlobalType) -> String {
+    if ty.mutable {
+        format!("(mut {})", ty.ty)
+    } else {
+        ty.ty.to_string()
+    }
+}
+
+/// Prints a string literal, escaping anything that isn't printable ASCII
+fn string(bytes: &[u8]) -> String {
+    let mut out = String::from("\"");
+    for byte in bytes.iter() {
+        match byte {
+            b'"' => out.push_str("\\\""),
+            b'\\' => out.push_str("\\\\"),
+            0x20..=0x7E => out.push(*byte as char),
+            _ => write!(out, "\\{:02x}", byte).unwrap(),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn f32_literal(value: f32) -> String {
+    if value.is_nan() {
+        let bits = value.to_bits();
+        let sign = if bits >> 31 != 0 { "-" } else { "" };
+        match bits & 0x7F_FFFF {
+            0x40_0000 => format!("{}nan", sign),
+            payload => format!("{}nan:0x{:x}", sign, payload),
+        }
+    } else if value.is_infinite() {
+        if value < 0.0 { "-inf" } else { "inf" }.to_string()
+    } else {
+        format!("{:?}", value)
+    }
+}
+
+fn f64_literal(value: f64) -> String {
+    if value.is_nan() {
+        let bits = value.to_bits();
+        let sign = if bits >> 63 != 0 { "-" } else { "" };
+        match bits & 0xF_FFFF_FFFF_FFFF {
+            0x8_0000_0000_0000 => format!("{}nan", sign),
+            payload => format!("{}nan:0x{:x}", sign, payload),
+        }
+    } else if value.is_infinite() {
+        if value < 0.0 { "-inf" } else { "inf" }.to_string()
+    } else {
+        format!("{:?}", value)
+    }
+}
+
+/// The memory used by an instruction, which is left out for memory 0
+fn memory_index(mem: MemoryIdx) -> String {
+    match mem {
+        0 => String::new(),
+        mem => format!(" {}", mem),
+    }
+}
+
+fn memarg(mem: &MemoryArgument, natural: u32) -> String {
+    let mut out = memory_index(mem.memory);
+    if mem.offset != 0 {
+        write!(out, " offset={}", mem.offset).unwrap();
+    }
+    if mem.alignment != natural {
+        match 1u64.checked_shl(mem.alignment) {
+            Some(align) => write!(out, " align={}", align).unwrap(),
+            None => write!(out, " align=2**{}", mem.alignment).unwrap(),
+        }
+    }
+    out
+}
+
+fn storage_bits(storage: StorageType) -> u32 {
+    match storage {
+        StorageType::I8 => 8,
+        StorageType::I16 => 16,
+        StorageType::I32 => 32,
+    }
+}
+
+fn sign(signed: bool) -> &'static str {
+    if signed {
+        "s"
+    } else {
+        "u"
+    }
+}
+
+/// The suffix of instructions that only take a signedness for integer lanes
+fn lane_sign(signed: Option<bool>) -> &'static str {
+    match signed {
+        Some(true) => "_s",
+        Some(false) => "_u",
+        None => "",
+    }
+}
+
+/// Prints a vector instruction along with its immediates
+pub(crate) fn vector(instr: &Instruction) -> Result<String> {
+    // Rejects the shapes that have no encoding
+    instr.simd_opcode()?;
+    let natural = instr.natural_alignment().unwrap_or(0);
+
+    Ok(match instr {
+        Instruction::V128LoadExtend { mem, shape, signed } => {
+            let half = shape.half().unwrap();
+            format!(
+                "v128.load{}x{}_{}{}",
+                8 << half.lane_alignment(),
+                shape.lanes(),
+                sign(*signed),
+                memarg(mem, natural)
+            )
+        }
+        Instruction::V128LoadSplat { mem, shape } => format!(
+            "v128.load{}_splat{}",
+            8 << shape.lane_alignment(),
+            memarg(mem, natural)
+        ),
+        Instruction::V128LoadZero { mem, shape } => format!(
+            "v128.load{}_zero{}",
+            8 << shape.lane_alignment(),
+            memarg(mem, natural)
+        ),
+        Instruction::V128LoadLane { mem, shape, lane } => format!(
+            "v128.load{}_lane{} {}",
+            8 << shape.lane_alignment(),
+            memarg(mem, natural),
+            lane
+        ),
+        Instruction::V128StoreLane { mem, shape, lane } => format!(
+            "v128.store{}_lane{} {}",
+            8 << shape.lane_alignment(),
+            memarg(mem, natural),
+            lane
+        ),
+        Instruction::I8x16Shuffle(lanes) => {
+            let mut out = String::from("i8x16.shuffle");
+            for lane in lanes.iter() {
+                write!(out, " {}", lane).unwrap();
+            }
+            out
+        }
+        Instruction::I8x16Swizzle => "i8x16.swizzle".to_string(),
+        Instruction::Splat(shape) => format!("{}.splat", shape),
+        Instruction::ExtractLane {
+            shape,
+            lane,
+            signed,
+        } => format!("{}.extract_lane{} {}", shape, lane_sign(*signed), lane),
+        Instruction::ReplaceLane { shape, lane } => format!("{}.replace_lane {}", shape, lane),
+        Instruction::VectorEqual(shape) => format!("{}.eq", shape),
+        Instruction::VectorNotEqual(shape) => format!("{}.ne", shape),
+        Instruction::VectorLessThan { shape, signed } => {
+            format!("{}.lt{}", shape, lane_sign(*signed))
+        }
+        Instruction::VectorGreaterThan { shape, signed } => {
+            format!("{}.gt{}", shape, lane_sign(*signed))
+        }
+        Instruction::VectorLessOrEqual { shape, signed } => {
+            format!("{}.le{}", shape, lane_sign(*signed))
+        }
+        Instruction::VectorGreaterOrEqual { shape, signed } => {
+            format!("{}.ge{}", shape, lane_sign(*signed))
+        }
+        Instruction::V128Not => "v128.not".to_string(),
+        Instruction::V128And => "v128.and".to_string(),
+        Instruction::V128AndNot => "v128.andnot".to_string(),
+        Instruction::V128Or => "v128.or".to_string(),
+        Instruction::V128Xor => "v128.xor".to_string(),
+        Instruction::V128BitSelect => "v128.bitselect".to_string(),
+        Instruction::V128AnyTrue => "v128.any_true".to_string(),
+        Instruction::VectorAbsolute(shape) => format!("{}.abs", shape),
+        Instruction::VectorNegate(shape) => format!("{}.neg", shape),
+        Instruction::I8x16CountOnes => "i8x16.popcnt".to_string(),
+        Instruction::VectorAllTrue(shape) => format!("{}.all_true", shape),
+        Instruction::VectorBitmask(shape) => format!("{}.bitmask", shape),
+        Instruction::VectorNarrow { shape, signed } => {
+            let wide = match shape {
+                LaneShape::I8x16 => LaneShape::I16x8,
+                _ => LaneShape::I32x4,
+            };
+            format!("{}.narrow_{}_{}", shape, wide, sign(*signed))
+        }
+        Instruction::VectorExtend {
+            shape,
+            high,
+            signed,
+        } => format!(
+            "{}.extend_{}_{}_{}",
+            shape,
+            half(*high),
+            shape.half().unwrap(),
+            sign(*signed)
+        ),
+        Instruction::VectorShiftLeft(shape) => format!("{}.shl", shape),
+        Instruction::VectorShiftRight { shape, signed } => {
+            format!("{}.shr_{}", shape, sign(*signed))
+        }
+        Instruction::VectorAdd(shape) => format!("{}.add", shape),
+        Instruction::VectorAddSaturate { shape, signed } => {
+            format!("{}.add_sat_{}", shape, sign(*signed))
+        }
+        Instruction::VectorSubtract(shape) => format!("{}.sub", shape),
+        Instruction::VectorSubtractSaturate { shape, signed } => {
+            format!("{}.sub_sat_{}", shape, sign(*signed))
+        }
+        Instruction::VectorMultiply(shape) => format!("{}.mul", shape),
+        Instruction::VectorMinimum { shape, signed } => {
+            format!("{}.min{}", shape, lane_sign(*signed))
+        }
+        Instruction::VectorMaximum { shape, signed } => {
+            format!("{}.max{}", shape, lane_sign(*signed))
+        }
+        Instruction::VectorAverage(shape) => format!("{}.avgr_u", shape),
+        Instruction::VectorExtAddPairwise { shape, signed } => format!(
+            "{}.extadd_pairwise_{}_{}",
+            shape,
+            shape.half().unwrap(),
+            sign(*signed)
+        ),
+        Instruction::VectorExtMultiply {
+            shape,
+            high,
+            signed,
+        } => format!(
+            "{}.extmul_{}_{}_{}",
+            shape,
+            half(*high),
+            shape.half().unwrap(),
+            sign(*signed)
+        ),
+        Instruction::I16x8Q15MulrSat => "i16x8.q15mulr_sat_s".to_string(),
+        Instruction::I32x4DotI16x8 => "i32x4.dot_i16x8_s".to_string(),
+        Instruction::VectorCeil(shape) => format!("{}.ceil", shape),
+        Instruction::VectorFloor(shape) => format!("{}.floor", shape),
+        Instruction::VectorTruncate(shape) => format!("{}.trunc", shape),
+        Instruction::VectorNearest(shape) => format!("{}.nearest", shape),
+        Instruction::VectorSquareRoot(shape) => format!("{}.sqrt", shape),
+        Instruction::VectorDivision(shape) => format!("{}.div", shape),
+        Instruction::VectorPseudoMinimum(shape) => format!("{}.pmin", shape),
+        Instruction::VectorPseudoMaximum(shape) => format!("{}.pmax", shape),
+        Instruction::VectorTruncateSaturate { float, signed } => match float {
+            FloatType::F32 => format!("i32x4.trunc_sat_f32x4_{}", sign(*signed)),
+            FloatType::F64 => format!("i32x4.trunc_sat_f64x2_{}_zero", sign(*signed)),
+        },
+        Instruction::VectorConvert { float, signed } => match float {
+            FloatType::F32 => format!("f32x4.convert_i32x4_{}", sign(*signed)),
+            FloatType::F64 => format!("f64x2.convert_low_i32x4_{}", sign(*signed)),
+        },
+        Instruction::F32x4DemoteF64x2Zero => "f32x4.demote_f64x2_zero".to_string(),
+        Instruction::F64x2PromoteLowF32x4 => "f64x2.promote_low_f32x4".to_string(),
+        _ => return Err(instr.invalid("not a vector instruction")),
+    })
+}
+
+fn half(high: bool) -> &'static str {
+    if high {
+        "high"
+    } else {
+        "low"
+    }
+}
+
+/// Prints an instruction that doesn't contain other instructions along with its immediates
+fn plain(instr: &Instruction) -> Result<String> {
+    Ok(match instr {
+        Instruction::Unreachable => "unreachable".to_string(),
+        Instruction::NOP => "nop".to_string(),
+        Instruction::Block { .. }
+        | Instruction::Loop { .. }
+        | Instruction::If { .. }
+        | Instruction::TryTable { .. }
+        | Instruction::Try { .. } => unreachable!("blocks are printed by the caller"),
+        Instruction::Throw(tag) => format!("throw {}", tag),
+        Instruction::ThrowRef => "throw_ref".to_string(),
+        Instruction::Rethrow(label) => format!("rethrow {}", label),
+        Instruction::Branch(idx) => format!("br {}", idx),
+        Instruction::BranchIf(idx) => format!("br_if {}", idx),
+        Instruction::BranchTable { labels, operand } => {
+            let mut out = String::from("br_table");
+            for label in labels.iter().chain(std::iter::once(operand)) {
+                write!(out, " {}", label).unwrap();
+            }
+            out
+        }
+        Instruction::Return => "return".to_string(),
+        Instruction::Call(idx) => format!("call {}", idx),
+        Instruction::CallIndirect { ty, table: 0 } => format!("call_indirect (type {})", ty),
+        Instruction::CallIndirect { ty, table } => {
+            format!("call_indirect {} (type {})", table, ty)
+        }
+        Instruction::ReturnCall(idx) => format!("return_call {}", idx),
+        Instruction::ReturnCallIndirect { ty, table: 0 } => {
+            format!("return_call_indirect (type {})", ty)
+        }
+        Instruction::ReturnCallIndirect { ty, table } => {
+            format!("return_call_indirect {} (type {})", table, ty)
+        }
+        Instruction::CallRef(ty) => format!("call_ref {}", ty),
+        Instruction::ReturnCallRef(ty) => format!("return_call_ref {}", ty),
+        Instruction::RefAsNonNull => "ref.as_non_null".to_string(),
+        Instruction::BranchOnNull(idx) => format!("br_on_null {}", idx),
+        Instruction::BranchOnNonNull(idx) => format!("br_on_non_null {}", idx),
+        Instruction::Drop => "drop".to_string(),
+        Instruction::Select => "select".to_string(),
+        Instruction::TypedSelect(types) => {
+            let mut out = String::from("select (result");
+            for ty in types {
+                write!(out, " {}", ty).unwrap();
+            }
+            out.push(')');
+            out
+        }
+        Instruction::LocalGet(idx) => format!("local.get {}", idx),
+        Instruction::LocalSet(idx) => format!("local.set {}", idx),
+        Instruction::LocalTee(idx) => format!("local.tee {}", idx),
+        Instruction::GlobalGet(idx) => format!("global.get {}", idx),
+        Instruction::GlobalSet(idx) => format!("global.set {}", idx),
+        Instruction::TableGet(idx) => format!("table.get {}", idx),
+        Instruction::TableSet(idx) => format!("table.set {}", idx),
+        Instruction::Load { mem, ty, storage } => {
+            let natural = instr
+                .natural_alignment()
+                .ok_or_else(|| instr.invalid("references can't be loaded from memory"))?;
+            match (ty, storage) {
+                (_, None) => format!("{}.load{}", ty, memarg(mem, natural)),
+                (ValType::I32, Some((_, StorageType::I32))) => {
+                    return Err(instr.invalid("i32 loads can't have a 32 bit storage size"))
+                }
+                (ValType::I32, Some((signed, storage)))
+                | (ValType::I64, Some((signed, storage))) => format!(
+                    "{}.load{}_{}{}",
+                    ty,
+                    storage_bits(*storage),
+                    sign(*signed),
+                    memarg(mem, natural)
+                ),
+                (ValType::V128, Some(_)) => {
+                    return Err(instr.invalid("v128 loads can't have a storage size"))
+                }
+                _ => return Err(instr.invalid("float loads can't have a storage size")),
+            }
+        }
+        Instruction::Store { mem, ty, storage } => {
+            let natural = instr
+                .natural_alignment()
+                .ok_or_else(|| instr.invalid("references can't be stored in memory"))?;
+            match (ty, storage) {
+                (_, None) => format!("{}.store{}", ty, memarg(mem, natural)),
+                (ValType::I32, Some(StorageType::I32)) => {
+                    return Err(instr.invalid("i32 stores can't have a 32 bit storage size"))
+                }
+                (ValType::I32, Some(storage)) | (ValType::I64, Some(storage)) => format!(
+                    "{}.store{}{}",
+                    ty,
+                    storage_bits(*storage),
+                    memarg(mem, natural)
+                ),
+                (ValType::V128, Some(_)) => {
+                    return Err(instr.invalid("v128 stores can't have a storage size"))
+                }
+                _ => return Err(instr.invalid("float stores can't have a storage size")),
+            }
+        }
+        Instruction::MemorySize(mem) => format!("memory.size{}", memory_index(*mem)),
+        Instruction::MemoryGrow(mem) => format!("memory.grow{}", memory_index(*mem)),
+        Instruction::Const(literal) => match literal {
+            Literal::I32(value) => format!("i32.const {}", value),
+            Literal::I64(value) => format!("i64.const {}", value),
+            Literal::F32(value) => format!("f32.const {}", f32_literal(*value)),
+            Literal::F64(value) => format!("f64.const {}", f64_literal(*value)),
+            Literal::V128(bits) => {
+                let mut out = String::from("v128.const i32x4");
+                for lane in 0..4 {
+                    write!(out, " 0x{:08x}", (bits >> (lane * 32)) as u32).unwrap();
+                }
+                out
+            }
+        },
+        Instruction::Equal(ty)
+        | Instruction::NotEqual(ty)
+        | Instruction::Add(ty)
+        | Instruction::Subtract(ty)
+        | Instruction::Multiply(ty)
+            if ty.is_ref() =>
+        {
+            return Err(instr.invalid("references don't support arithmetic or comparisons"))
+        }
+        Instruction::Equal(ValType::V128)
+        | Instruction::NotEqual(ValType::V128)
+        | Instruction::Add(ValType::V128)
+        | Instruction::Subtract(ValType::V128)
+        | Instruction::Multiply(ValType::V128) => {
+            return Err(instr.invalid("vectors need a lane shape"))
+        }
+        Instruction::EqualZero(ty) => format!("{}.eqz", ty),
+        Instruction::Equal(ty) => format!("{}.eq", ty),
+        Instruction::NotEqual(ty) => format!("{}.ne", ty),
+        Instruction::LessThanI32 { ty, signed } => format!("{}.lt_{}", ty, sign(*signed)),
+        Instruction::GreaterThanI32 { ty, signed } => format!("{}.gt_{}", ty, sign(*signed)),
+        Instruction::LessOrEqualI32 { ty, signed } => format!("{}.le_{}", ty, sign(*signed)),
+        Instruction::GreaterOrEqualI32 { ty, signed } => format!("{}.ge_{}", ty, sign(*signed)),
+        Instruction::LessThanFloat(ty) => format!("{}.lt", ty),
+        Instruction::GreaterThanFloat(ty) => format!("{}.gt", ty),
+        Instruction::LessOrEqualFloat(ty) => format!("{}.le", ty),
+        Instruction::GreaterOrEqualFloat(ty) => format!("{}.ge", ty),
+        Instruction::CountLeadingZero(ty) => format!("{}.clz", ty),
+        Instruction::CountTrailingZero(ty) => format!("{}.ctz", ty),
+        Instruction::CountOnes(ty) => format!("{}.popcnt", ty),
+        Instruction::Add(ty) => format!("{}.add", ty),
+        Instruction::Subtract(ty) => format!("{}.sub", ty),
+        Instruction::Multiply(ty) => format!("{}.mul", ty),
+        Instruction::I32Division { ty, signed } => format!("{}.div_{}", ty, sign(*signed)),
+        Instruction::FloatDivision(ty) => format!("{}.div", ty),
+        Instruction::Remainder { ty, signed } => format!("{}.rem_{}", ty, sign(*signed)),
+        Instruction::And(ty) => format!("{}.and", ty),
+        Instruction::Or(ty) => format!("{}.or", ty),
+        Instruction::Xor(ty) => format!("{}.xor", ty),
+        Instruction::ShiftLeft(ty) => format!("{}.shl", ty),
+        Instruction::ShiftRight { ty, signed } => format!("{}.shr_{}", ty, sign(*signed)),
+        Instruction::LeftRotation(ty) => format!("{}.rotl", ty),
+        Instruction::RightRotation(ty) => format!("{}.rotr", ty),
+        Instruction::Absolute(ty) => format!("{}.abs", ty),
+        Instruction::Negate(ty) => format!("{}.neg", ty),
+        Instruction::Ceil(ty) => format!("{}.ceil", ty),
+        Instruction::Floor(ty) => format!("{}.floor", ty),
+        Instruction::Truncate(ty) => format!("{}.trunc", ty),
+        Instruction::Nearest(ty) => format!("{}.nearest", ty),
+        Instruction::SquareRoot(ty) => format!("{}.sqrt", ty),
+        Instruction::Minimum(ty) => format!("{}.min", ty),
+        Instruction::Maximum(ty) => format!("{}.max", ty),
+        Instruction::CopySign(ty) => format!("{}.copysign", ty),
+        Instruction::I32Wrap => "i32.wrap_i64".to_string(),
+        Instruction::I32Extend(signed) => format!("i64.extend_i32_{}", sign(*signed)),
+        Instruction::I32Truncate { ty, float, signed } => {
+            format!("{}.trunc_{}_{}", ty, float, sign(*signed))
+        }
+        Instruction::Convert { ty, tgt_ty, signed } => {
+            format!("{}.convert_{}_{}", ty, tgt_ty, sign(*signed))
+        }
+        Instruction::FloatDemote => "f32.demote_f64".to_string(),
+        Instruction::FloatPromote => "f64.promote_f32".to_string(),
+        Instruction::I32ReI32erpret => "i32.reinterpret_f32".to_string(),
+        Instruction::LongReI32erpret => "i64.reinterpret_f64".to_string(),
+        Instruction::FloatReI32erpret => "f32.reinterpret_i32".to_string(),
+        Instruction::DoubleReI32erpret => "f64.reinterpret_i64".to_string(),
+        Instruction::Extend { ty, base } => match (ty, base) {
+            (IntegerType::I32, StorageType::I32) => {
+                return Err(instr.invalid("i32 can't be extended from 32 bits"))
+            }
+            _ => format!("{}.extend{}_s", ty, storage_bits(*base)),
+        },
+        Instruction::RefNull(ty) => format!("ref.null {}", ty),
+        Instruction::RefIsNull => "ref.is_null".to_string(),
+        Instruction::RefFunc(idx) => format!("ref.func {}", idx),
+        Instruction::RefEq => "ref.eq".to_string(),
+        Instruction::StructNew(ty) => format!("struct.new {}", ty),
+        Instruction::StructNewDefault(ty) => format!("struct.new_default {}", ty),
+        Instruction::StructGet { ty, field, signed } => {
+            format!("struct.get{} {} {}", lane_sign(*signed), ty, field)
+        }
+        Instruction::StructSet { ty, field } => format!("struct.set {} {}", ty, field),
+        Instruction::ArrayNew(ty) => format!("array.new {}", ty),
+        Instruction::ArrayNewDefault(ty) => format!("array.new_default {}", ty),
+        Instruction::ArrayNewFixed { ty, len } => format!("array.new_fixed {} {}", ty, len),
+        Instruction::ArrayNewData { ty, data } => format!("array.new_data {} {}", ty, data),
+        Instruction::ArrayNewElem { ty, elem } => format!("array.new_elem {} {}", ty, elem),
+        Instruction::ArrayGet { ty, signed } => format!("array.get{} {}", lane_sign(*signed), ty),
+        Instruction::ArraySet(ty) => format!("array.set {}", ty),
+        Instruction::ArrayLen => "array.len".to_string(),
+        Instruction::ArrayFill(ty) => format!("array.fill {}", ty),
+        Instruction::ArrayCopy { dst, src } => format!("array.copy {} {}", dst, src),
+        Instruction::ArrayInitData { ty, data } => format!("array.init_data {} {}", ty, data),
+        Instruction::ArrayInitElem { ty, elem } => format!("array.init_elem {} {}", ty, elem),
+        Instruction::RefTest(ty) => format!("ref.test {}", ty),
+        Instruction::RefCast(ty) => format!("ref.cast {}", ty),
+        Instruction::BranchOnCast { label, from, to } => {
+            format!("br_on_cast {} {} {}", label, from, to)
+        }
+        Instruction::BranchOnCastFail { label, from, to } => {
+            format!("br_on_cast_fail {} {} {}", label, from, to)
+        }
+        Instruction::AnyConvertExtern => "any.convert_extern".to_string(),
+        Instruction::ExternConvertAny => "extern.convert_any".to_string(),
+        Instruction::RefI31 => "ref.i31".to_string(),
+        Instruction::I31Get(signed) => format!("i31.get_{}", sign(*signed)),
+        Instruction::SaturateTruncate { ty, float, signed } => {
+            format!("{}.trunc_sat_{}_{}", ty, float, sign(*signed))
+        }
+        Instruction::TableGrow(idx) => format!("table.grow {}", idx),
+        Instruction::TableSize(idx) => format!("table.size {}", idx),
+        Instruction::TableFill(idx) => format!("table.fill {}", idx),
+        Instruction::MemoryInit { data, mem } => {
+            format!("memory.init{} {}", memory_index(*mem), data)
+        }
+        Instruction::DataDrop(idx) => format!("data.drop {}", idx),
+        Instruction::MemoryCopy { dst: 0, src: 0 } => "memory.copy".to_string(),
+        Instruction::MemoryCopy { dst, src } => format!("memory.copy {} {}", dst, src),
+        Instruction::MemoryFill(mem) => format!("memory.fill{}", memory_index(*mem)),
+        Instruction::TableInit { elem, table } => format!("table.init {} {}", table, elem),
+        Instruction::ElemDrop(idx) => format!("elem.drop {}", idx),
+        Instruction::TableCopy { dst, src } => format!("table.copy {} {}", dst, src),
+        Instruction::AtomicNotify(mem) => format!("memory.atomic.notify{}", memarg(mem, 2)),
+        Instruction::AtomicWait { mem, ty } => match ty {
+            IntegerType::I32 => format!("memory.atomic.wait32{}", memarg(mem, 2)),
+            IntegerType::I64 => format!("memory.atomic.wait64{}", memarg(mem, 3)),
+        },
+        Instruction::AtomicFence => "atomic.fence".to_string(),
+        Instruction::AtomicLoad { mem, ty, storage }
+        | Instruction::AtomicStore { mem, ty, storage }
+        | Instruction::AtomicRmw {
+            mem, ty, storage, ..
+        }
+        | Instruction::AtomicCompareExchange { mem, ty, storage } => {
+            // Rejects i32 accesses with a 32 bit storage size
+            instr.atomic_opcode()?;
+            let op = match (instr, storage.map(storage_bits)) {
+                (Instruction::AtomicLoad { .. }, None) => "load".to_string(),
+                (Instruction::AtomicLoad { .. }, Some(bits)) => format!("load{}_u", bits),
+                (Instruction::AtomicStore { .. }, None) => "store".to_string(),
+                (Instruction::AtomicStore { .. }, Some(bits)) => format!("store{}", bits),
+                (Instruction::AtomicRmw { op, .. }, None) => format!("rmw.{}", op),
+                (Instruction::AtomicRmw { op, .. }, Some(bits)) => format!("rmw{}.{}_u", bits, op),
+                (_, None) => "rmw.cmpxchg".to_string(),
+                (_, Some(bits)) => format!("rmw{}.cmpxchg_u", bits),
+            };
+            format!(
+                "{}.atomic.{}{}",
+                ty,
+                op,
+                memarg(mem, instr.natural_alignment().unwrap())
+            )
+        }
+        _ => vector(instr)?,
+    })
+}
@@ -1,5 +1,8 @@
-use crate::{instr::Expr, types};
-use std::io::{self, Write};
+use crate::{error::Result, instr::Expr, types};
+use std::{
+    borrow::Cow,
+    io::{self, Read, Write},
+};
 
 pub type LabelIdx = u32;
 pub type FuncIdx = u32;
@@ -8,10 +11,14 @@ pub type LocalIdx = u32;
 pub type GlobalIdx = u32;
 pub type MemoryIdx = u32;
 pub type TableIdx = u32;
+pub type ElemIdx = u32;
+pub type DataIdx = u32;
+pub type TagIdx = u32;
+pub type FieldIdx = u32;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 #[repr(u8)]
-enum Section {
+pub(crate) enum Section {
     Custom = 0,
     Type,
     Import,
@@ -24,12 +31,187 @@ enum Section {
     Element,
     Code,
     Data,
+    DataCount,
+    Tag,
+}
+
+impl Section {
+    pub(crate) fn from_id(id: u8) -> Option<Self> {
+        Some(match id {
+            0 => Section::Custom,
+            1 => Section::Type,
+            2 => Section::Import,
+            3 => Section::Function,
+            4 => Section::Table,
+            5 => Section::Memory,
+            6 => Section::Global,
+            7 => Section::Export,
+            8 => Section::Start,
+            9 => Section::Element,
+            10 => Section::Code,
+            11 => Section::Data,
+            12 => Section::DataCount,
+            13 => Section::Tag,
+            _ => return None,
+        })
+    }
+
+    /// The position of the section in a module, the data count section
+    /// comes before the code section and the tag section before the global section
+    /// despite their higher ids
+    pub(crate) fn order(self) -> u8 {
+        match self {
+            Section::Custom
+            | Section::Type
+            | Section::Import
+            | Section::Function
+            | Section::Table
+            | Section::Memory => self as u8,
+            Section::Tag => Section::Global as u8,
+            Section::Global | Section::Export | Section::Start | Section::Element => self as u8 + 1,
+            Section::DataCount => Section::Code as u8 + 1,
+            Section::Code | Section::Data => self as u8 + 2,
+        }
+    }
 }
 
-/// Describes an import or export
+/// The known sections of a module, in the order they're encoded
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SectionId {
+    /// The type section
+    Type,
+    /// The import section
+    Import,
+    /// The function section
+    Function,
+    /// The table section
+    Table,
+    /// The memory section
+    Memory,
+    /// The tag section
+    Tag,
+    /// The global section
+    Global,
+    /// The export section
+    Export,
+    /// The start section
+    Start,
+    /// The element section
+    Element,
+    /// The data count section
+    DataCount,
+    /// The code section
+    Code,
+    /// The data section
+    Data,
+}
+
+impl SectionId {
+    /// Every known section in the order they're encoded
+    pub(crate) const ALL: [SectionId; 13] = [
+        SectionId::Type,
+        SectionId::Import,
+        SectionId::Function,
+        SectionId::Table,
+        SectionId::Memory,
+        SectionId::Tag,
+        SectionId::Global,
+        SectionId::Export,
+        SectionId::Start,
+        SectionId::Element,
+        SectionId::DataCount,
+        SectionId::Code,
+        SectionId::Data,
+    ];
+
+    /// The known section, `None` for custom sections
+    pub(crate) fn from_section(section: Section) -> Option<Self> {
+        Some(match section {
+            Section::Custom => return None,
+            Section::Type => SectionId::Type,
+            Section::Import => SectionId::Import,
+            Section::Function => SectionId::Function,
+            Section::Table => SectionId::Table,
+            Section::Memory => SectionId::Memory,
+            Section::Global => SectionId::Global,
+            Section::Export => SectionId::Export,
+            Section::Start => SectionId::Start,
+            Section::Element => SectionId::Element,
+            Section::Code => SectionId::Code,
+            Section::Data => SectionId::Data,
+            Section::DataCount => SectionId::DataCount,
+            Section::Tag => SectionId::Tag,
+        })
+    }
+}
+
+/// Where a custom section is placed relative to the known sections
+///
+/// A section that is empty, and therefore left out of the binary, still has a position
+/// so custom sections placed around it keep their place between its neighbours
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Placement {
+    /// Right before the given section
+    Before(SectionId),
+    /// Right after the given section
+    After(SectionId),
+    /// After every known section
+    End,
+}
+
+/// A custom section holds arbitrary data that doesn't affect the semantics of the module
+///
+/// Custom sections with the same placement are encoded in the order they appear
+/// in [`Module::custom_sections`](../module/struct.Module.html#structfield.custom_sections)
+///
+/// ```
+/// # use wasm_builder::{module::Module, sections::*};
+/// let mut module = Module::new();
+/// module.custom_sections.push(CustomSection {
+///     name: "producers".to_string(),
+///     data: b"\x00".as_slice().into(),
+///     placement: Placement::After(SectionId::Data),
+/// });
+///
+/// let mut wasm = Vec::new();
+/// module.encode(&mut wasm).unwrap();
+///
+/// let module = Module::decode(&wasm).unwrap();
+/// assert_eq!(module.custom_sections[0].name, "producers");
+/// assert_eq!(&module.custom_sections[0].data[..], b"\x00");
+/// ```
+#[derive(Debug, Clone)]
+pub struct CustomSection<'a> {
+    /// The name identifying the section
+    pub name: String,
+    /// The contents of the section after its name
+    pub data: Cow<'a, [u8]>,
+    /// Where the section is placed in the binary
+    pub placement: Placement,
+}
+
+impl<'a> CustomSection<'a> {
+    pub(crate) fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        encode_custom_section(writer, &self.name, &self.data)
+    }
+
+    /// Decodes the payload of a custom section
+    pub(crate) fn decode(reader: &mut impl Read, placement: Placement) -> Result<Self> {
+        let name = types::decode_name(reader)?;
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        Ok(CustomSection {
+            name,
+            data: Cow::Owned(data),
+            placement,
+        })
+    }
+}
+
+/// Describes an import
 #[derive(Debug, Copy, Clone)]
 pub enum Desc {
-    /// A function index
+    /// A function, holds the index of its type
     Function(TypeIdx),
     /// A table
     Table(types::TableType),
@@ -37,35 +219,102 @@ pub enum Desc {
     Memory(types::MemoryType),
     /// A global
     Global(types::GlobalType),
+    /// A tag, holds the index of the type of its parameters, requires the "exceptions" proposal
+    Tag(TypeIdx),
 }
 
 impl Desc {
-    pub(crate) fn encode(&self, writer: &mut impl Write) -> io::Result<()> {
+    pub(crate) fn encode(&self, writer: &mut impl Write) -> Result<()> {
         match self {
             Desc::Function(func) => {
                 // Function identifier: 0x00
-                writer.write(&[0x00])?;
+                writer.write_all(&[0x00])?;
                 types::encode_u32(writer, *func)?;
             }
             Desc::Table(table) => {
                 // Table identifier: 0x01
-                writer.write(&[0x01])?;
+                writer.write_all(&[0x01])?;
                 table.encode(writer)?;
             }
             Desc::Memory(mem) => {
                 // Memory identifier: 0x02
-                writer.write(&[0x02])?;
+                writer.write_all(&[0x02])?;
                 mem.encode(writer)?;
             }
             Desc::Global(global) => {
                 // Global identifier: 0x03
-                writer.write(&[0x03])?;
+                writer.write_all(&[0x03])?;
                 global.encode(writer)?;
             }
+            Desc::Tag(ty) => {
+                // Tag identifier: 0x04
+                writer.write_all(&[0x04])?;
+                encode_tag(writer, *ty)?;
+            }
         }
 
         Ok(())
     }
+
+    pub(crate) fn decode(reader: &mut impl Read) -> Result<Self> {
+        match types::decode_byte(reader)? {
+            0x00 => Ok(Desc::Function(types::decode_u32(reader)?)),
+            0x01 => Ok(Desc::Table(types::TableType::decode(reader)?)),
+            0x02 => Ok(Desc::Memory(types::MemoryType::decode(reader)?)),
+            0x03 => Ok(Desc::Global(types::GlobalType::decode(reader)?)),
+            0x04 => Ok(Desc::Tag(decode_tag(reader)?)),
+            kind => Err(types::malformed(format!(
+                "invalid import kind 0x{:02X}",
+                kind
+            ))),
+        }
+    }
+}
+
+/// Describes an export by the index of the exported item
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ExportDesc {
+    /// A function index
+    Function(FuncIdx),
+    /// A table index
+    Table(TableIdx),
+    /// A memory index
+    Memory(MemoryIdx),
+    /// A global index
+    Global(GlobalIdx),
+    /// A tag index, requires the "exceptions" proposal
+    Tag(TagIdx),
+}
+
+impl ExportDesc {
+    pub(crate) fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        let (kind, idx) = match self {
+            ExportDesc::Function(idx) => (0x00, idx),
+            ExportDesc::Table(idx) => (0x01, idx),
+            ExportDesc::Memory(idx) => (0x02, idx),
+            ExportDesc::Global(idx) => (0x03, idx),
+            ExportDesc::Tag(idx) => (0x04, idx),
+        };
+        writer.write_all(&[kind])?;
+        types::encode_u32(writer, *idx)?;
+        Ok(())
+    }
+
+    pub(crate) fn decode(reader: &mut impl Read) -> Result<Self> {
+        let kind = types::decode_byte(reader)?;
+        let idx = types::decode_u32(reader)?;
+        match kind {
+            0x00 => Ok(ExportDesc::Function(idx)),
+            0x01 => Ok(ExportDesc::Table(idx)),
+            0x02 => Ok(ExportDesc::Memory(idx)),
+            0x03 => Ok(ExportDesc::Global(idx)),
+            0x04 => Ok(ExportDesc::Tag(idx)),
+            kind => Err(types::malformed(format!(
+                "invalid export kind 0x{:02X}",
+                kind
+            ))),
+        }
+    }
 }
 
 /// The import component defines a import that is need for
@@ -81,11 +330,72 @@ pub struct Import {
 }
 
 impl Import {
-    pub(crate) fn encode(&self, writer: &mut impl Write) -> io::Result<()> {
+    pub(crate) fn encode(&self, writer: &mut impl Write) -> Result<()> {
         types::encode_name(writer, &self.module)?;
         types::encode_name(writer, &self.name)?;
         self.desc.encode(writer)
     }
+
+    pub(crate) fn decode(reader: &mut impl Read) -> Result<Self> {
+        Ok(Import {
+            module: types::decode_name(reader)?,
+            name: types::decode_name(reader)?,
+            desc: Desc::decode(reader)?,
+        })
+    }
+}
+
+/// The table component defines a table
+#[derive(Debug, Clone)]
+pub struct Table {
+    /// The type of the table
+    pub ty: types::TableType,
+    /// The expression every element starts as, requires the "function-references" proposal
+    ///
+    /// Without it the elements start as null, so the element type must be nullable
+    pub init: Option<Expr>,
+}
+
+impl Table {
+    pub(crate) fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        match self.init {
+            Some(ref init) => {
+                types::encode_raw(writer, &[0x40, 0x00])?;
+                self.ty.encode(writer)?;
+                init.encode(writer)?;
+            }
+            None => self.ty.encode(writer)?,
+        }
+        Ok(())
+    }
+
+    pub(crate) fn decode(reader: &mut impl Read) -> Result<Self> {
+        match types::decode_byte(reader)? {
+            0x40 => {
+                let byte = types::decode_byte(reader)?;
+                if byte != 0x00 {
+                    return Err(types::malformed(format!(
+                        "invalid table reserved byte 0x{:02X}",
+                        byte
+                    )));
+                }
+                Ok(Table {
+                    ty: types::TableType::decode(reader)?,
+                    init: Some(Expr::decode(reader)?),
+                })
+            }
+            byte => Ok(Table {
+                ty: types::TableType::decode_after(byte, reader)?,
+                init: None,
+            }),
+        }
+    }
+}
+
+impl From<types::TableType> for Table {
+    fn from(ty: types::TableType) -> Self {
+        Table { ty, init: None }
+    }
 }
 
 /// The global component defines a global variable
@@ -98,11 +408,18 @@ pub struct Global {
 }
 
 impl Global {
-    pub(crate) fn encode(&self, writer: &mut impl Write) -> io::Result<()> {
+    pub(crate) fn encode(&self, writer: &mut impl Write) -> Result<()> {
         self.ty.encode(writer)?;
         self.init.encode(writer)?;
         Ok(())
     }
+
+    pub(crate) fn decode(reader: &mut impl Read) -> Result<Self> {
+        Ok(Global {
+            ty: types::GlobalType::decode(reader)?,
+            init: Expr::decode(reader)?,
+        })
+    }
 }
 
 /// The export component defines a export that becomes accessible
@@ -112,41 +429,172 @@ pub struct Export {
     /// The name of the export
     pub name: String,
     /// The export itself
-    pub desc: Desc,
+    pub desc: ExportDesc,
 }
 
 impl Export {
-    pub(crate) fn encode(&self, writer: &mut impl Write) -> io::Result<()> {
+    pub(crate) fn encode(&self, writer: &mut impl Write) -> Result<()> {
         types::encode_name(writer, &self.name)?;
         self.desc.encode(writer)
     }
+
+    pub(crate) fn decode(reader: &mut impl Read) -> Result<Self> {
+        Ok(Export {
+            name: types::decode_name(reader)?,
+            desc: ExportDesc::decode(reader)?,
+        })
+    }
+}
+
+/// Whether an element segment is copied into a table on instantiation or on demand
+#[derive(Debug, Clone)]
+pub enum ElementMode {
+    /// Only copied into a table by `table.init`
+    Passive,
+    /// Copied into a table on instantiation
+    Active {
+        /// The table being initialized
+        table: TableIdx,
+        /// The expression that gives the offset into the table
+        offset: Expr,
+    },
+    /// Never copied, only declares the functions that can be used by `ref.func`
+    Declarative,
+}
+
+/// The references held by an element segment
+#[derive(Debug, Clone)]
+pub enum ElementItems {
+    /// Function references given by their indices
+    Functions(Vec<FuncIdx>),
+    /// References of the given type produced by constant expressions
+    Expressions(types::RefType, Vec<Expr>),
+}
+
+impl ElementItems {
+    /// The type of the references
+    pub fn ty(&self) -> types::RefType {
+        match self {
+            ElementItems::Functions(_) => types::RefType::FUNCREF,
+            ElementItems::Expressions(ty, _) => *ty,
+        }
+    }
 }
 
 /// The Element component provides a way to initialize a subrange of a table
 #[derive(Debug, Clone)]
 pub struct Element {
-    /// The table being initialized
-    pub table: TableIdx,
-    /// The expression that gives the offset into the table
-    pub offset: Expr,
+    /// How the segment is used to initialize a table
+    pub mode: ElementMode,
     /// The data to fill the subrange
-    pub init: Vec<FuncIdx>,
+    pub items: ElementItems,
 }
 
 impl Element {
-    pub(crate) fn encode(&self, writer: &mut impl Write) -> io::Result<()> {
-        types::encode_u32(writer, self.table)?;
-        self.offset.encode(writer)?;
+    /// Writes the segment with the most compact flags for its mode and items
+    ///
+    /// Expressions are kept as expressions even if they're all `ref.func`,
+    /// so decoded segments are encoded back with the same flags
+    pub(crate) fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        let indices = match &self.items {
+            ElementItems::Functions(funcs) => Some(funcs),
+            ElementItems::Expressions(..) => None,
+        };
+
+        // Bit 0 marks passive and declarative segments, bit 1 an explicit table index
+        // or a declarative segment and bit 2 expressions instead of function indices
+        let mut flags = match self.mode {
+            ElementMode::Active { table: 0, .. } => 0x00,
+            ElementMode::Passive => 0x01,
+            ElementMode::Active { .. } => 0x02,
+            ElementMode::Declarative => 0x03,
+        };
+        if indices.is_none() {
+            flags |= 0x04;
+        }
+        // The table 0 forms can't give an element type
+        if flags & 0x03 == 0 && self.items.ty() != types::RefType::FUNCREF {
+            flags |= 0x02;
+        }
+        types::encode_u32(writer, flags)?;
 
-        let mut buf = Vec::with_capacity(std::mem::size_of_val(&self.init));
+        if let ElementMode::Active { table, offset } = &self.mode {
+            if flags & 0x02 != 0 {
+                types::encode_u32(writer, *table)?;
+            }
+            offset.encode(writer)?;
+        }
 
-        for idx in self.init.iter() {
-            types::encode_u32(&mut buf, *idx)?;
+        match indices {
+            Some(indices) => {
+                if flags & 0x03 != 0 {
+                    // Element kind: funcref
+                    writer.write_all(&[0x00])?;
+                }
+
+                let mut buf = Vec::with_capacity(indices.len());
+                for idx in indices.iter() {
+                    types::encode_u32(&mut buf, *idx)?;
+                }
+                types::encode_vec(writer, &buf, indices.len())?;
+            }
+            None => {
+                if flags & 0x03 != 0 {
+                    self.items.ty().encode(writer)?;
+                }
+
+                let exprs = match &self.items {
+                    ElementItems::Expressions(_, exprs) => exprs,
+                    ElementItems::Functions(_) => unreachable!(),
+                };
+                let mut buf = Vec::new();
+                for expr in exprs.iter() {
+                    expr.encode(&mut buf)?;
+                }
+                types::encode_vec(writer, &buf, exprs.len())?;
+            }
         }
 
-        types::encode_vec(writer, &buf, self.init.len() as u32)?;
         Ok(())
     }
+
+    pub(crate) fn decode(reader: &mut impl Read) -> Result<Self> {
+        let flags = types::decode_u32(reader)?;
+        if flags > 0x07 {
+            return Err(types::malformed(format!(
+                "invalid elem segment flags {}",
+                flags
+            )));
+        }
+
+        let mode = match flags & 0x03 {
+            0x00 => ElementMode::Active {
+                table: 0,
+                offset: Expr::decode(reader)?,
+            },
+            0x01 => ElementMode::Passive,
+            0x02 => ElementMode::Active {
+                table: types::decode_u32(reader)?,
+                offset: Expr::decode(reader)?,
+            },
+            _ => ElementMode::Declarative,
+        };
+
+        let items = if flags & 0x04 == 0 {
+            if flags & 0x03 != 0 && types::decode_byte(reader)? != 0x00 {
+                return Err(types::malformed("invalid element kind"));
+            }
+            ElementItems::Functions(types::decode_vec(reader, types::decode_u32)?)
+        } else {
+            let ty = match flags & 0x03 {
+                0x00 => types::RefType::FUNCREF,
+                _ => types::RefType::decode(reader)?,
+            };
+            ElementItems::Expressions(ty, types::decode_vec(reader, Expr::decode)?)
+        };
+
+        Ok(Element { mode, items })
+    }
 }
 
 /// Defines a mutable local variable
@@ -163,11 +611,18 @@ pub struct Local {
 }
 
 impl Local {
-    pub(crate) fn encode(&self, writer: &mut impl Write) -> io::Result<()> {
+    pub(crate) fn encode(&self, writer: &mut impl Write) -> Result<()> {
         types::encode_u32(writer, self.n)?;
         types::encode_val_type(writer, self.ty)?;
         Ok(())
     }
+
+    pub(crate) fn decode(reader: &mut impl Read) -> Result<Self> {
+        Ok(Local {
+            n: types::decode_u32(reader)?,
+            ty: types::decode_val_type(reader)?,
+        })
+    }
 }
 
 /// Defines a function component
@@ -180,129 +635,191 @@ pub struct Function {
 }
 
 impl Function {
-    pub(crate) fn encode(&self, writer: &mut impl Write) -> io::Result<usize> {
-        let mut buf = Vec::with_capacity(std::mem::size_of_val(&self.locals));
+    pub(crate) fn encode(&self, writer: &mut impl Write) -> Result<usize> {
+        let mut buf = Vec::with_capacity(self.locals.len() * 2);
 
         for local in self.locals.iter() {
             local.encode(&mut buf)?;
         }
 
-        let mut length = types::encode_vec(writer, &buf, self.locals.len() as u32)?;
+        let mut length = types::encode_vec(writer, &buf, self.locals.len())?;
         length += self.body.encode(writer)?;
         Ok(length)
     }
+
+    /// Decodes a function's locals declarations followed by its body
+    ///
+    /// This is the layout of an entry of the code section without the size prefix
+    pub fn decode(reader: &mut impl Read) -> Result<Self> {
+        Ok(Function {
+            locals: types::decode_vec(reader, Local::decode)?,
+            body: Expr::decode(reader)?,
+        })
+    }
+
+    /// Decodes a function that must span the whole of `bytes`
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut reader = bytes;
+        let func = Function::decode(&mut reader)?;
+        if !reader.is_empty() {
+            return Err(types::malformed(
+                "unexpected bytes after the end of the function",
+            ));
+        }
+        Ok(func)
+    }
+}
+
+/// Whether a data segment is copied into memory on instantiation or on demand
+#[derive(Debug, Clone)]
+pub enum DataMode {
+    /// Only copied into memory by `memory.init`
+    Passive,
+    /// Copied into memory on instantiation
+    Active {
+        /// The memory being initialized
+        mem: MemoryIdx,
+        /// The offset into the memory
+        offset: Expr,
+    },
 }
 
 /// The data component defines a vector of data to initialize a subrange of a memory
 #[derive(Debug, Clone)]
 pub struct Data<'a> {
-    /// The memory being initialized
-    pub mem: MemoryIdx,
-    /// The offset into the memory
-    pub offset: Expr,
+    /// How the data is used to initialize a memory
+    pub mode: DataMode,
     /// The data to initialize the subrange with
-    pub init: &'a [u8],
+    pub init: Cow<'a, [u8]>,
 }
 
 impl<'a> Data<'a> {
-    pub(crate) fn encode(&self, writer: &mut impl Write) -> io::Result<()> {
-        types::encode_u32(writer, self.mem)?;
-        self.offset.encode(writer)?;
-        types::encode_vec(writer, self.init, self.init.len() as u32)?;
+    pub(crate) fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        match self.mode {
+            DataMode::Active { mem: 0, ref offset } => {
+                writer.write_all(&[0x00])?;
+                offset.encode(writer)?;
+            }
+            DataMode::Passive => writer.write_all(&[0x01])?,
+            DataMode::Active { mem, ref offset } => {
+                writer.write_all(&[0x02])?;
+                types::encode_u32(writer, mem)?;
+                offset.encode(writer)?;
+            }
+        }
+        types::encode_vec(writer, &self.init, self.init.len())?;
         Ok(())
     }
+
+    pub(crate) fn decode(reader: &mut impl Read) -> Result<Self> {
+        let mode = match types::decode_u32(reader)? {
+            0 => DataMode::Active {
+                mem: 0,
+                offset: Expr::decode(reader)?,
+            },
+            1 => DataMode::Passive,
+            2 => DataMode::Active {
+                mem: types::decode_u32(reader)?,
+                offset: Expr::decode(reader)?,
+            },
+            flags => {
+                return Err(types::malformed(format!(
+                    "invalid data segment flags {}",
+                    flags
+                )))
+            }
+        };
+
+        Ok(Data {
+            mode,
+            init: Cow::Owned(types::decode_bytes(reader)?),
+        })
+    }
 }
 
-fn encode_section_header(writer: &mut impl Write, id: Section, size: u32) -> io::Result<()> {
-    writer.write(&[id as u8])?;
+fn encode_section_header(writer: &mut impl Write, id: Section, size: usize) -> Result<()> {
+    writer.write_all(&[id as u8])?;
 
-    types::encode_u32(writer, size)?;
+    types::encode_u32(writer, types::count_to_u32(size)?)?;
 
     Ok(())
 }
 
-#[allow(dead_code)]
 pub(crate) fn encode_custom_section(
     writer: &mut impl Write,
     name: &str,
     data: &[u8],
-) -> io::Result<()> {
+) -> Result<()> {
     let mut buf = Vec::with_capacity(data.len() + name.len());
 
     types::encode_name(&mut buf, name)?;
-    buf.write(data)?;
+    buf.write_all(data)?;
 
-    encode_section_header(writer, Section::Custom, std::mem::size_of_val(&buf) as u32)?;
-    writer.write(&buf)?;
+    encode_section_header(writer, Section::Custom, buf.len())?;
+    writer.write_all(&buf)?;
 
     Ok(())
 }
 
 pub(crate) fn encode_type_section(
     writer: &mut impl Write,
-    section: &[types::FunctionType],
-) -> io::Result<()> {
-    let mut buf = Vec::with_capacity(std::mem::size_of_val(&section));
+    section: &[types::RecGroup],
+) -> Result<()> {
+    let mut buf = Vec::with_capacity(section.len());
 
     for ty in section {
         ty.encode(&mut buf)?;
     }
 
     let mut data = Vec::with_capacity(buf.len() + 4);
-    let size = types::encode_vec(&mut data, &buf, section.len() as u32)?;
-    encode_section_header(writer, Section::Type, size as u32)?;
-    writer.write(&data)?;
+    let size = types::encode_vec(&mut data, &buf, section.len())?;
+    encode_section_header(writer, Section::Type, size)?;
+    writer.write_all(&data)?;
 
     Ok(())
 }
 
-pub(crate) fn encode_import_section(writer: &mut impl Write, section: &[Import]) -> io::Result<()> {
-    let mut buf = Vec::with_capacity(std::mem::size_of_val(&section));
+pub(crate) fn encode_import_section(writer: &mut impl Write, section: &[Import]) -> Result<()> {
+    let mut buf = Vec::with_capacity(section.len());
 
     for ty in section {
         ty.encode(&mut buf)?;
     }
 
     let mut data = Vec::with_capacity(buf.len() + 4);
-    let size = types::encode_vec(&mut data, &buf, section.len() as u32)?;
-    encode_section_header(writer, Section::Import, size as u32)?;
-    writer.write(&data)?;
+    let size = types::encode_vec(&mut data, &buf, section.len())?;
+    encode_section_header(writer, Section::Import, size)?;
+    writer.write_all(&data)?;
 
     Ok(())
 }
 
-pub(crate) fn encode_function_section(
-    writer: &mut impl Write,
-    section: &[TypeIdx],
-) -> io::Result<()> {
-    let mut buf = Vec::with_capacity(std::mem::size_of_val(&section));
+pub(crate) fn encode_function_section(writer: &mut impl Write, section: &[TypeIdx]) -> Result<()> {
+    let mut buf = Vec::with_capacity(section.len());
 
     for ty in section {
         types::encode_u32(&mut buf, *ty)?;
     }
 
     let mut data = Vec::with_capacity(buf.len() + 4);
-    let size = types::encode_vec(&mut data, &buf, section.len() as u32)?;
-    encode_section_header(writer, Section::Function, size as u32)?;
-    writer.write(&data)?;
+    let size = types::encode_vec(&mut data, &buf, section.len())?;
+    encode_section_header(writer, Section::Function, size)?;
+    writer.write_all(&data)?;
 
     Ok(())
 }
 
-pub(crate) fn encode_table_section(
-    writer: &mut impl Write,
-    section: &[types::TableType],
-) -> io::Result<()> {
-    let mut buf = Vec::with_capacity(std::mem::size_of_val(&section));
+pub(crate) fn encode_table_section(writer: &mut impl Write, section: &[Table]) -> Result<()> {
+    let mut buf = Vec::with_capacity(section.len());
 
-    for ty in section {
-        ty.encode(&mut buf)?;
+    for table in section {
+        table.encode(&mut buf)?;
     }
 
     let mut data = Vec::with_capacity(buf.len() + 4);
-    let size = types::encode_vec(&mut data, &buf, section.len() as u32)?;
-    encode_section_header(writer, Section::Table, size as u32)?;
-    writer.write(&data)?;
+    let size = types::encode_vec(&mut data, &buf, section.len())?;
+    encode_section_header(writer, Section::Table, size)?;
+    writer.write_all(&data)?;
 
     Ok(())
 }
@@ -310,115 +827,227 @@ pub(crate) fn encode_table_section(
 pub(crate) fn encode_memory_section(
     writer: &mut impl Write,
     section: &[types::MemoryType],
-) -> io::Result<()> {
-    let mut buf = Vec::with_capacity(std::mem::size_of_val(&section));
+) -> Result<()> {
+    let mut buf = Vec::with_capacity(section.len());
 
     for ty in section {
         ty.encode(&mut buf)?;
     }
 
     let mut data = Vec::with_capacity(buf.len() + 4);
-    let size = types::encode_vec(&mut data, &buf, section.len() as u32)?;
-    encode_section_header(writer, Section::Memory, size as u32)?;
-    writer.write(&data)?;
+    let size = types::encode_vec(&mut data, &buf, section.len())?;
+    encode_section_header(writer, Section::Memory, size)?;
+    writer.write_all(&data)?;
+
+    Ok(())
+}
+
+/// A tag is an attribute, which must be zero for exceptions, followed by its type index
+fn encode_tag(writer: &mut impl Write, ty: TypeIdx) -> Result<usize> {
+    let mut length = types::encode_raw(writer, &[0x00])?;
+    length += types::encode_u32(writer, ty)?;
+    Ok(length)
+}
+
+fn decode_tag(reader: &mut impl Read) -> Result<TypeIdx> {
+    match types::decode_byte(reader)? {
+        0x00 => types::decode_u32(reader),
+        attribute => Err(types::malformed(format!(
+            "invalid tag attribute 0x{:02X}",
+            attribute
+        ))),
+    }
+}
+
+pub(crate) fn encode_tag_section(writer: &mut impl Write, section: &[TypeIdx]) -> Result<()> {
+    let mut buf = Vec::with_capacity(section.len());
+
+    for ty in section {
+        encode_tag(&mut buf, *ty)?;
+    }
+
+    let mut data = Vec::with_capacity(buf.len() + 4);
+    let size = types::encode_vec(&mut data, &buf, section.len())?;
+    encode_section_header(writer, Section::Tag, size)?;
+    writer.write_all(&data)?;
 
     Ok(())
 }
 
-pub(crate) fn encode_global_section(writer: &mut impl Write, section: &[Global]) -> io::Result<()> {
-    let mut buf = Vec::with_capacity(std::mem::size_of_val(&section));
+pub(crate) fn encode_global_section(writer: &mut impl Write, section: &[Global]) -> Result<()> {
+    let mut buf = Vec::with_capacity(section.len());
 
     for ty in section {
         ty.encode(&mut buf)?;
     }
 
     let mut data = Vec::with_capacity(buf.len() + 4);
-    let size = types::encode_vec(&mut data, &buf, section.len() as u32)?;
-    encode_section_header(writer, Section::Global, size as u32)?;
-    writer.write(&data)?;
+    let size = types::encode_vec(&mut data, &buf, section.len())?;
+    encode_section_header(writer, Section::Global, size)?;
+    writer.write_all(&data)?;
 
     Ok(())
 }
 
-pub(crate) fn encode_export_section(writer: &mut impl Write, section: &[Export]) -> io::Result<()> {
-    let mut buf = Vec::with_capacity(std::mem::size_of_val(&section));
+pub(crate) fn encode_export_section(writer: &mut impl Write, section: &[Export]) -> Result<()> {
+    let mut buf = Vec::with_capacity(section.len());
 
     for ty in section {
         ty.encode(&mut buf)?;
     }
 
     let mut data = Vec::with_capacity(buf.len() + 4);
-    let size = types::encode_vec(&mut data, &buf, section.len() as u32)?;
-    encode_section_header(writer, Section::Export, size as u32)?;
-    writer.write(&data)?;
+    let size = types::encode_vec(&mut data, &buf, section.len())?;
+    encode_section_header(writer, Section::Export, size)?;
+    writer.write_all(&data)?;
 
     Ok(())
 }
 
-pub(crate) fn encode_start_section(writer: &mut impl Write, start: FuncIdx) -> io::Result<()> {
+pub(crate) fn encode_start_section(writer: &mut impl Write, start: FuncIdx) -> Result<()> {
     let mut buf = Vec::with_capacity(4);
 
     let size = types::encode_u32(&mut buf, start)?;
 
-    encode_section_header(writer, Section::Start, size as u32)?;
-    writer.write(&buf)?;
+    encode_section_header(writer, Section::Start, size)?;
+    writer.write_all(&buf)?;
 
     Ok(())
 }
 
-pub(crate) fn encode_element_section(
-    writer: &mut impl Write,
-    section: &[Element],
-) -> io::Result<()> {
-    let mut buf = Vec::with_capacity(std::mem::size_of_val(&section));
+pub(crate) fn encode_element_section(writer: &mut impl Write, section: &[Element]) -> Result<()> {
+    let mut buf = Vec::with_capacity(section.len());
 
     for ty in section {
         ty.encode(&mut buf)?;
     }
 
     let mut data = Vec::with_capacity(buf.len() + 4);
-    let size = types::encode_vec(&mut data, &buf, section.len() as u32)?;
-    encode_section_header(writer, Section::Element, size as u32)?;
-    writer.write(&data)?;
+    let size = types::encode_vec(&mut data, &buf, section.len())?;
+    encode_section_header(writer, Section::Element, size)?;
+    writer.write_all(&data)?;
 
     Ok(())
 }
 
-fn encode_code(writer: &mut impl Write, func: &Function) -> io::Result<()> {
+fn encode_code(writer: &mut impl Write, func: &Function) -> Result<()> {
     let mut buf = Vec::new();
     let size = func.encode(&mut buf)?;
-    types::encode_u32(writer, size as u32)?;
-    writer.write(&buf)?;
+    types::encode_u32(writer, types::count_to_u32(size)?)?;
+    writer.write_all(&buf)?;
 
     Ok(())
 }
 
-pub(crate) fn encode_code_section(writer: &mut impl Write, section: &[Function]) -> io::Result<()> {
-    let mut buf = Vec::with_capacity(std::mem::size_of_val(&section));
+pub(crate) fn encode_code_section(writer: &mut impl Write, section: &[Function]) -> Result<()> {
+    let mut buf = Vec::with_capacity(section.len());
 
     for func in section {
         encode_code(&mut buf, func)?;
     }
 
     let mut data = Vec::with_capacity(buf.len() + 4);
-    let size = types::encode_vec(&mut data, &buf, section.len() as u32)?;
-    encode_section_header(writer, Section::Code, size as u32)?;
-    writer.write(&data)?;
+    let size = types::encode_vec(&mut data, &buf, section.len())?;
+    encode_section_header(writer, Section::Code, size)?;
+    writer.write_all(&data)?;
 
     Ok(())
 }
 
-pub(crate) fn encode_data_section(writer: &mut impl Write, section: &[Data]) -> io::Result<()> {
-    let mut buf = Vec::with_capacity(std::mem::size_of_val(&section));
+pub(crate) fn encode_data_count_section(writer: &mut impl Write, count: u32) -> Result<()> {
+    let mut buf = Vec::with_capacity(4);
+    let size = types::encode_u32(&mut buf, count)?;
+
+    encode_section_header(writer, Section::DataCount, size)?;
+    writer.write_all(&buf)?;
+
+    Ok(())
+}
+
+pub(crate) fn encode_data_section(writer: &mut impl Write, section: &[Data]) -> Result<()> {
+    let mut buf = Vec::with_capacity(section.len());
 
     for data in section {
         data.encode(&mut buf)?;
     }
 
     let mut data = Vec::with_capacity(buf.len() + 4);
-    let size = types::encode_vec(&mut data, &buf, section.len() as u32)?;
-    encode_section_header(writer, Section::Data, size as u32)?;
-    writer.write(&data)?;
+    let size = types::encode_vec(&mut data, &buf, section.len())?;
+    encode_section_header(writer, Section::Data, size)?;
+    writer.write_all(&data)?;
 
     Ok(())
 }
+
+/// Reads a section header, returning `None` once the end of the module is reached
+pub(crate) fn decode_section_header(reader: &mut impl Read) -> Result<Option<(u8, u32)>> {
+    let mut id = [0];
+    if reader.read(&mut id)? == 0 {
+        return Ok(None);
+    }
+
+    Ok(Some((id[0], types::decode_u32(reader)?)))
+}
+
+pub(crate) fn decode_type_section(reader: &mut impl Read) -> Result<Vec<types::RecGroup>> {
+    types::decode_vec(reader, types::RecGroup::decode)
+}
+
+pub(crate) fn decode_import_section(reader: &mut impl Read) -> Result<Vec<Import>> {
+    types::decode_vec(reader, Import::decode)
+}
+
+pub(crate) fn decode_function_section(reader: &mut impl Read) -> Result<Vec<TypeIdx>> {
+    types::decode_vec(reader, types::decode_u32)
+}
+
+pub(crate) fn decode_table_section(reader: &mut impl Read) -> Result<Vec<Table>> {
+    types::decode_vec(reader, Table::decode)
+}
+
+pub(crate) fn decode_memory_section(reader: &mut impl Read) -> Result<Vec<types::MemoryType>> {
+    types::decode_vec(reader, types::MemoryType::decode)
+}
+
+pub(crate) fn decode_tag_section(reader: &mut impl Read) -> Result<Vec<TypeIdx>> {
+    types::decode_vec(reader, decode_tag)
+}
+
+pub(crate) fn decode_global_section(reader: &mut impl Read) -> Result<Vec<Global>> {
+    types::decode_vec(reader, Global::decode)
+}
+
+pub(crate) fn decode_export_section(reader: &mut impl Read) -> Result<Vec<Export>> {
+    types::decode_vec(reader, Export::decode)
+}
+
+pub(crate) fn decode_start_section(reader: &mut impl Read) -> Result<FuncIdx> {
+    types::decode_u32(reader)
+}
+
+pub(crate) fn decode_element_section(reader: &mut impl Read) -> Result<Vec<Element>> {
+    types::decode_vec(reader, Element::decode)
+}
+
+pub(crate) fn decode_data_count_section(reader: &mut impl Read) -> Result<u32> {
+    types::decode_u32(reader)
+}
+
+fn decode_code(reader: &mut impl Read) -> Result<Function> {
+    let size = types::decode_u32(reader)?;
+    let mut buf = Vec::new();
+    reader.take(size as u64).read_to_end(&mut buf)?;
+    if buf.len() != size as usize {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+
+    Function::from_bytes(&buf)
+}
+
+pub(crate) fn decode_code_section(reader: &mut impl Read) -> Result<Vec<Function>> {
+    types::decode_vec(reader, decode_code)
+}
+
+pub(crate) fn decode_data_section(reader: &mut impl Read) -> Result<Vec<Data<'static>>> {
+    types::decode_vec(reader, Data::decode)
+}
@@ -0,0 +1,1329 @@
+use crate::{
+    error::{Error, Result},
+    instr::*,
+    sections::*,
+    types::{self, FunctionType, HeapType, ValType},
+    validate::{error, Context, Location},
+};
+use std::fmt;
+
+/// The kind of construct that opened a control frame
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum FrameKind {
+    Function,
+    Block,
+    Loop,
+    If,
+    Else,
+    TryTable,
+    Try,
+    Catch,
+    CatchAll,
+}
+
+/// A control frame as described by the validation algorithm of the spec
+#[derive(Debug)]
+struct Frame {
+    kind: FrameKind,
+    params: Vec<ValType>,
+    results: Vec<ValType>,
+    /// The height of the operand stack when the frame was entered
+    height: usize,
+    /// The number of initialized locals when the frame was entered
+    inits: usize,
+    /// Whether the rest of the frame is unreachable (i.e. after a `br`)
+    unreachable: bool,
+}
+
+impl Frame {
+    /// The types a branch to this frame's label must provide
+    fn label_types(&self) -> &[ValType] {
+        match self.kind {
+            FrameKind::Loop => &self.params,
+            _ => &self.results,
+        }
+    }
+}
+
+/// A value on the operand stack, `None` stands for an unknown type in unreachable code
+type Operand = Option<ValType>;
+
+struct DisplayOperand(Operand);
+
+impl fmt::Display for DisplayOperand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(ty) => ty.fmt(f),
+            None => f.write_str("nothing"),
+        }
+    }
+}
+
+/// A reference to heap type `heap_type`
+fn ref_type(nullable: bool, heap_type: HeapType) -> ValType {
+    ValType::Ref(types::RefType {
+        nullable,
+        heap_type,
+    })
+}
+
+fn display_types(types: &[ValType]) -> String {
+    let types: Vec<_> = types.iter().map(|ty| ty.to_string()).collect();
+    format!("[{}]", types.join(" "))
+}
+
+/// Simulates the operand stack over a function body
+struct FuncChecker<'c, 'm> {
+    ctx: &'c Context<'m>,
+    /// Run-length encoded local types, each entry holds the index after its last local
+    locals: Vec<(u64, ValType)>,
+    /// The locals without a default value that have been set, a frame forgets the ones set inside it
+    inits: Vec<LocalIdx>,
+    stack: Vec<Operand>,
+    frames: Vec<Frame>,
+    location: Location,
+    /// The index of the instruction being checked in the current sequence, `None` at its end
+    current: Option<usize>,
+}
+
+impl<'c, 'm> FuncChecker<'c, 'm> {
+    fn error(&self, message: impl Into<String>) -> Error {
+        match self.current {
+            Some(idx) => error(self.location.instr(idx), message),
+            None => error(self.location.end(), message),
+        }
+    }
+
+    fn local(&self, idx: LocalIdx) -> Result<ValType> {
+        let pos = self.locals.partition_point(|(end, _)| *end <= idx as u64);
+        match self.locals.get(pos) {
+            Some((_, ty)) => Ok(*ty),
+            None => Err(self.error(format!("unknown local {}", idx))),
+        }
+    }
+
+    /// Records that a local was set, locals with a default value are always initialized
+    fn init_local(&mut self, idx: LocalIdx, ty: ValType) {
+        if !ty.is_defaultable() && !self.inits.contains(&idx) {
+            self.inits.push(idx);
+        }
+    }
+
+    fn block_type(&self, ty: &BlockType) -> Result<(Vec<ValType>, Vec<ValType>)> {
+        match ty {
+            BlockType::Empty => Ok((vec![], vec![])),
+            BlockType::Type(ty) => Ok((vec![], vec![*ty])),
+            BlockType::TypeIdx(idx) => {
+                let ty = self.func_type(*idx)?;
+                Ok((ty.parameter_types.clone(), ty.return_types.clone()))
+            }
+        }
+    }
+
+    fn composite_type(&self, idx: TypeIdx) -> Result<&'m types::CompositeType> {
+        match self.ctx.types.get(idx as usize) {
+            Some(ty) => Ok(&ty.composite),
+            None => Err(self.error(format!("unknown type {}", idx))),
+        }
+    }
+
+    fn func_type(&self, idx: TypeIdx) -> Result<&'m FunctionType> {
+        match self.composite_type(idx)? {
+            types::CompositeType::Func(ty) => Ok(ty),
+            _ => Err(self.error(format!("type {} is not a function type", idx))),
+        }
+    }
+
+    fn struct_type(&self, idx: TypeIdx) -> Result<&'m [types::FieldType]> {
+        match self.composite_type(idx)? {
+            types::CompositeType::Struct(fields) => Ok(fields),
+            _ => Err(self.error(format!("type {} is not a struct type", idx))),
+        }
+    }
+
+    fn struct_field(&self, idx: TypeIdx, field: FieldIdx) -> Result<types::FieldType> {
+        match self.struct_type(idx)?.get(field as usize) {
+            Some(ty) => Ok(*ty),
+            None => Err(self.error(format!("unknown field {} of type {}", field, idx))),
+        }
+    }
+
+    fn array_type(&self, idx: TypeIdx) -> Result<types::FieldType> {
+        match self.composite_type(idx)? {
+            types::CompositeType::Array(ty) => Ok(*ty),
+            _ => Err(self.error(format!("type {} is not an array type", idx))),
+        }
+    }
+
+    /// The element type of an array that can be written to
+    fn mutable_array_type(&self, idx: TypeIdx) -> Result<types::FieldType> {
+        let ty = self.array_type(idx)?;
+        if !ty.mutable {
+            return Err(self.error(format!("array type {} is immutable", idx)));
+        }
+        Ok(ty)
+    }
+
+    /// `array.new_data` and `array.init_data` copy bytes so they need numbers or vectors
+    fn check_data_array(&self, idx: TypeIdx, ty: types::FieldType) -> Result<()> {
+        if ty.storage.unpacked().is_ref() {
+            return Err(self.error(format!(
+                "array type {} holds references which can't come from a data segment",
+                idx
+            )));
+        }
+        Ok(())
+    }
+
+    /// The references of an element segment must fit in the array
+    fn check_elem_array(&self, idx: TypeIdx, ty: types::FieldType, elem: ElemIdx) -> Result<()> {
+        let elem_type = match self.ctx.elems.get(elem as usize) {
+            Some(ty) => ValType::from(*ty),
+            None => return Err(self.error(format!("unknown elem segment {}", elem))),
+        };
+        if !self
+            .ctx
+            .storage_subtype(types::StorageType::Val(elem_type), ty.storage)
+        {
+            return Err(self.error(format!(
+                "can't initialize array type {} with an elem segment of {}",
+                idx, elem_type
+            )));
+        }
+        Ok(())
+    }
+
+    /// Only packed fields need a sign extension, which is given by `signed`
+    fn check_packed(&self, ty: types::FieldType, signed: Option<bool>) -> Result<()> {
+        match (ty.storage.is_packed(), signed) {
+            (true, None) => Err(self.error(format!(
+                "a field of {} needs a signed or unsigned access",
+                ty.storage
+            ))),
+            (false, Some(_)) => Err(self.error(format!(
+                "a field of {} can't have a signed or unsigned access",
+                ty.storage
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// The parameters of a tag, the values an exception carries
+    fn tag(&self, idx: TagIdx) -> Result<&'m [ValType]> {
+        match self.ctx.tags.get(idx as usize) {
+            Some(ty) => Ok(&self.func_type(*ty)?.parameter_types),
+            None => Err(self.error(format!("unknown tag {}", idx))),
+        }
+    }
+
+    /// The element type of a table
+    fn table(&self, idx: TableIdx) -> Result<ValType> {
+        match self.ctx.tables.get(idx as usize) {
+            Some(table) => Ok(table.elem_type.into()),
+            None => Err(self.error(format!("unknown table {}", idx))),
+        }
+    }
+
+    /// The index type of a table
+    fn table_index(&self, idx: TableIdx) -> Result<ValType> {
+        match self.ctx.tables.get(idx as usize) {
+            Some(table) => Ok(table.index_type()),
+            None => Err(self.error(format!("unknown table {}", idx))),
+        }
+    }
+
+    /// The address type of a memory
+    fn memory(&self, idx: MemoryIdx) -> Result<ValType> {
+        match self.ctx.memories.get(idx as usize) {
+            Some(memory) => Ok(memory.index_type()),
+            None => Err(self.error(format!("unknown memory {}", idx))),
+        }
+    }
+
+    /// Checks that a load or store of `ty` can use `storage`, the shapes rejected here have no opcode
+    fn check_storage(&self, ty: ValType, storage: Option<StorageType>, access: &str) -> Result<()> {
+        let message = match (ty, storage) {
+            (ValType::Ref(_), _) if access == "load" => {
+                "references can't be loaded from memory".to_string()
+            }
+            (ValType::Ref(_), _) => "references can't be stored in memory".to_string(),
+            (_, None) | (ValType::I64, Some(_)) => return Ok(()),
+            (ValType::I32, Some(StorageType::I32)) => {
+                format!("i32 {}s can't have a 32 bit storage size", access)
+            }
+            (ValType::I32, Some(_)) => return Ok(()),
+            (ValType::F32, Some(_)) | (ValType::F64, Some(_)) => {
+                format!("float {}s can't have a storage size", access)
+            }
+            (ValType::V128, Some(_)) => format!("v128 {}s can't have a storage size", access),
+        };
+        Err(self.error(message))
+    }
+
+    fn natural_alignment(&self, instr: &Instruction) -> Result<u32> {
+        instr
+            .natural_alignment()
+            .ok_or_else(|| self.error("references can't be stored in memory"))
+    }
+
+    fn push(&mut self, ty: ValType) {
+        self.stack.push(Some(ty));
+    }
+
+    fn push_all(&mut self, types: &[ValType]) {
+        for ty in types {
+            self.push(*ty);
+        }
+    }
+
+    fn pop(&mut self) -> Result<Operand> {
+        let frame = self.frames.last().expect("no control frame");
+        if self.stack.len() == frame.height {
+            if frame.unreachable {
+                return Ok(None);
+            }
+            return Err(self.error("expected a value got nothing"));
+        }
+        Ok(self.stack.pop().expect("operand stack underflow"))
+    }
+
+    fn pop_expect(&mut self, expected: ValType) -> Result<Operand> {
+        let frame = self.frames.last().expect("no control frame");
+        if self.stack.len() == frame.height && !frame.unreachable {
+            return Err(self.error(format!("expected {} got nothing", expected)));
+        }
+
+        let actual = self.pop()?;
+        match actual {
+            Some(actual) if !self.ctx.is_subtype(actual, expected) => {
+                Err(self.error(format!("expected {} got {}", expected, actual)))
+            }
+            _ => Ok(actual),
+        }
+    }
+
+    /// Pops a reference, returning it as non-nullable
+    fn pop_ref(&mut self) -> Result<Operand> {
+        match self.pop()? {
+            Some(ValType::Ref(ty)) => Ok(Some(ValType::Ref(types::RefType {
+                nullable: false,
+                ..ty
+            }))),
+            Some(ty) => Err(self.error(format!("expected a reference got {}", ty))),
+            None => Ok(None),
+        }
+    }
+
+    fn pop_all(&mut self, types: &[ValType]) -> Result<()> {
+        for ty in types.iter().rev() {
+            self.pop_expect(*ty)?;
+        }
+        Ok(())
+    }
+
+    fn push_frame(&mut self, kind: FrameKind, params: Vec<ValType>, results: Vec<ValType>) {
+        self.frames.push(Frame {
+            kind,
+            height: self.stack.len(),
+            inits: self.inits.len(),
+            unreachable: false,
+            params: params.clone(),
+            results,
+        });
+        self.push_all(&params);
+    }
+
+    fn pop_frame(&mut self) -> Result<Frame> {
+        let results = self
+            .frames
+            .last()
+            .expect("no control frame")
+            .results
+            .clone();
+        self.pop_all(&results)?;
+
+        let frame = self.frames.last().expect("no control frame");
+        if self.stack.len() != frame.height {
+            // Show the results that were already popped after the values left behind
+            let extra: Vec<_> = self.stack[frame.height..]
+                .iter()
+                .map(|ty| DisplayOperand(*ty).to_string())
+                .chain(results.iter().map(|ty| ty.to_string()))
+                .collect();
+            return Err(self.error(format!(
+                "expected {} got [{}]",
+                display_types(&results),
+                extra.join(" ")
+            )));
+        }
+
+        let frame = self.frames.pop().expect("no control frame");
+        self.inits.truncate(frame.inits);
+        Ok(frame)
+    }
+
+    fn label_types(&self, depth: LabelIdx) -> Result<Vec<ValType>> {
+        let len = self.frames.len();
+        if depth as usize >= len {
+            return Err(self.error(format!("unknown label {}", depth)));
+        }
+        Ok(self.frames[len - 1 - depth as usize].label_types().to_vec())
+    }
+
+    fn unreachable(&mut self) {
+        let frame = self.frames.last_mut().expect("no control frame");
+        self.stack.truncate(frame.height);
+        frame.unreachable = true;
+    }
+
+    /// `[t*] -> [r*]`
+    fn op(&mut self, params: &[ValType], results: &[ValType]) -> Result<()> {
+        self.pop_all(params)?;
+        self.push_all(results);
+        Ok(())
+    }
+
+    fn unary(&mut self, ty: impl Into<ValType>) -> Result<()> {
+        let ty = ty.into();
+        self.op(&[ty], &[ty])
+    }
+
+    fn binary(&mut self, ty: impl Into<ValType>) -> Result<()> {
+        let ty = ty.into();
+        self.op(&[ty, ty], &[ty])
+    }
+
+    fn test(&mut self, ty: impl Into<ValType>) -> Result<()> {
+        self.op(&[ty.into()], &[ValType::I32])
+    }
+
+    fn compare(&mut self, ty: impl Into<ValType>) -> Result<()> {
+        let ty = ty.into();
+        self.op(&[ty, ty], &[ValType::I32])
+    }
+
+    fn convert(&mut self, from: impl Into<ValType>, to: impl Into<ValType>) -> Result<()> {
+        self.op(&[from.into()], &[to.into()])
+    }
+
+    fn check_memarg(&self, mem: &MemoryArgument, natural_alignment: u32) -> Result<()> {
+        if mem.alignment > natural_alignment {
+            return Err(self.error(format!(
+                "alignment 2^{} is larger than natural alignment 2^{}",
+                mem.alignment, natural_alignment
+            )));
+        }
+        Ok(())
+    }
+
+    /// Whether every type of `actual` can be used where the same type of `expected` is expected
+    fn types_match(&self, actual: &[ValType], expected: &[ValType]) -> bool {
+        actual.len() == expected.len()
+            && actual
+                .iter()
+                .zip(expected.iter())
+                .all(|(actual, expected)| self.ctx.is_subtype(*actual, *expected))
+    }
+
+    /// A tail call returns the callee's results from the caller so they must match
+    fn check_tail_call(&mut self, callee: &FunctionType) -> Result<()> {
+        let results = &self.frames[0].results;
+        if !self.types_match(&callee.return_types, results) {
+            return Err(self.error(format!(
+                "tail call results {} don't match the function's results {}",
+                display_types(&callee.return_types),
+                display_types(results)
+            )));
+        }
+        self.pop_all(&callee.parameter_types)?;
+        self.unreachable();
+        Ok(())
+    }
+
+    /// The values a catch clause delivers must match its label
+    fn check_catch(&self, catch: &Catch) -> Result<()> {
+        let (label, mut types) = match catch {
+            Catch::Catch { tag, label } => (*label, self.tag(*tag)?.to_vec()),
+            Catch::CatchRef { tag, label } => (*label, self.tag(*tag)?.to_vec()),
+            Catch::CatchAll(label) | Catch::CatchAllRef(label) => (*label, vec![]),
+        };
+        if let Catch::CatchRef { .. } | Catch::CatchAllRef(_) = catch {
+            types.push(ValType::EXNREF);
+        }
+        let expected = self.label_types(label)?;
+        if !self.types_match(&types, &expected) {
+            return Err(self.error(format!(
+                "catch delivers {} but label {} expects {}",
+                display_types(&types),
+                label,
+                display_types(&expected)
+            )));
+        }
+        Ok(())
+    }
+
+    /// Atomic accesses must use exactly their natural alignment
+    fn check_atomic_memarg(&self, mem: &MemoryArgument, natural_alignment: u32) -> Result<()> {
+        if mem.alignment != natural_alignment {
+            return Err(self.error(format!(
+                "atomic alignment 2^{} must be the natural alignment 2^{}",
+                mem.alignment, natural_alignment
+            )));
+        }
+        Ok(())
+    }
+
+    fn check_lane(&self, shape: LaneShape, lane: u8) -> Result<()> {
+        if lane >= shape.lanes() {
+            return Err(self.error(format!(
+                "lane {} is out of range for {} which has {} lanes",
+                lane,
+                shape,
+                shape.lanes()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Checks a block's body and its end, leaving the frame's results on the stack
+    fn check_block(
+        &mut self,
+        kind: FrameKind,
+        label: &'static str,
+        idx: usize,
+        params: Vec<ValType>,
+        results: Vec<ValType>,
+        instrs: &[Instruction],
+    ) -> Result<()> {
+        self.push_frame(kind, params, results);
+        self.location.push(label, idx);
+        self.check_instrs(instrs)?;
+        self.current = None;
+        let frame = self.pop_frame()?;
+        self.location.pop();
+        self.current = Some(idx);
+        self.push_all(&frame.results);
+        Ok(())
+    }
+
+    fn check_instrs(&mut self, instrs: &[Instruction]) -> Result<()> {
+        for (i, instr) in instrs.iter().enumerate() {
+            self.current = Some(i);
+            self.check_instr(i, instr)?;
+        }
+        Ok(())
+    }
+
+    fn check_instr(&mut self, i: usize, instr: &Instruction) -> Result<()> {
+        match instr {
+            Instruction::Unreachable => self.unreachable(),
+            Instruction::NOP => {}
+            Instruction::Block { ty, instrs } => {
+                let (params, results) = self.block_type(ty)?;
+                self.pop_all(&params)?;
+                self.check_block(FrameKind::Block, "block", i, params, results, instrs)?;
+            }
+            Instruction::Loop { ty, instrs } => {
+                let (params, results) = self.block_type(ty)?;
+                self.pop_all(&params)?;
+                self.check_block(FrameKind::Loop, "loop", i, params, results, instrs)?;
+            }
+            Instruction::If {
+                ty,
+                accept_instrs,
+                reject_instrs,
+            } => {
+                let (params, results) = self.block_type(ty)?;
+                self.pop_expect(ValType::I32)?;
+                self.pop_all(&params)?;
+                match reject_instrs {
+                    Some(reject_instrs) => {
+                        self.check_block(
+                            FrameKind::If,
+                            "if",
+                            i,
+                            params.clone(),
+                            results.clone(),
+                            accept_instrs,
+                        )?;
+                        // Both branches start from the same stack
+                        self.stack.truncate(self.stack.len() - results.len());
+                        self.check_block(
+                            FrameKind::Else,
+                            "else",
+                            i,
+                            params,
+                            results,
+                            reject_instrs,
+                        )?;
+                    }
+                    None => {
+                        if !self.types_match(&params, &results) {
+                            return Err(self.error(format!(
+                                "if without else must have matching parameters and results, \
+                                 got {} -> {}",
+                                display_types(&params),
+                                display_types(&results)
+                            )));
+                        }
+                        self.check_block(FrameKind::If, "if", i, params, results, accept_instrs)?;
+                    }
+                }
+            }
+            Instruction::Throw(tag) => {
+                let params = self.tag(*tag)?;
+                self.pop_all(params)?;
+                self.unreachable();
+            }
+            Instruction::ThrowRef => {
+                self.pop_expect(ValType::EXNREF)?;
+                self.unreachable();
+            }
+            Instruction::TryTable {
+                ty,
+                catches,
+                instrs,
+            } => {
+                let (params, results) = self.block_type(ty)?;
+                self.pop_all(&params)?;
+                // The labels of the catch clauses are relative to the try_table's parent
+                for catch in catches {
+                    self.check_catch(catch)?;
+                }
+                self.check_block(FrameKind::TryTable, "try_table", i, params, results, instrs)?;
+            }
+            Instruction::Try {
+                ty,
+                instrs,
+                handler,
+            } => {
+                let (params, results) = self.block_type(ty)?;
+                self.pop_all(&params)?;
+                self.check_block(FrameKind::Try, "try", i, params, results.clone(), instrs)?;
+                match handler {
+                    TryHandler::Catches { catches, catch_all } => {
+                        // Every handler starts from the stack the try block started with
+                        for (tag, instrs) in catches {
+                            self.stack.truncate(self.stack.len() - results.len());
+                            let params = self.tag(*tag)?.to_vec();
+                            self.check_block(
+                                FrameKind::Catch,
+                                "catch",
+                                i,
+                                params,
+                                results.clone(),
+                                instrs,
+                            )?;
+                        }
+                        if let Some(instrs) = catch_all {
+                            self.stack.truncate(self.stack.len() - results.len());
+                            self.check_block(
+                                FrameKind::CatchAll,
+                                "catch_all",
+                                i,
+                                vec![],
+                                results,
+                                instrs,
+                            )?;
+                        }
+                    }
+                    // The label is relative to the try's parent, which is the top frame again
+                    TryHandler::Delegate(depth) => {
+                        self.label_types(*depth)?;
+                    }
+                }
+            }
+            Instruction::Rethrow(depth) => {
+                self.label_types(*depth)?;
+                let frame = &self.frames[self.frames.len() - 1 - *depth as usize];
+                if !matches!(frame.kind, FrameKind::Catch | FrameKind::CatchAll) {
+                    return Err(self.error(format!("label {} isn't a catch block", depth)));
+                }
+                self.unreachable();
+            }
+            Instruction::Branch(depth) => {
+                let types = self.label_types(*depth)?;
+                self.pop_all(&types)?;
+                self.unreachable();
+            }
+            Instruction::BranchIf(depth) => {
+                self.pop_expect(ValType::I32)?;
+                let types = self.label_types(*depth)?;
+                self.op(&types, &types)?;
+            }
+            Instruction::BranchTable { labels, operand } => {
+                self.pop_expect(ValType::I32)?;
+                let default = self.label_types(*operand)?;
+                for label in labels {
+                    let types = self.label_types(*label)?;
+                    if types.len() != default.len() {
+                        return Err(self.error(format!(
+                            "label {} expects {} but the default label expects {}",
+                            label,
+                            display_types(&types),
+                            display_types(&default)
+                        )));
+                    }
+                    // Check the operands against each label without consuming them
+                    let mut operands = Vec::with_capacity(types.len());
+                    for ty in types.iter().rev() {
+                        operands.push(self.pop_expect(*ty)?);
+                    }
+                    self.stack.extend(operands.into_iter().rev());
+                }
+                self.pop_all(&default)?;
+                self.unreachable();
+            }
+            Instruction::Return => {
+                let results = self.frames[0].results.clone();
+                self.pop_all(&results)?;
+                self.unreachable();
+            }
+            Instruction::Call(idx) => {
+                let ty = match self.ctx.funcs.get(*idx as usize) {
+                    Some(ty) => self.func_type(*ty)?,
+                    None => return Err(self.error(format!("unknown function {}", idx))),
+                };
+                self.op(&ty.parameter_types, &ty.return_types)?;
+            }
+            Instruction::CallIndirect { ty, table } => {
+                let ty = self.func_type(*ty)?;
+                if !self.ctx.is_subtype(self.table(*table)?, ValType::FUNCREF) {
+                    return Err(self.error(format!("table {} must be a funcref table", table)));
+                }
+                self.pop_expect(self.table_index(*table)?)?;
+                self.op(&ty.parameter_types, &ty.return_types)?;
+            }
+            Instruction::ReturnCall(idx) => {
+                let ty = match self.ctx.funcs.get(*idx as usize) {
+                    Some(ty) => self.func_type(*ty)?,
+                    None => return Err(self.error(format!("unknown function {}", idx))),
+                };
+                self.check_tail_call(ty)?;
+            }
+            Instruction::ReturnCallIndirect { ty, table } => {
+                let ty = self.func_type(*ty)?;
+                if !self.ctx.is_subtype(self.table(*table)?, ValType::FUNCREF) {
+                    return Err(self.error(format!("table {} must be a funcref table", table)));
+                }
+                self.pop_expect(self.table_index(*table)?)?;
+                self.check_tail_call(ty)?;
+            }
+            Instruction::CallRef(idx) => {
+                let ty = self.func_type(*idx)?;
+                self.pop_expect(ref_type(true, HeapType::Concrete(*idx)))?;
+                self.op(&ty.parameter_types, &ty.return_types)?;
+            }
+            Instruction::ReturnCallRef(idx) => {
+                let ty = self.func_type(*idx)?;
+                self.pop_expect(ref_type(true, HeapType::Concrete(*idx)))?;
+                self.check_tail_call(ty)?;
+            }
+            Instruction::RefAsNonNull => {
+                let operand = self.pop_ref()?;
+                self.stack.push(operand);
+            }
+            Instruction::BranchOnNull(label) => {
+                let operand = self.pop_ref()?;
+                let types = self.label_types(*label)?;
+                self.op(&types, &types)?;
+                self.stack.push(operand);
+            }
+            Instruction::BranchOnNonNull(label) => {
+                let operand = self.pop_ref()?;
+                let mut types = self.label_types(*label)?;
+                match (types.pop(), operand) {
+                    (Some(expected), Some(ty)) if self.ctx.is_subtype(ty, expected) => {}
+                    (Some(ValType::Ref(_)), None) => {}
+                    _ => {
+                        return Err(self.error(format!(
+                            "label {} expects {} which doesn't end with a supertype of {}",
+                            label,
+                            display_types(&self.label_types(*label)?),
+                            DisplayOperand(operand)
+                        )))
+                    }
+                }
+                self.op(&types, &types)?;
+            }
+            Instruction::Drop => {
+                self.pop()?;
+            }
+            Instruction::Select => {
+                self.pop_expect(ValType::I32)?;
+                let first = self.pop()?;
+                let second = self.pop()?;
+                match (first, second) {
+                    (Some(first), Some(second)) if first != second => {
+                        return Err(self.error(format!(
+                            "select operands must have the same type, got {} and {}",
+                            second, first
+                        )))
+                    }
+                    (Some(ty), _) | (_, Some(ty)) if ty.is_ref() => {
+                        return Err(
+                            self.error(format!("select of {} operands needs an explicit type", ty))
+                        )
+                    }
+                    _ => self.stack.push(first.or(second)),
+                }
+            }
+            Instruction::TypedSelect(types) => {
+                let ty = match types[..] {
+                    [ty] => ty,
+                    _ => {
+                        return Err(self.error(format!(
+                            "select must have exactly one result, got {}",
+                            display_types(types)
+                        )))
+                    }
+                };
+                self.op(&[ty, ty, ValType::I32], &[ty])?;
+            }
+            Instruction::LocalGet(idx) => {
+                let ty = self.local(*idx)?;
+                if !ty.is_defaultable() && !self.inits.contains(idx) {
+                    return Err(self.error(format!("uninitialized local {}", idx)));
+                }
+                self.push(ty);
+            }
+            Instruction::LocalSet(idx) => {
+                let ty = self.local(*idx)?;
+                self.pop_expect(ty)?;
+                self.init_local(*idx, ty);
+            }
+            Instruction::LocalTee(idx) => {
+                let ty = self.local(*idx)?;
+                self.unary(ty)?;
+                self.init_local(*idx, ty);
+            }
+            Instruction::GlobalGet(idx) => match self.ctx.globals.get(*idx as usize) {
+                Some(global) => self.push(global.ty),
+                None => return Err(self.error(format!("unknown global {}", idx))),
+            },
+            Instruction::GlobalSet(idx) => match self.ctx.globals.get(*idx as usize) {
+                Some(global) if global.mutable => {
+                    self.pop_expect(global.ty)?;
+                }
+                Some(_) => return Err(self.error(format!("global {} is immutable", idx))),
+                None => return Err(self.error(format!("unknown global {}", idx))),
+            },
+            Instruction::TableGet(idx) => {
+                let ty = self.table(*idx)?;
+                self.op(&[self.table_index(*idx)?], &[ty])?;
+            }
+            Instruction::TableSet(idx) => {
+                let ty = self.table(*idx)?;
+                self.op(&[self.table_index(*idx)?, ty], &[])?;
+            }
+            Instruction::Load { mem, ty, storage } => {
+                self.check_storage(*ty, storage.map(|(_, storage)| storage), "load")?;
+                self.check_memarg(mem, self.natural_alignment(instr)?)?;
+                self.op(&[self.memory(mem.memory)?], &[*ty])?;
+            }
+            Instruction::Store { mem, ty, storage } => {
+                self.check_storage(*ty, *storage, "store")?;
+                self.check_memarg(mem, self.natural_alignment(instr)?)?;
+                self.op(&[self.memory(mem.memory)?, *ty], &[])?;
+            }
+            Instruction::MemorySize(idx) => self.push(self.memory(*idx)?),
+            Instruction::MemoryGrow(idx) => self.unary(self.memory(*idx)?)?,
+            Instruction::Const(literal) => self.push(literal.ty()),
+            Instruction::EqualZero(ty) => self.test(*ty)?,
+            Instruction::Equal(ty)
+            | Instruction::NotEqual(ty)
+            | Instruction::Add(ty)
+            | Instruction::Subtract(ty)
+            | Instruction::Multiply(ty)
+                if ty.is_ref() =>
+            {
+                return Err(self.error(format!("{} doesn't support arithmetic or comparisons", ty)))
+            }
+            Instruction::Equal(ty) | Instruction::NotEqual(ty) => self.compare(*ty)?,
+            Instruction::LessThanI32 { ty, .. }
+            | Instruction::GreaterThanI32 { ty, .. }
+            | Instruction::LessOrEqualI32 { ty, .. }
+            | Instruction::GreaterOrEqualI32 { ty, .. } => self.compare(*ty)?,
+            Instruction::LessThanFloat(ty)
+            | Instruction::GreaterThanFloat(ty)
+            | Instruction::LessOrEqualFloat(ty)
+            | Instruction::GreaterOrEqualFloat(ty) => self.compare(*ty)?,
+            Instruction::CountLeadingZero(ty)
+            | Instruction::CountTrailingZero(ty)
+            | Instruction::CountOnes(ty) => self.unary(*ty)?,
+            Instruction::Add(ty) | Instruction::Subtract(ty) | Instruction::Multiply(ty) => {
+                self.binary(*ty)?
+            }
+            Instruction::I32Division { ty, .. } | Instruction::Remainder { ty, .. } => {
+                self.binary(*ty)?
+            }
+            Instruction::FloatDivision(ty) => self.binary(*ty)?,
+            Instruction::And(ty)
+            | Instruction::Or(ty)
+            | Instruction::Xor(ty)
+            | Instruction::ShiftLeft(ty)
+            | Instruction::LeftRotation(ty)
+            | Instruction::RightRotation(ty) => self.binary(*ty)?,
+            Instruction::ShiftRight { ty, .. } => self.binary(*ty)?,
+            Instruction::Absolute(ty)
+            | Instruction::Negate(ty)
+            | Instruction::Ceil(ty)
+            | Instruction::Floor(ty)
+            | Instruction::Truncate(ty)
+            | Instruction::Nearest(ty)
+            | Instruction::SquareRoot(ty) => self.unary(*ty)?,
+            Instruction::Minimum(ty) | Instruction::Maximum(ty) | Instruction::CopySign(ty) => {
+                self.binary(*ty)?
+            }
+            Instruction::I32Wrap => self.convert(ValType::I64, ValType::I32)?,
+            Instruction::I32Extend(_) => self.convert(ValType::I32, ValType::I64)?,
+            Instruction::I32Truncate { ty, float, .. }
+            | Instruction::SaturateTruncate { ty, float, .. } => self.convert(*float, *ty)?,
+            Instruction::Convert { ty, tgt_ty, .. } => self.convert(*tgt_ty, *ty)?,
+            Instruction::FloatDemote => self.convert(ValType::F64, ValType::F32)?,
+            Instruction::FloatPromote => self.convert(ValType::F32, ValType::F64)?,
+            Instruction::I32ReI32erpret => self.convert(ValType::F32, ValType::I32)?,
+            Instruction::LongReI32erpret => self.convert(ValType::F64, ValType::I64)?,
+            Instruction::FloatReI32erpret => self.convert(ValType::I32, ValType::F32)?,
+            Instruction::DoubleReI32erpret => self.convert(ValType::I64, ValType::F64)?,
+            Instruction::Extend { ty, base } => {
+                if let (IntegerType::I32, StorageType::I32) = (ty, base) {
+                    return Err(self.error("i32 can't be extended from 32 bits"));
+                }
+                self.unary(*ty)?
+            }
+            Instruction::RefNull(heap_type) => self.push(ref_type(true, *heap_type)),
+            Instruction::RefIsNull => match self.pop()? {
+                Some(ty) if !ty.is_ref() => {
+                    return Err(self.error(format!("expected a reference got {}", ty)))
+                }
+                _ => self.push(ValType::I32),
+            },
+            Instruction::RefFunc(idx) => {
+                if *idx as usize >= self.ctx.funcs.len() {
+                    return Err(self.error(format!("unknown function {}", idx)));
+                }
+                if !self.ctx.refs.contains(idx) {
+                    return Err(self.error(format!(
+                        "function {} must be referenced outside of function bodies \
+                         (i.e. by an export) to be used by ref.func",
+                        idx
+                    )));
+                }
+                let ty = self.ctx.funcs[*idx as usize];
+                self.push(ref_type(false, HeapType::Concrete(ty)));
+            }
+            Instruction::TableGrow(idx) => {
+                let (ty, index) = (self.table(*idx)?, self.table_index(*idx)?);
+                self.op(&[ty, index], &[index])?;
+            }
+            Instruction::TableSize(idx) => {
+                let index = self.table_index(*idx)?;
+                self.push(index);
+            }
+            Instruction::TableFill(idx) => {
+                let (ty, index) = (self.table(*idx)?, self.table_index(*idx)?);
+                self.op(&[index, ty, index], &[])?;
+            }
+            Instruction::MemoryInit { mem, .. } => {
+                self.op(&[self.memory(*mem)?, ValType::I32, ValType::I32], &[])?
+            }
+            Instruction::MemoryCopy { dst, src } => {
+                let (dst, src) = (self.memory(*dst)?, self.memory(*src)?);
+                self.op(&[dst, src, min_index(dst, src)], &[])?
+            }
+            Instruction::MemoryFill(idx) => {
+                let addr = self.memory(*idx)?;
+                self.op(&[addr, ValType::I32, addr], &[])?
+            }
+            Instruction::DataDrop(_) | Instruction::ElemDrop(_) => {}
+            Instruction::TableInit { elem, table } => {
+                let elem_type = match self.ctx.elems.get(*elem as usize) {
+                    Some(ty) => ValType::from(*ty),
+                    None => return Err(self.error(format!("unknown elem segment {}", elem))),
+                };
+                if !self.ctx.is_subtype(elem_type, self.table(*table)?) {
+                    return Err(self.error(format!(
+                        "can't initialize a table of {} with an elem segment of {}",
+                        self.table(*table)?,
+                        elem_type
+                    )));
+                }
+                let index = self.table_index(*table)?;
+                self.op(&[index, ValType::I32, ValType::I32], &[])?;
+            }
+            Instruction::TableCopy { dst, src } => {
+                let (dst_index, src_index) = (self.table_index(*dst)?, self.table_index(*src)?);
+                let (dst, src) = (self.table(*dst)?, self.table(*src)?);
+                if !self.ctx.is_subtype(src, dst) {
+                    return Err(self.error(format!(
+                        "can't copy a table of {} into a table of {}",
+                        src, dst
+                    )));
+                }
+                self.op(
+                    &[dst_index, src_index, min_index(dst_index, src_index)],
+                    &[],
+                )?;
+            }
+            Instruction::V128LoadExtend { mem, .. }
+            | Instruction::V128LoadSplat { mem, .. }
+            | Instruction::V128LoadZero { mem, .. } => {
+                self.check_memarg(mem, self.natural_alignment(instr)?)?;
+                self.convert(self.memory(mem.memory)?, ValType::V128)?;
+            }
+            Instruction::V128LoadLane { mem, shape, lane } => {
+                self.check_memarg(mem, self.natural_alignment(instr)?)?;
+                self.check_lane(*shape, *lane)?;
+                self.op(&[self.memory(mem.memory)?, ValType::V128], &[ValType::V128])?;
+            }
+            Instruction::V128StoreLane { mem, shape, lane } => {
+                self.check_memarg(mem, self.natural_alignment(instr)?)?;
+                self.check_lane(*shape, *lane)?;
+                self.op(&[self.memory(mem.memory)?, ValType::V128], &[])?;
+            }
+            Instruction::I8x16Shuffle(lanes) => {
+                // The lanes index the concatenation of both operands
+                if let Some(lane) = lanes.iter().find(|lane| **lane >= 32) {
+                    return Err(self.error(format!("shuffle lane {} is out of range", lane)));
+                }
+                self.binary(ValType::V128)?
+            }
+            Instruction::Splat(shape) => self.convert(shape.lane_type(), ValType::V128)?,
+            Instruction::ExtractLane { shape, lane, .. } => {
+                self.check_lane(*shape, *lane)?;
+                self.convert(ValType::V128, shape.lane_type())?
+            }
+            Instruction::ReplaceLane { shape, lane } => {
+                self.check_lane(*shape, *lane)?;
+                self.op(&[ValType::V128, shape.lane_type()], &[ValType::V128])?
+            }
+            Instruction::V128AnyTrue
+            | Instruction::VectorAllTrue(_)
+            | Instruction::VectorBitmask(_) => self.test(ValType::V128)?,
+            Instruction::V128Not
+            | Instruction::VectorAbsolute(_)
+            | Instruction::VectorNegate(_)
+            | Instruction::I8x16CountOnes
+            | Instruction::VectorExtend { .. }
+            | Instruction::VectorExtAddPairwise { .. }
+            | Instruction::VectorCeil(_)
+            | Instruction::VectorFloor(_)
+            | Instruction::VectorTruncate(_)
+            | Instruction::VectorNearest(_)
+            | Instruction::VectorSquareRoot(_)
+            | Instruction::VectorTruncateSaturate { .. }
+            | Instruction::VectorConvert { .. }
+            | Instruction::F32x4DemoteF64x2Zero
+            | Instruction::F64x2PromoteLowF32x4 => self.unary(ValType::V128)?,
+            Instruction::I8x16Swizzle
+            | Instruction::VectorEqual(_)
+            | Instruction::VectorNotEqual(_)
+            | Instruction::VectorLessThan { .. }
+            | Instruction::VectorGreaterThan { .. }
+            | Instruction::VectorLessOrEqual { .. }
+            | Instruction::VectorGreaterOrEqual { .. }
+            | Instruction::V128And
+            | Instruction::V128AndNot
+            | Instruction::V128Or
+            | Instruction::V128Xor
+            | Instruction::VectorNarrow { .. }
+            | Instruction::VectorAdd(_)
+            | Instruction::VectorAddSaturate { .. }
+            | Instruction::VectorSubtract(_)
+            | Instruction::VectorSubtractSaturate { .. }
+            | Instruction::VectorMultiply(_)
+            | Instruction::VectorMinimum { .. }
+            | Instruction::VectorMaximum { .. }
+            | Instruction::VectorAverage(_)
+            | Instruction::VectorExtMultiply { .. }
+            | Instruction::I16x8Q15MulrSat
+            | Instruction::I32x4DotI16x8
+            | Instruction::VectorDivision(_)
+            | Instruction::VectorPseudoMinimum(_)
+            | Instruction::VectorPseudoMaximum(_) => self.binary(ValType::V128)?,
+            Instruction::V128BitSelect => {
+                self.op(&[ValType::V128; 3], &[ValType::V128])?;
+            }
+            Instruction::VectorShiftLeft(_) | Instruction::VectorShiftRight { .. } => {
+                self.op(&[ValType::V128, ValType::I32], &[ValType::V128])?
+            }
+            Instruction::AtomicNotify(mem) => {
+                self.check_atomic_memarg(mem, self.natural_alignment(instr)?)?;
+                let addr = self.memory(mem.memory)?;
+                self.op(&[addr, ValType::I32], &[ValType::I32])?;
+            }
+            Instruction::AtomicWait { mem, ty } => {
+                self.check_atomic_memarg(mem, self.natural_alignment(instr)?)?;
+                let addr = self.memory(mem.memory)?;
+                self.op(&[addr, (*ty).into(), ValType::I64], &[ValType::I32])?;
+            }
+            Instruction::AtomicFence => {}
+            Instruction::AtomicLoad { mem, ty, .. } => {
+                self.check_atomic_memarg(mem, self.natural_alignment(instr)?)?;
+                self.convert(self.memory(mem.memory)?, *ty)?;
+            }
+            Instruction::AtomicStore { mem, ty, .. } => {
+                self.check_atomic_memarg(mem, self.natural_alignment(instr)?)?;
+                self.op(&[self.memory(mem.memory)?, (*ty).into()], &[])?;
+            }
+            Instruction::AtomicRmw { mem, ty, .. } => {
+                self.check_atomic_memarg(mem, self.natural_alignment(instr)?)?;
+                let ty = ValType::from(*ty);
+                self.op(&[self.memory(mem.memory)?, ty], &[ty])?;
+            }
+            Instruction::AtomicCompareExchange { mem, ty, .. } => {
+                self.check_atomic_memarg(mem, self.natural_alignment(instr)?)?;
+                let ty = ValType::from(*ty);
+                self.op(&[self.memory(mem.memory)?, ty, ty], &[ty])?;
+            }
+            Instruction::RefEq => {
+                let eqref = ref_type(true, HeapType::Eq);
+                self.op(&[eqref, eqref], &[ValType::I32])?
+            }
+            Instruction::StructNew(ty) => {
+                let fields: Vec<_> = self
+                    .struct_type(*ty)?
+                    .iter()
+                    .map(|field| field.storage.unpacked())
+                    .collect();
+                self.op(&fields, &[ref_type(false, HeapType::Concrete(*ty))])?
+            }
+            Instruction::StructNewDefault(ty) => {
+                let fields = self.struct_type(*ty)?;
+                if let Some(field) = fields
+                    .iter()
+                    .position(|field| !field.storage.unpacked().is_defaultable())
+                {
+                    return Err(self.error(format!(
+                        "field {} of type {} has no default value",
+                        field, ty
+                    )));
+                }
+                self.push(ref_type(false, HeapType::Concrete(*ty)))
+            }
+            Instruction::StructGet { ty, field, signed } => {
+                let field_type = self.struct_field(*ty, *field)?;
+                self.check_packed(field_type, *signed)?;
+                let operand = ref_type(true, HeapType::Concrete(*ty));
+                self.op(&[operand], &[field_type.storage.unpacked()])?
+            }
+            Instruction::StructSet { ty, field } => {
+                let field_type = self.struct_field(*ty, *field)?;
+                if !field_type.mutable {
+                    return Err(self.error(format!("field {} of type {} is immutable", field, ty)));
+                }
+                let operand = ref_type(true, HeapType::Concrete(*ty));
+                self.op(&[operand, field_type.storage.unpacked()], &[])?
+            }
+            Instruction::ArrayNew(ty) => {
+                let elem = self.array_type(*ty)?.storage.unpacked();
+                let array = ref_type(false, HeapType::Concrete(*ty));
+                self.op(&[elem, ValType::I32], &[array])?
+            }
+            Instruction::ArrayNewDefault(ty) => {
+                if !self.array_type(*ty)?.storage.unpacked().is_defaultable() {
+                    return Err(self.error(format!(
+                        "the elements of array type {} have no default value",
+                        ty
+                    )));
+                }
+                self.op(&[ValType::I32], &[ref_type(false, HeapType::Concrete(*ty))])?
+            }
+            Instruction::ArrayNewFixed { ty, len } => {
+                let elem = self.array_type(*ty)?.storage.unpacked();
+                for _ in 0..*len {
+                    self.pop_expect(elem)?;
+                }
+                self.push(ref_type(false, HeapType::Concrete(*ty)))
+            }
+            Instruction::ArrayNewData { ty, .. } => {
+                let elem = self.array_type(*ty)?;
+                self.check_data_array(*ty, elem)?;
+                let array = ref_type(false, HeapType::Concrete(*ty));
+                self.op(&[ValType::I32, ValType::I32], &[array])?
+            }
+            Instruction::ArrayNewElem { ty, elem } => {
+                let elem_type = self.array_type(*ty)?;
+                self.check_elem_array(*ty, elem_type, *elem)?;
+                let array = ref_type(false, HeapType::Concrete(*ty));
+                self.op(&[ValType::I32, ValType::I32], &[array])?
+            }
+            Instruction::ArrayGet { ty, signed } => {
+                let elem = self.array_type(*ty)?;
+                self.check_packed(elem, *signed)?;
+                let array = ref_type(true, HeapType::Concrete(*ty));
+                self.op(&[array, ValType::I32], &[elem.storage.unpacked()])?
+            }
+            Instruction::ArraySet(ty) => {
+                let elem = self.mutable_array_type(*ty)?.storage.unpacked();
+                let array = ref_type(true, HeapType::Concrete(*ty));
+                self.op(&[array, ValType::I32, elem], &[])?
+            }
+            Instruction::ArrayLen => {
+                self.op(&[ref_type(true, HeapType::Array)], &[ValType::I32])?
+            }
+            Instruction::ArrayFill(ty) => {
+                let elem = self.mutable_array_type(*ty)?.storage.unpacked();
+                let array = ref_type(true, HeapType::Concrete(*ty));
+                self.op(&[array, ValType::I32, elem, ValType::I32], &[])?
+            }
+            Instruction::ArrayCopy { dst, src } => {
+                let dst_elem = self.mutable_array_type(*dst)?;
+                let src_elem = self.array_type(*src)?;
+                if !self.ctx.storage_subtype(src_elem.storage, dst_elem.storage) {
+                    return Err(self.error(format!(
+                        "can't copy an array of {} into an array of {}",
+                        src_elem.storage, dst_elem.storage
+                    )));
+                }
+                let dst = ref_type(true, HeapType::Concrete(*dst));
+                let src = ref_type(true, HeapType::Concrete(*src));
+                self.op(&[dst, ValType::I32, src, ValType::I32, ValType::I32], &[])?
+            }
+            Instruction::ArrayInitData { ty, .. } => {
+                let elem = self.mutable_array_type(*ty)?;
+                self.check_data_array(*ty, elem)?;
+                let array = ref_type(true, HeapType::Concrete(*ty));
+                self.op(&[array, ValType::I32, ValType::I32, ValType::I32], &[])?
+            }
+            Instruction::ArrayInitElem { ty, elem } => {
+                let elem_type = self.mutable_array_type(*ty)?;
+                self.check_elem_array(*ty, elem_type, *elem)?;
+                let array = ref_type(true, HeapType::Concrete(*ty));
+                self.op(&[array, ValType::I32, ValType::I32, ValType::I32], &[])?
+            }
+            Instruction::RefTest(ty) | Instruction::RefCast(ty) => {
+                // Any reference of the same hierarchy can be tested
+                let top = ref_type(true, self.ctx.top_type(ty.heap_type));
+                let result = match instr {
+                    Instruction::RefTest(_) => ValType::I32,
+                    _ => ValType::Ref(*ty),
+                };
+                self.op(&[top], &[result])?
+            }
+            Instruction::BranchOnCast { label, from, to }
+            | Instruction::BranchOnCastFail { label, from, to } => {
+                if !self.ctx.is_subtype((*to).into(), (*from).into()) {
+                    return Err(self.error(format!("cast from {} to {} never succeeds", from, to)));
+                }
+                // The type that's left once the cast succeeded
+                let diff = ValType::Ref(types::RefType {
+                    nullable: from.nullable && !to.nullable,
+                    heap_type: from.heap_type,
+                });
+                let (branch, fallthrough) = match instr {
+                    Instruction::BranchOnCast { .. } => (ValType::Ref(*to), diff),
+                    _ => (diff, ValType::Ref(*to)),
+                };
+
+                let mut types = self.label_types(*label)?;
+                match types.pop() {
+                    Some(expected) if self.ctx.is_subtype(branch, expected) => {}
+                    _ => {
+                        return Err(self.error(format!(
+                            "label {} expects {} which doesn't end with a supertype of {}",
+                            label,
+                            display_types(&self.label_types(*label)?),
+                            branch
+                        )))
+                    }
+                }
+                self.pop_expect((*from).into())?;
+                self.op(&types, &types)?;
+                self.push(fallthrough);
+            }
+            Instruction::AnyConvertExtern | Instruction::ExternConvertAny => {
+                let (from, to) = match instr {
+                    Instruction::AnyConvertExtern => (HeapType::Extern, HeapType::Any),
+                    _ => (HeapType::Any, HeapType::Extern),
+                };
+                // The nullability is kept
+                let nullable = match self.pop_expect(ref_type(true, from))? {
+                    Some(ValType::Ref(ty)) => ty.nullable,
+                    _ => false,
+                };
+                self.push(ref_type(nullable, to))
+            }
+            Instruction::RefI31 => self.op(&[ValType::I32], &[ref_type(false, HeapType::I31)])?,
+            Instruction::I31Get(_) => self.op(&[ref_type(true, HeapType::I31)], &[ValType::I32])?,
+        }
+
+        Ok(())
+    }
+}
+
+/// The type of the length operand of a copy between memories or tables indexed with `dst` and `src`,
+/// it's only an `i64` when both are
+fn min_index(dst: ValType, src: ValType) -> ValType {
+    match (dst, src) {
+        (ValType::I64, ValType::I64) => ValType::I64,
+        _ => ValType::I32,
+    }
+}
+
+/// Type checks a constant expression, which must produce a single value of type `expected`
+pub(crate) fn check_const_expr(
+    ctx: &Context,
+    instrs: &[Instruction],
+    expected: ValType,
+    location: Location,
+) -> Result<()> {
+    let mut checker = FuncChecker {
+        ctx,
+        locals: Vec::new(),
+        inits: Vec::new(),
+        stack: Vec::new(),
+        frames: Vec::new(),
+        location,
+        current: None,
+    };
+
+    checker.push_frame(FrameKind::Function, vec![], vec![expected]);
+    checker.check_instrs(instrs)?;
+    checker.current = None;
+    checker.pop_frame()?;
+
+    Ok(())
+}
+
+/// Type checks the body of the function with absolute index `idx`
+pub(crate) fn check_function(
+    ctx: &Context,
+    idx: usize,
+    ty: &FunctionType,
+    func: &Function,
+) -> Result<()> {
+    let mut locals = Vec::with_capacity(ty.parameter_types.len() + func.locals.len());
+    let mut count = 0;
+    for param in ty.parameter_types.iter() {
+        count += 1;
+        locals.push((count, *param));
+    }
+    for local in func.locals.iter() {
+        count += local.n as u64;
+        locals.push((count, local.ty));
+    }
+
+    let mut checker = FuncChecker {
+        ctx,
+        locals,
+        // Parameters are always initialized
+        inits: (0..ty.parameter_types.len() as LocalIdx).collect(),
+        stack: Vec::new(),
+        frames: Vec::new(),
+        location: Location::new(format!("func {}", idx)),
+        current: None,
+    };
+
+    if count > u32::MAX as u64 {
+        return Err(checker.error("too many locals"));
+    }
+
+    checker.push_frame(FrameKind::Function, vec![], ty.return_types.clone());
+    checker.check_instrs(&func.body.0)?;
+    checker.current = None;
+    checker.pop_frame()?;
+
+    Ok(())
+}
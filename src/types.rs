@@ -1,121 +1,771 @@
-use std::io::{self, Write};
+use crate::{
+    error::{Error, Result},
+    sections::TypeIdx,
+};
+use std::{
+    convert::TryFrom,
+    fmt,
+    io::{self, Read, Write},
+};
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum ValType {
     I32,
     I64,
     F32,
     F64,
+    /// A 128-bit vector, requires the "SIMD" proposal
+    V128,
+    /// A reference, requires the "reference types" proposal
+    Ref(RefType),
+}
+
+impl ValType {
+    /// A nullable reference to a function
+    pub const FUNCREF: ValType = ValType::Ref(RefType::FUNCREF);
+    /// A nullable reference to a host object
+    pub const EXTERNREF: ValType = ValType::Ref(RefType::EXTERNREF);
+    /// A nullable reference to a caught exception, requires the "exceptions" proposal
+    pub const EXNREF: ValType = ValType::Ref(RefType::EXNREF);
+
+    /// Whether the type is a reference type
+    pub fn is_ref(&self) -> bool {
+        matches!(self, ValType::Ref(_))
+    }
+
+    /// Whether locals of this type can start with a default value (zero or null)
+    pub fn is_defaultable(&self) -> bool {
+        match self {
+            ValType::Ref(ty) => ty.nullable,
+            _ => true,
+        }
+    }
+}
+
+impl fmt::Display for ValType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValType::I32 => f.write_str("i32"),
+            ValType::I64 => f.write_str("i64"),
+            ValType::F32 => f.write_str("f32"),
+            ValType::F64 => f.write_str("f64"),
+            ValType::V128 => f.write_str("v128"),
+            ValType::Ref(ty) => ty.fmt(f),
+        }
+    }
+}
+
+impl From<RefType> for ValType {
+    fn from(ty: RefType) -> Self {
+        ValType::Ref(ty)
+    }
+}
+
+/// What a reference points to
+///
+/// Every heap type but `Func`, `Extern` and `Concrete` requires the "GC" proposal
+/// (`Exn` and `NoExn` the "exceptions" one instead)
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum HeapType {
+    Func,
+    Extern,
+    Any,
+    Eq,
+    I31,
+    Struct,
+    Array,
+    Exn,
+    /// The bottom of `Any`, only `null` has this type
+    None,
+    /// The bottom of `Func`
+    NoFunc,
+    /// The bottom of `Extern`
+    NoExtern,
+    /// The bottom of `Exn`
+    NoExn,
+    /// A type of the type section, requires the "typed function references" proposal
+    Concrete(TypeIdx),
+}
+
+impl HeapType {
+    /// The byte encoding an abstract heap type
+    fn byte(&self) -> Option<u8> {
+        Some(match self {
+            HeapType::Func => 0x70,
+            HeapType::Extern => 0x6F,
+            HeapType::Any => 0x6E,
+            HeapType::Eq => 0x6D,
+            HeapType::I31 => 0x6C,
+            HeapType::Struct => 0x6B,
+            HeapType::Array => 0x6A,
+            HeapType::Exn => 0x69,
+            HeapType::None => 0x71,
+            HeapType::NoFunc => 0x73,
+            HeapType::NoExtern => 0x72,
+            HeapType::NoExn => 0x74,
+            HeapType::Concrete(_) => return None,
+        })
+    }
+
+    fn from_byte(byte: u8) -> Option<Self> {
+        Some(match byte {
+            0x70 => HeapType::Func,
+            0x6F => HeapType::Extern,
+            0x6E => HeapType::Any,
+            0x6D => HeapType::Eq,
+            0x6C => HeapType::I31,
+            0x6B => HeapType::Struct,
+            0x6A => HeapType::Array,
+            0x69 => HeapType::Exn,
+            0x71 => HeapType::None,
+            0x73 => HeapType::NoFunc,
+            0x72 => HeapType::NoExtern,
+            0x74 => HeapType::NoExn,
+            _ => return None,
+        })
+    }
+
+    pub(crate) fn encode(&self, writer: &mut impl Write) -> Result<usize> {
+        match (self.byte(), self) {
+            (Some(byte), _) => encode_raw(writer, &[byte]),
+            // Like block types the index is a positive s33 so it can't collide with the bytes above
+            (None, HeapType::Concrete(idx)) => encode_i64(writer, *idx as i64),
+            (None, _) => unreachable!("every abstract heap type has a byte"),
+        }
+    }
+
+    pub(crate) fn decode(reader: &mut impl Read) -> Result<Self> {
+        let byte = decode_byte(reader)?;
+        if let Some(ty) = HeapType::from_byte(byte) {
+            return Ok(ty);
+        }
+        let idx = decode_i64(&mut [byte].chain(reader))?;
+        if idx < 0 || idx > u32::MAX as i64 {
+            return Err(malformed(format!("invalid heap type 0x{:02X}", byte)));
+        }
+        Ok(HeapType::Concrete(idx as u32))
+    }
+}
+
+impl fmt::Display for HeapType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            HeapType::Func => "func",
+            HeapType::Extern => "extern",
+            HeapType::Any => "any",
+            HeapType::Eq => "eq",
+            HeapType::I31 => "i31",
+            HeapType::Struct => "struct",
+            HeapType::Array => "array",
+            HeapType::Exn => "exn",
+            HeapType::None => "none",
+            HeapType::NoFunc => "nofunc",
+            HeapType::NoExtern => "noextern",
+            HeapType::NoExn => "noexn",
+            HeapType::Concrete(idx) => return idx.fmt(f),
+        })
+    }
+}
+
+/// The type of a reference, used by tables and values
+///
+/// Non-nullable references require the "typed function references" proposal
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct RefType {
+    /// Whether the reference can be `null`
+    pub nullable: bool,
+    pub heap_type: HeapType,
+}
+
+impl RefType {
+    /// `funcref`, a nullable reference to a function
+    pub const FUNCREF: RefType = RefType {
+        nullable: true,
+        heap_type: HeapType::Func,
+    };
+    /// `externref`, a nullable reference to a host object
+    pub const EXTERNREF: RefType = RefType {
+        nullable: true,
+        heap_type: HeapType::Extern,
+    };
+    /// `exnref`, a nullable reference to a caught exception
+    pub const EXNREF: RefType = RefType {
+        nullable: true,
+        heap_type: HeapType::Exn,
+    };
+
+    pub(crate) fn encode(&self, writer: &mut impl Write) -> Result<usize> {
+        match (self.nullable, self.heap_type.byte()) {
+            // The nullable abstract types have a shorthand
+            (true, Some(byte)) => encode_raw(writer, &[byte]),
+            (true, None) => Ok(encode_raw(writer, &[0x63])? + self.heap_type.encode(writer)?),
+            (false, _) => Ok(encode_raw(writer, &[0x64])? + self.heap_type.encode(writer)?),
+        }
+    }
+
+    pub(crate) fn decode(reader: &mut impl Read) -> Result<Self> {
+        let byte = decode_byte(reader)?;
+        RefType::decode_after(byte, reader)?
+            .ok_or_else(|| malformed(format!("invalid reference type 0x{:02X}", byte)))
+    }
+
+    /// Decodes the reference type starting with `byte`, `None` if no reference type starts with it
+    pub(crate) fn decode_after(byte: u8, reader: &mut impl Read) -> Result<Option<Self>> {
+        let nullable = match byte {
+            0x63 => true,
+            0x64 => false,
+            _ => {
+                return Ok(HeapType::from_byte(byte).map(|heap_type| RefType {
+                    nullable: true,
+                    heap_type,
+                }))
+            }
+        };
+        Ok(Some(RefType {
+            nullable,
+            heap_type: HeapType::decode(reader)?,
+        }))
+    }
+}
+
+impl fmt::Display for RefType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.nullable {
+            let shorthand = match self.heap_type {
+                HeapType::Func => "funcref",
+                HeapType::Extern => "externref",
+                HeapType::Any => "anyref",
+                HeapType::Eq => "eqref",
+                HeapType::I31 => "i31ref",
+                HeapType::Struct => "structref",
+                HeapType::Array => "arrayref",
+                HeapType::Exn => "exnref",
+                HeapType::None => "nullref",
+                HeapType::NoFunc => "nullfuncref",
+                HeapType::NoExtern => "nullexternref",
+                HeapType::NoExn => "nullexnref",
+                HeapType::Concrete(idx) => return write!(f, "(ref null {})", idx),
+            };
+            return f.write_str(shorthand);
+        }
+        write!(f, "(ref {})", self.heap_type)
+    }
 }
 
 /// Describes a limit
+///
+/// The limits are 64 bits wide to fit the ones of 64 bit memories and tables,
+/// the ones of 32 bit memories and tables must fit in a `u32`
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Limits {
     /// minimum
-    pub min: u32,
+    pub min: u64,
     /// maximum (optional)
-    pub max: Option<u32>,
+    pub max: Option<u64>,
 }
 
 impl Limits {
-    pub fn encode(&self, writer: &mut impl Write) -> io::Result<()> {
+    pub fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        self.encode_with_flags(writer, 0x00)
+    }
+
+    /// Encodes the limits after a flags byte combining `flags` with the bit marking a maximum
+    pub(crate) fn encode_with_flags(&self, writer: &mut impl Write, flags: u8) -> Result<()> {
+        // A LEB128 doesn't depend on the width of the integer so 32 bit limits need no special case
         match self.max {
             Some(max) => {
-                writer.write(&[0x01])?;
-                encode_u32(writer, self.min)?;
-                encode_u32(writer, max)?;
+                writer.write_all(&[flags | 0x01])?;
+                encode_u64(writer, self.min)?;
+                encode_u64(writer, max)?;
             }
             None => {
-                writer.write(&[0x00])?;
-                encode_u32(writer, self.min)?;
+                writer.write_all(&[flags])?;
+                encode_u64(writer, self.min)?;
             }
         };
 
         Ok(())
     }
+
+    pub fn decode(reader: &mut impl Read) -> Result<Self> {
+        Ok(Limits::decode_with_flags(reader, 0x00)?.0)
+    }
+
+    /// Decodes the limits along with the bits of their flags byte other than the one marking a maximum,
+    /// only the bits in `allowed` may be set
+    ///
+    /// The limits are read as 64 bit integers when the `0x04` bit is set
+    pub(crate) fn decode_with_flags(reader: &mut impl Read, allowed: u8) -> Result<(Self, u8)> {
+        let flags = decode_byte(reader)?;
+        if flags & !(allowed | 0x01) != 0 {
+            return Err(malformed(format!("invalid limits flag 0x{:02X}", flags)));
+        }
+        let decode = |reader: &mut _| match flags & 0x04 {
+            0x04 => decode_u64(reader),
+            _ => decode_u32(reader).map(u64::from),
+        };
+        let min = decode(reader)?;
+        let max = match flags & 0x01 {
+            0x01 => Some(decode(reader)?),
+            _ => None,
+        };
+        Ok((Limits { min, max }, flags & !0x01))
+    }
 }
 
-pub(crate) fn encode_u32(writer: &mut impl Write, val: u32) -> io::Result<usize> {
+pub(crate) fn encode_u32(writer: &mut impl Write, val: u32) -> Result<usize> {
     let bytes = leb128::write::unsigned(writer, val as u64)?;
     assert!(bytes <= (32f32 / 7.0).ceil() as usize);
     Ok(bytes)
 }
 
-pub(crate) fn encode_i32(writer: &mut impl Write, val: i32) -> io::Result<usize> {
+pub(crate) fn encode_u64(writer: &mut impl Write, val: u64) -> Result<usize> {
+    let bytes = leb128::write::unsigned(writer, val)?;
+    assert!(bytes <= (64f32 / 7.0).ceil() as usize);
+    Ok(bytes)
+}
+
+pub(crate) fn encode_i32(writer: &mut impl Write, val: i32) -> Result<usize> {
     let bytes = leb128::write::signed(writer, val as i64)?;
     assert!(bytes <= (32f32 / 7.0).ceil() as usize);
     Ok(bytes)
 }
 
-pub(crate) fn encode_i64(writer: &mut impl Write, val: i64) -> io::Result<usize> {
+pub(crate) fn encode_i64(writer: &mut impl Write, val: i64) -> Result<usize> {
     let bytes = leb128::write::signed(writer, val)?;
     assert!(bytes <= (64f32 / 7.0).ceil() as usize);
     Ok(bytes)
 }
 
-pub(crate) fn encode_f32(writer: &mut impl Write, val: f32) -> io::Result<usize> {
-    writer.write(&val.to_le_bytes())
+pub(crate) fn encode_f32(writer: &mut impl Write, val: f32) -> Result<usize> {
+    encode_raw(writer, &val.to_le_bytes())
+}
+
+pub(crate) fn encode_f64(writer: &mut impl Write, val: f64) -> Result<usize> {
+    encode_raw(writer, &val.to_le_bytes())
+}
+
+pub(crate) fn encode_u128(writer: &mut impl Write, val: u128) -> Result<usize> {
+    encode_raw(writer, &val.to_le_bytes())
+}
+
+/// Converts a count to the `u32` used by the binary format
+pub(crate) fn count_to_u32(count: usize) -> Result<u32> {
+    u32::try_from(count).map_err(|_| Error::CountOverflow(count))
 }
 
-pub(crate) fn encode_f64(writer: &mut impl Write, val: f64) -> io::Result<usize> {
-    writer.write(&val.to_le_bytes())
+/// Writes raw bytes returning how many were written
+pub(crate) fn encode_raw(writer: &mut impl Write, bytes: &[u8]) -> Result<usize> {
+    writer.write_all(bytes)?;
+    Ok(bytes.len())
 }
 
-pub(crate) fn encode_vec(writer: &mut impl Write, bytes: &[u8], size: u32) -> io::Result<usize> {
-    let mut length = encode_u32(writer, size)?;
-    length += writer.write(bytes)?;
+pub(crate) fn encode_vec(writer: &mut impl Write, bytes: &[u8], count: usize) -> Result<usize> {
+    let mut length = encode_u32(writer, count_to_u32(count)?)?;
+    length += encode_raw(writer, bytes)?;
     Ok(length)
 }
 
-pub(crate) fn encode_name(writer: &mut impl Write, val: &str) -> io::Result<usize> {
-    encode_vec(writer, val.as_bytes(), val.chars().count() as u32)
+pub(crate) fn encode_name(writer: &mut impl Write, val: &str) -> Result<usize> {
+    // The length of a name is the number of bytes of its UTF-8 encoding, not of chars
+    encode_vec(writer, val.as_bytes(), val.len())
 }
 
-pub(crate) fn encode_val_type(writer: &mut impl Write, ty: ValType) -> io::Result<usize> {
+pub(crate) fn encode_val_type(writer: &mut impl Write, ty: ValType) -> Result<usize> {
     match ty {
-        ValType::I32 => writer.write(&[0x7F]),
-        ValType::I64 => writer.write(&[0x7E]),
-        ValType::F32 => writer.write(&[0x7D]),
-        ValType::F64 => writer.write(&[0x7C]),
+        ValType::I32 => encode_raw(writer, &[0x7F]),
+        ValType::I64 => encode_raw(writer, &[0x7E]),
+        ValType::F32 => encode_raw(writer, &[0x7D]),
+        ValType::F64 => encode_raw(writer, &[0x7C]),
+        ValType::V128 => encode_raw(writer, &[0x7B]),
+        ValType::Ref(ty) => ty.encode(writer),
     }
 }
 
-pub(crate) fn encode_result_type(writer: &mut impl Write, types: &[ValType]) -> io::Result<()> {
+pub(crate) fn malformed(msg: impl Into<String>) -> Error {
+    Error::Malformed(msg.into())
+}
+
+fn leb128_error(err: leb128::read::Error) -> Error {
+    match err {
+        leb128::read::Error::IoError(err) => Error::Io(err),
+        leb128::read::Error::Overflow => malformed("integer representation too long"),
+    }
+}
+
+pub(crate) fn decode_byte(reader: &mut impl Read) -> Result<u8> {
+    let mut byte = [0];
+    reader.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+pub(crate) fn decode_u32(reader: &mut impl Read) -> Result<u32> {
+    let val = leb128::read::unsigned(reader).map_err(leb128_error)?;
+    if val > u32::MAX as u64 {
+        return Err(malformed("integer too large"));
+    }
+    Ok(val as u32)
+}
+
+pub(crate) fn decode_u64(reader: &mut impl Read) -> Result<u64> {
+    leb128::read::unsigned(reader).map_err(leb128_error)
+}
+
+pub(crate) fn decode_i32(reader: &mut impl Read) -> Result<i32> {
+    let val = leb128::read::signed(reader).map_err(leb128_error)?;
+    if val < i32::MIN as i64 || val > i32::MAX as i64 {
+        return Err(malformed("integer too large"));
+    }
+    Ok(val as i32)
+}
+
+pub(crate) fn decode_i64(reader: &mut impl Read) -> Result<i64> {
+    leb128::read::signed(reader).map_err(leb128_error)
+}
+
+pub(crate) fn decode_f32(reader: &mut impl Read) -> Result<f32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(f32::from_le_bytes(bytes))
+}
+
+pub(crate) fn decode_f64(reader: &mut impl Read) -> Result<f64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(f64::from_le_bytes(bytes))
+}
+
+pub(crate) fn decode_u128(reader: &mut impl Read) -> Result<u128> {
+    let mut bytes = [0; 16];
+    reader.read_exact(&mut bytes)?;
+    Ok(u128::from_le_bytes(bytes))
+}
+
+/// Decodes a vector by reading its length and then calling `f` once per element
+pub(crate) fn decode_vec<R: Read, T>(
+    reader: &mut R,
+    mut f: impl FnMut(&mut R) -> Result<T>,
+) -> Result<Vec<T>> {
+    let len = decode_u32(reader)?;
+    // Don't trust the length for preallocation since it comes from untrusted input
+    let mut items = Vec::new();
+    for _ in 0..len {
+        items.push(f(reader)?);
+    }
+    Ok(items)
+}
+
+pub(crate) fn decode_bytes(reader: &mut impl Read) -> Result<Vec<u8>> {
+    let len = decode_u32(reader)?;
+    let mut bytes = Vec::new();
+    reader.take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != len as usize {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+    Ok(bytes)
+}
+
+pub(crate) fn decode_name(reader: &mut impl Read) -> Result<String> {
+    String::from_utf8(decode_bytes(reader)?).map_err(|_| malformed("malformed UTF-8 name"))
+}
+
+/// Decodes the value type starting with `byte`, `None` if no value type starts with it
+pub(crate) fn decode_val_type_after(byte: u8, reader: &mut impl Read) -> Result<Option<ValType>> {
+    Ok(match byte {
+        0x7F => Some(ValType::I32),
+        0x7E => Some(ValType::I64),
+        0x7D => Some(ValType::F32),
+        0x7C => Some(ValType::F64),
+        0x7B => Some(ValType::V128),
+        _ => RefType::decode_after(byte, reader)?.map(ValType::Ref),
+    })
+}
+
+pub(crate) fn decode_val_type(reader: &mut impl Read) -> Result<ValType> {
+    let byte = decode_byte(reader)?;
+    decode_val_type_after(byte, reader)?
+        .ok_or_else(|| malformed(format!("invalid value type 0x{:02X}", byte)))
+}
+
+pub(crate) fn decode_result_type(reader: &mut impl Read) -> Result<Vec<ValType>> {
+    decode_vec(reader, decode_val_type)
+}
+
+pub(crate) fn encode_result_type(writer: &mut impl Write, types: &[ValType]) -> Result<()> {
     let mut buf = Vec::with_capacity(types.len() + 1);
 
     for ty in types {
         encode_val_type(&mut buf, *ty)?;
     }
 
-    encode_vec(writer, &buf, types.len() as u32)?;
+    encode_vec(writer, &buf, types.len())?;
 
     Ok(())
 }
 
 /// A function type is composed of the types of the parameters and the types of the returns
 ///
-/// Warning: Multiple return types require the "multi-value" proposal
-/// (although this has been accepted and merged into the core spec beware)
-#[derive(Debug, Clone, PartialEq)]
+/// More than one return type requires the "multi-value" proposal
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct FunctionType {
     pub parameter_types: Vec<ValType>,
     pub return_types: Vec<ValType>,
 }
 
 impl FunctionType {
-    pub(crate) fn encode(&self, writer: &mut impl Write) -> io::Result<()> {
-        writer.write(&[0x60])?;
+    pub(crate) fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        writer.write_all(&[0x60])?;
 
         encode_result_type(writer, &self.parameter_types)?;
 
-        if self.return_types.len() > 1 {
-            log::debug!("Warning: Multiple return types require the multi-value proposal");
+        encode_result_type(writer, &self.return_types)?;
+
+        Ok(())
+    }
+
+    /// Decodes the rest of a function type after its 0x60 form
+    fn decode_after_form(reader: &mut impl Read) -> Result<Self> {
+        Ok(FunctionType {
+            parameter_types: decode_result_type(reader)?,
+            return_types: decode_result_type(reader)?,
+        })
+    }
+}
+
+/// What a field of a struct or the elements of an array hold
+///
+/// The packed types only exist in fields, they're read as `i32`s
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum StorageType {
+    Val(ValType),
+    I8,
+    I16,
+}
+
+impl StorageType {
+    /// The type of the values read from and written to the field
+    pub fn unpacked(&self) -> ValType {
+        match self {
+            StorageType::Val(ty) => *ty,
+            StorageType::I8 | StorageType::I16 => ValType::I32,
         }
+    }
 
-        encode_result_type(writer, &self.return_types)?;
+    /// Whether the field is packed, the only kind that can be read with a sign extension
+    pub fn is_packed(&self) -> bool {
+        !matches!(self, StorageType::Val(_))
+    }
+
+    fn encode(&self, writer: &mut impl Write) -> Result<usize> {
+        match self {
+            StorageType::Val(ty) => encode_val_type(writer, *ty),
+            StorageType::I8 => encode_raw(writer, &[0x78]),
+            StorageType::I16 => encode_raw(writer, &[0x77]),
+        }
+    }
+
+    fn decode(reader: &mut impl Read) -> Result<Self> {
+        match decode_byte(reader)? {
+            0x78 => Ok(StorageType::I8),
+            0x77 => Ok(StorageType::I16),
+            byte => match decode_val_type_after(byte, reader)? {
+                Some(ty) => Ok(StorageType::Val(ty)),
+                None => Err(malformed(format!("invalid storage type 0x{:02X}", byte))),
+            },
+        }
+    }
+}
+
+impl fmt::Display for StorageType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageType::Val(ty) => ty.fmt(f),
+            StorageType::I8 => f.write_str("i8"),
+            StorageType::I16 => f.write_str("i16"),
+        }
+    }
+}
+
+/// A field of a struct, or the elements of an array
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct FieldType {
+    pub storage: StorageType,
+    pub mutable: bool,
+}
+
+impl FieldType {
+    fn encode(&self, writer: &mut impl Write) -> Result<usize> {
+        let length = self.storage.encode(writer)?;
+        Ok(length + encode_raw(writer, &[self.mutable as u8])?)
+    }
+
+    fn decode(reader: &mut impl Read) -> Result<Self> {
+        let storage = StorageType::decode(reader)?;
+        let mutable = match decode_byte(reader)? {
+            0x00 => false,
+            0x01 => true,
+            flag => return Err(malformed(format!("invalid mutability 0x{:02X}", flag))),
+        };
+        Ok(FieldType { storage, mutable })
+    }
+}
+
+/// The structure of a type of the type section
+///
+/// Structs and arrays require the "GC" proposal
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum CompositeType {
+    Func(FunctionType),
+    Struct(Vec<FieldType>),
+    Array(FieldType),
+}
+
+impl CompositeType {
+    fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        match self {
+            CompositeType::Func(ty) => ty.encode(writer),
+            CompositeType::Struct(fields) => {
+                encode_raw(writer, &[0x5F])?;
+                encode_u32(writer, count_to_u32(fields.len())?)?;
+                for field in fields {
+                    field.encode(writer)?;
+                }
+                Ok(())
+            }
+            CompositeType::Array(field) => {
+                encode_raw(writer, &[0x5E])?;
+                field.encode(writer)?;
+                Ok(())
+            }
+        }
+    }
+
+    fn decode_after_form(form: u8, reader: &mut impl Read) -> Result<Self> {
+        match form {
+            0x60 => Ok(CompositeType::Func(FunctionType::decode_after_form(
+                reader,
+            )?)),
+            0x5F => Ok(CompositeType::Struct(decode_vec(
+                reader,
+                FieldType::decode,
+            )?)),
+            0x5E => Ok(CompositeType::Array(FieldType::decode(reader)?)),
+            form => Err(malformed(format!(
+                "invalid composite type form 0x{:02X}",
+                form
+            ))),
+        }
+    }
+}
+
+/// A type of the type section along with its place in the subtyping hierarchy
+///
+/// Anything but a final type without supertype requires the "GC" proposal
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SubType {
+    /// Whether other types can't declare this one as their supertype
+    pub is_final: bool,
+    /// The type this one is declared a subtype of, it must come before it in the type section
+    pub supertype: Option<TypeIdx>,
+    pub composite: CompositeType,
+}
+
+impl SubType {
+    /// The function type, if this is one
+    pub fn func_type(&self) -> Option<&FunctionType> {
+        match &self.composite {
+            CompositeType::Func(ty) => Some(ty),
+            _ => None,
+        }
+    }
+
+    fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        // A final type without supertype is just its composite type
+        if !self.is_final || self.supertype.is_some() {
+            encode_raw(writer, &[if self.is_final { 0x4F } else { 0x50 }])?;
+            match self.supertype {
+                Some(idx) => {
+                    encode_u32(writer, 1)?;
+                    encode_u32(writer, idx)?;
+                }
+                None => {
+                    encode_u32(writer, 0)?;
+                }
+            }
+        }
+        self.composite.encode(writer)
+    }
+
+    fn decode_after_form(form: u8, reader: &mut impl Read) -> Result<Self> {
+        let is_final = match form {
+            0x50 => false,
+            0x4F => true,
+            form => {
+                return Ok(SubType {
+                    is_final: true,
+                    supertype: None,
+                    composite: CompositeType::decode_after_form(form, reader)?,
+                })
+            }
+        };
+        let supertypes = decode_vec(reader, decode_u32)?;
+        if supertypes.len() > 1 {
+            return Err(malformed("multiple supertypes"));
+        }
+        Ok(SubType {
+            is_final,
+            supertype: supertypes.first().copied(),
+            composite: CompositeType::decode_after_form(decode_byte(reader)?, reader)?,
+        })
+    }
+}
 
+/// Final function types without supertype are the only types before the "GC" proposal
+impl From<FunctionType> for SubType {
+    fn from(ty: FunctionType) -> Self {
+        SubType {
+            is_final: true,
+            supertype: None,
+            composite: CompositeType::Func(ty),
+        }
+    }
+}
+
+/// Types that can refer to each other, every type of the type section belongs to one
+///
+/// The types of all the groups share a single index space, a group of one type is written
+/// without the `rec` wrapper, others require the "GC" proposal
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RecGroup(pub Vec<SubType>);
+
+impl RecGroup {
+    pub(crate) fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        if let [ty] = &self.0[..] {
+            return ty.encode(writer);
+        }
+        encode_raw(writer, &[0x4E])?;
+        encode_u32(writer, count_to_u32(self.0.len())?)?;
+        for ty in self.0.iter() {
+            ty.encode(writer)?;
+        }
         Ok(())
     }
+
+    pub(crate) fn decode(reader: &mut impl Read) -> Result<Self> {
+        match decode_byte(reader)? {
+            0x4E => Ok(RecGroup(decode_vec(reader, |reader| {
+                let form = decode_byte(reader)?;
+                SubType::decode_after_form(form, reader)
+            })?)),
+            form => Ok(RecGroup(vec![SubType::decode_after_form(form, reader)?])),
+        }
+    }
+}
+
+impl From<FunctionType> for RecGroup {
+    fn from(ty: FunctionType) -> Self {
+        RecGroup(vec![ty.into()])
+    }
 }
 
 /// Describes a memory object
@@ -123,32 +773,93 @@ impl FunctionType {
 pub struct MemoryType {
     /// the limits of the memory object
     pub lim: Limits,
+    /// whether the memory can be shared between threads, requires the "threads" proposal
+    ///
+    /// Shared memories must have a maximum
+    pub shared: bool,
+    /// whether the memory is addressed with `i64`s instead of `i32`s, requires the "memory64" proposal
+    pub memory64: bool,
 }
 
 impl MemoryType {
-    pub(crate) fn encode(&self, writer: &mut impl Write) -> io::Result<()> {
-        self.lim.encode(writer)
+    /// The type of the addresses used to access the memory
+    pub fn index_type(&self) -> ValType {
+        index_type(self.memory64)
+    }
+
+    pub(crate) fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        let mut flags = 0x00;
+        if self.shared {
+            flags |= 0x02;
+        }
+        if self.memory64 {
+            flags |= 0x04;
+        }
+        self.lim.encode_with_flags(writer, flags)
+    }
+
+    pub(crate) fn decode(reader: &mut impl Read) -> Result<Self> {
+        let (lim, flags) = Limits::decode_with_flags(reader, 0x02 | 0x04)?;
+        Ok(MemoryType {
+            lim,
+            shared: flags & 0x02 != 0,
+            memory64: flags & 0x04 != 0,
+        })
     }
 }
 
 /// Describes a table
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct TableType {
+    /// the type of the table's elements
+    pub elem_type: RefType,
     /// the limits of the table
     pub lim: Limits,
+    /// whether the table is indexed with `i64`s instead of `i32`s, requires the "memory64" proposal
+    pub table64: bool,
 }
 
 impl TableType {
-    pub(crate) fn encode(&self, writer: &mut impl Write) -> io::Result<()> {
-        writer.write(&[0x70])?;
-        self.lim.encode(writer)
+    /// The type of the indices used to access the table
+    pub fn index_type(&self) -> ValType {
+        index_type(self.table64)
+    }
+
+    pub(crate) fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        self.elem_type.encode(writer)?;
+        match self.table64 {
+            true => self.lim.encode_with_flags(writer, 0x04),
+            false => self.lim.encode_with_flags(writer, 0x00),
+        }
+    }
+
+    pub(crate) fn decode(reader: &mut impl Read) -> Result<Self> {
+        TableType::decode_after(decode_byte(reader)?, reader)
+    }
+
+    /// Decodes the table type whose element type starts with `byte`
+    pub(crate) fn decode_after(byte: u8, reader: &mut impl Read) -> Result<Self> {
+        let elem_type = RefType::decode_after(byte, reader)?
+            .ok_or_else(|| malformed(format!("invalid reference type 0x{:02X}", byte)))?;
+        let (lim, flags) = Limits::decode_with_flags(reader, 0x04)?;
+        Ok(TableType {
+            elem_type,
+            lim,
+            table64: flags & 0x04 != 0,
+        })
+    }
+}
+
+fn index_type(is_64: bool) -> ValType {
+    match is_64 {
+        true => ValType::I64,
+        false => ValType::I32,
     }
 }
 
 /// Describes the type of a global and it's mutability or lack of it
 ///
-/// Warning: Importing or Exporting a mutable global requires "Import/Export of Mutable Globals" proposal
-/// (although this has been accepted and merged into the core spec beware)
+/// Importing or exporting a mutable global requires the "mutable globals" proposal
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct GlobalType {
     pub ty: ValType,
@@ -156,12 +867,22 @@ pub struct GlobalType {
 }
 
 impl GlobalType {
-    pub(crate) fn encode(&self, writer: &mut impl Write) -> io::Result<()> {
+    pub(crate) fn encode(&self, writer: &mut impl Write) -> Result<()> {
         encode_val_type(writer, self.ty)?;
         match self.mutable {
-            true => writer.write(&[0x01]),
-            false => writer.write(&[0x00]),
+            true => encode_raw(writer, &[0x01]),
+            false => encode_raw(writer, &[0x00]),
         }?;
         Ok(())
     }
+
+    pub(crate) fn decode(reader: &mut impl Read) -> Result<Self> {
+        let ty = decode_val_type(reader)?;
+        let mutable = match decode_byte(reader)? {
+            0x00 => false,
+            0x01 => true,
+            flag => return Err(malformed(format!("invalid mutability 0x{:02X}", flag))),
+        };
+        Ok(GlobalType { ty, mutable })
+    }
 }
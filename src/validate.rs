@@ -0,0 +1,979 @@
+use crate::{
+    error::{Error, Result},
+    features::Features,
+    instr::{BlockType, Catch, Expr, Instruction, TryHandler},
+    module::Module,
+    sections::*,
+    typecheck, types,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+/// Builds a validation error
+pub(crate) fn error(context: impl fmt::Display, message: impl Into<String>) -> Error {
+    Error::Validation {
+        context: context.to_string(),
+        message: message.into(),
+    }
+}
+
+/// Points at an instruction inside a (possibly nested) instruction sequence
+///
+/// Displays as `func 3, block 2, instr 5`
+#[derive(Debug, Clone)]
+pub(crate) struct Location {
+    base: String,
+    path: Vec<(&'static str, usize)>,
+}
+
+impl Location {
+    pub(crate) fn new(base: impl Into<String>) -> Self {
+        Location {
+            base: base.into(),
+            path: Vec::new(),
+        }
+    }
+
+    /// Enters the body of the construct at index `idx` of the current sequence
+    pub(crate) fn push(&mut self, kind: &'static str, idx: usize) {
+        self.path.push((kind, idx));
+    }
+
+    pub(crate) fn pop(&mut self) {
+        self.path.pop();
+    }
+
+    /// The location of the instruction at index `idx` of the current sequence
+    pub(crate) fn instr(&self, idx: usize) -> InstrLocation<'_> {
+        InstrLocation {
+            location: self,
+            idx: Some(idx),
+        }
+    }
+
+    /// The location of the end of the current sequence
+    pub(crate) fn end(&self) -> InstrLocation<'_> {
+        InstrLocation {
+            location: self,
+            idx: None,
+        }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.base)?;
+        for (kind, idx) in self.path.iter() {
+            write!(f, ", {} {}", kind, idx)?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) struct InstrLocation<'a> {
+    location: &'a Location,
+    idx: Option<usize>,
+}
+
+impl<'a> fmt::Display for InstrLocation<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.idx {
+            Some(idx) => write!(f, "{}, instr {}", self.location, idx),
+            None => write!(f, "{}, end", self.location),
+        }
+    }
+}
+
+/// The maximum number of pages of a 32-bit memory (4GiB)
+const MAX_PAGES: u64 = 65536;
+/// The maximum number of pages of a 64-bit memory (2^64 bytes)
+const MAX_PAGES_64: u64 = 1 << 48;
+
+fn check_limits(context: impl fmt::Display, lim: &types::Limits, bound: u64) -> Result<()> {
+    if let Some(max) = lim.max {
+        if lim.min > max {
+            return Err(error(
+                context,
+                "minimum size must not be greater than the maximum",
+            ));
+        }
+    }
+    if lim.min > bound || lim.max.is_some_and(|max| max > bound) {
+        return Err(error(context, format!("size must be at most {}", bound)));
+    }
+    Ok(())
+}
+
+fn check_table_type(context: impl fmt::Display, ty: &types::TableType) -> Result<()> {
+    match ty.table64 {
+        true => check_limits(context, &ty.lim, u64::MAX),
+        false => check_limits(context, &ty.lim, u32::MAX as u64),
+    }
+}
+
+fn check_memory_type(context: impl fmt::Display, ty: &types::MemoryType) -> Result<()> {
+    if ty.shared && ty.lim.max.is_none() {
+        return Err(error(context, "shared memory must have a maximum"));
+    }
+    match ty.memory64 {
+        true => check_limits(context, &ty.lim, MAX_PAGES_64),
+        false => check_limits(context, &ty.lim, MAX_PAGES),
+    }
+}
+
+fn check_index(context: impl fmt::Display, kind: &str, idx: u32, len: usize) -> Result<()> {
+    if (idx as usize) < len {
+        Ok(())
+    } else {
+        Err(error(context, format!("unknown {} {}", kind, idx)))
+    }
+}
+
+/// The index spaces of a module
+///
+/// Imported items always come before the ones defined in the module
+pub(crate) struct Context<'m> {
+    /// The types of every recursion group, in index order
+    pub(crate) types: Vec<&'m types::SubType>,
+    /// The canonical index of every type, equivalent types share the index of the first one
+    canon: Vec<TypeIdx>,
+    /// The type index of every function
+    pub(crate) funcs: Vec<TypeIdx>,
+    pub(crate) tables: Vec<types::TableType>,
+    pub(crate) memories: Vec<types::MemoryType>,
+    pub(crate) globals: Vec<types::GlobalType>,
+    /// The type index of every tag
+    pub(crate) tags: Vec<TypeIdx>,
+    /// The type of every element segment
+    pub(crate) elems: Vec<types::RefType>,
+    pub(crate) datas: usize,
+    pub(crate) imported_funcs: usize,
+    pub(crate) imported_globals: usize,
+    /// The functions referenced outside of function bodies, only these can be used by `ref.func`
+    pub(crate) refs: HashSet<FuncIdx>,
+}
+
+impl<'m> Context<'m> {
+    pub(crate) fn new(module: &'m Module) -> Self {
+        let mut ctx = Context {
+            types: module
+                .types
+                .iter()
+                .flat_map(|group| group.0.iter())
+                .collect(),
+            canon: Vec::new(),
+            funcs: Vec::new(),
+            tables: Vec::new(),
+            memories: Vec::new(),
+            globals: Vec::new(),
+            tags: Vec::new(),
+            elems: module.elements.iter().map(|elem| elem.items.ty()).collect(),
+            datas: module.data.len(),
+            imported_funcs: 0,
+            imported_globals: 0,
+            refs: HashSet::new(),
+        };
+
+        for import in module.imports.iter() {
+            match import.desc {
+                Desc::Function(ty) => ctx.funcs.push(ty),
+                Desc::Table(ty) => ctx.tables.push(ty),
+                Desc::Memory(ty) => ctx.memories.push(ty),
+                Desc::Global(ty) => ctx.globals.push(ty),
+                Desc::Tag(ty) => ctx.tags.push(ty),
+            }
+        }
+        ctx.canonicalize(module);
+        ctx.imported_funcs = ctx.funcs.len();
+        ctx.imported_globals = ctx.globals.len();
+
+        ctx.funcs.extend_from_slice(&module.functions);
+        ctx.tables
+            .extend(module.tables.iter().map(|table| table.ty));
+        ctx.memories.extend_from_slice(&module.memory);
+        ctx.tags.extend_from_slice(&module.tags);
+        ctx.globals
+            .extend(module.globals.iter().map(|global| global.ty));
+
+        for export in module.exports.iter() {
+            if let ExportDesc::Function(idx) = export.desc {
+                ctx.refs.insert(idx);
+            }
+        }
+        for elem in module.elements.iter() {
+            match &elem.items {
+                ElementItems::Functions(funcs) => ctx.refs.extend(funcs.iter().copied()),
+                ElementItems::Expressions(_, exprs) => {
+                    for expr in exprs.iter() {
+                        ctx.add_refs(expr);
+                    }
+                }
+            }
+        }
+        for table in module.tables.iter() {
+            if let Some(ref init) = table.init {
+                ctx.add_refs(init);
+            }
+        }
+        for global in module.globals.iter() {
+            ctx.add_refs(&global.init);
+        }
+
+        ctx
+    }
+
+    /// Assigns the canonical indices, two types are equivalent when their recursion groups
+    /// are identical once the references to earlier groups are made canonical
+    fn canonicalize(&mut self, module: &Module) {
+        // References inside the group are counted down from the end of the index space so
+        // they never clash with the canonical indices of earlier groups
+        let mut groups: HashMap<Vec<types::SubType>, TypeIdx> = HashMap::new();
+        for group in module.types.iter() {
+            let first = self.canon.len() as TypeIdx;
+            let len = group.0.len() as TypeIdx;
+            let key = group
+                .0
+                .iter()
+                .map(|ty| {
+                    map_indices(ty, |idx| match idx {
+                        idx if idx < first => self.canon[idx as usize],
+                        idx if idx - first < len => TypeIdx::MAX - (idx - first),
+                        // Left for validation to report
+                        idx => idx,
+                    })
+                })
+                .collect();
+            let canon = *groups.entry(key).or_insert(first);
+            self.canon.extend(canon..canon + len);
+        }
+    }
+
+    fn add_refs(&mut self, expr: &Expr) {
+        for instr in expr.0.iter() {
+            if let Instruction::RefFunc(idx) = instr {
+                self.refs.insert(*idx);
+            }
+        }
+    }
+
+    pub(crate) fn check_type(&self, context: impl fmt::Display, idx: TypeIdx) -> Result<()> {
+        check_index(context, "type", idx, self.types.len())
+    }
+
+    pub(crate) fn check_func(&self, context: impl fmt::Display, idx: FuncIdx) -> Result<()> {
+        check_index(context, "function", idx, self.funcs.len())
+    }
+
+    pub(crate) fn check_table(&self, context: impl fmt::Display, idx: TableIdx) -> Result<()> {
+        check_index(context, "table", idx, self.tables.len())
+    }
+
+    pub(crate) fn check_memory(&self, context: impl fmt::Display, idx: MemoryIdx) -> Result<()> {
+        check_index(context, "memory", idx, self.memories.len())
+    }
+
+    pub(crate) fn check_global(&self, context: impl fmt::Display, idx: GlobalIdx) -> Result<()> {
+        check_index(context, "global", idx, self.globals.len())
+    }
+
+    pub(crate) fn check_tag(&self, context: impl fmt::Display, idx: TagIdx) -> Result<()> {
+        check_index(context, "tag", idx, self.tags.len())
+    }
+
+    /// Checks that `idx` is a function type
+    pub(crate) fn check_func_type(
+        &self,
+        context: impl fmt::Display,
+        idx: TypeIdx,
+    ) -> Result<&'m types::FunctionType> {
+        self.check_type(&context, idx)?;
+        match self.types[idx as usize].func_type() {
+            Some(ty) => Ok(ty),
+            None => Err(error(
+                context,
+                format!("type {} is not a function type", idx),
+            )),
+        }
+    }
+
+    /// Checks the type indices used by a value type
+    pub(crate) fn check_val_type(
+        &self,
+        context: impl fmt::Display,
+        ty: types::ValType,
+    ) -> Result<()> {
+        match ty {
+            types::ValType::Ref(ty) => self.check_ref_type(context, ty),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn check_ref_type(
+        &self,
+        context: impl fmt::Display,
+        ty: types::RefType,
+    ) -> Result<()> {
+        match ty.heap_type {
+            types::HeapType::Concrete(idx) => self.check_type(context, idx),
+            _ => Ok(()),
+        }
+    }
+
+    /// Exceptions don't return, so the type of a tag must not have results
+    fn check_tag_type(&self, context: impl fmt::Display, ty: TypeIdx) -> Result<()> {
+        if !self.check_func_type(&context, ty)?.return_types.is_empty() {
+            return Err(error(context, "the type of a tag must not have results"));
+        }
+        Ok(())
+    }
+
+    /// The abstract type at the top of the hierarchy a heap type belongs to
+    pub(crate) fn top_type(&self, ty: types::HeapType) -> types::HeapType {
+        use types::HeapType::*;
+        match ty {
+            Func | NoFunc => Func,
+            Extern | NoExtern => Extern,
+            Exn | NoExn => Exn,
+            Concrete(idx) => match self.types.get(idx as usize).map(|ty| &ty.composite) {
+                Some(types::CompositeType::Func(_)) => Func,
+                _ => Any,
+            },
+            Any | Eq | I31 | Struct | Array | None => Any,
+        }
+    }
+
+    /// Whether a value of heap type `a` can be used where `b` is expected
+    pub(crate) fn heap_subtype(&self, a: types::HeapType, b: types::HeapType) -> bool {
+        use types::HeapType::*;
+        match (a, b) {
+            (Concrete(a), Concrete(b)) => {
+                // Walk up the declared supertypes of `a`
+                let mut ty = Some(a);
+                while let Some(idx) = ty {
+                    if self.canon.get(idx as usize) == self.canon.get(b as usize) {
+                        return true;
+                    }
+                    ty = self.types.get(idx as usize).and_then(|ty| ty.supertype);
+                }
+                false
+            }
+            (Concrete(idx), b) => {
+                let a = match self.types.get(idx as usize).map(|ty| &ty.composite) {
+                    Some(types::CompositeType::Func(_)) => Func,
+                    Some(types::CompositeType::Struct(_)) => Struct,
+                    Some(types::CompositeType::Array(_)) => Array,
+                    Option::None => return false,
+                };
+                self.heap_subtype(a, b)
+            }
+            (None | NoFunc | NoExtern | NoExn, b) => a == b || self.top_type(a) == self.top_type(b),
+            (_, Concrete(_)) => false,
+            (a, b) if a == b => true,
+            (I31 | Struct | Array, Eq) => true,
+            (I31 | Struct | Array | Eq, Any) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether a value of type `a` can be used where `b` is expected
+    pub(crate) fn is_subtype(&self, a: types::ValType, b: types::ValType) -> bool {
+        match (a, b) {
+            (types::ValType::Ref(a), types::ValType::Ref(b)) => {
+                (b.nullable || !a.nullable) && self.heap_subtype(a.heap_type, b.heap_type)
+            }
+            (a, b) => a == b,
+        }
+    }
+
+    pub(crate) fn storage_subtype(&self, a: types::StorageType, b: types::StorageType) -> bool {
+        match (a, b) {
+            (types::StorageType::Val(a), types::StorageType::Val(b)) => self.is_subtype(a, b),
+            (a, b) => a == b,
+        }
+    }
+
+    /// Mutable fields can be both read and written so their types must be equivalent
+    fn field_subtype(&self, a: &types::FieldType, b: &types::FieldType) -> bool {
+        a.mutable == b.mutable
+            && self.storage_subtype(a.storage, b.storage)
+            && (!a.mutable || self.storage_subtype(b.storage, a.storage))
+    }
+
+    fn composite_subtype(&self, a: &types::CompositeType, b: &types::CompositeType) -> bool {
+        use types::CompositeType::*;
+        match (a, b) {
+            (Func(a), Func(b)) => {
+                a.parameter_types.len() == b.parameter_types.len()
+                    && a.return_types.len() == b.return_types.len()
+                    && a.parameter_types
+                        .iter()
+                        .zip(b.parameter_types.iter())
+                        .all(|(a, b)| self.is_subtype(*b, *a))
+                    && a.return_types
+                        .iter()
+                        .zip(b.return_types.iter())
+                        .all(|(a, b)| self.is_subtype(*a, *b))
+            }
+            (Struct(a), Struct(b)) => {
+                a.len() >= b.len()
+                    && a.iter()
+                        .zip(b.iter())
+                        .all(|(a, b)| self.field_subtype(a, b))
+            }
+            (Array(a), Array(b)) => self.field_subtype(a, b),
+            _ => false,
+        }
+    }
+
+    /// Checks the definitions of the types section
+    ///
+    /// Types can refer to any type of their own recursion group or of the previous ones,
+    /// but only to earlier types as their supertype
+    fn check_types(&self, module: &Module) -> Result<()> {
+        let mut idx = 0;
+        for group in module.types.iter() {
+            let end = idx + group.0.len();
+            for ty in group.0.iter() {
+                let context = format!("type {}", idx);
+                let mut unknown = None;
+                map_indices(ty, |other| {
+                    if other as usize >= end {
+                        unknown = unknown.or(Some(other));
+                    }
+                    other
+                });
+                if let Some(other) = unknown {
+                    return Err(error(context, format!("unknown type {}", other)));
+                }
+
+                if let Some(supertype) = ty.supertype {
+                    if supertype as usize >= idx {
+                        return Err(error(
+                            context,
+                            format!("supertype {} must be defined before the type", supertype),
+                        ));
+                    }
+                    let parent = self.types[supertype as usize];
+                    if parent.is_final {
+                        return Err(error(
+                            context,
+                            format!("type {} is final and can't have subtypes", supertype),
+                        ));
+                    }
+                    if !self.composite_subtype(&ty.composite, &parent.composite) {
+                        return Err(error(
+                            context,
+                            format!("type doesn't match its supertype {}", supertype),
+                        ));
+                    }
+                }
+                idx += 1;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn check_elem(&self, context: impl fmt::Display, idx: ElemIdx) -> Result<()> {
+        check_index(context, "elem segment", idx, self.elems.len())
+    }
+
+    pub(crate) fn check_data(&self, context: impl fmt::Display, idx: DataIdx) -> Result<()> {
+        check_index(context, "data segment", idx, self.datas)
+    }
+
+    /// Checks the indices referenced by a sequence of instructions
+    ///
+    /// `locals` is the number of locals (including parameters) in scope
+    fn check_instrs(
+        &self,
+        instrs: &[Instruction],
+        location: &mut Location,
+        locals: u64,
+    ) -> Result<()> {
+        for (i, instr) in instrs.iter().enumerate() {
+            let context = location.instr(i);
+
+            if let Some(mem) = instr.memarg() {
+                self.check_memory(&context, mem.memory)?;
+                if !self.memories[mem.memory as usize].memory64 && mem.offset > u32::MAX as u64 {
+                    return Err(error(context, "offset out of range for a 32-bit memory"));
+                }
+            }
+
+            match instr {
+                Instruction::Block { ty, instrs } | Instruction::Loop { ty, instrs } => {
+                    self.check_block_type(&context, ty)?;
+                    let kind = match instr {
+                        Instruction::Block { .. } => "block",
+                        _ => "loop",
+                    };
+                    location.push(kind, i);
+                    self.check_instrs(instrs, location, locals)?;
+                    location.pop();
+                }
+                Instruction::If {
+                    ty,
+                    accept_instrs,
+                    reject_instrs,
+                } => {
+                    self.check_block_type(&context, ty)?;
+                    location.push("if", i);
+                    self.check_instrs(accept_instrs, location, locals)?;
+                    location.pop();
+                    if let Some(reject_instrs) = reject_instrs {
+                        location.push("else", i);
+                        self.check_instrs(reject_instrs, location, locals)?;
+                        location.pop();
+                    }
+                }
+                Instruction::Throw(tag) => self.check_tag(&context, *tag)?,
+                Instruction::TryTable {
+                    ty,
+                    catches,
+                    instrs,
+                } => {
+                    self.check_block_type(&context, ty)?;
+                    for catch in catches {
+                        if let Catch::Catch { tag, .. } | Catch::CatchRef { tag, .. } = catch {
+                            self.check_tag(&context, *tag)?;
+                        }
+                    }
+                    location.push("try_table", i);
+                    self.check_instrs(instrs, location, locals)?;
+                    location.pop();
+                }
+                Instruction::Try {
+                    ty,
+                    instrs,
+                    handler,
+                } => {
+                    self.check_block_type(&context, ty)?;
+                    if let TryHandler::Catches { catches, .. } = handler {
+                        for (tag, _) in catches {
+                            self.check_tag(&context, *tag)?;
+                        }
+                    }
+                    location.push("try", i);
+                    self.check_instrs(instrs, location, locals)?;
+                    location.pop();
+                    if let TryHandler::Catches { catches, catch_all } = handler {
+                        for (_, instrs) in catches {
+                            location.push("catch", i);
+                            self.check_instrs(instrs, location, locals)?;
+                            location.pop();
+                        }
+                        if let Some(instrs) = catch_all {
+                            location.push("catch_all", i);
+                            self.check_instrs(instrs, location, locals)?;
+                            location.pop();
+                        }
+                    }
+                }
+                Instruction::Call(idx) | Instruction::ReturnCall(idx) => {
+                    self.check_func(&context, *idx)?
+                }
+                Instruction::CallIndirect { ty, table }
+                | Instruction::ReturnCallIndirect { ty, table } => {
+                    self.check_func_type(&context, *ty)?;
+                    self.check_table(&context, *table)?;
+                }
+                Instruction::CallRef(ty) | Instruction::ReturnCallRef(ty) => {
+                    self.check_func_type(&context, *ty)?;
+                }
+                Instruction::TypedSelect(types) => {
+                    for ty in types {
+                        self.check_val_type(&context, *ty)?;
+                    }
+                }
+                Instruction::RefNull(heap_type) => {
+                    let ty = types::RefType {
+                        nullable: true,
+                        heap_type: *heap_type,
+                    };
+                    self.check_ref_type(&context, ty)?
+                }
+                Instruction::RefFunc(idx) => self.check_func(&context, *idx)?,
+                Instruction::RefTest(ty) | Instruction::RefCast(ty) => {
+                    self.check_ref_type(&context, *ty)?
+                }
+                Instruction::BranchOnCast { from, to, .. }
+                | Instruction::BranchOnCastFail { from, to, .. } => {
+                    self.check_ref_type(&context, *from)?;
+                    self.check_ref_type(&context, *to)?;
+                }
+                Instruction::StructNew(ty)
+                | Instruction::StructNewDefault(ty)
+                | Instruction::StructGet { ty, .. }
+                | Instruction::StructSet { ty, .. }
+                | Instruction::ArrayNew(ty)
+                | Instruction::ArrayNewDefault(ty)
+                | Instruction::ArrayNewFixed { ty, .. }
+                | Instruction::ArrayGet { ty, .. }
+                | Instruction::ArraySet(ty)
+                | Instruction::ArrayFill(ty) => self.check_type(&context, *ty)?,
+                Instruction::ArrayNewData { ty, data }
+                | Instruction::ArrayInitData { ty, data } => {
+                    self.check_type(&context, *ty)?;
+                    self.check_data(&context, *data)?;
+                }
+                Instruction::ArrayNewElem { ty, elem }
+                | Instruction::ArrayInitElem { ty, elem } => {
+                    self.check_type(&context, *ty)?;
+                    self.check_elem(&context, *elem)?;
+                }
+                Instruction::ArrayCopy { dst, src } => {
+                    self.check_type(&context, *dst)?;
+                    self.check_type(&context, *src)?;
+                }
+                Instruction::TableGet(idx)
+                | Instruction::TableSet(idx)
+                | Instruction::TableGrow(idx)
+                | Instruction::TableSize(idx)
+                | Instruction::TableFill(idx) => self.check_table(&context, *idx)?,
+                Instruction::LocalGet(idx)
+                | Instruction::LocalSet(idx)
+                | Instruction::LocalTee(idx)
+                    if *idx as u64 >= locals =>
+                {
+                    return Err(error(&context, format!("unknown local {}", idx)));
+                }
+                Instruction::GlobalGet(idx) | Instruction::GlobalSet(idx) => {
+                    self.check_global(&context, *idx)?
+                }
+                Instruction::MemorySize(mem)
+                | Instruction::MemoryGrow(mem)
+                | Instruction::MemoryFill(mem) => self.check_memory(&context, *mem)?,
+                Instruction::MemoryCopy { dst, src } => {
+                    self.check_memory(&context, *dst)?;
+                    self.check_memory(&context, *src)?;
+                }
+                Instruction::MemoryInit { data, mem } => {
+                    self.check_memory(&context, *mem)?;
+                    self.check_data(&context, *data)?;
+                }
+                Instruction::DataDrop(idx) => self.check_data(&context, *idx)?,
+                Instruction::TableInit { elem, table } => {
+                    self.check_elem(&context, *elem)?;
+                    self.check_table(&context, *table)?;
+                }
+                Instruction::ElemDrop(idx) => self.check_elem(&context, *idx)?,
+                Instruction::TableCopy { dst, src } => {
+                    self.check_table(&context, *dst)?;
+                    self.check_table(&context, *src)?;
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_block_type(&self, context: impl fmt::Display, ty: &BlockType) -> Result<()> {
+        match ty {
+            BlockType::TypeIdx(idx) => self.check_func_type(context, *idx).map(|_| ()),
+            BlockType::Type(ty) => self.check_val_type(context, *ty),
+            BlockType::Empty => Ok(()),
+        }
+    }
+
+    /// Checks that an expression is constant and evaluates to a single value of type `expected`
+    ///
+    /// Constant expressions may only contain constants, `ref.null`, `ref.func` and `global.get`
+    /// of the first `globals` globals if they're immutable. `extended_const` also allows `add`,
+    /// `sub` and `mul` of integers and `gc` the allocation of structs, arrays and `i31`s
+    pub(crate) fn check_const_expr(
+        &self,
+        instrs: &[Instruction],
+        expected: types::ValType,
+        location: impl Into<String>,
+        globals: usize,
+        features: &Features,
+    ) -> Result<()> {
+        let location = Location::new(location);
+
+        for (i, instr) in instrs.iter().enumerate() {
+            let context = location.instr(i);
+
+            match instr {
+                Instruction::Const(_) | Instruction::RefNull(_) => {}
+                Instruction::RefFunc(idx) => self.check_func(&context, *idx)?,
+                Instruction::GlobalGet(idx) => {
+                    self.check_global(&context, *idx)?;
+                    if *idx as usize >= globals {
+                        let message = match features.gc {
+                            true => format!("global {} must be defined before the constant", idx),
+                            false => {
+                                format!("global {} must be imported to be used in a constant", idx)
+                            }
+                        };
+                        return Err(error(&context, message));
+                    }
+                    if self.globals[*idx as usize].mutable {
+                        return Err(error(
+                            &context,
+                            format!("global {} must be immutable to be used in a constant", idx),
+                        ));
+                    }
+                }
+                Instruction::Add(ty) | Instruction::Subtract(ty) | Instruction::Multiply(ty)
+                    if features.extended_const
+                        && (*ty == types::ValType::I32 || *ty == types::ValType::I64) => {}
+                Instruction::StructNew(_)
+                | Instruction::StructNewDefault(_)
+                | Instruction::ArrayNew(_)
+                | Instruction::ArrayNewDefault(_)
+                | Instruction::ArrayNewFixed { .. }
+                | Instruction::RefI31
+                | Instruction::AnyConvertExtern
+                | Instruction::ExternConvertAny
+                    if features.gc => {}
+                _ => return Err(error(&context, "constant expression required")),
+            }
+        }
+
+        self.check_instrs(instrs, &mut location.clone(), 0)?;
+        typecheck::check_const_expr(self, instrs, expected, location)
+    }
+}
+
+/// Checks that every index used in the module refers to an existing item
+/// and type checks every function body
+///
+/// Anything that needs a proposal disabled in `features` is rejected
+pub(crate) fn validate_module(module: &Module, features: &Features) -> Result<()> {
+    features.check_module(module)?;
+    let ctx = Context::new(module);
+    ctx.check_types(module)?;
+
+    for (i, import) in module.imports.iter().enumerate() {
+        let context = format!("import {}", i);
+        match import.desc {
+            Desc::Function(ty) => ctx.check_func_type(context, ty).map(|_| ())?,
+            Desc::Table(ty) => {
+                ctx.check_ref_type(&context, ty.elem_type)?;
+                check_table_type(context, &ty)?;
+            }
+            Desc::Memory(ty) => check_memory_type(context, &ty)?,
+            Desc::Global(ty) => ctx.check_val_type(context, ty.ty)?,
+            Desc::Tag(ty) => ctx.check_tag_type(context, ty)?,
+        }
+    }
+
+    for (i, ty) in module.functions.iter().enumerate() {
+        ctx.check_func_type(format_args!("func {}", ctx.imported_funcs + i), *ty)?;
+    }
+
+    let imported_tags = ctx.tags.len() - module.tags.len();
+    for (i, ty) in module.tags.iter().enumerate() {
+        ctx.check_tag_type(format_args!("tag {}", imported_tags + i), *ty)?;
+    }
+
+    let imported_tables = ctx.tables.len() - module.tables.len();
+    for (i, table) in module.tables.iter().enumerate() {
+        let context = format!("table {}", imported_tables + i);
+        ctx.check_ref_type(&context, table.ty.elem_type)?;
+        check_table_type(&context, &table.ty)?;
+        match table.init {
+            // Tables come before the globals so only the imported ones can be read
+            Some(ref init) => ctx.check_const_expr(
+                &init.0,
+                table.ty.elem_type.into(),
+                context,
+                ctx.imported_globals,
+                features,
+            )?,
+            None if !table.ty.elem_type.nullable => {
+                return Err(error(
+                    context,
+                    format!("a table of {} needs an init expression", table.ty.elem_type),
+                ))
+            }
+            None => {}
+        }
+    }
+
+    let imported_memories = ctx.memories.len() - module.memory.len();
+    for (i, memory) in module.memory.iter().enumerate() {
+        check_memory_type(format_args!("memory {}", imported_memories + i), memory)?;
+    }
+
+    if module.functions.len() != module.code.len() {
+        return Err(error(
+            "module",
+            format!(
+                "{} functions declared but {} bodies defined",
+                module.functions.len(),
+                module.code.len()
+            ),
+        ));
+    }
+
+    // Without GC constant expressions can only read imported globals,
+    // with it the ones defined before them too
+    let const_globals = |defined: usize| match features.gc {
+        true => ctx.imported_globals + defined,
+        false => ctx.imported_globals,
+    };
+
+    for (i, global) in module.globals.iter().enumerate() {
+        let location = format!("global {}", ctx.imported_globals + i);
+        ctx.check_val_type(&location, global.ty.ty)?;
+        ctx.check_const_expr(
+            &global.init.0,
+            global.ty.ty,
+            location,
+            const_globals(i),
+            features,
+        )?;
+    }
+
+    let mut names = HashSet::new();
+    for (i, export) in module.exports.iter().enumerate() {
+        let context = format!("export {}", i);
+        match export.desc {
+            ExportDesc::Function(idx) => ctx.check_func(&context, idx)?,
+            ExportDesc::Table(idx) => ctx.check_table(&context, idx)?,
+            ExportDesc::Memory(idx) => ctx.check_memory(&context, idx)?,
+            ExportDesc::Global(idx) => ctx.check_global(&context, idx)?,
+            ExportDesc::Tag(idx) => ctx.check_tag(&context, idx)?,
+        }
+        if !names.insert(export.name.as_str()) {
+            return Err(error(
+                context,
+                format!("duplicate export name {:?}", export.name),
+            ));
+        }
+    }
+
+    if let Some(start) = module.start {
+        ctx.check_func("start", start)?;
+        let ty = ctx.check_func_type("start", ctx.funcs[start as usize])?;
+        if !ty.parameter_types.is_empty() || !ty.return_types.is_empty() {
+            return Err(error("start", "the start function must have type [] -> []"));
+        }
+    }
+
+    for (i, elem) in module.elements.iter().enumerate() {
+        let context = format!("elem {}", i);
+        let ty = elem.items.ty();
+        ctx.check_ref_type(&context, ty)?;
+        if let ElementMode::Active { table, offset } = &elem.mode {
+            ctx.check_table(&context, *table)?;
+            let elem_type = ctx.tables[*table as usize].elem_type;
+            if !ctx.is_subtype(ty.into(), elem_type.into()) {
+                return Err(error(
+                    context,
+                    format!("table {} must be a {} table", table, ty),
+                ));
+            }
+            ctx.check_const_expr(
+                &offset.0,
+                ctx.tables[*table as usize].index_type(),
+                context.clone(),
+                const_globals(module.globals.len()),
+                features,
+            )?;
+        }
+        match &elem.items {
+            ElementItems::Functions(funcs) => {
+                for idx in funcs.iter() {
+                    ctx.check_func(&context, *idx)?;
+                }
+            }
+            ElementItems::Expressions(_, exprs) => {
+                for (j, expr) in exprs.iter().enumerate() {
+                    ctx.check_const_expr(
+                        &expr.0,
+                        ty.into(),
+                        format!("{}, item {}", context, j),
+                        const_globals(module.globals.len()),
+                        features,
+                    )?;
+                }
+            }
+        }
+    }
+
+    for (i, (ty, func)) in module.functions.iter().zip(module.code.iter()).enumerate() {
+        let context = format!("func {}", ctx.imported_funcs + i);
+        let ty = ctx.check_func_type(&context, *ty)?;
+        for local in func.locals.iter() {
+            ctx.check_val_type(&context, local.ty)?;
+        }
+        let params = ty.parameter_types.len() as u64;
+        let locals = func
+            .locals
+            .iter()
+            .fold(params, |count, local| count + local.n as u64);
+        let mut location = Location::new(context);
+        ctx.check_instrs(&func.body.0, &mut location, locals)?;
+        typecheck::check_function(&ctx, ctx.imported_funcs + i, ty, func)?;
+    }
+
+    for (i, data) in module.data.iter().enumerate() {
+        if let DataMode::Active { mem, offset } = &data.mode {
+            let context = format!("data {}", i);
+            ctx.check_memory(&context, *mem)?;
+            ctx.check_const_expr(
+                &offset.0,
+                ctx.memories[*mem as usize].index_type(),
+                context,
+                const_globals(module.globals.len()),
+                features,
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Copies a type definition, replacing every type index it refers to with `f(idx)`
+fn map_indices(ty: &types::SubType, mut f: impl FnMut(TypeIdx) -> TypeIdx) -> types::SubType {
+    let supertype = ty.supertype.map(&mut f);
+    let mut val = |ty: types::ValType| match ty {
+        types::ValType::Ref(types::RefType {
+            nullable,
+            heap_type: types::HeapType::Concrete(idx),
+        }) => types::ValType::Ref(types::RefType {
+            nullable,
+            heap_type: types::HeapType::Concrete(f(idx)),
+        }),
+        ty => ty,
+    };
+    let composite = match &ty.composite {
+        types::CompositeType::Func(ty) => types::CompositeType::Func(types::FunctionType {
+            parameter_types: ty.parameter_types.iter().map(|ty| val(*ty)).collect(),
+            return_types: ty.return_types.iter().map(|ty| val(*ty)).collect(),
+        }),
+        types::CompositeType::Struct(fields) => types::CompositeType::Struct(
+            fields
+                .iter()
+                .map(|field| map_field(field, &mut val))
+                .collect(),
+        ),
+        types::CompositeType::Array(field) => {
+            types::CompositeType::Array(map_field(field, &mut val))
+        }
+    };
+    types::SubType {
+        is_final: ty.is_final,
+        supertype,
+        composite,
+    }
+}
+
+fn map_field(
+    field: &types::FieldType,
+    val: &mut impl FnMut(types::ValType) -> types::ValType,
+) -> types::FieldType {
+    let storage = match field.storage {
+        types::StorageType::Val(ty) => types::StorageType::Val(val(ty)),
+        packed => packed,
+    };
+    types::FieldType {
+        storage,
+        mutable: field.mutable,
+    }
+}
@@ -0,0 +1,286 @@
+use std::borrow::Cow;
+use wasm_builder::{
+    features::Features,
+    instr::{Expr, MAX_NESTING},
+    module::Module,
+    parse::parse_module,
+    print::{print_module, Style},
+    sections::*,
+};
+
+/// Encodes `module`, decodes the binary and checks that encoding it again gives the same bytes
+fn round_trip(module: &Module) -> Module<'static> {
+    let mut wasm = Vec::new();
+    module
+        .encode_with_features(&mut wasm, &Features::all())
+        .unwrap();
+
+    let decoded = Module::decode(&wasm).unwrap();
+    let mut again = Vec::new();
+    decoded
+        .encode_with_features(&mut again, &Features::all())
+        .unwrap();
+    assert_eq!(wasm, again);
+    assert_eq!(
+        print_module(module, Style::Flat).unwrap(),
+        print_module(&decoded, Style::Flat).unwrap()
+    );
+
+    decoded
+}
+
+fn round_trip_text(text: &str) -> Module<'static> {
+    let module = parse_module(text).unwrap();
+    module.validate_with_features(&Features::all()).unwrap();
+    round_trip(&module)
+}
+
+#[test]
+fn empty_module() {
+    let module = round_trip(&Module::new());
+    assert!(module.types.is_empty());
+    assert!(module.code.is_empty());
+
+    let mut wasm = Vec::new();
+    Module::new().encode(&mut wasm).unwrap();
+    assert_eq!(wasm, b"\0asm\x01\0\0\0");
+}
+
+#[test]
+fn every_known_section() {
+    let module = round_trip_text(
+        r#"(module
+            (type $binary (func (param i32 i32) (result i32)))
+            (type $void (func))
+            (import "env" "log" (func $log (param i32)))
+            (import "env" "table" (table 1 funcref))
+            (import "env" "memory" (memory 1))
+            (import "env" "base" (global $base i32))
+            (import "env" "error" (tag $error (param i32)))
+            (table $funcs 2 10 funcref)
+            (memory $heap 1 2)
+            (tag $other)
+            (global $counter (mut i32) (i32.const 0))
+            (global $offset i32 (global.get $base))
+            (export "add" (func $add))
+            (export "funcs" (table $funcs))
+            (export "heap" (memory $heap))
+            (export "counter" (global $counter))
+            (export "other" (tag $other))
+            (start $init)
+            (elem (table $funcs) (i32.const 0) func $add $init)
+            (func $add (type $binary)
+                local.get 0
+                local.get 1
+                i32.add)
+            (func $init (type $void)
+                (local $i i32) (local f64 f64)
+                i32.const 1
+                call $log)
+            (data (memory $heap) (i32.const 8) "hello")
+        )"#,
+    );
+
+    assert_eq!(module.types.len(), 3);
+    assert_eq!(module.imports.len(), 5);
+    assert_eq!(module.functions, vec![0, 1]);
+    assert_eq!(module.tables.len(), 1);
+    assert_eq!(module.memory.len(), 1);
+    assert_eq!(module.tags.len(), 1);
+    assert_eq!(module.globals.len(), 2);
+    assert_eq!(module.exports.len(), 5);
+    assert_eq!(module.start, Some(2));
+    assert_eq!(module.elements.len(), 1);
+    assert_eq!(module.code.len(), 2);
+    assert_eq!(module.data.len(), 1);
+    assert_eq!(&module.data[0].init[..], b"hello");
+}
+
+#[test]
+fn data_count_section() {
+    let module = round_trip_text(
+        r#"(module
+            (memory 1)
+            (func
+                i32.const 0
+                i32.const 0
+                i32.const 2
+                memory.init 1
+                data.drop 0)
+            (data (i32.const 0) "ab")
+            (data "cd")
+        )"#,
+    );
+
+    assert_eq!(module.data.len(), 2);
+    assert!(matches!(module.data[1].mode, DataMode::Passive));
+}
+
+#[test]
+fn every_element_encoding() {
+    let module = round_trip_text(
+        r#"(module
+            (table $a 4 funcref)
+            (table $b 4 externref)
+            (func $f)
+            (elem (i32.const 0) $f)
+            (elem func $f)
+            (elem (table $a) (i32.const 1) func $f)
+            (elem declare func $f)
+            (elem (i32.const 2) funcref (ref.func $f) (ref.null func))
+            (elem funcref (ref.func $f))
+            (elem (table $b) (i32.const 0) externref (ref.null extern))
+            (elem declare funcref (ref.func $f))
+        )"#,
+    );
+
+    assert_eq!(module.elements.len(), 8);
+    assert!(matches!(module.elements[1].mode, ElementMode::Passive));
+    assert!(matches!(module.elements[3].mode, ElementMode::Declarative));
+}
+
+#[test]
+fn rec_groups_and_proposals() {
+    round_trip_text(
+        r#"(module
+            (rec
+                (type $node (struct (field $next (ref null $list))))
+                (type $list (array (mut (ref null $node)))))
+            (type $sig (func (param i64) (result i64)))
+            (memory $shared i64 1 2 shared)
+            (table 1 (ref null $sig))
+            (func $get (type $sig)
+                local.get 0
+                i64.atomic.load $shared
+                return_call $get)
+            (func (result (ref $node))
+                ref.null $list
+                struct.new $node)
+            (func (param v128) (result v128)
+                local.get 0
+                local.get 0
+                i32x4.add)
+        )"#,
+    );
+}
+
+#[test]
+fn custom_sections_keep_their_place() {
+    let mut module = parse_module("(module (memory 1) (func) (data (i32.const 0) \"x\"))").unwrap();
+    let placements = [
+        Placement::Before(SectionId::Type),
+        Placement::After(SectionId::Memory),
+        Placement::Before(SectionId::Code),
+        Placement::End,
+    ];
+    for (i, placement) in placements.iter().enumerate() {
+        module.custom_sections.push(CustomSection {
+            name: format!("custom{}", i),
+            data: Cow::Owned(vec![i as u8; i]),
+            placement: *placement,
+        });
+    }
+
+    let module = round_trip(&module);
+    let decoded: Vec<_> = module
+        .custom_sections
+        .iter()
+        .map(|section| (section.name.as_str(), section.data.len(), section.placement))
+        .collect();
+    assert_eq!(
+        decoded,
+        vec![
+            ("custom0", 0, Placement::Before(SectionId::Type)),
+            ("custom1", 1, Placement::After(SectionId::Memory)),
+            ("custom2", 2, Placement::After(SectionId::Memory)),
+            ("custom3", 3, Placement::After(SectionId::Data)),
+        ]
+    );
+}
+
+#[test]
+fn hand_written_binary() {
+    // (module (type (func (param i32) (result i32))) (func (type 0) local.get 0) (export "id" (func 0)))
+    let wasm = [
+        0x00, 0x61, 0x73, 0x6D, 0x01, 0x00, 0x00, 0x00, // header
+        0x01, 0x06, 0x01, 0x60, 0x01, 0x7F, 0x01, 0x7F, // type section
+        0x03, 0x02, 0x01, 0x00, // function section
+        0x07, 0x06, 0x01, 0x02, 0x69, 0x64, 0x00, 0x00, // export section
+        0x0A, 0x06, 0x01, 0x04, 0x00, 0x20, 0x00, 0x0B, // code section
+    ];
+
+    let module = Module::decode(&wasm).unwrap();
+    assert_eq!(module.exports[0].name, "id");
+    assert!(module.validate().is_ok());
+
+    let mut again = Vec::new();
+    module.encode(&mut again).unwrap();
+    assert_eq!(&again[..], &wasm[..]);
+}
+
+#[test]
+fn malformed_binaries() {
+    let errors = [
+        (
+            &b"\0asn\x01\0\0\0"[..],
+            "malformed module: magic header not detected",
+        ),
+        (
+            b"\0asm\x02\0\0\0",
+            "malformed module: unknown binary version",
+        ),
+        (
+            b"\0asm\x01\0\0\0\x0E\x00",
+            "malformed module: unknown section id 14",
+        ),
+        (
+            b"\0asm\x01\0\0\0\x03\x01\x00\x01\x01\x00",
+            "malformed module: unexpected Type section after Function section",
+        ),
+        (
+            b"\0asm\x01\0\0\0\x03\x02\x01\x00",
+            "malformed module: function and code section have inconsistent lengths",
+        ),
+    ];
+
+    for (wasm, message) in errors.iter() {
+        let err = Module::decode(wasm).unwrap_err();
+        assert_eq!(err.to_string(), *message);
+    }
+}
+
+#[test]
+fn encode_allows_every_proposal() {
+    let module = parse_module("(module (memory i64 1 2 shared))").unwrap();
+    let mut wasm = Vec::new();
+    module.encode(&mut wasm).unwrap();
+
+    let mut again = Vec::new();
+    Module::decode(&wasm).unwrap().encode(&mut again).unwrap();
+    assert_eq!(wasm, again);
+
+    let err = module
+        .encode_with_features(&mut Vec::new(), &Features::default())
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "memory 0: the threads proposal is not enabled"
+    );
+}
+
+/// A body of `depth` nested empty blocks followed by the final end
+fn nested_blocks(depth: usize) -> Vec<u8> {
+    let mut bytes = [0x02, 0x40].repeat(depth);
+    bytes.extend(vec![0x0B; depth + 1]);
+    bytes
+}
+
+#[test]
+fn nesting_is_limited() {
+    assert!(Expr::from_bytes(&nested_blocks(MAX_NESTING)).is_ok());
+
+    let err = Expr::from_bytes(&nested_blocks(MAX_NESTING + 1)).unwrap_err();
+    assert_eq!(err.to_string(), "malformed module: nesting too deep");
+    let err = Expr::from_bytes(&nested_blocks(10_000)).unwrap_err();
+    assert_eq!(err.to_string(), "malformed module: nesting too deep");
+}
@@ -0,0 +1,371 @@
+use wasm_builder::{
+    features::Features,
+    instr::{self, *},
+    module::Module,
+    parse::parse_module,
+    print::{print_module, Style},
+    sections::*,
+    types::{FunctionType, GlobalType, Limits, MemoryType, ValType},
+};
+
+/// Checks that `text` parses to the same module as `expected`
+fn assert_parses_to(text: &str, expected: &Module) {
+    let parsed = parse_module(text).unwrap();
+    assert_eq!(
+        print_module(&parsed, Style::Flat).unwrap(),
+        print_module(expected, Style::Flat).unwrap()
+    );
+
+    let mut parsed_wasm = Vec::new();
+    parsed
+        .encode_with_features(&mut parsed_wasm, &Features::all())
+        .unwrap();
+    let mut expected_wasm = Vec::new();
+    expected
+        .encode_with_features(&mut expected_wasm, &Features::all())
+        .unwrap();
+    assert_eq!(parsed_wasm, expected_wasm);
+}
+
+fn func_type(params: &[ValType], results: &[ValType]) -> FunctionType {
+    FunctionType {
+        parameter_types: params.to_vec(),
+        return_types: results.to_vec(),
+    }
+}
+
+fn i32_const(val: i32) -> Instruction {
+    Instruction::Const(Literal::I32(val))
+}
+
+#[test]
+fn names_resolve_to_indices() {
+    let text = r#"(module
+        (type $unary (func (param i32) (result i32)))
+        (global $step i32 (i32.const 2))
+        (global $total (mut i32) (i32.const 0))
+        (func $twice (type $unary) (param $x i32) (result i32)
+            (local $tmp i32) (local $unused f64) (local $acc i32)
+            local.get $x
+            call $inc
+            local.tee $tmp
+            local.get $tmp
+            i32.add
+            local.set $acc
+            global.get $total
+            local.get $acc
+            i32.add
+            global.set $total
+            local.get $acc)
+        (func $inc (type $unary)
+            local.get 0
+            global.get $step
+            i32.add)
+    )"#;
+
+    let mut expected = Module::new();
+    expected
+        .types
+        .push(func_type(&[ValType::I32], &[ValType::I32]).into());
+    expected.globals.push(Global {
+        ty: GlobalType {
+            ty: ValType::I32,
+            mutable: false,
+        },
+        init: Expr(vec![i32_const(2)]),
+    });
+    expected.globals.push(Global {
+        ty: GlobalType {
+            ty: ValType::I32,
+            mutable: true,
+        },
+        init: Expr(vec![i32_const(0)]),
+    });
+    expected.functions = vec![0, 0];
+    expected.code.push(Function {
+        locals: vec![
+            Local {
+                n: 1,
+                ty: ValType::I32,
+            },
+            Local {
+                n: 1,
+                ty: ValType::F64,
+            },
+            Local {
+                n: 1,
+                ty: ValType::I32,
+            },
+        ],
+        body: Expr(vec![
+            Instruction::LocalGet(0),
+            Instruction::Call(1),
+            Instruction::LocalTee(1),
+            Instruction::LocalGet(1),
+            Instruction::Add(ValType::I32),
+            Instruction::LocalSet(3),
+            Instruction::GlobalGet(1),
+            Instruction::LocalGet(3),
+            Instruction::Add(ValType::I32),
+            Instruction::GlobalSet(1),
+            Instruction::LocalGet(3),
+        ]),
+    });
+    expected.code.push(Function {
+        locals: vec![],
+        body: Expr(vec![
+            Instruction::LocalGet(0),
+            Instruction::GlobalGet(0),
+            Instruction::Add(ValType::I32),
+        ]),
+    });
+
+    assert_parses_to(text, &expected);
+}
+
+#[test]
+fn labels_resolve_to_depths() {
+    let text = r#"(module
+        (func $loop (param $n i32)
+            block $exit
+                loop $again
+                    local.get $n
+                    i32.eqz
+                    br_if $exit
+                    block $inner
+                        local.get $n
+                        br_table $inner $again $exit
+                    end
+                    br $again
+                end
+            end)
+    )"#;
+
+    let mut expected = Module::new();
+    expected.types.push(func_type(&[ValType::I32], &[]).into());
+    expected.functions.push(0);
+    expected.code.push(Function {
+        locals: vec![],
+        body: Expr(vec![Instruction::Block {
+            ty: BlockType::Empty,
+            instrs: vec![Instruction::Loop {
+                ty: BlockType::Empty,
+                instrs: vec![
+                    Instruction::LocalGet(0),
+                    Instruction::EqualZero(IntegerType::I32),
+                    Instruction::BranchIf(1),
+                    Instruction::Block {
+                        ty: BlockType::Empty,
+                        instrs: vec![
+                            Instruction::LocalGet(0),
+                            Instruction::BranchTable {
+                                labels: vec![0, 1],
+                                operand: 2,
+                            },
+                        ],
+                    },
+                    Instruction::Branch(0),
+                ],
+            }],
+        }]),
+    });
+
+    assert_parses_to(text, &expected);
+}
+
+#[test]
+fn inline_imports_and_exports() {
+    let text = r#"(module
+        (func $log (import "env" "log") (param i32))
+        (global $g (import "env" "g") i32)
+        (func $main (export "main")
+            global.get $g
+            call $log)
+        (memory $mem (export "memory") (export "mem") 1)
+    )"#;
+
+    let mut expected = Module::new();
+    expected.types.push(func_type(&[ValType::I32], &[]).into());
+    expected.types.push(func_type(&[], &[]).into());
+    expected.imports.push(Import {
+        module: "env".to_string(),
+        name: "log".to_string(),
+        desc: Desc::Function(0),
+    });
+    expected.imports.push(Import {
+        module: "env".to_string(),
+        name: "g".to_string(),
+        desc: Desc::Global(GlobalType {
+            ty: ValType::I32,
+            mutable: false,
+        }),
+    });
+    expected.functions.push(1);
+    expected.memory.push(MemoryType {
+        lim: Limits { min: 1, max: None },
+        shared: false,
+        memory64: false,
+    });
+    expected.exports = vec![
+        Export {
+            name: "main".to_string(),
+            desc: ExportDesc::Function(1),
+        },
+        Export {
+            name: "memory".to_string(),
+            desc: ExportDesc::Memory(0),
+        },
+        Export {
+            name: "mem".to_string(),
+            desc: ExportDesc::Memory(0),
+        },
+    ];
+    expected.code.push(Function {
+        locals: vec![],
+        body: Expr(vec![Instruction::GlobalGet(0), Instruction::Call(0)]),
+    });
+
+    assert_parses_to(text, &expected);
+}
+
+#[test]
+fn folded_instructions_are_flattened() {
+    let folded = r#"(module
+        (func $abs (param $x i32) (result i32)
+            (if (result i32) (i32.lt_s (local.get $x) (i32.const 0))
+                (then (i32.sub (i32.const 0) (local.get $x)))
+                (else (local.get $x))))
+        (func (result i32)
+            (block $b (result i32)
+                (br_if $b (i32.const 1) (i32.const 0))
+                (drop)
+                (call $abs (i32.const -5))))
+    )"#;
+    let flat = r#"(module
+        (func $abs (param $x i32) (result i32)
+            local.get $x
+            i32.const 0
+            i32.lt_s
+            if (result i32)
+                i32.const 0
+                local.get $x
+                i32.sub
+            else
+                local.get $x
+            end)
+        (func (result i32)
+            block $b (result i32)
+                i32.const 1
+                i32.const 0
+                br_if $b
+                drop
+                i32.const -5
+                call $abs
+            end)
+    )"#;
+
+    let mut expected = Module::new();
+    expected
+        .types
+        .push(func_type(&[ValType::I32], &[ValType::I32]).into());
+    expected.types.push(func_type(&[], &[ValType::I32]).into());
+    expected.functions = vec![0, 1];
+    expected.code.push(Function {
+        locals: vec![],
+        body: Expr(vec![
+            Instruction::LocalGet(0),
+            i32_const(0),
+            Instruction::LessThanI32 {
+                ty: IntegerType::I32,
+                signed: true,
+            },
+            Instruction::If {
+                ty: BlockType::Type(ValType::I32),
+                accept_instrs: vec![
+                    i32_const(0),
+                    Instruction::LocalGet(0),
+                    Instruction::Subtract(ValType::I32),
+                ],
+                reject_instrs: Some(vec![Instruction::LocalGet(0)]),
+            },
+        ]),
+    });
+    expected.code.push(Function {
+        locals: vec![],
+        body: Expr(vec![Instruction::Block {
+            ty: BlockType::Type(ValType::I32),
+            instrs: vec![
+                i32_const(1),
+                i32_const(0),
+                Instruction::BranchIf(0),
+                Instruction::Drop,
+                i32_const(-5),
+                Instruction::Call(0),
+            ],
+        }]),
+    });
+
+    assert_parses_to(folded, &expected);
+    assert_parses_to(flat, &expected);
+}
+
+#[test]
+fn errors_point_at_the_token() {
+    let errors = [
+        (
+            "(module (func call $missing))",
+            "1:20: unknown function $missing",
+        ),
+        (
+            "(module (func (param $a i32)) (func local.get $a drop))",
+            "1:47: unknown local $a",
+        ),
+        ("(module (func br $out))", "1:18: unknown label $out"),
+        (
+            "(module (func $f) (func $f))",
+            "1:19: duplicate identifier $f",
+        ),
+        (
+            "(module (func) (func (import \"env\" \"f\")))",
+            "1:16: imports must come before any definition",
+        ),
+    ];
+
+    for (text, message) in errors.iter() {
+        let err = parse_module(text).unwrap_err();
+        assert_eq!(err.to_string(), *message);
+    }
+}
+
+#[test]
+fn nesting_is_limited() {
+    let flat = |depth: usize| {
+        format!(
+            "(module (func {} {}))",
+            "block ".repeat(depth),
+            "end ".repeat(depth)
+        )
+    };
+    let folded = |depth: usize| {
+        format!(
+            "(module (func {} {}))",
+            "(block ".repeat(depth),
+            ")".repeat(depth)
+        )
+    };
+    let operands = |depth: usize| {
+        format!(
+            "(module (func (result i32) {} (i32.const 0) {}))",
+            "(i32.eqz ".repeat(depth - 1),
+            ")".repeat(depth - 1)
+        )
+    };
+
+    for text in [flat, folded, operands].iter() {
+        assert!(parse_module(&text(instr::MAX_NESTING)).is_ok());
+        for depth in [instr::MAX_NESTING + 1, 10_000].iter() {
+            let err = parse_module(&text(*depth)).unwrap_err();
+            assert!(err.to_string().ends_with(": nesting too deep"), "{}", err);
+        }
+    }
+}
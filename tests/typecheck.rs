@@ -0,0 +1,380 @@
+use wasm_builder::{
+    error::Error,
+    features::Features,
+    instr::*,
+    module::Module,
+    parse::parse_module,
+    print::{print_expr, Style},
+    sections::{Data, DataMode, Function},
+    types::{FunctionType, Limits, MemoryType, ValType},
+};
+
+/// A module with a memory and a single `[] -> []` function running `body`
+fn module_with_body(body: Vec<Instruction>) -> Module<'static> {
+    let mut module = Module::new();
+    module.types.push(
+        FunctionType {
+            parameter_types: vec![],
+            return_types: vec![],
+        }
+        .into(),
+    );
+    module.memory.push(MemoryType {
+        lim: Limits { min: 1, max: None },
+        shared: false,
+        memory64: false,
+    });
+    module.functions.push(0);
+    module.code.push(Function {
+        locals: vec![],
+        body: Expr(body),
+    });
+    module
+}
+
+const MEM: MemoryArgument = MemoryArgument {
+    alignment: 0,
+    offset: 0,
+    memory: 0,
+};
+
+#[test]
+fn loads_and_stores_without_an_opcode() {
+    let cases = [
+        (
+            Instruction::Load {
+                mem: MEM,
+                ty: ValType::F32,
+                storage: Some((true, StorageType::I8)),
+            },
+            Instruction::Drop,
+            "float loads can't have a storage size",
+        ),
+        (
+            Instruction::Load {
+                mem: MEM,
+                ty: ValType::V128,
+                storage: Some((false, StorageType::I16)),
+            },
+            Instruction::Drop,
+            "v128 loads can't have a storage size",
+        ),
+        (
+            Instruction::Load {
+                mem: MEM,
+                ty: ValType::I32,
+                storage: Some((true, StorageType::I32)),
+            },
+            Instruction::Drop,
+            "i32 loads can't have a 32 bit storage size",
+        ),
+        (
+            Instruction::Const(Literal::I32(0)),
+            Instruction::Store {
+                mem: MEM,
+                ty: ValType::I32,
+                storage: Some(StorageType::I32),
+            },
+            "i32 stores can't have a 32 bit storage size",
+        ),
+        (
+            Instruction::Const(Literal::F64(0.0)),
+            Instruction::Store {
+                mem: MEM,
+                ty: ValType::F64,
+                storage: Some(StorageType::I8),
+            },
+            "float stores can't have a storage size",
+        ),
+        (
+            Instruction::Const(Literal::V128(0)),
+            Instruction::Store {
+                mem: MEM,
+                ty: ValType::V128,
+                storage: Some(StorageType::I16),
+            },
+            "v128 stores can't have a storage size",
+        ),
+    ];
+
+    for (first, second, reason) in cases.iter() {
+        let (invalid, idx) = match first {
+            Instruction::Load { .. } => (first, 1),
+            _ => (second, 2),
+        };
+        let mut body = vec![Instruction::Const(Literal::I32(0))];
+        body.push(first.clone());
+        body.push(second.clone());
+        let module = module_with_body(body);
+
+        let err = module.validate_with_features(&Features::all()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("func 0, instr {}: {}", idx, reason)
+        );
+
+        // The printer and the encoder refuse them for the same reason
+        let err = print_expr(&Expr(vec![invalid.clone()]), Style::Flat).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("invalid instruction {:?}: {}", invalid, reason)
+        );
+
+        let err = module
+            .encode_with_features(&mut Vec::new(), &Features::all())
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("invalid instruction {:?}: {}", invalid, reason)
+        );
+    }
+}
+
+#[test]
+fn loads_and_stores_with_an_opcode() {
+    let module = module_with_body(vec![
+        Instruction::Const(Literal::I32(0)),
+        Instruction::Const(Literal::I32(0)),
+        Instruction::Load {
+            mem: MEM,
+            ty: ValType::I64,
+            storage: Some((false, StorageType::I32)),
+        },
+        Instruction::Store {
+            mem: MEM,
+            ty: ValType::I64,
+            storage: Some(StorageType::I16),
+        },
+    ]);
+    assert!(module.validate().is_ok());
+}
+
+/// Validates the text module, returning the message of the error
+fn error(text: &str) -> String {
+    parse_module(text)
+        .unwrap()
+        .validate_with_features(&Features::all())
+        .unwrap_err()
+        .to_string()
+}
+
+fn is_valid(text: &str) -> bool {
+    parse_module(text).unwrap().validate().is_ok()
+}
+
+#[test]
+fn errors_point_at_the_instruction() {
+    assert_eq!(
+        error("(module (func (result i32) block block i32.const 1 f32.const 1 i32.add drop end end i32.const 0))"),
+        "func 0, block 0, block 0, instr 2: expected i32 got f32"
+    );
+    assert_eq!(
+        error(
+            "(module (func) (func (param i32) local.get 0 if nop else i32.const 0 i64.const 1 i64.add drop end))"
+        ),
+        "func 1, else 1, instr 2: expected i64 got i32"
+    );
+    assert_eq!(
+        error("(module (func local.get 3 drop))"),
+        "func 0, instr 0: unknown local 3"
+    );
+    assert_eq!(
+        error("(module (func i32.const 0 br 1))"),
+        "func 0, instr 1: unknown label 1"
+    );
+}
+
+#[test]
+fn end_of_block_errors_show_the_whole_stack() {
+    assert_eq!(
+        error("(module (func (result i32) f64.const 1))"),
+        "func 0, end: expected i32 got f64"
+    );
+    assert_eq!(
+        error("(module (func (result i32) i32.const 1 i32.const 2))"),
+        "func 0, end: expected [i32] got [i32 i32]"
+    );
+    assert_eq!(
+        error("(module (func block (result i32) f32.const 1 i64.const 2 i32.const 3 end drop))"),
+        "func 0, block 0, end: expected [i32] got [f32 i64 i32]"
+    );
+    assert_eq!(
+        error("(module (func (result i32) block (result i32) i32.const 1 i64.const 2 end))"),
+        "func 0, block 0, end: expected i32 got i64"
+    );
+    assert_eq!(
+        error("(module (func (result i32)))"),
+        "func 0, end: expected i32 got nothing"
+    );
+}
+
+#[test]
+fn unreachable_code_is_polymorphic() {
+    assert!(is_valid("(module (func (result i32) unreachable))"));
+    assert!(is_valid("(module (func (result i32) unreachable i32.add))"));
+    assert!(is_valid(
+        "(module (func (result i32) i32.const 0 br 0 drop drop))"
+    ));
+    assert!(is_valid("(module (func return drop drop))"));
+
+    // Values pushed after the branch must still be of the right type
+    assert_eq!(
+        error("(module (func (result i32) unreachable f32.const 1 i32.add))"),
+        "func 0, instr 2: expected i32 got f32"
+    );
+    assert_eq!(
+        error("(module (func (result i32) unreachable i64.const 1))"),
+        "func 0, end: expected i32 got i64"
+    );
+    // Only the rest of the block is unreachable
+    assert_eq!(
+        error("(module (func (result i32) block br 0 end i32.add))"),
+        "func 0, instr 1: expected i32 got nothing"
+    );
+}
+
+#[test]
+fn br_table_labels_must_agree() {
+    assert!(is_valid(
+        "(module (func (param i32) (result i32) block (result i32) i32.const 7 local.get 0 br_table 0 1 end))"
+    ));
+    assert_eq!(
+        error("(module (func (param i32) block (result i32) i32.const 0 local.get 0 br_table 0 1 end drop))"),
+        "func 0, block 0, instr 2: label 0 expects [i32] but the default label expects []"
+    );
+    assert_eq!(
+        error(
+            "(module (func (param i32) (result i32) block (result i32) block (result i64) i64.const 0 local.get 0 br_table 0 1 end drop i32.const 0 end))"
+        ),
+        "func 0, block 0, block 0, instr 2: expected i32 got i64"
+    );
+    assert_eq!(
+        error("(module (func (param i32) block local.get 0 br_table 0 2 end))"),
+        "func 0, block 0, instr 1: unknown label 2"
+    );
+}
+
+#[test]
+fn memory_errors_count_imported_memories() {
+    assert_eq!(
+        error(r#"(module (import "env" "mem" (memory 1)) (memory 2 1))"#),
+        "memory 1: minimum size must not be greater than the maximum"
+    );
+}
+
+#[test]
+fn table_inits_declare_function_references() {
+    let text = r#"(module
+        (type $f (func))
+        (func $target (type $f))
+        (table 1 (ref $f) (ref.func $target))
+        (func (result (ref $f)) ref.func $target)
+    )"#;
+    assert!(parse_module(text)
+        .unwrap()
+        .validate_with_features(&Features::all())
+        .is_ok());
+
+    assert_eq!(
+        error("(module (type $f (func)) (func $target (type $f)) (func (result (ref $f)) ref.func $target))"),
+        "func 1, instr 0: function 0 must be referenced outside of function bodies (i.e. by an export) to be used by ref.func"
+    );
+}
+
+#[test]
+fn extended_const_allows_arithmetic_in_offsets() {
+    let extended = Features {
+        extended_const: true,
+        ..Features::default()
+    };
+
+    let global = parse_module(
+        r#"(module
+            (import "env" "base" (global i32))
+            (global i32 (i32.add (global.get 0) (i32.const 1))))"#,
+    )
+    .unwrap();
+    assert_eq!(
+        global.validate().unwrap_err().to_string(),
+        "global 1, instr 2: the extended-const proposal is not enabled"
+    );
+    assert!(global.validate_with_features(&extended).is_ok());
+
+    let mut data =
+        parse_module(r#"(module (import "env" "__memory_base" (global i32)) (memory 1))"#).unwrap();
+    data.data.push(Data {
+        mode: DataMode::Active {
+            mem: 0,
+            offset: Expr::global_offset(0, IntegerType::I32, 16),
+        },
+        init: b"hello"[..].into(),
+    });
+    assert_eq!(
+        data.validate().unwrap_err().to_string(),
+        "data 0, instr 2: the extended-const proposal is not enabled"
+    );
+    assert!(data.validate_with_features(&extended).is_ok());
+}
+
+#[test]
+fn mvp_allows_funcref_tables() {
+    let module = parse_module(
+        "(module (type (func)) (table 1 funcref) (func i32.const 0 call_indirect (type 0)))",
+    )
+    .unwrap();
+    assert!(module.validate_with_features(&Features::mvp()).is_ok());
+
+    let module = parse_module("(module (table 1 externref))").unwrap();
+    assert_eq!(
+        module
+            .validate_with_features(&Features::mvp())
+            .unwrap_err()
+            .to_string(),
+        "table 0: the reference-types proposal is not enabled"
+    );
+}
+
+#[test]
+fn atomic_accesses_must_be_naturally_aligned() {
+    assert_eq!(
+        error(
+            "(module (memory 1 1 shared) (func (result i32) i32.const 0 i32.atomic.load align=2))"
+        ),
+        "func 0, instr 1: atomic alignment 2^1 must be the natural alignment 2^2"
+    );
+    assert_eq!(
+        error("(module (memory 1 shared))"),
+        "memory 0: shared memory must have a maximum"
+    );
+}
+
+#[test]
+fn return_call_results_must_match() {
+    assert_eq!(
+        error("(module (func $f (result i64) i64.const 0) (func (result i32) return_call $f))"),
+        "func 1, instr 0: tail call results [i64] don't match the function's results [i32]"
+    );
+
+    let calls = [
+        "(module (func return_call 0))",
+        "(module (type (func)) (table 1 funcref) (func i32.const 0 return_call_indirect (type 0)))",
+    ];
+    for text in calls.iter() {
+        let module = parse_module(text).unwrap();
+        for features in [Features::mvp(), Features::default()].iter() {
+            let err = module.validate_with_features(features).unwrap_err();
+            assert!(
+                matches!(
+                    err,
+                    Error::FeatureDisabled {
+                        feature: "tail-call",
+                        ..
+                    }
+                ),
+                "{}",
+                err
+            );
+        }
+        assert!(module.validate_with_features(&Features::all()).is_ok());
+    }
+}